@@ -0,0 +1,13 @@
+# RUN: sh -ea @file
+
+# CHECK-BLOCK:
+#   first line
+#
+#   third line, after a blank
+# CHECK-NEXT: fourth line
+
+echo "header"
+echo "first line"
+echo ""
+echo "third line, after a blank"
+echo "fourth line"
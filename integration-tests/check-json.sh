@@ -0,0 +1,6 @@
+# RUN: sh @file
+# CHECK-JSON: $.name == "lit"
+# CHECK-JSON: $.versions[1] == 2.0
+# CHECK-JSON: $.authors == [{"email": null, "name": "Dylan"}]
+
+echo '{"name": "lit", "versions": [1, 2], "authors": [{"name": "Dylan", "email": null}]}'
@@ -0,0 +1,13 @@
+# RUN: sh -ea @file
+
+# CHECK: begin foo
+# CHECK-NOT-BETWEEN: warning
+# CHECK-END: end foo
+# CHECK: begin bar
+# CHECK: warning in bar
+
+echo "begin foo"
+echo "all good"
+echo "end foo"
+echo "begin bar"
+echo "warning in bar"
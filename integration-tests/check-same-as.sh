@@ -0,0 +1,5 @@
+# RUN: sh @file
+# CHECK-SAME-AS: printf 'hello\nworld\n'
+
+echo hello
+echo world
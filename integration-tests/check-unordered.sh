@@ -0,0 +1,12 @@
+# RUN: sh -ea @file
+# CHECK-UNORDERED:
+
+# CHECK: section b
+# CHECK-NEXT: b contents
+# CHECK: section a
+# CHECK: contents
+
+echo "section a"
+echo "a contents"
+echo "section b"
+echo "b contents"
@@ -0,0 +1,16 @@
+# ---
+# timeout = 60
+# tags = ["example"]
+#
+# [env]
+# LIT_FRONT_MATTER_GREETING = "hello from the environment"
+#
+# [constants]
+# subject = "front matter"
+# ---
+# RUN: sh @file "@subject"
+
+# CHECK: hello from the environment
+# CHECK-NEXT: with front matter
+echo "$LIT_FRONT_MATTER_GREETING"
+echo "with $1"
@@ -0,0 +1,7 @@
+# The builtin helpers behave the same on every platform.
+# RUN: sh -c "@echo hello from echo; @not false && echo not inverted; printf 'a\nb\n' | @count 2 && echo counted; @diff @file @file && echo identical"
+
+# CHECK: hello from echo
+# CHECK: not inverted
+# CHECK: counted
+# CHECK: identical
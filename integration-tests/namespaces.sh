@@ -0,0 +1,9 @@
+# RUN: sh -ea @file @env:HOME
+
+# CHECK: home is $$env:HOME
+# CHECK: id [[id:\d+]]
+# CHECK: again $$cap:id
+
+echo "home is $1"
+echo "id 42"
+echo "again 42"
@@ -0,0 +1,7 @@
+# RUN: sh @file
+# NORMALIZE-PRESET: timestamps
+# NORMALIZE-PRESET: durations
+# CHECK-SAME-AS: printf 'started at <timestamp>\nfinished in <duration>\n'
+
+date -u +'started at %Y-%m-%dT%TZ'
+echo "finished in 0.$$s"
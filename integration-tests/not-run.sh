@@ -0,0 +1,7 @@
+# Commands prefixed with '!' pass only when they exit unsuccessfully.
+# RUN: !sh @file
+# NOT-RUN: sh @file
+
+# CHECK: about to give up
+echo about to give up
+exit 3
@@ -0,0 +1,5 @@
+# RESOURCE: integration-test-lock
+# RUN: sh @file
+# CHECK: holding the lock
+
+echo "holding the lock"
@@ -0,0 +1,11 @@
+# RUN-BACKGROUND: sh @file server
+# WAIT-FOR: server is [[\w+]]
+# RUN: sh @file client
+# CHECK: client connected
+
+if [ "$1" = "server" ]; then
+  echo "server is listening"
+  sleep 30
+else
+  echo "client connected"
+fi
@@ -0,0 +1,11 @@
+# RUN-BACKGROUND: sh @file server @free_port
+# WAIT-FOR: listening on $$free_port
+# RUN: sh @file client @free_port @unique_id
+# CHECK: connecting to $$free_port as $$unique_id
+
+if [ "$1" = "server" ]; then
+  echo "listening on $2"
+  sleep 30
+else
+  echo "connecting to $2 as $3"
+fi
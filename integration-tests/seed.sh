@@ -0,0 +1,4 @@
+# RUN: sh @file @seed
+# CHECK: seed is [[\d+]]
+
+echo "seed is $1"
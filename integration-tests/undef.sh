@@ -0,0 +1,12 @@
+# RUN: sh -ea @file
+
+# CHECK: first id is [[id:\d+]]
+# CHECK: repeated $$id
+# UNDEF: id
+# CHECK: second id is [[id:\d+]]
+# CHECK: repeated $$id
+
+echo "first id is 12"
+echo "repeated 12"
+echo "second id is 34"
+echo "repeated 34"
@@ -3,13 +3,35 @@
 //! Use the code in this module to tune testing behaviour.
 
 #[cfg(feature = "clap")] pub mod clap;
+pub mod file;
+pub mod owners;
+pub mod presets;
+pub mod run_as;
 
+pub use self::run_as::RunAs;
+
+use crate::{executor::{self, Executor}, matcher::Matcher, model::{TestFilePath, TestResult}, plugin::Plugin, run::{CancellationToken, upload::Manifest}, test_source::{self, TestSource}};
 use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::fmt;
+use std::time::Duration;
+use regex::Regex;
 use tempfile::NamedTempFile;
 
 const DEFAULT_MAX_OUTPUT_CONTEXT_LINE_COUNT: usize = 10;
+const DEFAULT_MAX_REPEATED_WARNINGS: usize = 5;
+/// The separator between the name of a directive and its argument, as in `CHECK: hello`.
+pub const DEFAULT_DIRECTIVE_SEPARATOR: char = ':';
+/// Matches the names of environment variables that commonly hold secrets.
+const DEFAULT_ENVIRONMENT_REDACTION: &str = "(?i)(secret|token|passw(or)?d|credential|api_?key|private_?key)";
+const DEFAULT_BACKGROUND_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_TERMINATION_GRACE_PERIOD: Duration = Duration::from_secs(2);
+/// The default maximum compiled size of a generated regex, in bytes.
+///
+/// This is larger than the `regex` crate default, as patterns generated from
+/// long directives with many interpolated variables can get quite big.
+const DEFAULT_REGEX_SIZE_LIMIT: usize = 64 * 1024 * 1024;
 
 /// The configuration of the test runner.
 #[derive(Clone, Debug)]
@@ -17,10 +39,90 @@ pub struct Config
 {
     /// A list of file extensions which contain tests.
     pub supported_file_extensions: Vec<String>,
+    /// Patterns matching the whole names of files which contain tests, such as `Makefile.lit`
+    /// or `*.test.sh`, where `*` matches anything. See `Config::add_file_name_pattern`.
+    pub supported_file_name_patterns: Vec<String>,
+    /// The name of fixture files, such as `fixture.txt`, whose `RUN` commands run once before
+    /// the tests in their directory and beneath it, rather than being tests. See `run::fixtures`.
+    pub fixture_file_name: Option<String>,
+    /// The comments that directives must appear in, by file extension.
+    ///
+    /// Directives may appear anywhere in files whose extension is not listed.
+    pub comment_syntaxes: HashMap<String, CommentSyntax>,
+    /// The separator between the name of a directive and its argument, such as `=` for `CHECK= hello`.
+    pub directive_separator: char,
+    /// Directive separators that override `directive_separator`, by file extension.
+    pub directive_separators: HashMap<String, char>,
     /// Paths to tests or folders containing tests.
     pub test_paths: Vec<PathBuf>,
+    /// Extra sources of tests, in addition to those found on disk underneath `test_paths`.
+    pub test_sources: Vec<Arc<dyn TestSource>>,
     /// Constants that tests can refer to via `@<name>` syntax.
     pub constants: HashMap<String, String>,
+    /// Options used when building the regexes for text patterns.
+    pub regex_options: RegexOptions,
+    /// Reusable named regex snippets, referenced in patterns via `[[:<name>:]]`.
+    pub patterns: HashMap<String, String>,
+    /// Constants that only apply to tests located underneath a specific directory.
+    ///
+    /// When several directories contain a test, the constants from the most specific
+    /// (longest) directory path take precedence. Directory constants always override
+    /// the global `constants`.
+    pub directory_constants: Vec<DirectoryConstants>,
+    /// The seed substituted for `@seed`, so that tests of randomized tools are reproducible.
+    ///
+    /// A random seed is chosen for every run if this is not set.
+    pub seed: Option<u64>,
+    /// The maximum number of test files that are executed concurrently.
+    ///
+    /// Defaults to one, so that tests run serially unless asked otherwise.
+    pub jobs: usize,
+    /// The maximum number of instances of the same test file that are executed concurrently,
+    /// such as when a file is given more than once. Unlimited by default.
+    ///
+    /// Instances often share fixture paths next to the test file.
+    pub max_concurrent_instances_per_file: Option<usize>,
+    /// The maximum number of tests that may hold a resource at the same time.
+    ///
+    /// Resources without a configured limit are exclusive - only one test may
+    /// hold them at a time.
+    pub resource_limits: HashMap<String, usize>,
+    /// Resources that are implicitly held by all tests underneath a directory,
+    /// in addition to those declared via `RESOURCE` directives.
+    pub directory_resources: Vec<DirectoryResources>,
+    /// If set, only the tests in this shard of the suite are run.
+    pub shard: Option<Shard>,
+    /// If set, only the tests affected by changes since this git revision are run.
+    ///
+    /// A test is affected if it was modified itself, or if a path it declares via a
+    /// `DEPENDS` directive was modified.
+    pub changed_since: Option<String>,
+    /// Rules mapping test paths to their owners, used for tests without `OWNER` directives.
+    pub ownership_rules: Vec<owners::OwnershipRule>,
+    /// A file recording how long each test took in previous runs.
+    ///
+    /// When set, the file is updated after every run, and shards are balanced by
+    /// expected duration rather than by number of tests.
+    pub timing_history_file: Option<PathBuf>,
+    /// Whether the tests that failed the last time they ran are started before all others,
+    /// according to `Config::timing_history_file`.
+    pub fail_first: bool,
+    /// A saved environment, such as the `environment.txt` artifact of an earlier run, which
+    /// the environment of the suite must match. See `lit::run::environment`.
+    pub required_environment: Option<PathBuf>,
+    /// The format that test events are reported in.
+    ///
+    /// When set to anything but `MessageFormat::Human`, `run::tests` emits the requested
+    /// format alongside the events given to its event handler. The default event handler
+    /// prints nothing but warnings in that case.
+    pub message_format: MessageFormat,
+    /// How failures are reported by the default event handler.
+    pub error_format: ErrorFormat,
+    /// The format of the `events.log` test artifact, which records how long each phase of
+    /// each test took, and when tests were scheduled.
+    ///
+    /// The log is only written when artifacts are saved.
+    pub audit_log_format: Option<AuditLogFormat>,
     /// A function which used to dynamically lookup variables.
     ///
     /// The default variable lookup can be found at `Config::DEFAULT_VARIABLE_LOOKUP`.
@@ -34,27 +136,316 @@ pub struct Config
     /// This includes temporary files created by using `@tempfile`
     /// variables.
     pub cleanup_temporary_files: bool,
+    /// The directory that temporary files and directories are created in, instead of
+    /// the OS default, such as a RAM disk.
+    ///
+    /// This covers `@tempfile` variables, in-memory test suites, and, via `TMPDIR`,
+    /// the scratch files of the commands under test. It is created if it does not exist.
+    pub temp_root: Option<PathBuf>,
     /// Export all generated test artifacts to the specified directory.
     pub save_artifacts_to_directory: Option<PathBuf>,
+    /// An archive, such as `failures.tar.gz`, that the artifacts of failing tests are packed
+    /// into when the suite finishes, for attaching to bug reports.
+    ///
+    /// The archive is only written when artifacts are saved and a test failed.
+    pub bundle_failures_to: Option<PathBuf>,
+    /// A function run once the artifacts of a suite have been saved, such as to upload them.
+    /// It is only run if artifacts were written. See `Config::set_after_artifacts`.
+    pub after_artifacts: Option<AfterArtifacts>,
+    /// A shell command run once the artifacts of a suite have been saved, with the paths of the
+    /// artifacts directory and its manifest in the `LIT_ARTIFACTS_DIRECTORY` and
+    /// `LIT_ARTIFACTS_MANIFEST` environment variables. It is only run if artifacts were written.
+    pub after_artifacts_command: Option<String>,
+    /// The features of the environment, such as `docker`, that tests can require via the
+    /// `requires` key of their front matter. Tests requiring other features are skipped.
+    pub available_features: Vec<String>,
+    /// Environment variables whose names match any of these regexes have their values
+    /// hidden in the `env.txt` artifact.
+    pub environment_redactions: Vec<Regex>,
     /// Whether verbose information about resolved variables should be printed to stderr.
     pub dump_variable_resolution: bool,
+    /// Whether statistics about the compiled regex cache should be printed to stderr.
+    pub dump_regex_cache_statistics: bool,
+    /// Whether the time taken to discover test files should be printed to stderr.
+    pub dump_discovery_time: bool,
+    /// Whether the reason each test was selected or skipped by `changed_since` should be
+    /// printed to stderr.
+    pub dump_test_selection: bool,
     /// If set, debug output should be truncated to this many number of
     /// context lines.
     pub truncate_output_context_to_number_of_lines: Option<usize>,
+    /// Whether truncated context keeps both its first and last lines, rather than
+    /// only those closest to the failure.
+    pub truncate_output_context_in_middle: bool,
+    /// If set, lines of failure context that are longer than this many characters are
+    /// wrapped onto continuation lines, which are marked with `+` instead of a line number.
+    pub wrap_output_context_at: Option<usize>,
+    /// How terminal control sequences in program output, such as progress bars redrawn
+    /// with carriage returns, are shown in failure context.
+    pub control_sequences_in_context: ControlSequences,
+    /// Executables that tests expect to find on the `$PATH`, such as `python3`.
+    ///
+    /// These are only checked by `lit doctor`.
+    pub required_tools: Vec<String>,
     /// A list of extra directory paths that should be included in the `$PATH` when
     /// executing processes specified inside the tests.
     pub extra_executable_search_paths: Vec<PathBuf>,
+    /// The maximum number of bytes a `RUN` command may write to stdout or stderr.
+    ///
+    /// Commands that write more are killed, and fail the test. Setting a limit turns
+    /// off `early_exit_on_check_completion`. Unlimited by default.
+    pub max_output_bytes: Option<usize>,
+    /// An executable that runs the builtin helpers, such as `@not`, when invoked as
+    /// `<executable> helper <name>`. See `lit::helpers`.
+    pub helper_executable: Option<PathBuf>,
     /// Whether messages on the standard error streams emitted during test runs
     /// should always be shown.
     pub always_show_stderr: bool,
+    /// Whether `RUN` commands that exit successfully but write anything to stderr fail their test,
+    /// such as for tools with a zero-warnings policy.
+    ///
+    /// Tests can opt out individually with the `ALLOW-STDERR` directive.
+    pub fail_on_stderr: bool,
+    /// Whether lines of output that contain a directive are skipped by checks, so that tests
+    /// checking their own source, such as with `cat @file`, cannot match their own directives.
+    pub skip_directive_lines_in_output: bool,
+    /// Whether the patterns of all checks only match at the start of a line, as if every check
+    /// was written `CHECK{^}:`, so that they cannot match the middle of unrelated longer lines.
+    pub anchor_checks_to_line_start: bool,
+    /// Whether warnings and empty tests fail the suite.
+    pub strict: bool,
+    /// The number of times an identical warning is shown, after which it is only counted, and
+    /// summarized when the suite finishes. If `None`, every warning is shown.
+    pub max_repeated_warnings: Option<usize>,
+    /// What happens when the content of a test file changes while the suite is running,
+    /// such as when a tool updating test expectations runs at the same time.
+    pub modified_test_files: ModifiedTestFiles,
+    /// Whether `CHECK` and `CHECK-NEXT` directives should be evaluated while the program
+    /// is still running, killing the program as soon as all checks have passed, or a
+    /// check has certainly failed.
+    ///
+    /// The exit code of a program that is killed early is not checked.
+    /// Tests using other directives, such as `CHECK-SAME-AS`, and tests whose exit codes
+    /// matter, such as those with `XFAIL` or negated `RUN` commands, always run to completion.
+    pub early_exit_on_check_completion: bool,
+    /// Whether programs under test share the stdin of lit, and have their stdout and stderr
+    /// copied to those of lit as they are captured.
+    ///
+    /// Otherwise programs under test never see the streams of lit, so that tools which
+    /// detect terminals behave the same when lit is run interactively as they do in CI.
+    pub forward_output: bool,
+    /// Whether `RUN` commands are attached to a pseudo-terminal, rather than to pipes, so that
+    /// tools which behave differently in a terminal can be tested in their interactive mode.
+    ///
+    /// Tests can opt in individually with the `PTY` directive. Only supported on Unix.
+    pub use_pty: bool,
+    /// The user that the commands of tests are run as, such as an unprivileged user when lit
+    /// runs as root. Commands run as the user running lit if this is not set.
+    pub run_as: Option<RunAs>,
+    /// The maximum time a `WAIT-FOR` directive waits for background output to match.
+    pub background_wait_timeout: Duration,
+    /// How long programs that time out, and background programs at the end of their tests,
+    /// are given to exit after `SIGTERM` before they are killed with `SIGKILL`.
+    ///
+    /// This lets tools with cleanup handlers flush their logs. They are killed immediately
+    /// if this is zero, and on platforms other than Unix.
+    pub termination_grace_period: Duration,
+    /// If set, a keep-alive note is emitted whenever a test has been running for this
+    /// long without finishing, so that CI systems with inactivity timeouts don't
+    /// kill the job.
+    pub heartbeat_interval: Option<Duration>,
+    /// If set, no more tests are started once the suite has been running for this long.
+    ///
+    /// The tests that were never started are reported as `TestResultKind::NotRun`, and
+    /// `run::tests` returns `run::SuiteError::TimedOut`, for which the lit binary exits with
+    /// `run::SUITE_TIMEOUT_EXIT_CODE`, so that CI jobs end with a report rather than being killed.
+    pub suite_timeout: Option<Duration>,
+    /// Stops the suite from starting any more tests once it is cancelled from another thread.
+    pub cancellation_token: CancellationToken,
+    /// The backend that spawns the commands of tests.
+    pub executor: Arc<dyn Executor>,
+    /// A command run through the shell once before any tests are discovered, such as
+    /// `cargo build`. The suite fails without running any tests if it fails.
+    pub build_command: Option<String>,
     /// Which shell to use (defaults to 'bash').
     pub shell: String,
+    /// The shells to run every test with, in place of `shell`.
+    ///
+    /// When non-empty, each test is run once per shell, and the results are
+    /// reported per shell.
+    pub shell_matrix: Vec<String>,
+    /// Substitutions applied to program output before it is compared
+    /// against the output of a `CHECK-SAME-AS` reference command.
+    pub output_normalizers: Vec<OutputNormalizer>,
+    /// If set, every test is additionally run under this configuration, and any
+    /// tests whose outcome or normalized output differs are reported.
+    pub comparison_config: Option<Box<Config>>,
+    /// Plugins registered via `Config::register_plugin`.
+    pub plugins: Vec<Arc<dyn Plugin>>,
+    /// Engines that checks can select by name, as in `CHECK[name]:`, in addition to the
+    /// builtin engines. See `Config::register_matcher`.
+    pub matchers: Vec<Arc<dyn Matcher>>,
+    /// The engine that matches the patterns of checks which do not name one, such as `exact`,
+    /// or `None` to match them as regexes. See `lit::matcher`.
+    pub default_matcher: Option<String>,
+    /// The templates of user-facing messages, which may be overridden to reword or
+    /// localize them.
+    pub messages: crate::messages::Catalog,
+    /// Functions applied, in order, to every command line after its variables have
+    /// been resolved. See `Config::add_command_line_rewriter`.
+    pub command_line_rewriters: Vec<CommandLineRewriter>,
+    /// Renames tests in reports by the prefix of their relative path, such as to drop a
+    /// directory or add a suite label. The rule with the longest matching prefix applies.
+    pub test_name_prefixes: Vec<TestNamePrefix>,
+    /// A function giving the name a test is reported under from its relative path,
+    /// taking precedence over `test_name_prefixes`. See `Config::set_test_namer`.
+    pub test_namer: Option<TestNamer>,
+    /// Functions applied, in order, to the result of every test before it is reported.
+    /// See `Config::add_result_transformer`.
+    pub result_transformers: Vec<ResultTransformer>,
+}
+
+/// A regex substitution used to normalize volatile parts of program output.
+#[derive(Clone, Debug)]
+pub struct OutputNormalizer {
+    /// The pattern to search for.
+    pub pattern: Regex,
+    /// The replacement text, which may refer to capture groups like `$1`.
+    pub replacement: String,
+}
+
+/// The comment syntax of a language, used to only find directives inside comments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommentSyntax {
+    /// Leaders of comments that run until the end of the line, such as `//`.
+    pub line_comments: Vec<String>,
+    /// The opening and closing delimiters of block comments, such as `(*` and `*)`.
+    pub block_comments: Vec<(String, String)>,
+    /// Characters that delimit string literals, which are never treated as comments.
+    pub string_delimiters: Vec<char>,
+}
+
+/// Options that control how text pattern regexes are constructed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RegexOptions {
+    /// The maximum compiled size of a regex, in bytes.
+    pub size_limit: usize,
+    /// Whether Unicode support is enabled, e.g. for `\w` and `.`.
+    pub unicode: bool,
+    /// Whether `.` also matches new lines.
+    pub dot_matches_new_line: bool,
+    /// Whether patterns match case-insensitively.
+    pub case_insensitive: bool,
+}
+
+/// A set of constants scoped to all tests within a directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirectoryConstants {
+    /// The directory the constants apply to.
+    pub directory: PathBuf,
+    /// The constants available to tests within the directory.
+    pub constants: HashMap<String, String>,
+}
+
+/// A rule renaming the tests underneath a path prefix in reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestNamePrefix {
+    /// The prefix of the relative paths of the tests, such as `integration/network`.
+    pub prefix: PathBuf,
+    /// The text the prefix is replaced with, such as `network :: `.
+    pub replacement: String,
+}
+
+/// A format for reporting test events.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Human-readable, colored text.
+    Human,
+    /// One JSON object per event and line on stdout.
+    JsonLines,
+}
+
+/// A format for the `events.log` test artifact.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AuditLogFormat {
+    /// One human-readable line per event.
+    Text,
+    /// One JSON object per event and line.
+    JsonLines,
+}
+
+/// A format for reporting test failures.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Multi-line blocks with the failure context and program output.
+    Long,
+    /// A single `<path>:<line>: <KIND>: <message>` line per failing directive,
+    /// suitable for editor quickfix lists.
+    Short,
+}
+
+/// What happens when test files are modified while the suite is running.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModifiedTestFiles {
+    /// Modifications are not checked for.
+    Ignore,
+    /// A warning is raised for every modified test file.
+    Warn,
+    /// A warning is raised for every modified test file, and the suite fails.
+    Fail,
+}
+
+/// How terminal control sequences in program output are shown.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControlSequences {
+    /// Control sequences are shown as-is, and are interpreted by the terminal showing them.
+    Raw,
+    /// Control characters are shown as visible escapes, such as `\r` and `\x1b`.
+    Escape,
+    /// The output is shown as a terminal would display it once it has all been written.
+    Render,
+}
+
+/// One of several equally sized partitions of the test suite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shard {
+    /// The zero-based index of the shard.
+    pub index: usize,
+    /// The total number of shards.
+    pub count: usize,
+}
+
+/// A set of resources held by all tests within a directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirectoryResources {
+    /// The directory the resources apply to.
+    pub directory: PathBuf,
+    /// The names of the resources held by tests within the directory.
+    pub resources: Vec<String>,
 }
 
 /// A function which can dynamically define newly used variables in a test.
 #[derive(Clone)]
 pub struct VariableLookup(fn(&str) -> Option<String>);
 
+/// A function that transforms a command line before it is executed.
+#[derive(Clone)]
+pub struct CommandLineRewriter(pub Arc<dyn Fn(&str) -> String + Send + Sync>);
+
+/// A function that gives the name a test is reported under.
+#[derive(Clone)]
+pub struct TestNamer(pub Arc<dyn Fn(&Path) -> String + Send + Sync>);
+
+/// A function that transforms the result of a test before it is reported.
+#[derive(Clone)]
+pub struct ResultTransformer(pub Arc<dyn Fn(TestResult) -> TestResult + Send + Sync>);
+
+/// A function that is given the artifacts of a suite once they have been saved.
+#[derive(Clone)]
+pub struct AfterArtifacts(pub Arc<AfterArtifactsFn>);
+
+type AfterArtifactsFn = dyn Fn(&Path, &Manifest) + Send + Sync;
+
 impl Config
 {
     /// The default variable lookup function.
@@ -85,6 +476,57 @@ impl Config
         self.supported_file_extensions.extend(extensions.iter().map(|s| s.to_string()));
     }
 
+    /// Marks files whose whole name matches a pattern as tests, in addition to the files with a
+    /// supported extension. This allows files without an extension, or whose names have several
+    /// dots, to be selected precisely.
+    ///
+    /// ```
+    /// let mut config = lit::Config::default();
+    /// config.add_file_name_pattern("Makefile.lit");
+    /// config.add_file_name_pattern("*.test.sh");
+    /// ```
+    pub fn add_file_name_pattern(&mut self, pattern: impl Into<String>) {
+        self.supported_file_name_patterns.push(pattern.into())
+    }
+
+    /// Only finds directives inside the comments of files with the given extension.
+    ///
+    /// ```
+    /// use lit::config::CommentSyntax;
+    ///
+    /// let mut config = lit::Config::default();
+    /// config.set_comment_syntax("rs", CommentSyntax::line("//"));
+    /// config.set_comment_syntax("ml", CommentSyntax::block("(*", "*)"));
+    /// ```
+    pub fn set_comment_syntax(&mut self, extension: impl Into<String>, syntax: CommentSyntax) {
+        self.comment_syntaxes.insert(extension.into(), syntax);
+    }
+
+    /// Gets the comment syntax of a test file, if directives are restricted to comments.
+    pub fn comment_syntax_of(&self, path: &Path) -> Option<&CommentSyntax> {
+        let extension = path.extension()?.to_str()?;
+        self.comment_syntaxes.get(extension)
+    }
+
+    /// Separates directives from their arguments with the given character in files with the given extension.
+    ///
+    /// ```
+    /// let mut config = lit::Config::default();
+    /// config.set_directive_separator("legacy", '>').unwrap();
+    /// assert!(config.set_directive_separator("legacy", 'X').is_err());
+    /// ```
+    pub fn set_directive_separator(&mut self, extension: impl Into<String>, separator: char) -> Result<(), String> {
+        validate_directive_separator(separator)?;
+        self.directive_separators.insert(extension.into(), separator);
+        Ok(())
+    }
+
+    /// Gets the directive separator used by a test file.
+    pub fn directive_separator_of(&self, path: &Path) -> char {
+        let extension = path.extension().and_then(|e| e.to_str());
+        extension.and_then(|e| self.directive_separators.get(e)).cloned().unwrap_or(self.directive_separator)
+    }
+
     /// Adds a search path to the test runner.
     ///
     /// We will recurse through the path to find tests.
@@ -92,11 +534,236 @@ impl Config
         self.test_paths.push(PathBuf::from(path.into()).canonicalize().unwrap());
     }
 
+    /// Adds an extra source of tests, such as an in-memory suite.
+    pub fn add_test_source(&mut self, test_source: impl TestSource + 'static) {
+        self.test_sources.push(Arc::new(test_source));
+    }
+
+    /// Adds a function that transforms every command line before it is executed,
+    /// such as to wrap commands with `valgrind` or add extra flags.
+    ///
+    /// ```
+    /// let mut config = lit::Config::default();
+    /// config.add_command_line_rewriter(|command_line| format!("time {}", command_line));
+    /// ```
+    pub fn add_command_line_rewriter(&mut self, rewrite: impl Fn(&str) -> String + Send + Sync + 'static) {
+        self.command_line_rewriters.push(CommandLineRewriter(Arc::new(rewrite)));
+    }
+
+    /// Adds a function that transforms the result of every test, including tests that were not
+    /// run, before any event handler sees it and before its artifacts are saved, such as to
+    /// attach hints to known failures or to strip sensitive output.
+    ///
+    /// ```
+    /// use lit::model::TestResultKind;
+    ///
+    /// let mut config = lit::Config::default();
+    /// config.add_result_transformer(|mut result| {
+    ///     if let TestResultKind::Fail { ref mut hint, .. } = result.overall_result {
+    ///         hint.get_or_insert_with(|| "see https://example.com/known-failures".to_owned());
+    ///     }
+    ///     result
+    /// });
+    /// ```
+    pub fn add_result_transformer(&mut self, transform: impl Fn(TestResult) -> TestResult + Send + Sync + 'static) {
+        self.result_transformers.push(ResultTransformer(Arc::new(transform)));
+    }
+
+    /// Sets a function that is given the artifacts directory and the list of artifacts once
+    /// a suite has saved them, such as to upload them to object storage from CI.
+    ///
+    /// It is only called if artifacts were written.
+    ///
+    /// ```
+    /// let mut config = lit::Config::default();
+    /// config.set_after_artifacts(|artifacts_directory, manifest| {
+    ///     for file in manifest.files.iter() {
+    ///         println!("uploading {}", artifacts_directory.join(file).display());
+    ///     }
+    /// });
+    /// ```
+    pub fn set_after_artifacts(&mut self, after_artifacts: impl Fn(&Path, &Manifest) + Send + Sync + 'static) {
+        self.after_artifacts = Some(AfterArtifacts(Arc::new(after_artifacts)));
+    }
+
+    /// Applies all result transformers to the result of a test.
+    pub fn transform_result(&self, result: TestResult) -> TestResult {
+        self.result_transformers.iter().fold(result, |result, transformer| (transformer.0)(result))
+    }
+
+    /// Reports the tests underneath a prefix of their relative paths under different names,
+    /// replacing the prefix.
+    ///
+    /// ```
+    /// let mut config = lit::Config::default();
+    /// config.add_test_name_prefix("integration/network", "network :: ");
+    /// ```
+    pub fn add_test_name_prefix(&mut self, prefix: impl AsRef<Path>, replacement: impl Into<String>) {
+        self.test_name_prefixes.push(TestNamePrefix { prefix: prefix.as_ref().to_owned(), replacement: replacement.into() });
+    }
+
+    /// Sets a function that gives the name each test is reported under from its relative path.
+    ///
+    /// ```
+    /// let mut config = lit::Config::default();
+    /// config.set_test_namer(|path| format!("compiler :: {}", path.with_extension("").display()));
+    /// ```
+    pub fn set_test_namer(&mut self, name: impl Fn(&Path) -> String + Send + Sync + 'static) {
+        self.test_namer = Some(TestNamer(Arc::new(name)));
+    }
+
+    /// Gets the name a test is reported under.
+    ///
+    /// This is the relative path of the test unless a test namer or name prefixes are configured.
+    pub fn test_name(&self, path: &TestFilePath) -> String {
+        if let Some(ref test_namer) = self.test_namer {
+            return (test_namer.0)(&path.relative);
+        }
+
+        let longest_prefix = self.test_name_prefixes.iter()
+            .filter(|rule| path.relative.starts_with(&rule.prefix))
+            .max_by_key(|rule| rule.prefix.components().count());
+
+        match longest_prefix {
+            Some(rule) => {
+                let rest = path.relative.strip_prefix(&rule.prefix).expect("the prefix was just matched");
+                format!("{}{}", rule.replacement, rest.display())
+            },
+            None => path.relative.display().to_string(),
+        }
+    }
+
+    /// Reads the text of a test from whichever source provided it.
+    pub fn read_test(&self, path: &TestFilePath) -> Result<String, String> {
+        for test_source in self.test_sources.iter() {
+            if let Some(text) = test_source.read(path) {
+                return text;
+            }
+        }
+
+        test_source::Filesystem.read(path).unwrap()
+    }
+
+    /// Loads the rules of a `CODEOWNERS`-style file mapping tests to their owners.
+    pub fn add_owners_file(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read owners file '{}': {}", path.display(), e))?;
+
+        let rules = owners::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.ownership_rules.extend(rules);
+        Ok(())
+    }
+
+    /// Gets the owners of a test.
+    ///
+    /// `OWNER` directives take precedence over the configured ownership rules.
+    pub fn owners_of(&self, test_file: &crate::model::TestFile) -> Vec<String> {
+        let declared_owners: Vec<String> = test_file.owners().map(ToOwned::to_owned).collect();
+
+        if !declared_owners.is_empty() {
+            declared_owners
+        } else {
+            owners::owners_of(&self.ownership_rules, &test_file.path.relative).to_vec()
+        }
+    }
+
     /// Adds an extra executable directory to the OS `$PATH` when executing tests.
     pub fn add_executable_search_path<P>(&mut self, path: P) where P: AsRef<Path> {
         self.extra_executable_search_paths.push(path.as_ref().to_owned())
     }
 
+    /// Defines a constant that is only visible to tests inside the given directory.
+    pub fn add_directory_constant<P>(&mut self, directory: P, name: impl Into<String>, value: impl Into<String>)
+        where P: AsRef<Path> {
+        let directory = directory.as_ref().canonicalize().unwrap_or_else(|_| directory.as_ref().to_owned());
+
+        let index = match self.directory_constants.iter().position(|d| d.directory == directory) {
+            Some(index) => index,
+            None => {
+                self.directory_constants.push(DirectoryConstants { directory, constants: HashMap::new() });
+                self.directory_constants.len() - 1
+            },
+        };
+
+        self.directory_constants[index].constants.insert(name.into(), value.into());
+    }
+
+    /// Gets all constants visible to the test at the given path.
+    ///
+    /// Global constants are overridden by directory constants, which are in
+    /// turn resolved by longest-prefix match against the test path.
+    pub fn constants_for_test(&self, test_path: &Path) -> HashMap<String, String> {
+        let mut matching_directories: Vec<_> = self.directory_constants.iter()
+            .filter(|d| test_path.starts_with(&d.directory))
+            .collect();
+        matching_directories.sort_by_key(|d| d.directory.components().count());
+
+        let mut constants = self.constants.clone();
+        for directory in matching_directories {
+            constants.extend(directory.constants.clone());
+        }
+        constants
+    }
+
+    /// Marks all tests inside the given directory as holding a resource while they run.
+    pub fn add_directory_resource<P>(&mut self, directory: P, resource: impl Into<String>)
+        where P: AsRef<Path> {
+        let directory = directory.as_ref().canonicalize().unwrap_or_else(|_| directory.as_ref().to_owned());
+
+        let index = match self.directory_resources.iter().position(|d| d.directory == directory) {
+            Some(index) => index,
+            None => {
+                self.directory_resources.push(DirectoryResources { directory, resources: Vec::new() });
+                self.directory_resources.len() - 1
+            },
+        };
+
+        self.directory_resources[index].resources.push(resource.into());
+    }
+
+    /// Gets the resources implicitly held by the test at the given path.
+    pub fn resources_for_test(&self, test_path: &Path) -> Vec<String> {
+        self.directory_resources.iter()
+            .filter(|d| test_path.starts_with(&d.directory))
+            .flat_map(|d| d.resources.iter().cloned())
+            .collect()
+    }
+
+    /// Gets the maximum number of tests that may concurrently hold a resource.
+    pub fn resource_limit(&self, resource: &str) -> usize {
+        self.resource_limits.get(resource).cloned().unwrap_or(1).max(1)
+    }
+
+    /// Adds a regex substitution applied to outputs before they are compared.
+    pub fn add_output_normalizer(&mut self, pattern: &str, replacement: impl Into<String>) {
+        let pattern = Regex::new(pattern).expect("invalid output normalizer regex");
+        self.output_normalizers.push(OutputNormalizer { pattern, replacement: replacement.into() });
+    }
+
+    /// Adds the output normalizers of a builtin preset, such as `timestamps`.
+    ///
+    /// See `config::presets` for the available presets.
+    pub fn add_normalizer_preset(&mut self, name: &str) -> Result<(), String> {
+        presets::validate(name)?;
+        self.output_normalizers.extend(presets::normalizers(name).unwrap());
+        Ok(())
+    }
+
+    /// Applies all output normalizers to a piece of program output.
+    pub fn normalize_output(&self, output: &str) -> String {
+        normalize(output.to_owned(), &self.output_normalizers)
+    }
+
+    /// Applies all output normalizers to a piece of program output, followed by
+    /// the presets enabled by the `NORMALIZE-PRESET` directives of a test.
+    pub fn normalize_test_output(&self, test_file: &crate::model::TestFile, output: &str) -> String {
+        test_file.normalizer_presets().fold(self.normalize_output(output), |output, name| {
+            // Preset names were validated when the directive was parsed.
+            normalize(output, &presets::normalizers(name).unwrap_or_default())
+        })
+    }
+
     /// Gets an iterator over all test search directories.
     pub fn test_search_directories(&self) -> impl Iterator<Item=&Path> {
         self.test_paths.iter().filter(|p| {
@@ -111,11 +778,58 @@ impl Config
             find(|ext| &ext[..] == extension).is_some()
     }
 
+    /// Checks if a file with the given name, without its directory, contains tests, by either
+    /// its extension or its whole name.
+    pub fn is_test_file_name(&self, file_name: &str) -> bool {
+        let has_supported_extension = Path::new(file_name).extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| self.is_extension_supported(extension))
+            .unwrap_or(false);
+
+        has_supported_extension || self.supported_file_name_patterns.iter().any(|pattern| crate::util::glob_matches(pattern, file_name))
+    }
+
+    /// Expands a regex that refers to a named pattern from the pattern library.
+    ///
+    /// Regexes of the form `:<name>:` are replaced with the named pattern. All
+    /// other regexes are returned unchanged.
+    pub fn expand_pattern_macro<'a>(&'a self, regex: &'a str) -> std::borrow::Cow<'a, str> {
+        let name = if regex.len() > 2 && regex.starts_with(':') && regex.ends_with(':') {
+            &regex[1..regex.len() - 1]
+        } else {
+            return regex.into();
+        };
+
+        match self.patterns.get(name) {
+            Some(pattern) => format!("(?:{})", pattern).into(),
+            None => regex.into(),
+        }
+    }
+
     /// Looks up a variable.
     pub fn lookup_variable<'a>(&self,
                            name: &str,
                            variables: &'a mut HashMap<String, String>)
         -> &'a str {
+        self.try_lookup_variable(name, variables).expect(&format!("no variable with the name '{}' exists", name))
+    }
+
+    /// Looks up a variable, returning `None` if it is not defined.
+    pub fn try_lookup_variable<'a>(&self,
+                           name: &str,
+                           variables: &'a mut HashMap<String, String>)
+        -> Option<&'a str> {
+        if let (false, Some(environment_variable)) = (variables.contains_key(name), name.strip_prefix("env:")) {
+            if let Ok(value) = std::env::var(environment_variable) {
+                variables.insert(name.to_owned(), value);
+            }
+        }
+
+        if let (false, Some(temp_root), true) = (variables.contains_key(name), self.temp_root.as_ref(), name.contains("tempfile")) {
+            let temp_file = NamedTempFile::new_in(temp_root).expect("failed to create a temporary file");
+            variables.insert(name.to_owned(), temp_file.into_temp_path().to_str().expect("temp file path is not utf-8").to_owned());
+        }
+
         if !variables.contains_key(name) {
             match self.variable_lookup.0(name) {
                 Some(initial_value) => {
@@ -125,7 +839,13 @@ impl Config
             }
         }
 
-        variables.get(name).expect(&format!("no variable with the name '{}' exists", name))
+        if !variables.contains_key(name) {
+            if let Some(value) = self.plugins.iter().find_map(|p| p.lookup_variable(name)) {
+                variables.insert(name.to_owned(), value);
+            }
+        }
+
+        variables.get(name).map(|value| &value[..])
     }
 }
 
@@ -144,16 +864,124 @@ impl Default for Config
 
         Config {
             supported_file_extensions: Vec::new(),
+            supported_file_name_patterns: Vec::new(),
+            fixture_file_name: None,
+            comment_syntaxes: HashMap::new(),
+            directive_separator: DEFAULT_DIRECTIVE_SEPARATOR,
+            directive_separators: HashMap::new(),
             test_paths: Vec::new(),
+            test_sources: Vec::new(),
             constants: HashMap::new(),
+            regex_options: RegexOptions::default(),
+            patterns: HashMap::new(),
+            directory_constants: Vec::new(),
+            seed: None,
+            jobs: 1,
+            max_concurrent_instances_per_file: None,
+            resource_limits: HashMap::new(),
+            directory_resources: Vec::new(),
+            shard: None,
+            changed_since: None,
+            ownership_rules: Vec::new(),
+            message_format: MessageFormat::Human,
+            error_format: ErrorFormat::Long,
+            audit_log_format: None,
+            timing_history_file: None,
+            fail_first: false,
+            required_environment: None,
             variable_lookup: Config::DEFAULT_VARIABLE_LOOKUP,
             cleanup_temporary_files: true,
+            temp_root: None,
             save_artifacts_to_directory: None,
+            bundle_failures_to: None,
+            after_artifacts: None,
+            after_artifacts_command: None,
+            available_features: Vec::new(),
+            environment_redactions: vec![Regex::new(DEFAULT_ENVIRONMENT_REDACTION).unwrap()],
             dump_variable_resolution: false,
+            dump_regex_cache_statistics: false,
+            dump_discovery_time: false,
+            dump_test_selection: false,
             always_show_stderr: false,
+            fail_on_stderr: false,
+            skip_directive_lines_in_output: false,
+            anchor_checks_to_line_start: false,
+            strict: false,
+            max_repeated_warnings: Some(DEFAULT_MAX_REPEATED_WARNINGS),
+            modified_test_files: ModifiedTestFiles::Warn,
             truncate_output_context_to_number_of_lines: Some(DEFAULT_MAX_OUTPUT_CONTEXT_LINE_COUNT),
+            truncate_output_context_in_middle: false,
+            wrap_output_context_at: None,
+            control_sequences_in_context: ControlSequences::Raw,
+            required_tools: Vec::new(),
             extra_executable_search_paths,
+            max_output_bytes: None,
+            helper_executable: None,
+            early_exit_on_check_completion: false,
+            forward_output: false,
+            use_pty: false,
+            run_as: None,
+            background_wait_timeout: DEFAULT_BACKGROUND_WAIT_TIMEOUT,
+            termination_grace_period: DEFAULT_TERMINATION_GRACE_PERIOD,
+            heartbeat_interval: None,
+            suite_timeout: None,
+            cancellation_token: CancellationToken::new(),
+            executor: Arc::new(executor::Shell),
+            build_command: None,
             shell: "bash".to_string(),
+            shell_matrix: Vec::new(),
+            output_normalizers: Vec::new(),
+            comparison_config: None,
+            plugins: Vec::new(),
+            matchers: Vec::new(),
+            default_matcher: None,
+            command_line_rewriters: Vec::new(),
+            test_name_prefixes: Vec::new(),
+            test_namer: None,
+            result_transformers: Vec::new(),
+            messages: Default::default(),
+        }
+    }
+}
+
+impl CommentSyntax {
+    /// Creates the syntax of a language with line comments, such as `//`, and `"` strings.
+    pub fn line(leader: impl Into<String>) -> Self {
+        CommentSyntax::default().with_line_comment(leader).with_string_delimiter('"')
+    }
+
+    /// Creates the syntax of a language with block comments, such as `(*` and `*)`, and `"` strings.
+    pub fn block(open: impl Into<String>, close: impl Into<String>) -> Self {
+        CommentSyntax::default().with_block_comment(open, close).with_string_delimiter('"')
+    }
+
+    /// Adds a line comment leader.
+    pub fn with_line_comment(mut self, leader: impl Into<String>) -> Self {
+        self.line_comments.push(leader.into());
+        self
+    }
+
+    /// Adds a pair of block comment delimiters.
+    pub fn with_block_comment(mut self, open: impl Into<String>, close: impl Into<String>) -> Self {
+        self.block_comments.push((open.into(), close.into()));
+        self
+    }
+
+    /// Adds a character that delimits string literals.
+    pub fn with_string_delimiter(mut self, delimiter: char) -> Self {
+        self.string_delimiters.push(delimiter);
+        self
+    }
+}
+
+impl Default for RegexOptions
+{
+    fn default() -> Self {
+        RegexOptions {
+            size_limit: DEFAULT_REGEX_SIZE_LIMIT,
+            unicode: true,
+            dot_matches_new_line: false,
+            case_insensitive: false,
         }
     }
 }
@@ -164,10 +992,98 @@ impl fmt::Debug for VariableLookup {
     }
 }
 
+impl fmt::Debug for CommandLineRewriter {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        "<function>".fmt(fmt)
+    }
+}
+
+impl fmt::Debug for TestNamer {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        "<function>".fmt(fmt)
+    }
+}
+
+impl fmt::Debug for ResultTransformer {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        "<function>".fmt(fmt)
+    }
+}
+
+impl fmt::Debug for AfterArtifacts {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        "<function>".fmt(fmt)
+    }
+}
+
+fn normalize(output: String, normalizers: &[OutputNormalizer]) -> String {
+    normalizers.iter().fold(output, |output, normalizer| {
+        normalizer.pattern.replace_all(&output, &normalizer.replacement[..]).into_owned()
+    })
+}
+
+/// Checks that a character cannot be mistaken for part of a directive name, or its argument.
+pub(crate) fn validate_directive_separator(separator: char) -> Result<(), String> {
+    if separator.is_alphanumeric() || separator.is_whitespace() || separator == '-' {
+        Err(format!("invalid directive separator '{}', expected a punctuation character", separator))
+    } else {
+        Ok(())
+    }
+}
+
+/// Parses a directive separator given as text, such as in a config file.
+pub(crate) fn parse_directive_separator(text: &str) -> Result<char, String> {
+    let mut chars = text.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(separator), None) => validate_directive_separator(separator).map(|()| separator),
+        _ => Err(format!("invalid directive separator '{}', expected a single character", text)),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn tempfiles_are_created_under_the_temp_root() {
+        let temp_root = tempfile::tempdir().unwrap();
+        let config = Config { temp_root: Some(temp_root.path().to_owned()), ..Config::default() };
+
+        let tempfile = config.lookup_variable("tempfile", &mut HashMap::new()).to_owned();
+        assert!(std::path::Path::new(&tempfile).starts_with(temp_root.path()), "'{}' is outside of the temp root", tempfile);
+    }
+
+    #[test]
+    fn tests_are_renamed_by_their_longest_matching_prefix() {
+        let mut config = Config::default();
+        config.add_test_name_prefix("integration", "");
+        config.add_test_name_prefix("integration/network", "network :: ");
+        let name = |config: &Config, relative: &str| config.test_name(&TestFilePath::new(Path::new("/suite").join(relative), relative));
+
+        assert_eq!(name(&config, "integration/network/dns.txt"), "network :: dns.txt");
+        assert_eq!(name(&config, "integration/disk.txt"), "disk.txt");
+        assert_eq!(name(&config, "integration-old/disk.txt"), "integration-old/disk.txt");
+
+        config.set_test_namer(|path| path.file_stem().unwrap().to_string_lossy().to_uppercase());
+        assert_eq!(name(&config, "integration/network/dns.txt"), "DNS");
+    }
+
+    #[test]
+    fn test_files_are_recognized_by_extension_or_whole_name() {
+        let mut config = Config::default();
+        config.add_extension("txt");
+        config.add_file_name_pattern("Makefile.lit");
+        config.add_file_name_pattern("*.test.sh");
+
+        assert!(config.is_test_file_name("basic.txt"));
+        assert!(config.is_test_file_name("basic.lit.txt"));
+        assert!(config.is_test_file_name("Makefile.lit"));
+        assert!(config.is_test_file_name("parse.test.sh"));
+        assert!(!config.is_test_file_name("build.sh"));
+        assert!(!config.is_test_file_name("Makefile"));
+    }
+
     #[test]
     fn lookup_variable_works_correctly() {
         let config = Config {
@@ -196,5 +1112,82 @@ mod test {
                    config.lookup_variable("second_tempfile", &mut variables),
                    "second temp has changed its value");
     }
-}
 
+    #[test]
+    fn constants_for_test_prefers_most_specific_directory() {
+        let mut config = Config {
+            constants: vec![("cflags".to_owned(), "-O0".to_owned()), ("cc".to_owned(), "gcc".to_owned())].into_iter().collect(),
+            ..Config::default()
+        };
+        config.add_directory_constant("/nonexistent/tests/", "cflags", "-O1");
+        config.add_directory_constant("/nonexistent/tests/optimized", "cflags", "-O3");
+
+        let constants = config.constants_for_test(Path::new("/nonexistent/tests/optimized/foo.c"));
+        assert_eq!(constants["cflags"], "-O3");
+        assert_eq!(constants["cc"], "gcc", "global constants should still be visible");
+
+        let constants = config.constants_for_test(Path::new("/nonexistent/tests/bar.c"));
+        assert_eq!(constants["cflags"], "-O1");
+
+        let constants = config.constants_for_test(Path::new("/nonexistent/other/bar.c"));
+        assert_eq!(constants["cflags"], "-O0");
+    }
+
+    #[test]
+    fn result_transformers_apply_in_order_before_artifacts_are_saved() {
+        use crate::{model::*, run::execute_test_file};
+
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let mut config = Config { shell: "sh".to_owned(), save_artifacts_to_directory: Some(artifacts_dir.path().to_owned()), ..Config::default() };
+        config.add_result_transformer(|mut result| {
+            if let TestResultKind::Fail { ref mut hint, .. } = result.overall_result {
+                *hint = Some("see the docs".to_owned());
+            }
+            result
+        });
+        config.add_result_transformer(|mut result| {
+            if let TestResultKind::Fail { hint: Some(ref mut hint), .. } = result.overall_result {
+                hint.push_str(" for known failures");
+            }
+            result.individual_run_results.iter_mut().for_each(|run| run.output.stdout = "<redacted>".into());
+            result
+        });
+
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/transformed.txt", "transformed.txt"))
+            .with_command(CommandKind::Run(Invocation::new("echo secret; false")));
+
+        let result = execute_test_file(&test_file, &config);
+        assert!(matches!(result.overall_result, TestResultKind::Fail { hint: Some(ref hint), .. } if hint == "see the docs for known failures"));
+        assert_eq!(&*result.individual_run_results[0].output.stdout, "<redacted>");
+        if cfg!(feature = "artifacts") {
+            assert_eq!(std::fs::read_to_string(artifacts_dir.path().join("transformed.txt/stdout.txt")).unwrap(), "<redacted>");
+        }
+    }
+
+    #[test]
+    fn result_transformers_apply_to_tests_that_were_not_run() {
+        use crate::{event_handler::{EventHandler, SuiteProgress}, model::TestResult, test_source};
+        use std::sync::Mutex;
+
+        struct Recorder(Arc<Mutex<Vec<Vec<String>>>>);
+
+        impl EventHandler for Recorder {
+            fn on_test_finished(&mut self, result: TestResult, _: &SuiteProgress, _: &Config) {
+                self.0.lock().unwrap().push(result.owners);
+            }
+        }
+
+        let owners = Arc::new(Mutex::new(Vec::new()));
+        let _ = crate::run::tests(Recorder(owners.clone()), |config| {
+            config.shell = "sh".to_owned();
+            config.suite_timeout = Some(std::time::Duration::from_secs(0));
+            config.add_test_source(test_source::InMemory::new().with_file("passes.txt", "RUN: true\n"));
+            config.add_result_transformer(|mut result| {
+                result.owners.push("triage".to_owned());
+                result
+            });
+        });
+
+        assert_eq!(*owners.lock().unwrap(), vec![vec!["triage".to_owned()]]);
+    }
+}
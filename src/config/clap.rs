@@ -2,15 +2,24 @@
 //!
 //! These routines can be used to update `Config` objects with automatic CLI arguments.
 
-use crate::Config;
+use crate::{Config, config::{AuditLogFormat, ControlSequences, ErrorFormat, MessageFormat, ModifiedTestFiles}, vars};
 use clap::{App, Arg, ArgMatches, SubCommand};
-use std::{io::Write, path::Path};
+use std::{io::Write, path::{Path, PathBuf}};
 
 /// The set of available debug parameters.
 const DEBUG_OPTION_VALUES: &'static [(&'static str, fn(&mut Config))] = &[
     ("variable-resolution", |config: &mut Config| {
         config.dump_variable_resolution = true;
     }),
+    ("regex-cache", |config: &mut Config| {
+        config.dump_regex_cache_statistics = true;
+    }),
+    ("discovery-time", |config: &mut Config| {
+        config.dump_discovery_time = true;
+    }),
+    ("test-selection", |config: &mut Config| {
+        config.dump_test_selection = true;
+    }),
 ];
 
 const SHOW_OPTION_VALUES: &'static [(&'static str, fn(&Config, &mut dyn Write) -> std::io::Result<()>)] = &[
@@ -23,6 +32,29 @@ const SHOW_OPTION_VALUES: &'static [(&'static str, fn(&Config, &mut dyn Write) -
         Ok(())
 
     }),
+    ("shard-plan", |config, writer| {
+        let test_file_paths = match crate::run::find_files::with_config(config) {
+            Ok(test_file_paths) => test_file_paths,
+            Err(e) => fatal_error(format!("could not find test files: {}", e)),
+        };
+        let shards = match crate::run::sharding::plan_with_config(test_file_paths, config) {
+            Ok(shards) => shards,
+            Err(e) => fatal_error(e),
+        };
+
+        for (i, shard) in shards.iter().enumerate() {
+            match shard.expected_duration {
+                Some(duration) => writeln!(writer, "shard {}/{} ({} tests, expected {:.1?}):", i + 1, shards.len(), shard.test_paths.len(), duration)?,
+                None => writeln!(writer, "shard {}/{} ({} tests):", i + 1, shards.len(), shard.test_paths.len())?,
+            }
+
+            for test_path in shard.test_paths.iter() {
+                writeln!(writer, "    {}", test_path.relative.display())?;
+            }
+        }
+
+        Ok(())
+    }),
     ("lit-config", |config, writer| {
         writeln!(writer, "{:#?}", config)
     }),
@@ -54,12 +86,28 @@ pub fn mount_inside_app<'a, 'b>(
     test_paths_as_positional_arguments: bool,
 ) -> App<'a, 'b> {
     let app = app
+        .arg(Arg::with_name("config")
+            .long("config")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Loads settings from a config file, such as the lit.toml created by 'lit init', before applying the other options"))
         .arg(Arg::with_name("supported-file-extension")
             .long("add-file-extension")
             .takes_value(true)
             .value_name("EXT")
             .multiple(true)
             .help("Adds a file extension to the test search list. Extensions can be specified either with or without a leading period"))
+        .arg(Arg::with_name("file-name-pattern")
+            .long("add-file-name-pattern")
+            .takes_value(true)
+            .value_name("PATTERN")
+            .multiple(true)
+            .help("Adds a pattern matching the whole names of test files, such as 'Makefile.lit' or '*.test.sh', for files without an extension or with several dots in their names. '*' matches anything"))
+        .arg(Arg::with_name("fixture-file-name")
+            .long("fixture-file-name")
+            .takes_value(true)
+            .value_name("NAME")
+            .help("Treats files with this name, such as 'fixture.txt', as fixtures rather than tests. The RUN commands of a fixture run once, before the tests in its directory and beneath it, and can write outputs shared with those tests to '@fixture_out'"))
         .arg(Arg::with_name("constant")
             .long("define-constant")
             .short("c")
@@ -67,24 +115,231 @@ pub fn mount_inside_app<'a, 'b>(
             .value_name("NAME>=<VALUE") // this shows as '<NAME>=<VALUE>'
             .multiple(true)
             .help("Sets a constant, accessible in the test via '@<NAME>"))
+        .arg(Arg::with_name("directory-constant")
+            .long("define-constant-for")
+            .takes_value(true)
+            .number_of_values(2)
+            .value_names(&["DIR", "NAME>=<VALUE"])
+            .multiple(true)
+            .help("Sets a constant that is only visible to tests inside <DIR>. The most specific directory wins when several match a test"))
+        .arg(Arg::with_name("jobs")
+            .long("jobs")
+            .short("j")
+            .takes_value(true)
+            .value_name("N")
+            .help("Sets the maximum number of test files executed concurrently. Defaults to 1"))
+        .arg(Arg::with_name("resource-limit")
+            .long("resource-limit")
+            .takes_value(true)
+            .value_name("RESOURCE>=<N") // this shows as '<RESOURCE>=<N>'
+            .multiple(true)
+            .help("Allows up to <N> tests holding the given resource to run concurrently. Resources are exclusive by default"))
+        .arg(Arg::with_name("shard")
+            .long("shard")
+            .takes_value(true)
+            .value_name("INDEX>/<COUNT") // this shows as '<INDEX>/<COUNT>'
+            .help("Only runs the tests in the given shard of the suite, e.g. '2/4'. Shard indices start at 1"))
+        .arg(Arg::with_name("changed-since")
+            .long("changed-since")
+            .takes_value(true)
+            .value_name("REV")
+            .help("Only runs the tests affected by git changes since the given revision. Tests can declare extra dependencies via DEPENDS"))
+        .arg(Arg::with_name("owners-file")
+            .long("owners-file")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Maps tests to their owners via a CODEOWNERS-style file, grouping failures by owner"))
+        .arg(Arg::with_name("timing-history")
+            .long("timing-history")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Records how long each test took in the given file, and uses previously recorded durations to balance shards"))
+        .arg(Arg::with_name("fail-first")
+            .long("fail-first")
+            .help("Starts the tests that failed the last time they ran before all others, according to the timing history"))
+        .arg(Arg::with_name("require-environment")
+            .long("require-environment")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Fails the suite before running any tests if the environment differs from the one in the given file, such as the environment.txt artifact of an earlier run"))
+        .arg(Arg::with_name("heartbeat")
+            .long("heartbeat")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help("Prints a 'still running' note for tests that have not finished after this many seconds, and again every time the interval passes"))
+        .arg(Arg::with_name("suite-timeout")
+            .long("suite-timeout")
+            .takes_value(true)
+            .value_name("DURATION")
+            .help("Stops starting tests once the suite has run for this long, such as '30m', reporting the rest as not run and exiting with code 124"))
+        .arg(Arg::with_name("termination-grace-period")
+            .long("termination-grace-period")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help("Gives commands that time out this many seconds to exit after SIGTERM before they are killed with SIGKILL, so that they can clean up. Defaults to 2"))
+        .arg(Arg::with_name("message-format")
+            .long("message-format")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .possible_values(&["human", "json-lines"])
+            .help("Sets the format that test events are reported in. 'json-lines' emits one JSON object per event on stdout"))
+        .arg(Arg::with_name("audit-log")
+            .long("audit-log")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .possible_values(&["text", "json-lines"])
+            .help("Writes an 'events.log' artifact recording how long each phase of each test took. Requires --save-artifacts-to"))
+        .arg(Arg::with_name("error-format")
+            .long("error-format")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .possible_values(&["long", "short"])
+            .help("Sets how failures are reported. 'short' prints a single '<path>:<line>: <KIND>: <message>' line per failure, for editor quickfix lists"))
+        .arg(Arg::with_name("wasi-runtime")
+            .long("wasi-runtime")
+            .takes_value(true)
+            .value_name("RUNTIME")
+            .help("Runs '.wasm' programs through the given WASI runtime, e.g. 'wasmtime'. The runtime is also available to tests via '@wasm_runner'"))
         .arg(Arg::with_name("show-context-lines")
             .long("show-context-lines")
             .short("C")
             .takes_value(true)
             .value_name("NUMBER OF CONTEXT LINES")
-            .help("Sets the number of output lines to be displayed when showing failure context. Set to '-1' to disable truncation."))
+            .allow_hyphen_values(true)
+            .help("Sets the number of output lines to be displayed when showing failure context. Set to '-1' to disable truncation. Other negative numbers show both the first and last lines of the context, such as '-20'."))
+        .arg(Arg::with_name("wrap-context-at")
+            .long("wrap-context-at")
+            .takes_value(true)
+            .value_name("COLUMNS")
+            .help("Wraps lines of failure context longer than this many characters, keeping the line numbers aligned"))
+        .arg(Arg::with_name("control-sequences")
+            .long("control-sequences")
+            .takes_value(true)
+            .value_name("MODE")
+            .possible_values(&["raw", "escape", "render"])
+            .help("Sets how terminal control sequences in failure context, such as progress bars, are shown. 'escape' makes them visible, and 'render' shows the output as a terminal would. Defaults to 'raw'"))
         .arg(Arg::with_name("always-show-stderr")
             .long("always-show-stderr")
             .help("Always echo the stderr streams emitted by programs under test. By default this is only done if the program exits with an error code. Stderr is also always printed when verbose mode is on."))
+        .arg(Arg::with_name("fail-on-stderr")
+            .long("fail-on-stderr")
+            .help("Fails tests whose RUN commands exit successfully but write anything to stderr, unless the test contains an ALLOW-STDERR directive"))
+        .arg(Arg::with_name("default-matcher")
+            .long("default-matcher")
+            .takes_value(true)
+            .value_name("MATCHER")
+            .help("Matches the patterns of checks that do not name an engine, as in 'CHECK[exact]:', with this engine rather than as regexes. One of 'regex', 'exact', 'case-insensitive' or 'whitespace'"))
+        .arg(Arg::with_name("skip-directive-lines-in-output")
+            .long("skip-directive-lines-in-output")
+            .help("Skips lines of output containing a directive when checking, so that tests checking their own source, such as with 'cat @file', cannot match their own directives"))
+        .arg(Arg::with_name("anchor-checks-to-line-start")
+            .long("anchor-checks-to-line-start")
+            .help("Only matches the patterns of checks at the start of a line, as if every check was written 'CHECK{^}:'"))
+        .arg(Arg::with_name("max-repeated-warnings")
+            .long("max-repeated-warnings")
+            .takes_value(true)
+            .value_name("COUNT")
+            .allow_hyphen_values(true)
+            .help("Sets how many times an identical warning is shown before it is only summarized at the end of the suite. Set to '-1' to show every warning. Defaults to 5"))
+        .arg(Arg::with_name("strict")
+            .long("strict")
+            .help("Fails the test suite if any warnings are raised, or if any tests are empty"))
+        .arg(Arg::with_name("modified-test-files")
+            .long("modified-test-files")
+            .takes_value(true)
+            .value_name("POLICY")
+            .possible_values(&["ignore", "warn", "fail"])
+            .help("Sets what happens when test files are modified while the suite is running, such as by a tool updating test expectations. Defaults to 'warn'"))
+        .arg(Arg::with_name("early-exit")
+            .long("early-exit")
+            .help("Evaluates CHECK and CHECK-NEXT directives as output arrives, killing the program under test as soon as the outcome is known. The exit code of killed programs is ignored, so tests with XFAIL or negated RUN commands are never killed"))
+        .arg(Arg::with_name("forward-output")
+            .long("forward-output")
+            .help("Lets programs under test read the stdin of lit, and copies their stdout and stderr to those of lit as well as checking them. By default, programs under test never see the streams of lit"))
+        .arg(Arg::with_name("use-pty")
+            .long("use-pty")
+            .help("Attaches RUN commands to a pseudo-terminal rather than to pipes, so that tools which behave differently in a terminal can be tested in their interactive mode"))
+        .arg(Arg::with_name("run-as")
+            .long("run-as")
+            .takes_value(true)
+            .value_name("USER")
+            .conflicts_with("run-as-wrapper")
+            .help("Runs the commands of tests as another user, switching to them right before each command is executed. lit must run as root. Only supported on Unix"))
+        .arg(Arg::with_name("run-as-wrapper")
+            .long("run-as-wrapper")
+            .takes_value(true)
+            .value_name("PREFIX")
+            .help("Prefixes the commands of tests with a wrapper that runs them as another user, such as 'sudo -n -u postgres --'"))
+        .arg(Arg::with_name("max-output-bytes")
+            .long("max-output-bytes")
+            .takes_value(true)
+            .value_name("BYTES")
+            .help("Kills RUN commands that write more than this many bytes to stdout or stderr, failing the test"))
+        .arg(Arg::with_name("seed")
+            .long("seed")
+            .takes_value(true)
+            .value_name("SEED")
+            .help("Sets the value of '@seed' in tests, such as to replay a failing run. A random seed is chosen for every run by default"))
         .arg(Arg::with_name("keep-tempfiles")
             .long("keep-tempfiles")
             .help("Disables automatic deletion of tempfiles generated during the test run"))
+        .arg(Arg::with_name("directive-separator")
+            .long("directive-separator")
+            .takes_value(true)
+            .value_name("CHAR")
+            .help("Separates the names of directives from their arguments with a character other than ':', as in 'CHECK= hello'"))
+        .arg(Arg::with_name("temp-root")
+            .long("temp-root")
+            .takes_value(true)
+            .value_name("DIRECTORY")
+            .help("Creates all temporary files and directories underneath a directory, such as a RAM disk, rather than the OS default"))
         .arg(Arg::with_name("save-artifacts-to")
             .long("save-artifacts-to")
             .short("O")
             .takes_value(true)
             .value_name("DIRECTORY")
             .help("Exports all program outputs, temporary files, and logs, to a directory at the specified path. Will create the directory if it does not yet exist."))
+        .arg(Arg::with_name("bundle-failures")
+            .long("bundle-failures")
+            .takes_value(true)
+            .value_name("ARCHIVE")
+            .help("Packs the artifacts of failing tests into a single .tar.gz archive at the given path when the suite finishes, for attaching to bug reports. Requires --save-artifacts-to"))
+        .arg(Arg::with_name("after-artifacts-command")
+            .long("after-artifacts-command")
+            .takes_value(true)
+            .value_name("COMMAND")
+            .help("Runs a shell command once artifacts have been saved, such as to upload them from CI. The paths of the artifacts directory and of a manifest listing every artifact are given in the LIT_ARTIFACTS_DIRECTORY and LIT_ARTIFACTS_MANIFEST environment variables. Only runs if artifacts were written"))
+        .arg(Arg::with_name("feature")
+            .long("feature")
+            .takes_value(true)
+            .value_name("NAME")
+            .multiple(true)
+            .help("Marks a feature as available, so that tests requiring it in their front matter are run instead of skipped"))
+        .arg(Arg::with_name("cargo-workspace")
+            .long("cargo-workspace")
+            .help("Also runs the tests in the 'tests/lit' directory of every member of the cargo workspace containing the current directory"))
+        .arg(Arg::with_name("build-command")
+            .long("build-command")
+            .takes_value(true)
+            .value_name("COMMAND")
+            .help("Runs a command through the shell before running any tests, such as 'cargo build'. No tests are run if it fails"))
+        .arg(Arg::with_name("shell-matrix")
+            .long("shell-matrix")
+            .takes_value(true)
+            .value_name("SHELL")
+            .multiple(true)
+            .help("Runs every test with each of the given shells, reporting the results per shell"))
+        .arg(Arg::with_name("redact-env")
+            .long("redact-env")
+            .takes_value(true)
+            .value_name("REGEX")
+            .multiple(true)
+            .help("Hides the values of environment variables whose names match the regex in the env.txt artifact. Common secret names are always hidden"))
+        .arg(Arg::with_name("compare-against-config")
+            .long("compare-against-config")
+            .takes_value(true)
+            .value_name("CONFIG FILE")
+            .help("Runs every test a second time with the settings from the given config file applied, reporting any tests whose result or normalized output differs"))
         .arg(Arg::with_name("verbose")
             .long("verbose")
             .short("v")
@@ -100,12 +355,38 @@ pub fn mount_inside_app<'a, 'b>(
             .value_name("FLAG")
             .multiple(true)
             .help(&DEBUG_OPTION_HELP[..]))
+        .arg(Arg::with_name("explain-selection")
+            .long("explain-selection")
+            .help("Prints a table of every discovered file, whether it would be run, and the extension, --changed-since, --shard or 'requires' rule that decided it, without running tests"))
         .subcommand(SubCommand::with_name("show")
             .about("Shows information about the test suite, without running tests")
             .arg(Arg::with_name("what")
                 .takes_value(true)
                 .value_name("WHAT")
-                .help(&SHOW_SUBCOMMAND_WHAT_OPTION_HELP)));
+                .help(&SHOW_SUBCOMMAND_WHAT_OPTION_HELP)))
+        .subcommand(SubCommand::with_name("init")
+            .about("Creates a lit.toml config file, a tests/lit directory with an example test, and optionally a cargo integration test running them, leaving existing files alone")
+            .arg(Arg::with_name("directory")
+                .takes_value(true)
+                .value_name("DIRECTORY")
+                .help("The project directory to create the files in. Defaults to the current directory"))
+            .arg(Arg::with_name("cargo-test")
+                .long("cargo-test")
+                .help("Also creates tests/lit.rs, a cargo integration test that runs the suite with lit::run::tests")))
+        .subcommand(SubCommand::with_name("doctor")
+            .about("Checks that the shell, required tools, and directories used by tests are available, without running tests"))
+        .subcommand(SubCommand::with_name("rerun")
+            .about("Runs a single test again with the seed, shell, features, constants and environment of the last run that saved artifacts, showing its output in full")
+            .arg(Arg::with_name("test")
+                .required(true)
+                .takes_value(true)
+                .value_name("RELATIVE TEST PATH")
+                .help("The path of the test, relative to the artifacts directory. Tests run under a shell matrix are prefixed with the shell, as in 'bash/parser/empty.txt'"))
+            .arg(Arg::with_name("artifacts")
+                .long("artifacts")
+                .takes_value(true)
+                .value_name("DIRECTORY")
+                .help("The artifacts directory of the last run. Defaults to the directory given by '--save-artifacts-to'")));
 
     // Test paths argument
     let test_paths_arg = {
@@ -133,12 +414,28 @@ pub fn mount_inside_app<'a, 'b>(
 /// Parses command line arguments from `clap` into a destination `Config` object.
 pub fn parse_arguments(matches: &ArgMatches,
                        destination_config: &mut Config) {
+    if let Some(config_file) = matches.value_of("config") {
+        if let Err(e) = super::file::load(Path::new(config_file), destination_config) {
+            fatal_error(e);
+        }
+    }
+
     if let Some(extensions) = matches.values_of("supported-file-extension") {
         for extension in extensions {
             destination_config.add_extension(extension);
         }
     }
 
+    if let Some(patterns) = matches.values_of("file-name-pattern") {
+        for pattern in patterns {
+            destination_config.add_file_name_pattern(pattern);
+        }
+    }
+
+    if let Some(fixture_file_name) = matches.value_of("fixture-file-name") {
+        destination_config.fixture_file_name = Some(fixture_file_name.to_owned());
+    }
+
     if let Some(test_paths) = matches.values_of("add-tests") {
         for test_path in test_paths {
             destination_config.add_search_path(test_path);
@@ -151,19 +448,220 @@ pub fn parse_arguments(matches: &ArgMatches,
                 Ok(c) => c,
                 Err(e) => panic!("could not parse constant definition: {}", e),
             };
+            if let Err(e) = vars::validate_constant_name(&constant_definition.name) { fatal_error(e); }
 
             destination_config.constants.insert(constant_definition.name, constant_definition.value);
         }
     }
 
+    if let Some(values) = matches.values_of("directory-constant") {
+        let values: Vec<_> = values.collect();
+
+        for pair in values.chunks(2) {
+            let (directory, constant_define_str) = (pair[0], pair[1]);
+            let constant_definition: ConstantDefinition = match constant_define_str.parse() {
+                Ok(c) => c,
+                Err(e) => panic!("could not parse constant definition: {}", e),
+            };
+            if let Err(e) = vars::validate_constant_name(&constant_definition.name) { fatal_error(e); }
+
+            destination_config.add_directory_constant(directory, constant_definition.name, constant_definition.value);
+        }
+    }
+
+    if let Some(jobs) = matches.value_of("jobs") {
+        match jobs.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => destination_config.jobs = jobs,
+            _ => fatal_error(format!("invalid number of jobs: '{}' - must be a positive integer", jobs)),
+        }
+    }
+
+    if let Some(resource_limit_strs) = matches.values_of("resource-limit") {
+        for resource_limit_str in resource_limit_strs {
+            let definition: ConstantDefinition = match resource_limit_str.parse() {
+                Ok(c) => c,
+                Err(e) => fatal_error(format!("could not parse resource limit: {}", e)),
+            };
+
+            match definition.value.parse::<usize>() {
+                Ok(limit) if limit > 0 => { destination_config.resource_limits.insert(definition.name, limit); },
+                _ => fatal_error(format!("invalid limit for resource '{}': '{}' - must be a positive integer", definition.name, definition.value)),
+            }
+        }
+    }
+
+    if let Some(shard) = matches.value_of("shard") {
+        destination_config.shard = Some(match parse_shard(shard) {
+            Some(shard) => shard,
+            None => fatal_error(format!("invalid shard: '{}' - must be of the form '<INDEX>/<COUNT>', with 1 <= INDEX <= COUNT", shard)),
+        });
+    }
+
+    if let Some(revision) = matches.value_of("changed-since") {
+        destination_config.changed_since = Some(revision.to_owned());
+        destination_config.dump_test_selection = true;
+    }
+
+    if let Some(owners_file) = matches.value_of("owners-file") {
+        if let Err(e) = destination_config.add_owners_file(owners_file) {
+            fatal_error(e);
+        }
+    }
+
+    if let Some(timing_history_path) = matches.value_of("timing-history") {
+        destination_config.timing_history_file = Some(Path::new(timing_history_path).to_owned());
+    }
+
+    if matches.is_present("fail-first") {
+        destination_config.fail_first = true;
+    }
+
+    if let Some(required_environment) = matches.value_of("require-environment") {
+        destination_config.required_environment = Some(Path::new(required_environment).to_owned());
+    }
+
+    if let Some(grace_period) = matches.value_of("termination-grace-period") {
+        match grace_period.parse::<u64>() {
+            Ok(seconds) => destination_config.termination_grace_period = std::time::Duration::from_secs(seconds),
+            Err(..) => fatal_error(format!("invalid termination grace period: '{}' - must be a number of seconds", grace_period)),
+        }
+    }
+
+    if let Some(suite_timeout) = matches.value_of("suite-timeout") {
+        match crate::util::parse_duration(suite_timeout) {
+            Ok(suite_timeout) => destination_config.suite_timeout = Some(suite_timeout),
+            Err(e) => fatal_error(e),
+        }
+    }
+
+    if let Some(heartbeat) = matches.value_of("heartbeat") {
+        match heartbeat.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => destination_config.heartbeat_interval = Some(std::time::Duration::from_secs(seconds)),
+            _ => fatal_error(format!("invalid heartbeat interval: '{}' - must be a positive number of seconds", heartbeat)),
+        }
+    }
+
+    match matches.value_of("message-format") {
+        Some("json-lines") => destination_config.message_format = MessageFormat::JsonLines,
+        Some(_) => destination_config.message_format = MessageFormat::Human,
+        None => (),
+    }
+
+    match matches.value_of("audit-log") {
+        Some("json-lines") => destination_config.audit_log_format = Some(AuditLogFormat::JsonLines),
+        Some(_) => destination_config.audit_log_format = Some(AuditLogFormat::Text),
+        None => (),
+    }
+
+    match matches.value_of("error-format") {
+        Some("short") => destination_config.error_format = ErrorFormat::Short,
+        Some(_) => destination_config.error_format = ErrorFormat::Long,
+        None => (),
+    }
+
+    match matches.value_of("modified-test-files") {
+        Some("ignore") => destination_config.modified_test_files = ModifiedTestFiles::Ignore,
+        Some("fail") => destination_config.modified_test_files = ModifiedTestFiles::Fail,
+        Some(_) => destination_config.modified_test_files = ModifiedTestFiles::Warn,
+        None => (),
+    }
+
+    if let Some(wasi_runtime) = matches.value_of("wasi-runtime") {
+        destination_config.executor = std::sync::Arc::new(crate::executor::Wasi::new(wasi_runtime));
+    }
+
+    if matches.is_present("forward-output") {
+        destination_config.forward_output = true;
+    }
+
+    if matches.is_present("use-pty") {
+        destination_config.use_pty = true;
+    }
+
+    if let Some(user) = matches.value_of("run-as") {
+        match crate::config::RunAs::user(user) {
+            Ok(run_as) => destination_config.run_as = Some(run_as),
+            Err(e) => fatal_error(e),
+        }
+    }
+
+    if let Some(prefix) = matches.value_of("run-as-wrapper") {
+        match crate::config::RunAs::wrapper(prefix) {
+            Ok(run_as) => destination_config.run_as = Some(run_as),
+            Err(e) => fatal_error(e),
+        }
+    }
+
+    if matches.is_present("early-exit") {
+        destination_config.early_exit_on_check_completion = true;
+    }
+
+    if let Some(max_output_bytes) = matches.value_of("max-output-bytes") {
+        match max_output_bytes.parse::<usize>() {
+            Ok(max_output_bytes) => destination_config.max_output_bytes = Some(max_output_bytes),
+            Err(..) => fatal_error(format!("invalid output limit: '{}' - must be a number of bytes", max_output_bytes)),
+        }
+    }
+
+    if let Some(seed) = matches.value_of("seed") {
+        match seed.parse::<u64>() {
+            Ok(seed) => destination_config.seed = Some(seed),
+            Err(..) => fatal_error(format!("invalid seed: '{}' - must be a non-negative integer", seed)),
+        }
+    }
+
     if matches.is_present("keep-tempfiles") {
         destination_config.cleanup_temporary_files = false;
     }
 
+    if let Some(separator) = matches.value_of("directive-separator") {
+        match crate::config::parse_directive_separator(separator) {
+            Ok(separator) => destination_config.directive_separator = separator,
+            Err(e) => fatal_error(e),
+        }
+    }
+
+    if let Some(temp_root) = matches.value_of("temp-root") {
+        destination_config.temp_root = Some(Path::new(temp_root).to_owned());
+    }
+
     if let Some(artifacts_path) = matches.value_of("save-artifacts-to") {
         destination_config.save_artifacts_to_directory = Some(Path::new(artifacts_path).to_owned());
     }
 
+    if let Some(archive_path) = matches.value_of("bundle-failures") {
+        destination_config.bundle_failures_to = Some(Path::new(archive_path).to_owned());
+    }
+
+    if let Some(command_line) = matches.value_of("after-artifacts-command") {
+        destination_config.after_artifacts_command = Some(command_line.to_owned());
+    }
+
+    if let Some(features) = matches.values_of("feature") {
+        destination_config.available_features.extend(features.map(ToOwned::to_owned));
+    }
+
+    if matches.is_present("cargo-workspace") {
+        destination_config.add_test_source(crate::test_source::CargoWorkspace::new());
+    }
+
+    if let Some(build_command) = matches.value_of("build-command") {
+        destination_config.build_command = Some(build_command.to_owned());
+    }
+
+    if let Some(shells) = matches.values_of("shell-matrix") {
+        destination_config.shell_matrix.extend(shells.map(ToOwned::to_owned));
+    }
+
+    if let Some(redactions) = matches.values_of("redact-env") {
+        for redaction in redactions {
+            match regex::Regex::new(redaction) {
+                Ok(regex) => destination_config.environment_redactions.push(regex),
+                Err(e) => fatal_error(format!("invalid environment redaction '{}': {}", redaction, e)),
+            }
+        }
+    }
+
     // Parse verbosity.
     {
         let verbosity_level = matches.occurrences_of("verbose");
@@ -179,6 +677,7 @@ pub fn parse_arguments(matches: &ArgMatches,
 
             if verbosity_level >= 1 {
                 destination_config.always_show_stderr = true;
+                destination_config.dump_discovery_time = true;
             }
 
             if verbosity_level >= 2 {
@@ -191,6 +690,37 @@ pub fn parse_arguments(matches: &ArgMatches,
         destination_config.always_show_stderr = true;
     }
 
+    if matches.is_present("fail-on-stderr") {
+        destination_config.fail_on_stderr = true;
+    }
+
+    if let Some(matcher) = matches.value_of("default-matcher") {
+        if let Err(e) = crate::matcher::find(matcher, destination_config) {
+            fatal_error(e);
+        }
+        destination_config.default_matcher = Some(matcher.to_owned());
+    }
+
+    if matches.is_present("skip-directive-lines-in-output") {
+        destination_config.skip_directive_lines_in_output = true;
+    }
+
+    if matches.is_present("anchor-checks-to-line-start") {
+        destination_config.anchor_checks_to_line_start = true;
+    }
+
+    if let Some(count) = matches.value_of("max-repeated-warnings") {
+        match count.parse::<isize>() {
+            Ok(-1) => destination_config.max_repeated_warnings = None,
+            Ok(count) if count >= 0 => destination_config.max_repeated_warnings = Some(count as usize),
+            _ => fatal_error(format!("invalid number of repeated warnings: '{}' - must be a number, or '-1' to show every warning", count)),
+        }
+    }
+
+    if matches.is_present("strict") {
+        destination_config.strict = true;
+    }
+
     if let Some(debug_flags) = matches.values_of("debug") {
         for debug_flag in debug_flags {
             let apply_fn = DEBUG_OPTION_VALUES.iter().find(|(k, _)| k == &debug_flag.trim()).map(|d| d.1);
@@ -213,15 +743,93 @@ pub fn parse_arguments(matches: &ArgMatches,
             Ok(-1) => {
                 destination_config.truncate_output_context_to_number_of_lines = None;
             },
-            Ok(lines) if lines < 0 => fatal_error(format!("invalid number of context lines: '{}' - must be a positive integer, or '-1' to disable truncation", cli_show_context_lines)),
+            Ok(lines) if lines < 0 => {
+                destination_config.truncate_output_context_to_number_of_lines = Some(lines.unsigned_abs());
+                destination_config.truncate_output_context_in_middle = true;
+            },
             Ok(lines) => {
                 destination_config.truncate_output_context_to_number_of_lines = Some(lines as usize);
             },
-            Err(_) => fatal_error(format!("invalid number of context lines: '{}' - must be a positive integer, or '-1' to disable truncation", cli_show_context_lines)),
+            Err(_) => fatal_error(format!("invalid number of context lines: '{}' - must be an integer, or '-1' to disable truncation", cli_show_context_lines)),
         }
     }
 
+    match matches.value_of("control-sequences") {
+        Some("escape") => destination_config.control_sequences_in_context = ControlSequences::Escape,
+        Some("render") => destination_config.control_sequences_in_context = ControlSequences::Render,
+        Some(_) => destination_config.control_sequences_in_context = ControlSequences::Raw,
+        None => (),
+    }
+
+    if let Some(columns) = matches.value_of("wrap-context-at") {
+        match columns.parse::<usize>() {
+            Ok(columns) if columns > 0 => destination_config.wrap_output_context_at = Some(columns),
+            _ => fatal_error(format!("invalid number of columns to wrap context at: '{}'", columns)),
+        }
+    }
+
+    // NOTE: should be processed after all other arguments, the comparison
+    // config is derived from the otherwise-complete config.
+    if let Some(comparison_config_path) = matches.value_of("compare-against-config") {
+        let mut comparison_config = destination_config.clone();
+        if let Err(e) = crate::config::file::load(Path::new(comparison_config_path), &mut comparison_config) {
+            fatal_error(e);
+        }
+
+        destination_config.comparison_config = Some(Box::new(comparison_config));
+    }
+
     // NOTE: should process subcommands at the very end
+    if let Some(matches) = matches.subcommand_matches("init") {
+        let with_cargo_test = matches.is_present("cargo-test");
+        match crate::init::scaffold(Path::new(matches.value_of("directory").unwrap_or(".")), with_cargo_test) {
+            Ok(files) => for file in files {
+                println!("{}", file);
+            },
+            Err(e) => fatal_error(e),
+        }
+
+        match with_cargo_test {
+            true => println!("run the tests with 'cargo test --test lit', once lit is in the [dev-dependencies] of Cargo.toml"),
+            false => println!("run the tests with 'lit --config {} {}'", crate::init::CONFIG_FILE_NAME, crate::init::TEST_DIRECTORY),
+        }
+
+        // No tests should be ran when running this subcommand.
+        std::process::exit(0);
+    }
+
+    if matches.subcommand_matches("doctor").is_some() {
+        let diagnostics = crate::doctor::diagnose(destination_config);
+        for diagnostic in diagnostics.iter() {
+            println!("{}", diagnostic);
+        }
+
+        // No tests should be ran when running this subcommand.
+        std::process::exit(if crate::doctor::has_errors(&diagnostics) { 1 } else { 0 });
+    }
+
+    if let Some(matches) = matches.subcommand_matches("rerun") {
+        let artifacts_directory = match matches.value_of("artifacts").map(PathBuf::from).or_else(|| destination_config.save_artifacts_to_directory.clone()) {
+            Some(directory) => directory,
+            None => fatal_error("no artifacts directory to rerun the test from, pass '--artifacts <DIRECTORY>'"),
+        };
+
+        match crate::run::rerun::configure(&artifacts_directory, Path::new(matches.value_of("test").unwrap()), destination_config) {
+            Ok(environment) => crate::run::rerun::set_environment(&environment, destination_config),
+            Err(e) => fatal_error(e),
+        }
+    }
+
+    if matches.is_present("explain-selection") {
+        match crate::run::selection::explain(destination_config) {
+            Ok(decisions) => print!("{}", crate::run::selection::render(&decisions)),
+            Err(e) => fatal_error(format!("could not select tests: {}", e)),
+        }
+
+        // No tests should be ran when explaining the selection.
+        std::process::exit(0);
+    }
+
     if let Some(matches) = matches.subcommand_matches("show") {
         let what_fns: Vec<_> = match matches.value_of("what") {
             Some(what) => {
@@ -260,6 +868,16 @@ pub fn parse_arguments(matches: &ArgMatches,
     }
 }
 
+/// Parses a one-based `<INDEX>/<COUNT>` shard specification.
+fn parse_shard(s: &str) -> Option<crate::config::Shard> {
+    let mut parts = s.splitn(2, '/');
+    let index: usize = parts.next()?.trim().parse().ok()?;
+    let count: usize = parts.next()?.trim().parse().ok()?;
+
+    if index == 0 || index > count { return None; }
+    Some(crate::config::Shard { index: index - 1, count })
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct ConstantDefinition {
     pub name: String,
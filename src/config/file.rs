@@ -0,0 +1,577 @@
+//! Loading of configuration from TOML-style files.
+//!
+//! Only a small subset of TOML is supported - tables, and keys holding strings,
+//! integers, booleans, or single-line arrays of those. Keys are either bare, or quoted
+//! without escape sequences or `=`. Nested and array tables, dotted keys, inline tables,
+//! multi-line strings and arrays, floats, and `_` in integers are all rejected.
+//!
+//! ``` toml
+//! shell = "sh"
+//! extensions = ["txt", "sh"]
+//! file_names = ["Makefile.lit"]
+//!
+//! [constants]
+//! cc = "clang"
+//!
+//! [patterns]
+//! ident = "[a-zA-Z_][a-zA-Z0-9_]*"
+//!
+//! [resource_limits]
+//! gpu = 2
+//!
+//! [test_names]
+//! "integration/network" = "network :: "
+//! ```
+
+use crate::Config;
+use std::path::Path;
+
+/// A value in a configuration file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+/// A single `key = value` entry from a configuration file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// The table the entry lives in, empty for top-level keys.
+    pub table: String,
+    pub key: String,
+    pub value: Value,
+    /// The line number the entry was defined on.
+    pub line_number: usize,
+}
+
+/// Loads a configuration file, applying its settings on top of a config.
+pub fn load(path: &Path, config: &mut Config) -> Result<(), String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read config file '{}': {}", path.display(), e))?;
+
+    apply(&text, config).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Applies the settings in configuration file text on top of a config.
+pub fn apply(text: &str, config: &mut Config) -> Result<(), String> {
+    for entry in self::parse(text)? {
+        apply_entry(&entry, config).map_err(|e| format!("line {}: {}", entry.line_number, e))?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn apply_entry(entry: &Entry, config: &mut Config) -> Result<(), String> {
+    match (&entry.table[..], &entry.key[..]) {
+        ("", "cargo_workspace") => if entry.value.as_bool()? {
+            config.add_test_source(crate::test_source::CargoWorkspace::new());
+        },
+        ("", "build_command") => config.build_command = Some(entry.value.as_str()?.to_owned()),
+        ("", "shell") => config.shell = entry.value.as_str()?.to_owned(),
+        ("", "shell_matrix") => {
+            for shell in entry.value.as_array()? {
+                config.shell_matrix.push(shell.as_str()?.to_owned());
+            }
+        },
+        ("", "extensions") => {
+            for extension in entry.value.as_array()? {
+                config.add_extension(extension.as_str()?);
+            }
+        },
+        ("", "file_names") => {
+            for pattern in entry.value.as_array()? {
+                config.add_file_name_pattern(pattern.as_str()?);
+            }
+        },
+        ("", "fixture_file_name") => config.fixture_file_name = Some(entry.value.as_str()?.to_owned()),
+        ("", "audit_log") => {
+            config.audit_log_format = match entry.value.as_str()? {
+                "text" => Some(crate::config::AuditLogFormat::Text),
+                "json-lines" => Some(crate::config::AuditLogFormat::JsonLines),
+                format => return Err(format!("invalid audit log format '{}', expected 'text' or 'json-lines'", format)),
+            };
+        },
+        ("", "always_show_stderr") => config.always_show_stderr = entry.value.as_bool()?,
+        ("", "fail_on_stderr") => config.fail_on_stderr = entry.value.as_bool()?,
+        ("", "default_matcher") => {
+            let matcher = entry.value.as_str()?;
+            crate::matcher::find(matcher, config)?;
+            config.default_matcher = Some(matcher.to_owned());
+        },
+        ("", "skip_directive_lines_in_output") => config.skip_directive_lines_in_output = entry.value.as_bool()?,
+        ("", "anchor_checks_to_line_start") => config.anchor_checks_to_line_start = entry.value.as_bool()?,
+        ("", "strict") => config.strict = entry.value.as_bool()?,
+        ("", "max_repeated_warnings") => {
+            config.max_repeated_warnings = match entry.value.as_integer()? {
+                -1 => None,
+                count if count >= 0 => Some(count as usize),
+                count => return Err(format!("invalid number of repeated warnings: '{}'", count)),
+            };
+        },
+        ("", "modified_test_files") => {
+            config.modified_test_files = match entry.value.as_str()? {
+                "ignore" => crate::config::ModifiedTestFiles::Ignore,
+                "warn" => crate::config::ModifiedTestFiles::Warn,
+                "fail" => crate::config::ModifiedTestFiles::Fail,
+                policy => return Err(format!("invalid modified test files policy '{}', expected 'ignore', 'warn' or 'fail'", policy)),
+            };
+        },
+        ("", "early_exit_on_check_completion") => config.early_exit_on_check_completion = entry.value.as_bool()?,
+        ("", "forward_output") => config.forward_output = entry.value.as_bool()?,
+        ("", "use_pty") => config.use_pty = entry.value.as_bool()?,
+        ("", "run_as") => config.run_as = Some(crate::config::RunAs::user(entry.value.as_str()?)?),
+        ("", "run_as_wrapper") => config.run_as = Some(crate::config::RunAs::wrapper(entry.value.as_str()?)?),
+        ("", "features") => {
+            for feature in entry.value.as_array()? {
+                config.available_features.push(feature.as_str()?.to_owned());
+            }
+        },
+        ("", "suite_timeout") => config.suite_timeout = Some(crate::util::parse_duration(entry.value.as_str()?)?),
+        ("", "heartbeat_interval") => {
+            config.heartbeat_interval = match entry.value.as_integer()? {
+                seconds if seconds > 0 => Some(std::time::Duration::from_secs(seconds as u64)),
+                seconds => return Err(format!("invalid heartbeat interval: '{}'", seconds)),
+            };
+        },
+        ("", "termination_grace_period") => {
+            config.termination_grace_period = match entry.value.as_integer()? {
+                seconds if seconds >= 0 => std::time::Duration::from_secs(seconds as u64),
+                seconds => return Err(format!("invalid termination grace period: '{}'", seconds)),
+            };
+        },
+        ("", "jobs") => {
+            config.jobs = match entry.value.as_integer()? {
+                jobs if jobs > 0 => jobs as usize,
+                jobs => return Err(format!("invalid number of jobs: '{}'", jobs)),
+            };
+        },
+        ("", "seed") => {
+            config.seed = match entry.value.as_integer()? {
+                seed if seed >= 0 => Some(seed as u64),
+                seed => return Err(format!("invalid seed: '{}'", seed)),
+            };
+        },
+        ("", "max_concurrent_instances_per_file") => {
+            config.max_concurrent_instances_per_file = match entry.value.as_integer()? {
+                instances if instances > 0 => Some(instances as usize),
+                instances => return Err(format!("invalid number of concurrent instances per file: '{}'", instances)),
+            };
+        },
+        ("", "max_output_bytes") => {
+            config.max_output_bytes = match entry.value.as_integer()? {
+                bytes if bytes >= 0 => Some(bytes as usize),
+                bytes => return Err(format!("invalid output limit: '{}'", bytes)),
+            };
+        },
+        ("", "normalize_presets") => {
+            for preset in entry.value.as_array()? {
+                config.add_normalizer_preset(preset.as_str()?)?;
+            }
+        },
+        ("", "owners_file") => config.add_owners_file(entry.value.as_str()?)?,
+        ("", "required_tools") => {
+            for tool in entry.value.as_array()? {
+                config.required_tools.push(tool.as_str()?.to_owned());
+            }
+        },
+        ("", "redact_env") => {
+            for redaction in entry.value.as_array()? {
+                let redaction = redaction.as_str()?;
+                let regex = regex::Regex::new(redaction).map_err(|e| format!("invalid environment redaction '{}': {}", redaction, e))?;
+                config.environment_redactions.push(regex);
+            }
+        },
+        ("", "bundle_failures") => config.bundle_failures_to = Some(entry.value.as_str()?.into()),
+        ("", "after_artifacts_command") => config.after_artifacts_command = Some(entry.value.as_str()?.to_owned()),
+        ("", "temp_root") => config.temp_root = Some(entry.value.as_str()?.into()),
+        ("", "require_environment") => config.required_environment = Some(entry.value.as_str()?.into()),
+        ("", "timing_history") => config.timing_history_file = Some(entry.value.as_str()?.into()),
+        ("", "fail_first") => config.fail_first = entry.value.as_bool()?,
+        ("", "wasi_runtime") => config.executor = std::sync::Arc::new(crate::executor::Wasi::new(entry.value.as_str()?)),
+        ("", "keep_tempfiles") => config.cleanup_temporary_files = !entry.value.as_bool()?,
+        ("", "show_context_lines") => {
+            config.truncate_output_context_to_number_of_lines = match entry.value.as_integer()? {
+                -1 => None,
+                lines if lines < 0 => {
+                    config.truncate_output_context_in_middle = true;
+                    Some(lines.unsigned_abs() as usize)
+                },
+                lines => Some(lines as usize),
+            };
+        },
+        ("", "directive_separator") => config.directive_separator = crate::config::parse_directive_separator(entry.value.as_str()?)?,
+        ("directive_separators", extension) => {
+            config.set_directive_separator(extension, crate::config::parse_directive_separator(entry.value.as_str()?)?)?;
+        },
+        ("comments", extension) => {
+            // Each item is either a line comment leader, or block comment delimiters separated by a space.
+            let mut syntax = crate::config::CommentSyntax::default().with_string_delimiter('"');
+            for delimiters in entry.value.as_array()? {
+                let delimiters = delimiters.as_str()?;
+                syntax = match delimiters.split_whitespace().collect::<Vec<_>>()[..] {
+                    [leader] => syntax.with_line_comment(leader),
+                    [open, close] => syntax.with_block_comment(open, close),
+                    _ => return Err(format!("invalid comment delimiters '{}'", delimiters)),
+                };
+            }
+            config.set_comment_syntax(extension, syntax);
+        },
+        ("", "control_sequences") => {
+            config.control_sequences_in_context = match entry.value.as_str()? {
+                "raw" => crate::config::ControlSequences::Raw,
+                "escape" => crate::config::ControlSequences::Escape,
+                "render" => crate::config::ControlSequences::Render,
+                mode => return Err(format!("invalid control sequence mode '{}', expected 'raw', 'escape' or 'render'", mode)),
+            };
+        },
+        ("", "wrap_context_at") => {
+            config.wrap_output_context_at = match entry.value.as_integer()? {
+                columns if columns > 0 => Some(columns as usize),
+                columns => return Err(format!("invalid number of columns to wrap context at: '{}'", columns)),
+            };
+        },
+        ("test_names", prefix) => config.add_test_name_prefix(prefix, entry.value.as_str()?),
+        ("constants", name) => {
+            crate::vars::validate_constant_name(name)?;
+            config.constants.insert(name.to_owned(), entry.value.as_str()?.to_owned());
+        },
+        ("regex", "size_limit") => config.regex_options.size_limit = entry.value.as_integer()? as usize,
+        ("regex", "unicode") => config.regex_options.unicode = entry.value.as_bool()?,
+        ("regex", "dot_matches_new_line") => config.regex_options.dot_matches_new_line = entry.value.as_bool()?,
+        ("regex", "case_insensitive") => config.regex_options.case_insensitive = entry.value.as_bool()?,
+        ("resource_limits", name) => {
+            let limit = match entry.value.as_integer()? {
+                limit if limit > 0 => limit as usize,
+                limit => return Err(format!("invalid limit for resource '{}': '{}'", name, limit)),
+            };
+            config.resource_limits.insert(name.to_owned(), limit);
+        },
+        ("messages", id) => match crate::messages::MessageId::parse(id) {
+            Some(id) => config.messages.set(id, entry.value.as_str()?),
+            None => return Err(format!("unknown message '{}'", id)),
+        },
+        ("patterns", name) => {
+            config.patterns.insert(name.to_owned(), entry.value.as_str()?.to_owned());
+        },
+        (table, key) => {
+            let qualified_key = if table.is_empty() { key.to_owned() } else { format!("{}.{}", table, key) };
+            return Err(format!("unknown configuration key '{}'", qualified_key));
+        },
+    }
+
+    Ok(())
+}
+
+/// Parses configuration file text into a list of entries.
+pub fn parse(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut current_table = String::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_number = line_idx + 1;
+        let line = strip_comment(line).trim();
+
+        if line.is_empty() { continue; }
+
+        if line.starts_with("[[") {
+            return Err(format!("line {}: array tables are not supported", line_number));
+        }
+        if line.starts_with('[') {
+            if !line.ends_with(']') {
+                return Err(format!("line {}: unterminated table header", line_number));
+            }
+            current_table = line[1..line.len() - 1].trim().to_owned();
+            if current_table.contains('.') {
+                return Err(format!("line {}: nested tables are not supported", line_number));
+            }
+            if !is_bare_key(&current_table) {
+                return Err(format!("line {}: invalid table name '{}'", line_number, current_table));
+            }
+            continue;
+        }
+
+        let (key, value_text) = parse_key(line).map_err(|e| format!("line {}: {}", line_number, e))?;
+        let mut chars = value_text.trim().chars().peekable();
+        let value = parse_value(&mut chars).map_err(|e| format!("line {}: {}", line_number, e))?;
+
+        if chars.any(|c| !c.is_whitespace()) {
+            return Err(format!("line {}: unexpected trailing characters after value", line_number));
+        }
+
+        entries.push(Entry { table: current_table.clone(), key, value, line_number });
+    }
+
+    Ok(entries)
+}
+
+/// Quotes a string so that it is parsed back as the same value.
+pub(crate) fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t"))
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = None;
+    let mut escaped = false;
+
+    for (idx, c) in line.char_indices() {
+        match (in_string, c) {
+            (None, '#') => return &line[..idx],
+            (None, '"') | (None, '\'') => in_string = Some(c),
+            // Escaped quotes do not end basic strings.
+            (Some('"'), '\\') => { escaped = !escaped; continue; },
+            (Some(quote), c) if c == quote && !escaped => in_string = None,
+            _ => (),
+        }
+        escaped = false;
+    }
+
+    line
+}
+
+/// Splits a `key = value` line into its key and the text of its value.
+fn parse_key(line: &str) -> Result<(String, &str), String> {
+    let (key, rest) = match line.chars().next() {
+        Some(quote) if quote == '"' || quote == '\'' => {
+            let (key, rest) = match line[1..].find(quote) {
+                Some(idx) => (&line[1..idx + 1], &line[idx + 2..]),
+                None => return Err("unterminated key".to_owned()),
+            };
+            if quote == '"' && key.contains('\\') {
+                return Err(format!("escape sequences in keys are not supported, in key '{}'", key));
+            }
+            if key.contains('=') {
+                return Err(format!("quoted keys containing '=' are not supported, in key '{}'", key));
+            }
+            (key, rest.trim_start())
+        },
+        _ => {
+            let equals_idx = match line.find('=') {
+                Some(idx) => idx,
+                None => return Err("expected 'key = value'".to_owned()),
+            };
+            let key = line[..equals_idx].trim();
+            if key.contains('.') {
+                return Err(format!("dotted keys are not supported, quote the key '{}' instead", key));
+            }
+            if !is_bare_key(key) {
+                return Err(format!("invalid key '{}', keys with characters other than letters, digits, '_' and '-' must be quoted", key));
+            }
+            (key, &line[equals_idx..])
+        },
+    };
+
+    match rest.strip_prefix('=') {
+        Some(value_text) => Ok((key.to_owned(), value_text)),
+        None => Err("expected 'key = value'".to_owned()),
+    }
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn parse_value<I>(chars: &mut std::iter::Peekable<I>) -> Result<Value, String>
+    where I: Iterator<Item=char> {
+    while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) { chars.next(); }
+
+    match chars.peek().cloned() {
+        Some('"') => {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    // An empty string followed by another quote opens a multi-line string.
+                    Some('"') if s.is_empty() && chars.peek() == Some(&'"') => return Err("multi-line strings are not supported".to_owned()),
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('\\') => s.push('\\'),
+                        Some('"') => s.push('"'),
+                        Some(c) => return Err(format!("unknown escape sequence '\\{}'", c)),
+                        None => return Err("unterminated string".to_owned()),
+                    },
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_owned()),
+                }
+            }
+            Ok(Value::String(s))
+        },
+        Some('\'') => {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('\'') if s.is_empty() && chars.peek() == Some(&'\'') => return Err("multi-line strings are not supported".to_owned()),
+                    Some('\'') => break,
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_owned()),
+                }
+            }
+            Ok(Value::String(s))
+        },
+        Some('[') => {
+            chars.next();
+            let mut values = Vec::new();
+            loop {
+                while chars.peek().map(|c| c.is_whitespace() || *c == ',').unwrap_or(false) { chars.next(); }
+
+                match chars.peek() {
+                    Some(']') => { chars.next(); break; },
+                    Some(_) => values.push(parse_value(chars)?),
+                    None => return Err("unterminated array, arrays must be on a single line".to_owned()),
+                }
+            }
+            Ok(Value::Array(values))
+        },
+        Some('{') => Err("inline tables are not supported".to_owned()),
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ',' || c == ']' { break; }
+                word.push(c);
+                chars.next();
+            }
+
+            match &word[..] {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ if word.contains('_') => Err(format!("'_' in integers is not supported, in '{}'", word)),
+                _ => word.parse().map(Value::Integer).map_err(|_| format!("invalid value '{}'", word)),
+            }
+        },
+        None => Err("expected a value".to_owned()),
+    }
+}
+
+impl Value {
+    pub fn as_str(&self) -> Result<&str, String> {
+        match *self {
+            Value::String(ref s) => Ok(s),
+            ref v => Err(format!("expected a string but got {:?}", v)),
+        }
+    }
+
+    pub fn as_integer(&self) -> Result<i64, String> {
+        match *self {
+            Value::Integer(i) => Ok(i),
+            ref v => Err(format!("expected an integer but got {:?}", v)),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, String> {
+        match *self {
+            Value::Boolean(b) => Ok(b),
+            ref v => Err(format!("expected a boolean but got {:?}", v)),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&[Value], String> {
+        match *self {
+            Value::Array(ref values) => Ok(values),
+            ref v => Err(format!("expected an array but got {:?}", v)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_tables_and_values() {
+        let entries = parse("shell = \"sh\" # comment\nextensions = ['txt', \"sh\"]\n\n[constants]\ncc = \"clang#1\"\n").unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].value, Value::String("sh".to_owned()));
+        assert_eq!(entries[1].value, Value::Array(vec![Value::String("txt".to_owned()), Value::String("sh".to_owned())]));
+        assert_eq!((&entries[2].table[..], &entries[2].key[..]), ("constants", "cc"));
+        assert_eq!(entries[2].value, Value::String("clang#1".to_owned()));
+
+        let value = "say \"#1\"\\";
+        assert_eq!(parse(&format!("key = {} # comment", quote(value))).unwrap()[0].value, Value::String(value.to_owned()));
+    }
+
+    #[test]
+    fn rejects_unsupported_syntax() {
+        let error = |text: &str| parse(text).unwrap_err();
+
+        assert_eq!(parse("'a.b' = 1\n[test_names]\n\"x/y\" = \"z\"").unwrap()[0].key, "a.b");
+        assert_eq!(error("\"a=b\" = 1"), "line 1: quoted keys containing '=' are not supported, in key 'a=b'");
+        assert_eq!(error("\"a\\\"b\" = 1"), "line 1: escape sequences in keys are not supported, in key 'a\\'");
+        assert_eq!(error("a.b = 1"), "line 1: dotted keys are not supported, quote the key 'a.b' instead");
+        assert_eq!(error("a b = 1"), "line 1: invalid key 'a b', keys with characters other than letters, digits, '_' and '-' must be quoted");
+        assert_eq!(error("[constants]\n[regex.options]"), "line 2: nested tables are not supported");
+        assert_eq!(error("[[shells]]"), "line 1: array tables are not supported");
+        assert_eq!(error("jobs = 1_000"), "line 1: '_' in integers is not supported, in '1_000'");
+        assert_eq!(error("a = { b = 1 }"), "line 1: inline tables are not supported");
+        assert_eq!(error("a = \"\"\"\nb\n\"\"\""), "line 1: multi-line strings are not supported");
+        assert_eq!(error("a = [\n\"b\",\n]"), "line 1: unterminated array, arrays must be on a single line");
+        assert_eq!(error("a = 1.5"), "line 1: invalid value '1.5'");
+    }
+
+    #[test]
+    fn applies_settings_to_config() {
+        let mut config = Config::default();
+        apply("show_context_lines = -1\n[constants]\nname = \"bob\"", &mut config).unwrap();
+
+        assert_eq!(config.truncate_output_context_to_number_of_lines, None);
+        assert_eq!(config.constants["name"], "bob");
+    }
+
+    #[test]
+    fn sets_comment_syntaxes() {
+        let mut config = Config::default();
+        apply("[comments]\nrs = [\"//\", \"/* */\"]", &mut config).unwrap();
+
+        let expected = crate::config::CommentSyntax::line("//").with_block_comment("/*", "*/");
+        assert_eq!(config.comment_syntaxes["rs"], expected);
+        assert!(apply("[comments]\nml = [\"(* * *)\"]", &mut config).unwrap_err().contains("invalid comment delimiters"));
+    }
+
+    #[test]
+    fn sets_directive_separators() {
+        let mut config = Config::default();
+        apply("directive_separator = \"=\"\n[directive_separators]\nlegacy = \">\"", &mut config).unwrap();
+
+        assert_eq!(config.directive_separator_of(std::path::Path::new("a.txt")), '=');
+        assert_eq!(config.directive_separator_of(std::path::Path::new("a.legacy")), '>');
+        assert!(apply("directive_separator = \"=>\"", &mut config).unwrap_err().contains("single character"));
+        assert!(apply("directive_separator = \"-\"", &mut config).unwrap_err().contains("punctuation"));
+    }
+
+    #[test]
+    fn enables_normalizer_presets() {
+        let mut config = Config::default();
+        apply("normalize_presets = [\"durations\", \"uuids\"]", &mut config).unwrap();
+
+        assert_eq!(config.output_normalizers.len(), 2);
+        assert_eq!(config.normalize_output("took 1.5s"), "took <duration>");
+        assert!(apply("normalize_presets = [\"dates\"]", &mut config).unwrap_err().contains("unknown normalizer preset 'dates'"));
+    }
+
+    #[test]
+    fn rejects_unknown_matchers() {
+        let mut config = Config::default();
+        apply("default_matcher = \"exact\"", &mut config).unwrap();
+
+        assert_eq!(config.default_matcher.as_deref(), Some("exact"));
+        assert_eq!(apply("default_matcher = \"exct\"", &mut config).unwrap_err(), "line 1: matcher 'exct' not known, did you mean 'exact'?");
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(apply("[bogus]\nfoo = 1", &mut Config::default()).unwrap_err().contains("bogus.foo"));
+        assert!(apply("[messages]\n\"fail.bogus\" = \"x\"", &mut Config::default()).unwrap_err().contains("fail.bogus"));
+    }
+
+    #[test]
+    fn overrides_messages() {
+        let mut config = Config::default();
+        apply("[messages]\n\"warning.no-tests-found\" = \"aucun test\"", &mut config).unwrap();
+
+        assert_eq!(config.messages.template(crate::messages::MessageId::NoTestsFound), "aucun test");
+    }
+}
@@ -0,0 +1,81 @@
+//! Loading of `CODEOWNERS`-style files mapping tests to their owners.
+//!
+//! Each line holds a path pattern, relative to the test root, followed by one
+//! or more owners. When several patterns match a test, the last one wins.
+//!
+//! ``` text
+//! # Everything is owned by the core team unless stated otherwise.
+//! *               core-team
+//! codegen/        backend-team
+//! codegen/*.ll    backend-team llvm-team
+//! ```
+
+use crate::util;
+use std::path::Path;
+
+/// A single line of an owners file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnershipRule {
+    /// The path pattern, where `*` matches any sequence of characters and a
+    /// trailing `/` matches everything beneath a directory.
+    pub pattern: String,
+    pub owners: Vec<String>,
+}
+
+/// Parses the text of an owners file.
+pub fn parse(text: &str) -> Result<Vec<OwnershipRule>, String> {
+    let mut rules = Vec::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() { continue; }
+
+        let mut words = line.split_whitespace();
+        let pattern = words.next().unwrap().trim_start_matches('/').to_owned();
+        let owners: Vec<String> = words.map(ToOwned::to_owned).collect();
+
+        if owners.is_empty() {
+            return Err(format!("line {}: pattern '{}' has no owners", line_idx + 1, pattern));
+        }
+
+        rules.push(OwnershipRule { pattern, owners });
+    }
+
+    Ok(rules)
+}
+
+/// Gets the owners of a test, given its path relative to the test root.
+pub fn owners_of<'a>(rules: &'a [OwnershipRule], relative_path: &Path) -> &'a [String] {
+    let relative_path = relative_path.to_string_lossy().replace('\\', "/");
+
+    rules.iter().rev()
+        .find(|rule| rule.matches(&relative_path))
+        .map(|rule| &rule.owners[..])
+        .unwrap_or(&[])
+}
+
+impl OwnershipRule {
+    fn matches(&self, relative_path: &str) -> bool {
+        if self.pattern.ends_with('/') {
+            util::glob_matches(&format!("{}*", self.pattern), relative_path)
+        } else {
+            util::glob_matches(&self.pattern, relative_path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let rules = parse("* core # fallback\n/codegen/ backend\ncodegen/*.ll backend llvm\n").unwrap();
+        let owners = |path: &str| owners_of(&rules, Path::new(path)).to_vec();
+
+        assert_eq!(owners("parser/basic.txt"), vec!["core"]);
+        assert_eq!(owners("codegen/add.c"), vec!["backend"]);
+        assert_eq!(owners("codegen/x86/add.ll"), vec!["backend", "llvm"]);
+        assert!(parse("codegen/\n").is_err());
+    }
+}
@@ -0,0 +1,62 @@
+//! Builtin output normalizers for commonly volatile values, enabled by name.
+//!
+//! Presets can be enabled for all tests via `Config::add_normalizer_preset`, or
+//! for a single test via a `NORMALIZE-PRESET` directive.
+
+use super::OutputNormalizer;
+use regex::Regex;
+
+/// The names of all builtin normalizer presets.
+pub const NAMES: &[&str] = &["timestamps", "durations", "addresses", "uuids"];
+
+/// Gets the normalizers of a preset, or `None` if there is no preset with the name.
+pub fn normalizers(name: &str) -> Option<Vec<OutputNormalizer>> {
+    let (pattern, replacement) = match name {
+        // ISO 8601 dates and times, such as `2020-01-02T03:04:05.678Z`.
+        "timestamps" => (r"\b\d{4}-\d{2}-\d{2}(?:[T ]\d{2}:\d{2}(?::\d{2}(?:[.,]\d+)?)?(?:Z|[+-]\d{2}:?\d{2})?)?\b", "<timestamp>"),
+        "durations" => (r"\b\d+(?:\.\d+)?(?:ns|us|µs|ms|s|m|h)\b", "<duration>"),
+        "addresses" => (r"\b0x[0-9a-fA-F]+\b", "<address>"),
+        "uuids" => (r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b", "<uuid>"),
+        _ => return None,
+    };
+
+    Some(vec![OutputNormalizer { pattern: Regex::new(pattern).unwrap(), replacement: replacement.to_owned() }])
+}
+
+/// Checks that a preset with the given name exists.
+pub fn validate(name: &str) -> Result<(), String> {
+    if NAMES.contains(&name) {
+        return Ok(());
+    }
+
+    match crate::util::closest_match(name, NAMES.iter().cloned()) {
+        Some(suggestion) => Err(format!("unknown normalizer preset '{}', did you mean '{}'?", name, suggestion)),
+        None => Err(format!("unknown normalizer preset '{}', expected one of {}", name, NAMES.join(", "))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn normalize(name: &str, text: &str) -> String {
+        normalizers(name).unwrap().iter().fold(text.to_owned(), |text, n| n.pattern.replace_all(&text, &n.replacement[..]).into_owned())
+    }
+
+    #[test]
+    fn scrubs_volatile_values() {
+        assert_eq!(normalize("timestamps", "at 2020-01-02T03:04:05.678Z and 2020-01-02 03:04:05+01:00 on 2020-01-02"),
+                   "at <timestamp> and <timestamp> on <timestamp>");
+        assert_eq!(normalize("durations", "took 1.23s (450ms), 12 tests"), "took <duration> (<duration>), 12 tests");
+        assert_eq!(normalize("addresses", "ptr 0x7ffd5e8c and 0xDEAD"), "ptr <address> and <address>");
+        assert_eq!(normalize("uuids", "id 123e4567-e89b-12d3-a456-426614174000."), "id <uuid>.");
+    }
+
+    #[test]
+    fn every_name_has_a_preset() {
+        for name in NAMES {
+            assert!(normalizers(name).is_some(), "no normalizers for '{}'", name);
+        }
+        assert_eq!(validate("timestamp").unwrap_err(), "unknown normalizer preset 'timestamp', did you mean 'timestamps'?");
+    }
+}
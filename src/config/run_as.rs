@@ -0,0 +1,214 @@
+//! Running the commands of tests as a different user than the one running lit.
+//!
+//! This lets suites that must not run as root, or that must run as a specific
+//! service user, be handled by the harness rather than by `su` in every test.
+//!
+//! ```no_run
+//! let mut config = lit::Config::default();
+//! config.run_as = Some(lit::config::RunAs::user("nobody").unwrap());
+//! ```
+
+use std::process;
+
+/// How the commands of tests are run as another user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunAs {
+    /// Switch to a user, along with their groups, right before executing each command.
+    ///
+    /// lit must run as root, unless it already runs as the user. Only supported on Unix.
+    User {
+        name: String,
+        uid: u32,
+        gid: u32,
+        /// The supplementary groups of the user.
+        groups: Vec<u32>,
+    },
+    /// Prefix every command with a wrapper that changes user, such as `sudo -n -u postgres --`.
+    ///
+    /// The wrapper decides which environment variables are passed on to the command.
+    Wrapper(Vec<String>),
+}
+
+impl RunAs {
+    /// Looks up the user to switch to, checking that lit is able to switch to them.
+    #[cfg(unix)]
+    pub fn user(name: &str) -> Result<Self, String> {
+        use std::ffi::CString;
+
+        let c_name = CString::new(name).map_err(|_| format!("invalid user name '{}'", name))?;
+
+        // Users are looked up while lit is configured, before any tests run on other
+        // threads, so the shared result buffer of `getpwnam` is not overwritten.
+        let (uid, gid) = unsafe {
+            let passwd = libc::getpwnam(c_name.as_ptr());
+            if passwd.is_null() {
+                return Err(format!("cannot run tests as '{}': no such user", name));
+            }
+            ((*passwd).pw_uid, (*passwd).pw_gid)
+        };
+
+        let mut groups: Vec<libc::gid_t> = vec![0; 64];
+        loop {
+            let mut count = groups.len() as libc::c_int;
+            let result = unsafe { libc::getgrouplist(c_name.as_ptr(), gid as _, groups.as_mut_ptr() as *mut _, &mut count) };
+
+            if result >= 0 {
+                groups.truncate(count as usize);
+                break;
+            }
+            groups.resize((count as usize).max(groups.len() * 2), 0);
+        }
+
+        let euid = unsafe { libc::geteuid() };
+        if euid != 0 && euid != uid {
+            return Err(format!("cannot run tests as '{}': lit must run as root to switch users", name));
+        }
+
+        Ok(RunAs::User { name: name.to_owned(), uid, gid, groups })
+    }
+
+    #[cfg(not(unix))]
+    pub fn user(name: &str) -> Result<Self, String> {
+        Err(format!("cannot run tests as '{}': switching users is only supported on Unix", name))
+    }
+
+    /// Creates a wrapper from a command line prefix, such as `sudo -n -u postgres --`.
+    pub fn wrapper(prefix: &str) -> Result<Self, String> {
+        let words: Vec<String> = prefix.split_whitespace().map(ToOwned::to_owned).collect();
+
+        match words.is_empty() {
+            true => Err("the command wrapper used to run tests as another user is empty".to_owned()),
+            false => Ok(RunAs::Wrapper(words)),
+        }
+    }
+
+    /// Describes who commands are run as, such as in error messages.
+    pub fn description(&self) -> String {
+        match *self {
+            RunAs::User { ref name, .. } => format!("user '{}'", name),
+            RunAs::Wrapper(ref words) => format!("wrapper '{}'", words.join(" ")),
+        }
+    }
+
+    /// Makes a command run as the user.
+    ///
+    /// The working directory and environment variables already set on the command are
+    /// carried over to the wrapper, which decides whether to pass them on.
+    pub(crate) fn apply(&self, command: process::Command) -> process::Command {
+        match *self {
+            RunAs::User { uid, gid, ref groups, .. } => self::switch_user(command, uid, gid, groups.clone()),
+            RunAs::Wrapper(ref words) => {
+                let mut wrapped = process::Command::new(&words[0]);
+                wrapped.args(&words[1..]).arg(command.get_program()).args(command.get_args());
+
+                if let Some(directory) = command.get_current_dir() {
+                    wrapped.current_dir(directory);
+                }
+                for (name, value) in command.get_envs() {
+                    match value {
+                        Some(value) => wrapped.env(name, value),
+                        None => wrapped.env_remove(name),
+                    };
+                }
+                wrapped
+            },
+        }
+    }
+}
+
+#[cfg(unix)]
+fn switch_user(mut command: process::Command, uid: u32, gid: u32, groups: Vec<u32>) -> process::Command {
+    use std::os::unix::process::CommandExt;
+
+    // Only root may change its groups, so a user running lit as themselves keeps theirs.
+    if unsafe { libc::geteuid() } == uid && uid != 0 {
+        return command;
+    }
+
+    // Groups must be changed first, as they can no longer be changed once the user has.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setgroups(groups.len() as _, groups.as_ptr() as *const _) != 0 ||
+                libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command
+}
+
+#[cfg(not(unix))]
+fn switch_user(command: process::Command, _: u32, _: u32, _: Vec<u32>) -> process::Command {
+    // Users can only be created by `RunAs::user`, which fails on other platforms.
+    command
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(unix)]
+    use crate::{model::*, run::execute_test_file, Config};
+
+    #[test]
+    fn wrappers_prefix_commands() {
+        let mut command = process::Command::new("sh");
+        command.args(&["-c", "true"]);
+
+        let wrapped = RunAs::wrapper("sudo -n -u postgres --").unwrap().apply(command);
+        let words: Vec<_> = std::iter::once(wrapped.get_program()).chain(wrapped.get_args()).map(|w| w.to_string_lossy().into_owned()).collect();
+
+        assert_eq!(words, vec!["sudo", "-n", "-u", "postgres", "--", "sh", "-c", "true"]);
+        assert!(RunAs::wrapper("  ").is_err());
+    }
+
+    #[test]
+    fn wrappers_keep_the_environment_of_commands() {
+        let mut command = process::Command::new("true");
+        command.env("LIT_SET", "1").env_remove("LIT_REMOVED");
+
+        let wrapped = RunAs::wrapper("env").unwrap().apply(command);
+        let envs: Vec<_> = wrapped.get_envs().collect();
+
+        assert!(envs.contains(&("LIT_SET".as_ref(), Some("1".as_ref()))));
+        assert!(envs.contains(&("LIT_REMOVED".as_ref(), None)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn users_running_lit_as_themselves_keep_their_groups() {
+        let uid = unsafe { libc::geteuid() };
+        // A group the user is unlikely to belong to, which only root could switch to.
+        let run_as = RunAs::User { name: "self".to_owned(), uid, gid: unsafe { libc::getegid() }, groups: vec![65533] };
+
+        let output = run_as.apply(process::Command::new("id")).arg("-u").output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{}\n", uid));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unknown_users_are_rejected() {
+        assert_eq!(RunAs::user("lit-nonexistent-user"), Err("cannot run tests as 'lit-nonexistent-user': no such user".to_owned()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn commands_run_as_the_configured_user() {
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/user.txt", "user.txt"))
+            .with_command(CommandKind::Run(Invocation::new("echo \"$LIT_USER $(id -u)\"")));
+        let stdout_with = |run_as| {
+            let config = Config { shell: "sh".to_owned(), run_as: Some(run_as), ..Config::default() };
+            execute_test_file(&test_file, &config).individual_run_results[0].output.stdout.to_string()
+        };
+
+        assert_eq!(stdout_with(RunAs::wrapper("env LIT_USER=wrapped").unwrap()), format!("wrapped {}\n", unsafe { libc::geteuid() }));
+
+        // Only root can switch to another user.
+        if unsafe { libc::geteuid() } == 0 {
+            let nobody = RunAs::user("nobody").unwrap();
+            let uid = match nobody { RunAs::User { uid, .. } => uid, _ => unreachable!() };
+            assert_eq!(stdout_with(nobody), format!(" {}\n", uid));
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! A minimal line-based diff renderer.
+
+/// A single line in a computed diff.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    /// The line is present in both texts.
+    Same(&'a str),
+    /// The line only exists in the expected text.
+    Removed(&'a str),
+    /// The line only exists in the actual text.
+    Added(&'a str),
+}
+
+/// Computes the line diff between two pieces of text, using the
+/// longest common subsequence of their lines.
+pub fn lines<'a>(expected: &'a str, actual: &'a str) -> Vec<DiffLine<'a>> {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+
+    // lcs_lengths[i][j] is the LCS length of expected[i..] and actual[j..].
+    let mut lcs_lengths = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs_lengths[i][j] = if expected[i] == actual[j] {
+                lcs_lengths[i + 1][j + 1] + 1
+            } else {
+                lcs_lengths[i + 1][j].max(lcs_lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut diff = Vec::new();
+    while i < expected.len() && j < actual.len() {
+        if expected[i] == actual[j] {
+            diff.push(DiffLine::Same(expected[i]));
+            i += 1;
+            j += 1;
+        } else if lcs_lengths[i + 1][j] >= lcs_lengths[i][j + 1] {
+            diff.push(DiffLine::Removed(expected[i]));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(actual[j]));
+            j += 1;
+        }
+    }
+    diff.extend(expected[i..].iter().map(|l| DiffLine::Removed(l)));
+    diff.extend(actual[j..].iter().map(|l| DiffLine::Added(l)));
+
+    diff
+}
+
+/// Renders a unified-style line diff, prefixing each line with
+/// `-`, `+`, or a space.
+pub fn render(expected: &str, actual: &str) -> String {
+    self::lines(expected, actual).into_iter().map(|line| match line {
+        DiffLine::Same(l) => format!("  {}", l),
+        DiffLine::Removed(l) => format!("- {}", l),
+        DiffLine::Added(l) => format!("+ {}", l),
+    }).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identical_text_has_no_changes() {
+        assert_eq!(lines("a\nb", "a\nb"), vec![DiffLine::Same("a"), DiffLine::Same("b")]);
+    }
+
+    #[test]
+    fn changed_line_is_removed_then_added() {
+        assert_eq!(render("a\nb\nc", "a\nx\nc"), "  a\n- b\n+ x\n  c");
+    }
+
+    #[test]
+    fn trailing_lines_are_reported() {
+        assert_eq!(lines("a", "a\nb"), vec![DiffLine::Same("a"), DiffLine::Added("b")]);
+        assert_eq!(lines("a\nb", ""), vec![DiffLine::Removed("a"), DiffLine::Removed("b")]);
+    }
+}
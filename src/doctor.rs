@@ -0,0 +1,196 @@
+//! Diagnostics of the environment that tests run in, as shown by `lit doctor`.
+//!
+//! Each check either passes, or describes what is wrong and how to fix it, so that
+//! problems with the environment are found before they show up as confusing test failures.
+//!
+//! ```
+//! let config = lit::Config::default();
+//!
+//! for diagnostic in lit::doctor::diagnose(&config) {
+//!     println!("{}", diagnostic);
+//! }
+//! ```
+
+use crate::Config;
+use std::{env, fmt, fs, path::{Path, PathBuf}, process};
+
+/// The outcome of a single check of the environment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// What was checked, such as `shell 'bash'`.
+    pub subject: String,
+    pub status: Status,
+    /// What was found, or what is wrong and how to fix it.
+    pub detail: String,
+}
+
+/// How serious the outcome of a check is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    /// Tests can run, but not as well as they could.
+    Warning,
+    /// Tests are likely to fail because of the environment.
+    Error,
+}
+
+/// Checks the environment described by a config.
+pub fn diagnose(config: &Config) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![Diagnostic::ok("lit", format!("version {}", env!("CARGO_PKG_VERSION")))];
+
+    let mut shells = vec![&config.shell];
+    shells.extend(config.shell_matrix.iter().filter(|&shell| *shell != config.shell));
+    for shell in shells {
+        diagnostics.push(self::shell(shell, config));
+    }
+
+    for tool in config.executor.required_tools().iter().chain(config.required_tools.iter()) {
+        let subject = format!("tool '{}'", tool);
+
+        diagnostics.push(match self::find_executable(tool, config) {
+            Some(path) => Diagnostic::ok(subject, path.display().to_string()),
+            None => Diagnostic::error(subject, format!("could not find '{}', install it or add its directory to the PATH", tool)),
+        });
+    }
+
+    if let Some(ref helper_executable) = config.helper_executable {
+        diagnostics.push(match helper_executable.is_file() {
+            true => Diagnostic::ok("helper executable", helper_executable.display().to_string()),
+            false => Diagnostic::error("helper executable", format!("'{}' does not exist, so helpers such as '@not' will fail", helper_executable.display())),
+        });
+    }
+
+    let temp_root = config.temp_root.clone().unwrap_or_else(env::temp_dir);
+    diagnostics.push(self::writable_directory("temp root", &temp_root));
+    if let Some(ref artifacts_directory) = config.save_artifacts_to_directory {
+        diagnostics.push(self::writable_directory("artifacts directory", artifacts_directory));
+    }
+
+    diagnostics.push(self::color());
+    diagnostics
+}
+
+/// Checks that a shell can be found, and gets its version if it has one.
+fn shell(shell: &str, config: &Config) -> Diagnostic {
+    let subject = format!("shell '{}'", shell);
+    let path = match self::find_executable(shell, config) {
+        Some(path) => path,
+        None => return Diagnostic::error(subject, format!("could not find '{}', install it or configure a different shell", shell)),
+    };
+
+    match self::version(&path) {
+        Some(version) => Diagnostic::ok(subject, format!("{} ({})", path.display(), version)),
+        None => Diagnostic::ok(subject, path.display().to_string()),
+    }
+}
+
+/// Gets the first line that an executable prints when run with `--version`, if it succeeds.
+pub(crate) fn version(path: &Path) -> Option<String> {
+    process::Command::new(path).arg("--version").stdin(process::Stdio::null()).output().ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).lines().find(|l| !l.trim().is_empty()).map(|l| l.trim().to_owned()))
+}
+
+/// Checks that files can be created in a directory, creating the directory if necessary.
+fn writable_directory(subject: &str, directory: &Path) -> Diagnostic {
+    let result = fs::create_dir_all(directory).and_then(|()| tempfile::tempfile_in(directory));
+
+    match result {
+        Ok(..) => Diagnostic::ok(subject, directory.display().to_string()),
+        Err(e) => Diagnostic::error(subject, format!("could not create files in '{}': {}", directory.display(), e)),
+    }
+}
+
+#[cfg(feature = "color")]
+fn color() -> Diagnostic {
+    use termcolor::WriteColor;
+
+    match termcolor::StandardStream::stdout(termcolor::ColorChoice::Auto).supports_color() {
+        true => Diagnostic::ok("color", "supported"),
+        false => Diagnostic::warning("color", "stdout is not a terminal that supports color, so output will not be colored"),
+    }
+}
+
+#[cfg(not(feature = "color"))]
+fn color() -> Diagnostic {
+    Diagnostic::warning("color", "lit was built without the 'color' feature, so output will not be colored")
+}
+
+/// Finds an executable in the extra executable search paths of a config, or the `PATH`.
+///
+/// Names containing a path separator are only checked for existence.
+pub(crate) fn find_executable(name: &str, config: &Config) -> Option<PathBuf> {
+    if Path::new(name).components().count() > 1 {
+        return Some(PathBuf::from(name)).filter(|path| path.is_file());
+    }
+
+    let path_directories = env::var_os("PATH").map(|path| env::split_paths(&path).collect::<Vec<_>>()).unwrap_or_default();
+    let extensions: &[&str] = if cfg!(windows) { &["", "exe", "bat", "cmd"] } else { &[""] };
+
+    config.extra_executable_search_paths.iter().chain(path_directories.iter())
+        .flat_map(|directory| extensions.iter().map(move |extension| match *extension {
+            "" => directory.join(name),
+            extension => directory.join(format!("{}.{}", name, extension)),
+        }))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Checks if any of the diagnostics are errors.
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.status == Status::Error)
+}
+
+impl Diagnostic {
+    fn ok(subject: impl Into<String>, detail: impl Into<String>) -> Self {
+        Diagnostic { subject: subject.into(), status: Status::Ok, detail: detail.into() }
+    }
+
+    fn warning(subject: impl Into<String>, detail: impl Into<String>) -> Self {
+        Diagnostic { subject: subject.into(), status: Status::Warning, detail: detail.into() }
+    }
+
+    fn error(subject: impl Into<String>, detail: impl Into<String>) -> Self {
+        Diagnostic { subject: subject.into(), status: Status::Error, detail: detail.into() }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self.status {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+        };
+
+        write!(fmt, "[{}] {}: {}", label, self.subject, self.detail)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn reports_missing_tools_and_unwritable_directories() {
+        let temp_root = tempfile::tempdir().unwrap();
+        let config = Config {
+            shell: "sh".to_owned(),
+            required_tools: vec!["sh".to_owned(), "lit-doctor-nonexistent-tool".to_owned()],
+            temp_root: Some(temp_root.path().join("created")),
+            save_artifacts_to_directory: Some(PathBuf::from("/dev/null/artifacts")),
+            ..Config::default()
+        };
+
+        let diagnostics = diagnose(&config);
+        let status_of = |subject: &str| diagnostics.iter().find(|d| d.subject == subject).map(|d| d.status);
+
+        assert_eq!(status_of("shell 'sh'"), Some(Status::Ok));
+        assert_eq!(status_of("tool 'sh'"), Some(Status::Ok));
+        assert_eq!(status_of("tool 'lit-doctor-nonexistent-tool'"), Some(Status::Error));
+        assert_eq!(status_of("temp root"), Some(Status::Ok));
+        assert!(temp_root.path().join("created").is_dir());
+        assert_eq!(status_of("artifacts directory"), Some(Status::Error));
+        assert!(has_errors(&diagnostics));
+    }
+}
@@ -0,0 +1,147 @@
+//! Embedding of test suites into `cargo test`, with one `#[test]` per test file.
+//!
+//! A build script generates the tests:
+//!
+//! ``` no_run
+//! // in build.rs, inside `fn main`
+//! lit::embed::generate_tests("tests/lit", &["txt"]).unwrap();
+//! ```
+//!
+//! And the `lit_tests!` macro includes them into a test crate, optionally with
+//! a function to customize the config:
+//!
+//! ``` ignore
+//! // tests/lit.rs
+//! lit::lit_tests!(|config| {
+//!     config.constants.insert("cc".to_owned(), "clang".to_owned());
+//! });
+//! ```
+
+use crate::{event_handler::{EventHandler, SuiteProgress}, model::*, run, Config};
+use std::{env, fs, io, path::{Path, PathBuf}};
+
+/// The name of the generated file within `OUT_DIR`.
+pub const GENERATED_FILE_NAME: &str = "lit_tests.rs";
+
+/// Includes the tests generated by `embed::generate_tests`, one `#[test]` per file.
+///
+/// Accepts an optional, non-capturing closure that customizes the config of every test.
+#[macro_export]
+macro_rules! lit_tests {
+    () => {
+        $crate::lit_tests!(|_| ());
+    };
+    ($configure:expr) => {
+        fn __lit_configure(config: &mut $crate::Config) {
+            let configure: fn(&mut $crate::Config) = $configure;
+            configure(config)
+        }
+
+        include!(concat!(env!("OUT_DIR"), "/lit_tests.rs"));
+    };
+}
+
+/// Generates a `#[test]` function for every test file underneath a directory.
+///
+/// Must be called from a build script. The generated file is written to `OUT_DIR`,
+/// and included via `lit_tests!`.
+pub fn generate_tests(test_directory: impl AsRef<Path>, extensions: &[&str]) -> io::Result<()> {
+    let out_dir = env::var_os("OUT_DIR").expect("lit::embed::generate_tests must be called from a build script");
+
+    let mut config = Config::default();
+    config.add_search_path(test_directory.as_ref().display().to_string());
+    config.add_extensions(extensions);
+
+    let test_paths = run::find_files::on_disk(&config)
+        .map_err(io::Error::other)?;
+
+    println!("cargo:rerun-if-changed={}", test_directory.as_ref().display());
+    for test_path in test_paths.iter() {
+        println!("cargo:rerun-if-changed={}", test_path.absolute.display());
+    }
+
+    fs::write(PathBuf::from(out_dir).join(GENERATED_FILE_NAME), self::generated_source(&test_paths))
+}
+
+/// Runs a single embedded test, panicking if it does not succeed.
+///
+/// This is called by the tests generated via `lit_tests!`.
+#[doc(hidden)]
+pub fn run_test(relative_path: &str, absolute_path: &str, text: &str, configure: fn(&mut Config)) {
+    let mut config = Config::default();
+    configure(&mut config);
+
+    let test_file = match crate::parse::test_file_for_config(TestFilePath::new(absolute_path, relative_path), text, &config) {
+        Ok(test_file) => test_file,
+        Err(e) => panic!("could not parse '{}': {}", relative_path, e),
+    };
+
+    let result = run::execute_test_file(&test_file, &config);
+    let is_erroneous = result.overall_result.is_erroneous();
+    let progress = SuiteProgress { completed: 1, remaining: 0, failures: is_erroneous as usize, elapsed: result.duration };
+
+    crate::event_handler::Default::new().on_test_finished(result, &progress, &config);
+
+    if is_erroneous {
+        panic!("lit test '{}' failed", relative_path);
+    }
+}
+
+fn generated_source(test_paths: &[TestFilePath]) -> String {
+    let mut source = String::new();
+
+    for test_path in test_paths {
+        source.push_str(&format!(
+            "#[test]\n#[allow(non_snake_case)]\nfn {}() {{\n    ::lit::embed::run_test({:?}, {:?}, include_str!({:?}), __lit_configure);\n}}\n\n",
+            self::function_name(&test_path.relative), test_path.relative.display().to_string(),
+            test_path.absolute.display().to_string(), test_path.absolute.display().to_string()));
+    }
+
+    source
+}
+
+/// Derives a valid Rust identifier from the path of a test.
+///
+/// Every other character is escaped with a distinct sequence starting with an
+/// underscore, so that no two paths map to the same name.
+fn function_name(relative_path: &Path) -> String {
+    let mut name = "lit_".to_owned();
+
+    for c in relative_path.display().to_string().chars() {
+        match c {
+            c if c.is_ascii_alphanumeric() => name.push(c),
+            '/' | '\\' => name.push_str("__"),
+            '.' => name.push_str("_dot_"),
+            '-' => name.push_str("_dash_"),
+            '_' => name.push_str("_u_"),
+            c => name.push_str(&format!("_x{:x}_", c as u32)),
+        }
+    }
+
+    name
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn generates_one_test_per_file() {
+        let source = generated_source(&[
+            TestFilePath::new("/suite/a-b/c.txt", "a-b/c.txt"),
+            TestFilePath::new("/suite/a_b/c.txt", "a_b/c.txt"),
+        ]);
+
+        assert!(source.contains("fn lit_a_dash_b__c_dot_txt() {"));
+        assert!(source.contains("fn lit_a_u_b__c_dot_txt() {"));
+        assert!(source.contains(r#"::lit::embed::run_test("a-b/c.txt", "/suite/a-b/c.txt", include_str!("/suite/a-b/c.txt"), __lit_configure);"#));
+    }
+
+    #[test]
+    fn names_tests_uniquely_by_their_full_path() {
+        let paths = ["c.txt", "c_txt", "c_dot_txt", "c.txt_1", "c_txt_1", "a/c.txt", "a_c.txt", "A.txt", "a.txt", "c d.txt"];
+        let names: std::collections::HashSet<_> = paths.iter().map(|path| function_name(Path::new(path))).collect();
+
+        assert_eq!(names.len(), paths.len());
+    }
+}
@@ -2,25 +2,55 @@
 //!
 //! All "UI" logic is driven through the `EventHandler` trait.
 
+pub(crate) use self::broadcast::Broadcast;
 pub use self::default::EventHandler as Default;
+#[cfg(feature = "json")] pub use self::json_lines::EventHandler as JsonLines;
 
-use crate::{Config, model::{TestResult}};
+use crate::{Config, model::{ConfigurationDifference, TestFilePath, TestResult}};
+use std::time::Duration;
 
+mod broadcast;
 mod default;
+#[cfg(feature = "json")] pub(crate) mod json_lines;
 
 /// An object which listens to events that occur during a test suite run.
+///
+/// Every event is ignored by default, so handlers only implement the ones they need.
 pub trait EventHandler {
     /// Called to notify before the test suite has started.
-    fn on_test_suite_started(&mut self, suite_details: &TestSuiteDetails, config: &Config);
+    fn on_test_suite_started(&mut self, _suite_details: &TestSuiteDetails, _config: &Config) { }
 
     /// Called to notify when the entire test suite has finished execution.
-    fn on_test_suite_finished(&mut self, passed: bool, config: &Config);
+    fn on_test_suite_finished(&mut self, _passed: bool, _config: &Config) { }
+
+    /// Called with each line that `Config::build_command` writes to stdout or stderr.
+    fn on_build_output(&mut self, _line: &str, _config: &Config) { }
+
+    /// Called to notify when a test has started executing.
+    fn on_test_started(&mut self, _test_file_path: &TestFilePath, _config: &Config) { }
+
+    /// Called to notify when a test has been running for another `Config::heartbeat_interval`
+    /// without finishing.
+    fn on_test_still_running(&mut self, _test_file_path: &TestFilePath, _elapsed: Duration, _config: &Config) { }
+
+    /// Called when a test has been executed, before its result is reported or counted
+    /// towards the outcome of the suite.
+    ///
+    /// The result may be modified, for example to mark a known failure as expected.
+    /// Artifacts have already been saved with the original result.
+    fn on_test_result_pending(&mut self, _result: &mut TestResult, _config: &Config) { }
 
     /// Called to notify when a test has been executed.
-    fn on_test_finished(&mut self, result: TestResult, config: &Config);
+    ///
+    /// The progress of the suite already includes the finished test.
+    fn on_test_finished(&mut self, _result: TestResult, _progress: &SuiteProgress, _config: &Config) { }
 
     /// Called to notify about a nonfatal warning.
-    fn note_warning(&mut self, message: &str);
+    fn note_warning(&mut self, _message: &str) { }
+
+    /// Called before the suite finishes with all tests that behaved differently
+    /// under `Config::comparison_config`, if one is set.
+    fn on_configuration_differences(&mut self, _differences: &[ConfigurationDifference], _config: &Config) { }
 }
 
 /// Stores details about the test suite.
@@ -28,5 +58,52 @@ pub trait EventHandler {
 pub struct TestSuiteDetails {
     /// The number of test files in the suite.
     pub number_of_test_files: usize,
+    /// The absolute path of the directory artifacts are saved to, if any.
+    pub artifacts_directory: Option<std::path::PathBuf>,
+    /// The seed substituted for `@seed` in every test.
+    pub seed: u64,
+    /// The host, lit version, shells and tools that the suite runs with.
+    pub environment: crate::run::environment::Environment,
+}
+
+/// How far along a test suite is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SuiteProgress {
+    /// The number of tests that have finished.
+    pub completed: usize,
+    /// The number of tests that have not finished yet.
+    pub remaining: usize,
+    /// The number of finished tests that failed.
+    pub failures: usize,
+    /// The time since the suite started executing tests.
+    pub elapsed: Duration,
+}
+
+impl SuiteProgress {
+    /// Gets the total number of tests in the suite.
+    ///
+    /// Each test file counts once per shell in `Config::shell_matrix`.
+    pub fn total(&self) -> usize {
+        self.completed + self.remaining
+    }
+
+    /// Gets the percentage of tests that have finished.
+    pub fn percentage(&self) -> f64 {
+        match self.total() {
+            0 => 100.0,
+            total => self.completed as f64 * 100.0 / total as f64,
+        }
+    }
+
+    /// Estimates the time until the suite finishes, assuming the remaining tests take as long
+    /// as the finished ones on average.
+    ///
+    /// Returns `None` until at least one test has finished.
+    pub fn estimated_time_remaining(&self) -> Option<Duration> {
+        match self.completed {
+            0 => None,
+            completed => Some(self.elapsed.mul_f64(self.remaining as f64 / completed as f64)),
+        }
+    }
 }
 
@@ -0,0 +1,73 @@
+//! An event handler that forwards every event to several other event handlers.
+
+use super::{EventHandler, SuiteProgress, TestSuiteDetails};
+use crate::{Config, model::{ConfigurationDifference, TestFilePath, TestResult}};
+use std::time::Duration;
+
+/// Forwards events to each of its event handlers, in order.
+pub struct Broadcast<'a> {
+    event_handlers: Vec<Box<dyn EventHandler + 'a>>,
+}
+
+impl<'a> Broadcast<'a> {
+    /// Creates a new broadcasting event handler.
+    pub fn new(event_handlers: Vec<Box<dyn EventHandler + 'a>>) -> Self {
+        Broadcast { event_handlers }
+    }
+}
+
+impl EventHandler for Broadcast<'_> {
+    fn on_test_suite_started(&mut self, suite_details: &TestSuiteDetails, config: &Config) {
+        for event_handler in self.event_handlers.iter_mut() {
+            event_handler.on_test_suite_started(suite_details, config);
+        }
+    }
+
+    fn on_test_suite_finished(&mut self, passed: bool, config: &Config) {
+        for event_handler in self.event_handlers.iter_mut() {
+            event_handler.on_test_suite_finished(passed, config);
+        }
+    }
+
+    fn on_build_output(&mut self, line: &str, config: &Config) {
+        for event_handler in self.event_handlers.iter_mut() {
+            event_handler.on_build_output(line, config);
+        }
+    }
+
+    fn on_test_started(&mut self, test_file_path: &TestFilePath, config: &Config) {
+        for event_handler in self.event_handlers.iter_mut() {
+            event_handler.on_test_started(test_file_path, config);
+        }
+    }
+
+    fn on_test_still_running(&mut self, test_file_path: &TestFilePath, elapsed: Duration, config: &Config) {
+        for event_handler in self.event_handlers.iter_mut() {
+            event_handler.on_test_still_running(test_file_path, elapsed, config);
+        }
+    }
+
+    fn on_test_result_pending(&mut self, result: &mut TestResult, config: &Config) {
+        for event_handler in self.event_handlers.iter_mut() {
+            event_handler.on_test_result_pending(result, config);
+        }
+    }
+
+    fn on_test_finished(&mut self, result: TestResult, progress: &SuiteProgress, config: &Config) {
+        for event_handler in self.event_handlers.iter_mut() {
+            event_handler.on_test_finished(result.clone(), progress, config);
+        }
+    }
+
+    fn note_warning(&mut self, message: &str) {
+        for event_handler in self.event_handlers.iter_mut() {
+            event_handler.note_warning(message);
+        }
+    }
+
+    fn on_configuration_differences(&mut self, differences: &[ConfigurationDifference], config: &Config) {
+        for event_handler in self.event_handlers.iter_mut() {
+            event_handler.on_configuration_differences(differences, config);
+        }
+    }
+}
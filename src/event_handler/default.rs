@@ -1,19 +1,41 @@
-use crate::{util, Config, model::*};
+use crate::{format::{self, OutputOptions, TruncateDirection}, Config, config::{ErrorFormat, MessageFormat}, model::*};
 
 use itertools::Itertools;
-use std::io;
-use std::io::prelude::*;
-use term;
 
 /// The default event handler, logging to stdout/stderr.
+///
+/// It prints nothing but warnings unless `Config::message_format` is `MessageFormat::Human`,
+/// so that its output does not get mixed into that of the requested format.
 pub struct EventHandler {
     test_results: Vec<TestResult>,
+    artifacts_directory: Option<std::path::PathBuf>,
+    writer: print::Writer,
 }
 
 impl EventHandler {
     /// Creates a new default event handler.
+    #[cfg(feature = "color")]
     pub fn new() -> Self {
-        EventHandler { test_results: Vec::new() }
+        EventHandler::with_writers(termcolor::StandardStream::stdout(termcolor::ColorChoice::Auto), termcolor::StandardStream::stderr(termcolor::ColorChoice::Auto))
+    }
+
+    /// Creates a new default event handler.
+    #[cfg(not(feature = "color"))]
+    pub fn new() -> Self {
+        EventHandler::with_writers(std::io::stdout(), std::io::stderr())
+    }
+
+    /// Creates a new default event handler that writes to the given streams in place of
+    /// stdout and stderr, such as to show the output in a GUI.
+    ///
+    /// The streams are `termcolor::WriteColor`s with the `color` feature, and plain
+    /// `std::io::Write`s without it.
+    pub fn with_writers(stdout: impl print::Stream + 'static, stderr: impl print::Stream + 'static) -> Self {
+        EventHandler {
+            test_results: Vec::new(),
+            artifacts_directory: None,
+            writer: print::Writer { stdout: Box::new(stdout), stderr: Box::new(stderr) },
+        }
     }
 }
 
@@ -24,270 +46,701 @@ impl std::default::Default for EventHandler {
 }
 
 impl super::EventHandler for EventHandler {
-    fn on_test_suite_started(&mut self, suite_details: &super::TestSuiteDetails, _: &Config) {
-        print::reset_colors(); // our white might not match initial console white. we should be consistent.
-
-        print::line();
-        print::horizontal_rule();
-        print::textln(format!("Running tests ({} files)", suite_details.number_of_test_files));
-        print::horizontal_rule();
-        print::line();
+    fn on_test_suite_started(&mut self, suite_details: &super::TestSuiteDetails, config: &Config) {
+        if config.message_format != MessageFormat::Human { return; }
+        self.writer.reset_colors(); // our white might not match initial console white. we should be consistent.
+        self.artifacts_directory = suite_details.artifacts_directory.clone();
+
+        self.writer.line();
+        self.writer.horizontal_rule();
+        self.writer.textln(format!("Running tests ({} files)", suite_details.number_of_test_files));
+        self.writer.horizontal_rule();
+        self.writer.line();
     }
 
     fn on_test_suite_finished(&mut self, passed: bool, config: &Config) {
+        if config.message_format != MessageFormat::Human { return; }
         // Sort the test results so that they will be consecutive.
         // This is required for itertools group_by used before to work properly.
         self.test_results.sort_by_key(|r| r.overall_result.human_label_pluralized());
 
-        print::line();
-        print::textln("finished running tests");
-        print::test_suite_status_message(passed, false, &self.test_results);
-        print::line();
-        print::horizontal_rule();
-        print::horizontal_rule();
-        print::line();
+        self.writer.line();
+        self.writer.textln("finished running tests");
+        self.writer.test_suite_status_message(passed, false, &self.test_results);
+        self.writer.line();
+        self.writer.horizontal_rule();
+        self.writer.horizontal_rule();
+        self.writer.line();
 
         if !passed {
             let failed_results = self.test_results.iter().filter(|r| r.overall_result.is_erroneous()).collect::<Vec<_>>();
 
-            print::line();
-            print::textln_colored(format!("Failing tests ({}/{}):", failed_results.len(), self.test_results.len()), print::YELLOW);
-            print::line();
+            self.writer.line();
+            self.writer.textln_colored(format!("Failing tests ({}/{}):", failed_results.len(), self.test_results.len()), print::Color::Yellow);
+            self.writer.line();
+
+            if failed_results.iter().any(|r| !r.owners.is_empty()) {
+                for (owner, owned_results) in failures_by_owner(&failed_results) {
+                    self.writer.textln(format!("  {} ({}):", owner.unwrap_or("no owner"), owned_results.len()));
+
+                    for failed_test_result in owned_results {
+                        self.writer.with("    ", print::StdStream::Err, print::Color::Red); // indent the errors.
+                        self::result(&mut self.writer, failed_test_result, false, config);
+                    }
+                }
+            } else {
+                for failed_test_result in failed_results.iter() {
+                    self.writer.with("  ", print::StdStream::Err, print::Color::Red); // indent the errors.
+                    self::result(&mut self.writer, failed_test_result, false, config);
+                }
+            }
+
+            let common_failures = common_failure_signatures(&failed_results);
+            if !common_failures.is_empty() {
+                self.writer.line();
+                self.writer.textln_colored("Common failures:", print::Color::Yellow);
+
+                for (signature, count) in common_failures {
+                    self.writer.textln(format!("  {} tests failed with: {}", count, signature));
+                }
+            }
+
+            if let Some(seed) = config.seed {
+                self.writer.line();
+                self.writer.textln(format!("Seed: {} (replay with --seed {})", seed, seed));
+            }
+        }
+
+        if !config.shell_matrix.is_empty() {
+            self.writer.line();
+            self.writer.textln("Results per shell:");
+
+            for (shell, succeeded, failed) in results_by_shell(&self.test_results, &config.shell_matrix) {
+                let message = format!("  {}: {} succeeded, {} failed", shell, succeeded, failed);
+                if failed == 0 { self.writer.success(message) } else { self.writer.failure(message) }
+            }
+        }
+
+        // Groups only help when tests are spread across several directories.
+        let groups = results_by_group(&self.test_results);
+        let has_ungrouped_tests = self.test_results.iter().any(|r| r.path.group().is_none());
+        if groups.iter().filter(|(group, ..)| group.len() == 1).count() + has_ungrouped_tests as usize > 1 {
+            self.writer.line();
+            self.writer.textln("Results per group:");
+
+            for (group, succeeded, failed) in groups {
+                let total = succeeded + failed;
+                let message = format!("{}{}: {}/{} passed ({}%)", "  ".repeat(group.len()), group.last().unwrap(), succeeded, total, succeeded * 100 / total);
+                if failed == 0 { self.writer.success(message) } else { self.writer.failure(message) }
+            }
+        }
+
+        if let Some(ref artifacts_directory) = self.artifacts_directory {
+            self.writer.line();
+            self.writer.textln(format!("Artifacts saved to: {}", print::link(&artifacts_directory.display().to_string(), artifacts_directory)));
 
-            for failed_test_result in failed_results.iter() {
-                print::with("  ", print::StdStream::Err, print::RED); // indent the errors.
-                self::result(failed_test_result, false, config);
+            for (name, run_directory) in failed_run_artifacts(&self.test_results, config) {
+                self.writer.textln(format!("  {}: {}", name, print::link(&run_directory.display().to_string(), run_directory)));
             }
         }
 
-        print::test_suite_status_message(passed, true, &self.test_results);
+        self.writer.test_suite_status_message(passed, true, &self.test_results);
 
         // 'cargo test' will use the color we last emitted if we don't do this.
-        print::reset_colors();
+        self.writer.reset_colors();
     }
 
-    fn on_test_finished(&mut self, result: TestResult, config: &Config) {
-        self::result(&result, true, config);
+    fn on_build_output(&mut self, line: &str, config: &Config) {
+        if config.message_format != MessageFormat::Human { return; }
+        self.writer.textln(line);
+    }
+
+    fn on_test_still_running(&mut self, test_file_path: &TestFilePath, elapsed: std::time::Duration, config: &Config) {
+        if config.message_format != MessageFormat::Human { return; }
+        let name = print::link(&config.test_name(test_file_path), &test_file_path.absolute);
+        self.writer.textln(format!("still running: {}, {}s elapsed", name, elapsed.as_secs()));
+    }
+
+    fn on_test_finished(&mut self, result: TestResult, _: &super::SuiteProgress, config: &Config) {
+        if config.message_format != MessageFormat::Human { return; }
+        self::result(&mut self.writer, &result, true, config);
 
         self.test_results.push(result);
     }
 
     fn note_warning(&mut self, message: &str) {
-        print::warning(message);
+        self.writer.warning(message);
+    }
+
+    fn on_configuration_differences(&mut self, differences: &[ConfigurationDifference], config: &Config) {
+        if config.message_format != MessageFormat::Human { return; }
+        self.writer.line();
+
+        if differences.is_empty() {
+            self.writer.success("no tests behaved differently under the comparison config");
+            return;
+        }
+
+        self.writer.textln_colored(format!("Tests that behaved differently under the comparison config ({}):", differences.len()), print::Color::Yellow);
+        self.writer.line();
+
+        for difference in differences {
+            let mut notes = Vec::new();
+            if difference.primary_result.is_erroneous() != difference.comparison_result.is_erroneous() {
+                notes.push(format!("{} -> {}", short_result_label(&difference.primary_result), short_result_label(&difference.comparison_result)));
+            }
+            if difference.output_differs {
+                notes.push("output differs".to_owned());
+            }
+
+            let name = print::link(&config.test_name(&difference.path), &difference.path.absolute);
+            self.writer.failure(format!("  DIFF :: {} ({})", name, notes.join(", ")));
+        }
     }
 }
 
-pub fn result(result: &TestResult, verbose: bool, config: &Config) {
+/// Counts the failure signatures shared by more than one test, most common first.
+fn common_failure_signatures(failed_results: &[&TestResult]) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+
+    for signature in failed_results.iter().filter_map(|r| r.failure_signature()) {
+        match counts.iter_mut().find(|(s, _)| *s == signature) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((signature, 1)),
+        }
+    }
+
+    counts.retain(|&(_, count)| count > 1);
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    counts
+}
+
+/// Finds the artifact directories of the failing runs of each failing test.
+///
+/// Tests without failing runs of their own, such as unexpected passes, refer to the artifacts of the whole test.
+fn failed_run_artifacts<'a>(test_results: &'a [TestResult], config: &Config) -> Vec<(String, &'a std::path::Path)> {
+    test_results.iter().filter(|r| r.overall_result.is_erroneous()).flat_map(|result| {
+        let failing_runs = result.individual_run_results.iter()
+            .filter(|run| run.kind.is_erroneous())
+            .filter_map(|run| run.artifacts_directory.as_deref())
+            .collect::<Vec<_>>();
+        let directories = if failing_runs.is_empty() { result.artifacts_directory.as_deref().into_iter().collect() } else { failing_runs };
+
+        directories.into_iter().map(move |directory| (result.display_name(config), directory))
+    }).collect()
+}
+
+/// Counts the tests that succeeded and failed with each shell of a shell matrix.
+fn results_by_shell<'a>(test_results: &[TestResult], shells: &'a [String]) -> Vec<(&'a str, usize, usize)> {
+    shells.iter().map(|shell| {
+        let shell_results = test_results.iter().filter(|r| r.shell.as_ref() == Some(shell));
+        let (failed, succeeded): (Vec<_>, Vec<_>) = shell_results.partition(|r| r.overall_result.is_erroneous());
+
+        (&shell[..], succeeded.len(), failed.len())
+    }).collect()
+}
+
+/// Counts the tests that succeeded and failed in each group, including those in nested groups.
+///
+/// Groups are given as their directory components, and sorted so that nested groups follow their parent.
+fn results_by_group(test_results: &[TestResult]) -> Vec<(Vec<String>, usize, usize)> {
+    let mut groups: std::collections::BTreeMap<Vec<String>, (usize, usize)> = std::collections::BTreeMap::new();
+
+    for result in test_results {
+        let components: Vec<String> = match result.path.group() {
+            Some(group) => group.split('/').map(ToOwned::to_owned).collect(),
+            None => continue,
+        };
+
+        for depth in 1..=components.len() {
+            let counts = groups.entry(components[..depth].to_vec()).or_insert((0, 0));
+            if result.overall_result.is_erroneous() { counts.1 += 1 } else { counts.0 += 1 }
+        }
+    }
+
+    groups.into_iter().map(|(group, (succeeded, failed))| (group, succeeded, failed)).collect()
+}
+
+/// Groups failing tests by owner, with unowned tests last.
+///
+/// Tests with several owners appear under each of them.
+fn failures_by_owner<'a>(failed_results: &[&'a TestResult]) -> Vec<(Option<&'a str>, Vec<&'a TestResult>)> {
+    let mut groups: Vec<(Option<&str>, Vec<&TestResult>)> = Vec::new();
+
+    for &result in failed_results {
+        let owners: Vec<Option<&str>> = if result.owners.is_empty() {
+            vec![None]
+        } else {
+            result.owners.iter().map(|o| Some(&o[..])).collect()
+        };
+
+        for owner in owners {
+            match groups.iter_mut().find(|(o, _)| *o == owner) {
+                Some((_, results)) => results.push(result),
+                None => groups.push((owner, vec![result])),
+            }
+        }
+    }
+
+    groups.sort_by_key(|&(owner, _)| (owner.is_none(), owner));
+    groups
+}
+
+fn short_result_label(result: &TestResultKind) -> &'static str {
+    if result.is_erroneous() { "fail" } else { "pass" }
+}
+
+pub fn result(writer: &mut print::Writer, result: &TestResult, verbose: bool, config: &Config) {
+    if config.error_format == ErrorFormat::Short && result.overall_result.is_erroneous() {
+        self::short_failures(writer, result, config);
+        return;
+    }
+
+    let name = print::link(&result.display_name(config), &result.path.absolute);
+
     match result.overall_result {
         TestResultKind::Pass => {
-            print::success(format!("PASS :: {}", result.path.relative.display()));
+            writer.success(format!("PASS :: {}", name));
         },
         TestResultKind::UnexpectedPass => {
-            print::failure(format!("UNEXPECTED PASS :: {}", result.path.relative.display()));
+            writer.failure(format!("UNEXPECTED PASS :: {}{}", name, failure_annotation(result)));
         },
         TestResultKind::Skip => {
-            print::line();
-            print::warning(format!(
-                "SKIP :: {} (test does not contain any test commands, perhaps you meant to add a 'CHECK'?)",
-                     result.path.relative.display()));
-            print::line();
+            let reason = result.individual_run_results.iter().flat_map(|r| r.warnings.iter()).next()
+                .map(|warning| warning.as_str())
+                .unwrap_or("test does not contain any test commands, perhaps you meant to add a 'CHECK'?");
+
+            writer.line();
+            writer.warning(format!("SKIP :: {} ({})", name, reason));
+            writer.line();
+        },
+        TestResultKind::NotRun => {
+            writer.warning(format!("NOT RUN :: {}", name));
         },
         TestResultKind::Error { ref message } => {
-            if verbose { print::line(); }
+            if verbose { writer.line(); }
 
-            print::error(format!("ERROR :: {}", result.path.relative.display()));
+            writer.error(format!("ERROR :: {}{}", name, failure_annotation(result)));
 
             if verbose {
-                print::textln(message);
+                writer.textln(message);
 
-                print::line();
+                writer.line();
             }
         }
         TestResultKind::Fail { ref reason, ref hint } => {
-            if verbose { print::line(); }
+            if verbose { writer.line(); }
 
-            print::failure(format!("FAIL :: {}", result.path.relative.display()));
+            writer.failure(format!("FAIL :: {}{}", name, failure_annotation(result)));
 
             // FIXME: improve formatting
 
             if verbose {
-                print::line();
-                print::text("test failed: ");
-                print::textln_colored(reason.human_summary(), print::RED);
-                print::line();
-                print::textln(reason.human_detail_message(config));
+                writer.line();
+                writer.text("test failed: ");
+                writer.textln_colored(config.messages.format(reason.summary_id(), &[]), print::Color::Red);
+                writer.line();
+                writer.textln(reason.human_detail_message(config));
 
                 if let Some(hint_text) = hint {
-                    print::textln(format!("hint: {}", hint_text));
+                    // Hints may be followed by a comparison of what was expected with the output.
+                    let mut hint_lines = hint_text.lines();
+                    writer.textln(format!("hint: {}", hint_lines.next().unwrap_or("")));
+                    for line in hint_lines {
+                        writer.diff_textln(line);
+                    }
+                }
+
+                if let Some(ref artifacts_directory) = result.artifacts_directory {
+                    writer.textln(format!("artifacts: {}", print::link(&artifacts_directory.display().to_string(), artifacts_directory)));
                 }
 
-                print::line();
+                writer.line();
             }
         },
         TestResultKind::ExpectedFailure { .. } => {
-            print::warning(format!("XFAIL :: {}", result.path.relative.display()));
+            writer.warning(format!("XFAIL :: {}", name));
         },
         TestResultKind::EmptyTest { .. } => {
-            print::error(format!("EMPTY TEST :: {}", result.path.relative.display()));
+            writer.error(format!("EMPTY TEST :: {}", name));
         },
     }
 
     if verbose && (result.overall_result.is_erroneous() || config.always_show_stderr) {
         for individual_run_result in result.individual_run_results.iter() {
-            let (_, _, command_line, output) = individual_run_result;
+            let (command_line, output) = (&individual_run_result.command_line, &individual_run_result.output);
 
-            let formatted_stderr = crate::model::format_test_output("stderr", &output.stderr, 1, util::TruncateDirection::Bottom, config);
+            let formatted_stderr = format::test_output("stderr", &output.stderr, &OutputOptions::for_config(config, TruncateDirection::Bottom));
             if !output.stderr.is_empty() {
-                print::textln(format!("NOTE: the program '{}' emitted text on standard error:", command_line));
-                print::line();
-                print::textln(formatted_stderr);
-                print::line();
+                writer.textln(format!("NOTE: the program '{}' emitted text on standard error:", command_line));
+                writer.line();
+                writer.textln(formatted_stderr);
+                writer.line();
             }
         }
     }
 }
 
+/// Describes how long a test has been failing for, if its history is known.
+fn failure_annotation(result: &TestResult) -> String {
+    match result.history.as_ref().and_then(|h| h.failure_annotation()) {
+        Some(annotation) => format!(" ({})", annotation),
+        None => String::new(),
+    }
+}
+
+/// Prints a single `<path>:<line>: <KIND>: <message>` line per failing run.
+///
+/// The lines are printed without colors so that editors can parse them.
+fn short_failures(writer: &mut print::Writer, result: &TestResult, config: &Config) {
+    for run_result in result.individual_run_results.iter().filter(|r| r.kind.is_erroneous()) {
+        writer.uncolored_textln(format!("{}:{}: {}: {}",
+                 result.path.absolute.display(),
+                 run_result.directive_line_number,
+                 run_result.kind.machine_label().to_uppercase(),
+                 run_result.kind.short_failure_message_in(&config.messages).unwrap_or_default()));
+    }
+}
+
 mod print {
-    pub use term::color::*;
     use super::*;
+    use std::path::Path;
+
+    /// The streams output is written to, which can be colored with the `color` feature.
+    #[cfg(feature = "color")]
+    pub use termcolor::WriteColor as Stream;
+    #[cfg(not(feature = "color"))]
+    pub use std::io::Write as Stream;
 
     #[derive(Copy, Clone)]
-    pub enum StdStream { Out, Err }
+    pub enum Color { White, Red, Green, Yellow, Magenta }
 
-    pub fn line() {
-        with("\n",
-             StdStream::Out,
-             term::color::WHITE);
+    lazy_static! {
+        static ref HYPERLINKS_SUPPORTED: bool = hyperlinks_supported(|name| std::env::var(name).ok(), is_terminal());
     }
 
-    pub fn horizontal_rule() {
-        with("=================================================================\n",
-             StdStream::Out,
-             term::color::WHITE);
+    /// Makes text a link to a file, if the terminal supports OSC 8 hyperlinks.
+    pub fn link(text: &str, path: &Path) -> String {
+        if *HYPERLINKS_SUPPORTED { hyperlink(text, path) } else { text.to_owned() }
     }
 
-    pub fn textln<S>(msg: S)
-        where S: Into<String> {
-        text(format!("{}\n", msg.into()))
+    /// Wraps text in an OSC 8 escape sequence linking to a file.
+    pub fn hyperlink(text: &str, path: &Path) -> String {
+        let url: String = path.display().to_string().chars().map(|c| match c {
+            ' ' => "%20".to_owned(),
+            '%' => "%25".to_owned(),
+            c if c.is_control() => format!("%{:02X}", c as u32),
+            c => c.to_string(),
+        }).collect();
+        let url = if url.starts_with('/') { format!("file://{}", url) } else { format!("file:///{}", url.replace('\\', "/")) };
+
+        format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
     }
 
-    pub fn text<S>(msg: S)
-        where S: Into<String> {
-        with(format!("{}", msg.into()),
-             StdStream::Out,
-             term::color::WHITE);
-    }
+    /// Decides whether the terminal supports hyperlinks from its environment variables.
+    ///
+    /// `FORCE_HYPERLINK=1` or `FORCE_HYPERLINK=0` overrides the detection.
+    pub fn hyperlinks_supported(env_var: impl Fn(&str) -> Option<String>, is_terminal: bool) -> bool {
+        if let Some(force) = env_var("FORCE_HYPERLINK") {
+            return force != "0";
+        }
+        if !is_terminal { return false; }
 
+        let known_terminal_program = env_var("TERM_PROGRAM").map(|program| ["iTerm.app", "vscode", "WezTerm", "Hyper"].contains(&&program[..]));
+        let vte_version = env_var("VTE_VERSION").and_then(|version| version.parse::<u32>().ok());
 
-    pub fn textln_colored<S>(msg: S, color: u32)
-        where S: Into<String> {
-        with(format!("{}\n", msg.into()),
-             StdStream::Out,
-             color);
+        known_terminal_program == Some(true) || vte_version.map(|version| version >= 5000).unwrap_or(false) ||
+            env_var("WT_SESSION").is_some() || env_var("KONSOLE_VERSION").is_some() ||
+            env_var("TERM").as_deref() == Some("xterm-kitty")
     }
 
-
-    pub fn success<S>(msg: S)
-        where S: Into<String> {
-        with(format!("{}\n", msg.into()),
-             StdStream::Out,
-             term::color::GREEN);
+    #[cfg(unix)]
+    fn is_terminal() -> bool {
+        unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 && libc::isatty(libc::STDERR_FILENO) == 1 }
     }
 
-    pub fn warning<S>(msg: S)
-        where S: Into<String> {
-        with(format!("{}\n", msg.into()),
-             StdStream::Err,
-             term::color::YELLOW);
-    }
+    #[cfg(not(unix))]
+    fn is_terminal() -> bool { false }
 
-    pub fn error<S>(msg: S)
-        where S: Into<String> {
-        with(format!("{}\n", msg.into()),
-             StdStream::Err,
-             term::color::RED);
-    }
+    #[derive(Copy, Clone)]
+    pub enum StdStream { Out, Err }
 
-    pub fn failure<S>(msg: S)
-        where S: Into<String> {
-        with(format!("{}\n", msg.into()),
-             StdStream::Err,
-             term::color::MAGENTA);
+    /// The streams that output is written to.
+    pub struct Writer {
+        pub stdout: Box<dyn Stream>,
+        pub stderr: Box<dyn Stream>,
     }
 
-    pub fn test_suite_status_message(passed: bool, verbose: bool, test_results: &[TestResult]) {
-        if verbose {
-            self::line();
-            self::horizontal_rule();
+    impl Writer {
+        pub fn line(&mut self) {
+            self.with("\n",
+                      StdStream::Out,
+                      Color::White);
+        }
+
+        pub fn horizontal_rule(&mut self) {
+            self.with("=================================================================\n",
+                      StdStream::Out,
+                      Color::White);
+        }
+
+        pub fn textln<S>(&mut self, msg: S)
+            where S: Into<String> {
+            self.text(format!("{}\n", msg.into()))
         }
 
-        if verbose {
-            self::textln("Suite Status:");
-            self::line();
+        pub fn text<S>(&mut self, msg: S)
+            where S: Into<String> {
+            self.with(msg,
+                      StdStream::Out,
+                      Color::White);
+        }
+
+        /// Writes a line without any colors, such as for editors to parse.
+        pub fn uncolored_textln<S>(&mut self, msg: S)
+            where S: Into<String> {
+            writeln!(self.stdout, "{}", msg.into()).unwrap();
+        }
+
+        pub fn textln_colored<S>(&mut self, msg: S, color: Color)
+            where S: Into<String> {
+            self.with(format!("{}\n", msg.into()),
+                      StdStream::Out,
+                      color);
+        }
 
-            for (result_label, corresponding_results) in &test_results.iter().group_by(|r| r.overall_result.human_label_pluralized()) {
-                self::textln(format!("  {}: {}", result_label, corresponding_results.count()));
+        /// Writes a line of a diff, in red if it was removed, or in green if it was added.
+        pub fn diff_textln(&mut self, line: &str) {
+            match line.chars().next() {
+                Some('-') => self.textln_colored(line, Color::Red),
+                Some('+') => self.textln_colored(line, Color::Green),
+                _ => self.textln(line),
             }
+        }
 
-            self::line();
-            self::horizontal_rule();
-            self::line();
+        pub fn success<S>(&mut self, msg: S)
+            where S: Into<String> {
+            self.with(format!("{}\n", msg.into()),
+                      StdStream::Out,
+                      Color::Green);
         }
 
-        match passed {
-            true => self::success("all tests succeeded"),
-            false => self::error("error: tests failed"),
+        pub fn warning<S>(&mut self, msg: S)
+            where S: Into<String> {
+            self.with(format!("{}\n", msg.into()),
+                      StdStream::Err,
+                      Color::Yellow);
         }
-    }
 
-    pub fn with<S>(msg: S,
-                   stream: StdStream,
-                   color: term::color::Color)
-        where S: Into<String> {
-        set_color(Some(msg), stream, color);
-        reset_colors();
-    }
+        pub fn error<S>(&mut self, msg: S)
+            where S: Into<String> {
+            self.with(format!("{}\n", msg.into()),
+                      StdStream::Err,
+                      Color::Red);
+        }
 
-    pub fn set_color<S>(msg: Option<S>,
-                        stream: StdStream,
-                        color: term::color::Color)
-        where S: Into<String> {
+        pub fn failure<S>(&mut self, msg: S)
+            where S: Into<String> {
+            self.with(format!("{}\n", msg.into()),
+                      StdStream::Err,
+                      Color::Magenta);
+        }
 
+        pub fn test_suite_status_message(&mut self, passed: bool, verbose: bool, test_results: &[TestResult]) {
+            if verbose {
+                self.line();
+                self.horizontal_rule();
+            }
 
-        match stream {
-            StdStream::Out => {
-                let stdout_term_color = term::stdout().and_then(|mut t| if let Ok(()) = t.fg(color) { Some(t) } else { None });
+            if verbose {
+                self.textln("Suite Status:");
+                self.line();
 
-                if let Some(mut color_term) = stdout_term_color {
-                    if let Some(msg) = msg {
-                        write!(color_term, "{}", msg.into()).unwrap();
-                    }
-                } else {
-                    if let Some(msg) = msg {
-                        write!(io::stdout(), "{}", msg.into()).unwrap();
-                    }
+                for (result_label, corresponding_results) in &test_results.iter().group_by(|r| r.overall_result.human_label_pluralized()) {
+                    self.textln(format!("  {}: {}", result_label, corresponding_results.count()));
                 }
-            },
-            StdStream::Err => {
-                let stderr_term_color = term::stderr().and_then(|mut t| if let Ok(()) = t.fg(color) { Some(t) } else { None });
 
-                if let Some(mut color_term) = stderr_term_color {
-                    if let Some(msg) = msg {
-                        write!(color_term, "{}", msg.into()).unwrap();
-                    }
-                } else {
-                    if let Some(msg) = msg {
-                        write!(io::stderr(), "{}", msg.into()).unwrap();
-                    }
-                }
-            },
+                self.line();
+                self.horizontal_rule();
+                self.line();
+            }
+
+            match passed {
+                true => self.success("all tests succeeded"),
+                false => self.error("error: tests failed"),
+            }
+        }
+
+        pub fn with<S>(&mut self,
+                       msg: S,
+                       stream: StdStream,
+                       color: Color)
+            where S: Into<String> {
+            let stream = self.stream(stream);
+
+            set_color(stream, Some(color));
+            write!(stream, "{}", msg.into()).unwrap();
+            set_color(stream, None);
         }
-    }
 
-    pub fn reset_colors() {
-        for stream in [StdStream::Out, StdStream::Err].iter().cloned() {
-            set_color::<String>(None, stream, term::color::WHITE);
+        pub fn reset_colors(&mut self) {
+            for stream in [StdStream::Out, StdStream::Err].iter().cloned() {
+                set_color(self.stream(stream), None);
+            }
+        }
+
+        fn stream(&mut self, stream: StdStream) -> &mut dyn Stream {
+            match stream {
+                StdStream::Out => &mut *self.stdout,
+                StdStream::Err => &mut *self.stderr,
+            }
         }
     }
+
+    /// Changes the color of the text written to a stream next, or resets it.
+    #[cfg(feature = "color")]
+    fn set_color(stream: &mut dyn Stream, color: Option<Color>) {
+        let color = match color {
+            Some(color) => color,
+            None => { stream.reset().ok(); return; },
+        };
+        let color = match color {
+            Color::White => termcolor::Color::White,
+            Color::Red => termcolor::Color::Red,
+            Color::Green => termcolor::Color::Green,
+            Color::Yellow => termcolor::Color::Yellow,
+            Color::Magenta => termcolor::Color::Magenta,
+        };
+
+        stream.set_color(termcolor::ColorSpec::new().set_fg(Some(color))).ok();
+    }
+
+    /// Output is never colored without the `color` feature.
+    #[cfg(not(feature = "color"))]
+    fn set_color(_: &mut dyn Stream, _: Option<Color>) { }
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event_handler::{EventHandler as _, SuiteProgress};
+    use std::{cell::RefCell, io, path::Path, rc::Rc};
+
+    /// A stream whose content can be read back after it is given to an event handler.
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.borrow_mut().write(buf) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    impl Captured {
+        fn text(&self) -> String { String::from_utf8(self.0.borrow().clone()).unwrap() }
+
+        #[cfg(feature = "color")]
+        fn stream(&self) -> termcolor::NoColor<Captured> { termcolor::NoColor::new(self.clone()) }
+
+        #[cfg(not(feature = "color"))]
+        fn stream(&self) -> Captured { self.clone() }
+    }
+
+    #[test]
+    fn writes_to_the_given_streams() {
+        let (stdout, stderr) = (Captured::default(), Captured::default());
+        let mut event_handler = EventHandler::with_writers(stdout.stream(), stderr.stream());
+        let config = Config::default();
+        let progress = SuiteProgress { completed: 1, remaining: 0, failures: 0, elapsed: std::time::Duration::from_secs(0) };
+        let result = |path: &str, overall_result| TestResult {
+            path: TestFilePath::new(Path::new("/nonexistent").join(path), path),
+            overall_result,
+            individual_run_results: Vec::new(),
+            duration: std::time::Duration::from_secs(0),
+            owners: Vec::new(),
+            history: None,
+            shell: None,
+            artifacts_directory: None,
+        };
+
+        event_handler.on_test_finished(result("passes.txt", TestResultKind::Pass), &progress, &config);
+        event_handler.on_test_finished(result("skipped.txt", TestResultKind::Skip), &progress, &config);
+        event_handler.note_warning("careful");
+
+        assert_eq!(stdout.text(), "PASS :: passes.txt\n\n\n");
+        assert_eq!(stderr.text(), "SKIP :: skipped.txt (test does not contain any test commands, perhaps you meant to add a 'CHECK'?)\ncareful\n");
+    }
+
+    #[test]
+    fn short_error_format_prints_one_line_per_failing_run() {
+        let config = Config { shell: "sh".to_owned(), error_format: ErrorFormat::Short, ..Config::default() };
+        let short_failures = |name: &str, text: &str| {
+            let stdout = Captured::default();
+            let mut event_handler = EventHandler::with_writers(stdout.stream(), Captured::default().stream());
+            let progress = SuiteProgress { completed: 1, remaining: 0, failures: 1, elapsed: std::time::Duration::from_secs(0) };
+            let test_file = crate::parse::test_file(TestFilePath::new(Path::new("/nonexistent").join(name), name), text).unwrap();
+
+            event_handler.on_test_finished(crate::run::execute_test_file(&test_file, &config), &progress, &config);
+            stdout.text()
+        };
+
+        assert_eq!(short_failures("check.txt", "RUN: echo hello\nCHECK: goodbye\n"), "/nonexistent/check.txt:2: FAIL: expected text 'goodbye' but that was not found\n");
+        assert_eq!(short_failures("check-next.txt", "RUN: printf 'a\\nb\\nc\\n'\nCHECK: a\nCHECK-NEXT: c\n"),
+                   "/nonexistent/check-next.txt:3: FAIL: expected text 'c' but that was not found (found a match for 'c', but it does not appear on the next line, as required by the CHECK-NEXT directive)\n");
+        assert_eq!(short_failures("exit-code.txt", "RUN: exit 3\n"), "/nonexistent/exit-code.txt:1: FAIL: command 'exit 3' exited with code '3'\n");
+    }
+
+    #[test]
+    fn short_error_format_is_used_in_the_failure_summary() {
+        let config = Config { shell: "sh".to_owned(), error_format: ErrorFormat::Short, ..Config::default() };
+        let test_file = crate::parse::test_file(TestFilePath::new(Path::new("/nonexistent/exit-code.txt"), "exit-code.txt"), "RUN: exit 3\n").unwrap();
+        let stdout = Captured::default();
+        let mut writer = print::Writer { stdout: Box::new(stdout.stream()), stderr: Box::new(Captured::default().stream()) };
+
+        self::result(&mut writer, &crate::run::execute_test_file(&test_file, &config), false, &config);
+
+        assert_eq!(stdout.text(), "/nonexistent/exit-code.txt:1: FAIL: command 'exit 3' exited with code '3'\n");
+    }
+
+    #[test]
+    fn counts_results_of_nested_groups_in_their_parents() {
+        let result = |path: &str, overall_result| TestResult {
+            path: TestFilePath::new(Path::new("/nonexistent").join(path), path),
+            overall_result,
+            individual_run_results: Vec::new(),
+            duration: std::time::Duration::from_secs(0),
+            owners: Vec::new(),
+            history: None,
+            shell: None,
+            artifacts_directory: None,
+        };
+        let results = vec![
+            result("run-pass/a.txt", TestResultKind::Pass),
+            result("run-pass/nested/b.txt", TestResultKind::Error { message: "failed".to_owned() }),
+            result("run-fail/c.txt", TestResultKind::Pass),
+            result("d.txt", TestResultKind::Pass),
+        ];
+        let group = |components: &[&str]| components.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(results_by_group(&results), vec![
+            (group(&["run-fail"]), 1, 0),
+            (group(&["run-pass"]), 1, 1),
+            (group(&["run-pass", "nested"]), 0, 1),
+        ]);
+    }
+
+    #[test]
+    fn links_paths_only_in_supporting_terminals() {
+        let env = |vars: &'static [(&'static str, &'static str)]| move |name: &str| {
+            vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        };
+
+        assert!(print::hyperlinks_supported(env(&[("TERM_PROGRAM", "vscode")]), true));
+        assert!(!print::hyperlinks_supported(env(&[("TERM_PROGRAM", "vscode")]), false));
+        assert!(!print::hyperlinks_supported(env(&[("TERM", "xterm")]), true));
+        assert!(print::hyperlinks_supported(env(&[("FORCE_HYPERLINK", "1")]), false));
+        assert!(!print::hyperlinks_supported(env(&[("FORCE_HYPERLINK", "0"), ("WT_SESSION", "1")]), true));
+
+        assert_eq!(print::hyperlink("a.txt", Path::new("/my tests/a.txt")), "\x1b]8;;file:///my%20tests/a.txt\x1b\\a.txt\x1b]8;;\x1b\\");
+    }
+}
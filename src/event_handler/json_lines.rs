@@ -0,0 +1,128 @@
+//! An event handler that emits one JSON object per line on stdout.
+
+use crate::{Config, json, messages::MessageId, model::*};
+use std::io::Write;
+
+/// An event handler for tools that consume test events programmatically.
+///
+/// Every event is written to stdout as a single-line JSON object, with a
+/// `"type"` field naming the event.
+pub struct EventHandler {
+    _priv: (),
+}
+
+impl EventHandler {
+    /// Creates a new JSON lines event handler.
+    pub fn new() -> Self {
+        EventHandler { _priv: () }
+    }
+
+    fn emit(&mut self, event: json::Value) {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+
+        writeln!(stdout, "{}", event).ok();
+        stdout.flush().ok();
+    }
+}
+
+impl std::default::Default for EventHandler {
+    fn default() -> Self {
+        EventHandler::new()
+    }
+}
+
+impl super::EventHandler for EventHandler {
+    fn on_test_suite_started(&mut self, suite_details: &super::TestSuiteDetails, _: &Config) {
+        self.emit(json::Value::object()
+            .field("type", "suite-started")
+            .field("format_version", crate::report::FORMAT_VERSION)
+            .field("test_file_count", suite_details.number_of_test_files)
+            .field("artifacts_directory", suite_details.artifacts_directory.as_ref().map(|dir| dir.display().to_string()))
+            // A string, as JSON numbers cannot represent every 64-bit seed exactly.
+            .field("seed", suite_details.seed.to_string())
+            .field("environment", suite_details.environment.iter().fold(json::Value::object(), |object, (key, value)| object.field(key.clone(), value.clone()))));
+    }
+
+    fn on_test_suite_finished(&mut self, passed: bool, _: &Config) {
+        self.emit(json::Value::object()
+            .field("type", "suite-finished")
+            .field("passed", passed));
+    }
+
+    fn on_build_output(&mut self, line: &str, _: &Config) {
+        self.emit(json::Value::object()
+            .field("type", "build-output")
+            .field("line", line));
+    }
+
+    fn on_test_started(&mut self, test_file_path: &TestFilePath, config: &Config) {
+        self.emit(json::Value::object()
+            .field("type", "test-started")
+            .field("path", test_file_path.relative.display().to_string())
+            .field("name", config.test_name(test_file_path)));
+    }
+
+    fn on_test_still_running(&mut self, test_file_path: &TestFilePath, elapsed: std::time::Duration, config: &Config) {
+        self.emit(json::Value::object()
+            .field("type", "test-still-running")
+            .field("path", test_file_path.relative.display().to_string())
+            .field("name", config.test_name(test_file_path))
+            .field("elapsed_ms", elapsed.as_secs_f64() * 1000.0));
+    }
+
+    fn on_test_finished(&mut self, result: TestResult, _: &super::SuiteProgress, config: &Config) {
+        self.emit(self::test_finished_event(&result, config));
+    }
+
+    fn note_warning(&mut self, message: &str) {
+        self.emit(json::Value::object()
+            .field("type", "warning")
+            .field("message", message));
+    }
+
+    fn on_configuration_differences(&mut self, differences: &[ConfigurationDifference], config: &Config) {
+        for difference in differences {
+            self.emit(json::Value::object()
+                .field("type", "configuration-difference")
+                .field("path", difference.path.relative.display().to_string())
+                .field("name", config.test_name(&difference.path))
+                .field("primary_result", difference.primary_result.machine_label())
+                .field("comparison_result", difference.comparison_result.machine_label())
+                .field("output_differs", difference.output_differs));
+        }
+    }
+}
+
+/// Describes a finished test as a `test-finished` event.
+pub(crate) fn test_finished_event(result: &TestResult, config: &Config) -> json::Value {
+    let runs: Vec<_> = result.individual_run_results.iter().map(|run_result| {
+        json::Value::object()
+            .field("command_line", run_result.command_line.0.clone())
+            .field("line", run_result.directive_line_number as usize)
+            .field("result", run_result.kind.machine_label())
+            .field("message", run_result.kind.failure_message(config))
+            .field("message_id", run_result.kind.message_id().map(MessageId::as_str))
+            .field("check_matches", run_result.check_matches.iter().map(CheckMatch::to_json).collect::<Vec<_>>())
+            .field("artifacts_directory", run_result.artifacts_directory.as_ref().map(|dir| dir.display().to_string()))
+            .field("user_time_ms", run_result.resource_usage.map(|usage| usage.user_time.as_secs_f64() * 1000.0))
+            .field("system_time_ms", run_result.resource_usage.map(|usage| usage.system_time.as_secs_f64() * 1000.0))
+            .field("max_rss_bytes", run_result.resource_usage.map(|usage| usage.max_rss_bytes))
+    }).collect();
+
+    json::Value::object()
+        .field("type", "test-finished")
+        .field("path", result.path.relative.display().to_string())
+        .field("name", config.test_name(&result.path))
+        .field("group", result.path.group())
+        .field("shell", result.shell.clone())
+        .field("result", result.overall_result.machine_label())
+        .field("message", result.overall_result.failure_message(config))
+        .field("message_id", result.overall_result.message_id().map(MessageId::as_str))
+        .field("failure_signature", result.failure_signature())
+        .field("duration_ms", result.duration.as_secs_f64() * 1000.0)
+        .field("owners", result.owners.clone())
+        .field("failure_streak", result.history.as_ref().map(|h| if h.streak < 0 { -h.streak } else { 0 }))
+        .field("artifacts_directory", result.artifacts_directory.as_ref().map(|dir| dir.display().to_string()))
+        .field("runs", runs)
+}
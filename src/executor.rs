@@ -0,0 +1,99 @@
+//! Backends responsible for spawning the commands of a test.
+
+use crate::{Config, Variables};
+use std::{fmt, process};
+
+/// Builds the OS processes for the commands in tests.
+pub trait Executor: fmt::Debug + Send + Sync {
+    /// Builds the process for a command line, after variables have been resolved.
+    fn command(&self, command_line: &str, config: &Config) -> process::Command;
+
+    /// Extra variables made available to all tests executed by this backend.
+    fn variables(&self) -> Variables { Variables::new() }
+
+    /// Executables the backend needs, other than the shell, which `lit doctor` checks for.
+    fn required_tools(&self) -> Vec<String> { Vec::new() }
+}
+
+/// Runs commands through `Config::shell`.
+#[derive(Clone, Debug, Default)]
+pub struct Shell;
+
+/// Runs WebAssembly programs through a WASI runtime, such as `wasmtime`.
+///
+/// Command lines whose program ends in `.wasm` are automatically prefixed with the
+/// runtime. Tests may also refer to the runtime explicitly via `@wasm_runner`.
+#[derive(Clone, Debug)]
+pub struct Wasi {
+    /// The runtime executable.
+    pub runtime: String,
+    /// Arguments passed to the runtime before the module path.
+    pub runtime_args: Vec<String>,
+}
+
+impl Executor for Shell {
+    fn command(&self, command_line: &str, config: &Config) -> process::Command {
+        let mut cmd = process::Command::new(&config.shell);
+        cmd.args(["-c", command_line]);
+        cmd
+    }
+}
+
+impl Wasi {
+    /// Creates a backend that runs modules via `<runtime> run <module> <args>`.
+    pub fn new(runtime: impl Into<String>) -> Self {
+        Wasi { runtime: runtime.into(), runtime_args: vec!["run".to_owned()] }
+    }
+
+    /// Sets the arguments passed to the runtime before the module path.
+    pub fn with_runtime_args(mut self, runtime_args: &[&str]) -> Self {
+        self.runtime_args = runtime_args.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// The command line prefix that runs a module.
+    pub fn runner(&self) -> String {
+        std::iter::once(&self.runtime).chain(self.runtime_args.iter()).cloned().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Prefixes a command line with the runtime if it directly invokes a module.
+    pub fn rewrite(&self, command_line: &str) -> String {
+        let program = command_line.split_whitespace().next().unwrap_or("");
+
+        if program.ends_with(".wasm") {
+            format!("{} {}", self.runner(), command_line.trim_start())
+        } else {
+            command_line.to_owned()
+        }
+    }
+}
+
+impl Executor for Wasi {
+    fn command(&self, command_line: &str, config: &Config) -> process::Command {
+        Shell.command(&self.rewrite(command_line), config)
+    }
+
+    fn variables(&self) -> Variables {
+        let mut variables = Variables::new();
+        variables.insert("wasm_runner".to_owned(), self.runner());
+        variables
+    }
+
+    fn required_tools(&self) -> Vec<String> {
+        vec![self.runtime.clone()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wasi_prefixes_modules_with_the_runtime() {
+        let wasi = Wasi::new("wasmtime").with_runtime_args(&["run", "--dir=."]);
+
+        assert_eq!(wasi.rewrite("tool.wasm --input a.txt"), "wasmtime run --dir=. tool.wasm --input a.txt");
+        assert_eq!(wasi.rewrite("cat a.txt"), "cat a.txt");
+        assert_eq!(wasi.variables()["wasm_runner"], "wasmtime run --dir=.");
+    }
+}
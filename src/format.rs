@@ -0,0 +1,185 @@
+//! Formatting of program output for failure messages.
+//!
+//! These are the building blocks the default event handler renders failures with,
+//! so that custom event handlers can present output consistently with it.
+//!
+//! ```
+//! use lit::format::{self, OutputOptions, TruncateDirection};
+//!
+//! let options = OutputOptions { max_line_count: Some(2), ..OutputOptions::default() };
+//! let rendered = format::test_output("stdout", "one\ntwo\nthree\n", &options);
+//!
+//! assert!(rendered.starts_with("<stdout>:\n\n  1|      one\n  2|      two\n"));
+//! assert!(rendered.contains("... (truncated)"));
+//! ```
+
+use crate::{config::ControlSequences, Config};
+
+const DEFAULT_INDENT_ATOM: &str = "  ";
+const TRUNCATED_TEXT_MARKER: &str = "... (truncated)";
+/// Replaces the line number of lines continuing a wrapped line.
+const WRAPPED_LINE_MARKER: &str = "+";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TruncateDirection {
+    /// Removes lines from the top, keeping the last lines.
+    Top,
+    /// Removes lines from the bottom, keeping the first lines.
+    Bottom,
+    /// Removes lines from the middle, keeping both the first and the last lines.
+    Middle,
+}
+
+/// How program output is rendered by `test_output`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputOptions {
+    /// The line number shown next to the first line of the output.
+    pub first_line_number: usize,
+    /// The number of characters that longer lines are wrapped at, if any.
+    pub wrap_width: Option<usize>,
+    /// The maximum number of lines shown, or `None` to show all of them.
+    pub max_line_count: Option<usize>,
+    /// Which lines are removed when there are too many.
+    pub truncate_direction: TruncateDirection,
+    /// How terminal control sequences in the output are presented.
+    pub control_sequences: ControlSequences,
+}
+
+impl OutputOptions {
+    /// Gets the options the default event handler uses under a config.
+    ///
+    /// Output is truncated in the given direction, unless the config truncates context in the middle.
+    pub fn for_config(config: &Config, truncate_direction: TruncateDirection) -> Self {
+        OutputOptions {
+            first_line_number: 1,
+            wrap_width: config.wrap_output_context_at,
+            max_line_count: config.truncate_output_context_to_number_of_lines,
+            truncate_direction: self::truncate_direction(truncate_direction, config),
+            control_sequences: config.control_sequences_in_context,
+        }
+    }
+
+    /// Numbers the lines of the output starting from a line other than the first.
+    pub fn starting_at_line(self, first_line_number: usize) -> Self {
+        OutputOptions { first_line_number, ..self }
+    }
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            first_line_number: 1,
+            wrap_width: None,
+            max_line_count: None,
+            truncate_direction: TruncateDirection::Bottom,
+            control_sequences: ControlSequences::Raw,
+        }
+    }
+}
+
+/// Renders program output with line numbers inside `<label>` tags, as shown in failure messages.
+pub fn test_output(label: &str, output: &str, options: &OutputOptions) -> String {
+    let output = crate::terminal::present(output, options.control_sequences);
+    let mut formatted_output = self::decorate_with_line_numbers(&output, options.first_line_number, options.wrap_width);
+
+    if let Some(max_line_count) = options.max_line_count {
+        formatted_output = self::truncate_to_max_lines(&formatted_output, max_line_count, options.truncate_direction);
+    }
+    let formatted_output = self::indent(&formatted_output, 1);
+
+    format!("<{}>:\n\n{}\n</{}>", label, formatted_output, label)
+}
+
+/// Gets the direction to truncate context in under a config, which is the middle if so configured.
+pub fn truncate_direction(default_direction: TruncateDirection, config: &Config) -> TruncateDirection {
+    if config.truncate_output_context_in_middle { TruncateDirection::Middle } else { default_direction }
+}
+
+/// Indents every line of a piece of text, trimming the whitespace already around it.
+pub fn indent(text: &str, level: usize) -> String {
+    indent_ext(text, level, DEFAULT_INDENT_ATOM)
+}
+
+/// Indents every line of a piece of text with a custom indentation atom.
+pub fn indent_ext(text: &str, level: usize, indentation_atom: &str) -> String {
+    let indent = (0..level).map(|_| indentation_atom).collect::<Vec<_>>().join("");
+    text.lines().map(|l| format!("{}{}", indent, l.trim())).collect::<Vec<_>>().join("\n") + "\n"
+}
+
+/// Prefixes every line with its line number.
+///
+/// If a wrap width is given, longer lines are split into several, with the line number
+/// replaced by a marker on the continuation lines so that the gutter stays aligned.
+pub fn decorate_with_line_numbers(text: &str, starts_from_line_number: usize, wrap_width: Option<usize>) -> String {
+    let max_line_num_digits = (starts_from_line_number + text.lines().count()).to_string().len();
+    let gutter = |label: &str| format!("{}{}|      ", label, " ".repeat(max_line_num_digits.saturating_sub(label.len())));
+
+    text.lines().enumerate().flat_map(|(relative_lineno, line)| {
+        let line_number_str = (starts_from_line_number + relative_lineno).to_string();
+
+        wrap(line, wrap_width).into_iter().enumerate().map(move |(segment_idx, segment)| {
+            let label = if segment_idx == 0 { &line_number_str[..] } else { WRAPPED_LINE_MARKER };
+            format!("{}{}", gutter(label), segment)
+        }).collect::<Vec<_>>()
+    }).collect::<Vec<_>>().join("\n")
+}
+
+/// Splits a line into segments of at most `width` characters.
+fn wrap(line: &str, width: Option<usize>) -> Vec<String> {
+    let width = match width {
+        Some(width) if width > 0 && line.chars().count() > width => width,
+        _ => return vec![line.to_owned()],
+    };
+
+    let chars: Vec<char> = line.chars().collect();
+    chars.chunks(width).map(|chunk| chunk.iter().collect()).collect()
+}
+
+/// Removes lines from a piece of text so that at most a number of them remain, marking where they were removed.
+pub fn truncate_to_max_lines(
+    text: &str,
+    max_line_count: usize,
+    truncate_direction: TruncateDirection) -> String {
+    let lines = text.lines().collect::<Vec<_>>();
+
+    let is_truncated = lines.len() > max_line_count;
+
+    if !is_truncated {
+        return lines.join("\n"); // the text does not need to be truncated
+    }
+
+    let head_line_count = match truncate_direction {
+        TruncateDirection::Bottom => max_line_count,
+        TruncateDirection::Top => 0,
+        // Favour the head if the lines cannot be split evenly.
+        TruncateDirection::Middle => max_line_count.div_ceil(2),
+    };
+    let head = &lines[..head_line_count];
+    let tail = &lines[lines.len() - (max_line_count - head_line_count)..];
+
+    let sections: Vec<String> = vec![head.join("\n"), TRUNCATED_TEXT_MARKER.to_owned(), tail.join("\n")];
+    sections.into_iter().filter(|section| !section.is_empty()).collect::<Vec<_>>().join("\n\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wraps_long_lines_without_disturbing_the_gutter() {
+        let decorated = decorate_with_line_numbers("short\nabcdefghijkl", 9, Some(5));
+
+        assert_eq!(decorated, "9 |      short\n10|      abcde\n+ |      fghij\n+ |      kl");
+        assert_eq!(decorate_with_line_numbers("abcdefghij", 1, None), "1|      abcdefghij");
+    }
+
+    #[test]
+    fn truncates_lines_in_every_direction() {
+        let text = "1\n2\n3\n4\n5";
+
+        assert_eq!(truncate_to_max_lines(text, 2, TruncateDirection::Bottom), "1\n2\n\n... (truncated)");
+        assert_eq!(truncate_to_max_lines(text, 2, TruncateDirection::Top), "... (truncated)\n\n4\n5");
+        assert_eq!(truncate_to_max_lines(text, 3, TruncateDirection::Middle), "1\n2\n\n... (truncated)\n\n5");
+        assert_eq!(truncate_to_max_lines(text, 5, TruncateDirection::Middle), text);
+    }
+}
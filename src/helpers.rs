@@ -0,0 +1,159 @@
+//! Portable implementations of the utilities that tests commonly rely on.
+//!
+//! The helpers are run as `<executable> helper <name> [args...]`, and `RUN` lines
+//! refer to them via `@not`, `@count`, `@echo` and `@diff`, so that suites behave
+//! identically on platforms without coreutils.
+//!
+//! An executable becomes a helper executable by calling `run_if_invoked` at the
+//! start of `main`, and is then configured via `Config::helper_executable`.
+//!
+//! ``` no_run
+//! lit::helpers::run_if_invoked();
+//!
+//! let mut config = lit::Config::default();
+//! config.helper_executable = std::env::current_exe().ok();
+//! ```
+
+use crate::{Config, Variables, diff};
+use std::{env, fs, io::{self, Read}, process};
+
+/// The argument that selects a helper instead of the regular program.
+pub const HELPER_ARGUMENT: &str = "helper";
+
+/// The names of the available helpers.
+pub const HELPERS: &[&str] = &["not", "count", "echo", "diff"];
+
+/// Runs a helper and exits, if the program was invoked as `<executable> helper <name>`.
+pub fn run_if_invoked() {
+    let arguments: Vec<String> = env::args().skip(1).collect();
+
+    if arguments.first().map(|a| &a[..]) == Some(HELPER_ARGUMENT) {
+        let exit_code = match arguments.get(1) {
+            Some(name) => self::run(name, &arguments[2..]),
+            None => {
+                eprintln!("error: expected a helper name, one of {}", HELPERS.join(", "));
+                2
+            },
+        };
+
+        process::exit(exit_code);
+    }
+}
+
+/// Runs a helper, returning its exit code.
+pub fn run(name: &str, arguments: &[String]) -> i32 {
+    let result = match name {
+        "not" => self::not(arguments),
+        "count" => self::count(arguments),
+        "echo" => self::echo(arguments),
+        "diff" => self::diff(arguments),
+        _ => Err(format!("unknown helper '{}', expected one of {}", name, HELPERS.join(", "))),
+    };
+
+    match result {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            2
+        },
+    }
+}
+
+/// Gets the variables that refer to the helpers, if a helper executable is configured.
+pub fn variables(config: &Config) -> Variables {
+    let executable = match config.helper_executable {
+        Some(ref executable) => executable.display().to_string(),
+        None => return Variables::new(),
+    };
+    let executable = if executable.contains(char::is_whitespace) { format!("\"{}\"", executable) } else { executable };
+
+    HELPERS.iter().map(|&name| (name.to_owned(), format!("{} {} {}", executable, HELPER_ARGUMENT, name))).collect()
+}
+
+/// Runs a command, succeeding only if the command exits unsuccessfully.
+///
+/// Like LLVM's `not` tool, a command that crashes is still a failure.
+fn not(arguments: &[String]) -> Result<i32, String> {
+    let (program, program_arguments) = arguments.split_first().ok_or("expected a command to run")?;
+
+    let status = process::Command::new(program).args(program_arguments).status()
+        .map_err(|e| format!("could not run '{}': {}", program, e))?;
+
+    Ok(match status.code() {
+        Some(0) => 1,
+        Some(_) => 0,
+        None => 1,
+    })
+}
+
+/// Succeeds if standard input has exactly the given number of lines.
+fn count(arguments: &[String]) -> Result<i32, String> {
+    let expected: usize = match arguments {
+        [count] => count.parse().map_err(|_| format!("invalid line count '{}'", count))?,
+        _ => return Err("expected a single line count".to_owned()),
+    };
+
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).map_err(|e| e.to_string())?;
+
+    let actual = input.lines().count();
+    if actual == expected {
+        Ok(0)
+    } else {
+        eprintln!("expected {} lines, but got {}", expected, actual);
+        Ok(1)
+    }
+}
+
+/// Prints its arguments, separated by spaces. `-n` omits the trailing new line.
+fn echo(arguments: &[String]) -> Result<i32, String> {
+    let (newline, arguments) = match arguments.split_first() {
+        Some((first, rest)) if first == "-n" => (false, rest),
+        _ => (true, arguments),
+    };
+
+    print!("{}{}", arguments.join(" "), if newline { "\n" } else { "" });
+    Ok(0)
+}
+
+/// Succeeds if two files are identical, and prints a line diff otherwise.
+fn diff(arguments: &[String]) -> Result<i32, String> {
+    let (expected_path, actual_path) = match arguments {
+        [expected_path, actual_path] => (expected_path, actual_path),
+        _ => return Err("expected two files to compare".to_owned()),
+    };
+
+    let read = |path: &str| fs::read_to_string(path).map_err(|e| format!("could not read '{}': {}", path, e));
+    let (expected, actual) = (read(expected_path)?, read(actual_path)?);
+
+    if expected == actual {
+        Ok(0)
+    } else {
+        println!("--- {}\n+++ {}", expected_path, actual_path);
+        println!("{}", diff::render(&expected, &actual));
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn helpers_are_only_available_with_a_helper_executable() {
+        let mut config = Config::default();
+        assert!(variables(&config).is_empty());
+
+        config.helper_executable = Some("/opt/my tools/lit".into());
+        assert_eq!(variables(&config)["not"], "\"/opt/my tools/lit\" helper not");
+        assert_eq!(variables(&config).len(), HELPERS.len());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn not_inverts_the_exit_status() {
+        assert_eq!(run("not", &["false".to_owned()]), 0);
+        assert_eq!(run("not", &["true".to_owned()]), 1);
+        assert_eq!(run("not", &[]), 2);
+    }
+}
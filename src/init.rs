@@ -0,0 +1,149 @@
+//! Scaffolding of a new test suite, as done by `lit init`.
+//!
+//! A suite is made of a `lit.toml` config file, a `tests/lit` directory holding an example
+//! test, and optionally a cargo integration test at `tests/lit.rs` that runs the suite with
+//! `lit::run::tests`. Files that already exist are left alone.
+//!
+//! ```no_run
+//! for file in lit::init::scaffold(std::path::Path::new("."), true).unwrap() {
+//!     println!("{}", file);
+//! }
+//! ```
+
+use std::{fmt, fs, path::{Path, PathBuf}};
+
+/// The directory the tests of a scaffolded suite live in, relative to the project.
+pub const TEST_DIRECTORY: &str = "tests/lit";
+
+/// The config file of a scaffolded suite, relative to the project, which `lit --config` loads.
+pub const CONFIG_FILE_NAME: &str = "lit.toml";
+
+const CONFIG_FILE: (&str, &str) = (CONFIG_FILE_NAME, "\
+# The settings of the lit suite in tests/lit, in the TOML subset described by `lit::config::file`.
+# They are read by `lit --config lit.toml`, and by `lit::config::file::load`.
+extensions = [\"sh\", \"txt\"]
+
+# Fails the suite if any warnings are raised.
+# strict = true
+
+# Records how long each test took, to balance shards and run failing tests first.
+# timing_history = \"target/lit-history.txt\"
+");
+
+const EXAMPLE_TEST: (&str, &str) = ("tests/lit/example.sh", "\
+# RUN: sh @file
+#
+# Each RUN command is run through the shell, with @file replaced by the path of this file,
+# and its output must contain the text of every CHECK directive, in order.
+
+# CHECK: hello world
+echo hello world
+
+# CHECK: 1 + 1 = [[\\d+]]
+echo \"1 + 1 = $((1 + 1))\"
+");
+
+const CARGO_TEST: (&str, &str) = ("tests/lit.rs", "\
+//! Runs the lit tests in `tests/lit`.
+
+use std::path::Path;
+
+#[test]
+fn lit() {
+    let project_directory = Path::new(env!(\"CARGO_MANIFEST_DIR\"));
+
+    lit::run::tests(lit::event_handler::Default::default(), |config| {
+        if let Err(e) = lit::config::file::load(&project_directory.join(\"lit.toml\"), config) {
+            panic!(\"{}\", e);
+        }
+        config.add_search_path(project_directory.join(\"tests/lit\").display().to_string());
+    }).expect(\"lit tests failed\");
+}
+");
+
+/// A file of a scaffolded suite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScaffoldedFile {
+    /// The path of the file, relative to the project.
+    pub path: PathBuf,
+    /// Whether the file was written, rather than left alone as it already existed.
+    pub created: bool,
+}
+
+/// Writes the files of a new suite into a project directory.
+///
+/// With `with_cargo_test`, also writes a cargo integration test that runs the suite.
+pub fn scaffold(project_directory: &Path, with_cargo_test: bool) -> Result<Vec<ScaffoldedFile>, String> {
+    let mut templates = vec![CONFIG_FILE, EXAMPLE_TEST];
+    if with_cargo_test {
+        templates.push(CARGO_TEST);
+    }
+
+    templates.into_iter().map(|(relative_path, contents)| {
+        let path = project_directory.join(relative_path);
+        if path.exists() {
+            return Ok(ScaffoldedFile { path: relative_path.into(), created: false });
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("could not create '{}': {}", parent.display(), e))?;
+        }
+        fs::write(&path, contents).map_err(|e| format!("could not write '{}': {}", path.display(), e))?;
+
+        Ok(ScaffoldedFile { path: relative_path.into(), created: true })
+    }).collect()
+}
+
+impl fmt::Display for ScaffoldedFile {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.created {
+            true => write!(fmt, "created {}", self.path.display()),
+            false => write!(fmt, "skipped {}, as it already exists", self.path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Config, event_handler};
+
+    #[test]
+    fn scaffolded_suites_pass_and_keep_existing_files() {
+        let project_directory = tempfile::tempdir().unwrap();
+        fs::write(project_directory.path().join("lit.toml"), "extensions = [\"sh\"]\n").unwrap();
+
+        let files = scaffold(project_directory.path(), false).unwrap();
+        assert_eq!(files.iter().map(ToString::to_string).collect::<Vec<_>>(), vec![
+            "skipped lit.toml, as it already exists".to_owned(),
+            "created tests/lit/example.sh".to_owned(),
+        ]);
+        assert_eq!(fs::read_to_string(project_directory.path().join("lit.toml")).unwrap(), "extensions = [\"sh\"]\n");
+        assert!(scaffold(project_directory.path(), true).unwrap()[2].created);
+
+        fs::remove_file(project_directory.path().join("lit.toml")).unwrap();
+        scaffold(project_directory.path(), false).unwrap();
+
+        let result = crate::run::tests(event_handler::Default::default(), |config: &mut Config| {
+            crate::config::file::load(&project_directory.path().join("lit.toml"), config).unwrap();
+            config.shell = "sh".to_owned();
+            config.add_search_path(project_directory.path().join(TEST_DIRECTORY).display().to_string());
+        });
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    #[cfg(feature = "clap")]
+    fn scaffolded_config_files_are_loaded_with_the_config_option() {
+        let project_directory = tempfile::tempdir().unwrap();
+        scaffold(project_directory.path(), false).unwrap();
+        let config_file = project_directory.path().join(CONFIG_FILE_NAME);
+
+        let app = crate::config::clap::mount_inside_app(clap::App::new("lit"), true);
+        let matches = app.get_matches_from(vec!["lit", "--config", config_file.to_str().unwrap(), "--add-file-extension", "lit"]);
+        let mut config = Config::default();
+        crate::config::clap::parse_arguments(&matches, &mut config);
+
+        assert_eq!(config.supported_file_extensions, vec!["sh", "txt", "lit"]);
+    }
+}
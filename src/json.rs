@@ -0,0 +1,450 @@
+//! A minimal JSON value type, used for machine-readable output and for reading the
+//! output of tools such as `cargo metadata`.
+
+use std::fmt;
+
+/// A JSON value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// An object, with its keys kept in insertion order.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Creates an empty object.
+    pub fn object() -> Self { Value::Object(Vec::new()) }
+
+    /// Adds a field to an object, returning the object.
+    ///
+    /// Panics if the value is not an object.
+    pub fn field(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        match self {
+            Value::Object(ref mut fields) => fields.push((key.into(), value.into())),
+            _ => panic!("can only add fields to JSON objects"),
+        }
+        self
+    }
+
+    /// Parses a JSON document.
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut parser = Parser { text, position: 0 };
+        let value = parser.value()?;
+
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(value),
+            Some(c) => Err(parser.error(&format!("unexpected '{}' after the end of the document", c))),
+        }
+    }
+
+    /// Gets a field of an object, if the value is an object with the field.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Gets the string, if the value is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Gets the number, if the value is a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Gets the boolean, if the value is a boolean.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Gets the elements, if the value is an array.
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match *self {
+            Value::Array(ref values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// A step along a path into a JSON document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathSegment {
+    /// A field of an object.
+    Key(String),
+    /// An element of an array.
+    Index(usize),
+}
+
+impl Value {
+    /// Follows a path into the value, if every step of the path exists.
+    pub fn at(&self, path: &[PathSegment]) -> Option<&Value> {
+        path.iter().try_fold(self, |value, segment| match (segment, value) {
+            (PathSegment::Key(key), _) => value.get(key),
+            (PathSegment::Index(index), Value::Array(values)) => values.get(*index),
+            (PathSegment::Index(..), _) => None,
+        })
+    }
+
+    /// Checks whether two values are equal, where objects with the same fields in different orders are equal.
+    pub fn equals_ignoring_key_order(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.equals_ignoring_key_order(b)),
+            (Value::Object(a), Value::Object(b)) => a.len() == b.len() && a.iter().all(|(key, a)| {
+                other.get(key).map(|b| a.equals_ignoring_key_order(b)).unwrap_or(false)
+            }),
+            (a, b) => a == b,
+        }
+    }
+}
+
+/// Parses the path at the start of some text, such as `$.items[0]["display name"]`.
+///
+/// Also returns the text after the path.
+pub fn path_prefix(text: &str) -> Result<(Vec<PathSegment>, &str), String> {
+    let mut parser = Parser { text, position: 0 };
+    let invalid = |parser: &Parser, message: &str| format!("invalid JSON path '{}' at byte {}: {}", text, parser.position, message);
+
+    if !parser.eat('$') {
+        return Err(invalid(&parser, "expected '$'"));
+    }
+
+    let mut segments = Vec::new();
+    loop {
+        if parser.eat('.') {
+            let start = parser.position;
+            while parser.peek().map(|c| c.is_alphanumeric() || c == '_' || c == '-').unwrap_or(false) {
+                parser.next();
+            }
+            if parser.position == start {
+                return Err(invalid(&parser, "expected a key after '.'"));
+            }
+            segments.push(PathSegment::Key(text[start..parser.position].to_owned()));
+        } else if parser.eat('[') {
+            let segment = match parser.peek() {
+                Some('"') => PathSegment::Key(parser.string()?),
+                _ => {
+                    let start = parser.position;
+                    while parser.peek().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+                        parser.next();
+                    }
+                    let index = text[start..parser.position].parse().map_err(|_| invalid(&parser, "expected an index or a quoted key"))?;
+                    PathSegment::Index(index)
+                },
+            };
+            if !parser.eat(']') {
+                return Err(invalid(&parser, "expected ']'"));
+            }
+            segments.push(segment);
+        } else {
+            return Ok((segments, &text[parser.position..]));
+        }
+    }
+}
+
+/// A recursive descent parser over JSON text.
+struct Parser<'a> {
+    text: &'a str,
+    /// The byte offset of the next character.
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Value::String),
+            Some('t') => self.literal("true", Value::Boolean(true)),
+            Some('f') => self.literal("false", Value::Boolean(false)),
+            Some('n') => self.literal("null", Value::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(self.error(&format!("unexpected '{}'", c))),
+            None => Err(self.error("unexpected end of document")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+
+        self.skip_whitespace();
+        if self.eat('}') { return Ok(Value::Object(fields)); }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+
+            self.skip_whitespace();
+            if self.eat('}') { return Ok(Value::Object(fields)); }
+            self.expect(',')?;
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+
+        self.skip_whitespace();
+        if self.eat(']') { return Ok(Value::Array(values)); }
+
+        loop {
+            values.push(self.value()?);
+
+            self.skip_whitespace();
+            if self.eat(']') { return Ok(Value::Array(values)); }
+            self.expect(',')?;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+
+        loop {
+            match self.next().ok_or_else(|| self.error("unterminated string"))? {
+                '"' => return Ok(s),
+                '\\' => match self.next().ok_or_else(|| self.error("unterminated string"))? {
+                    '"' => s.push('"'),
+                    '\\' => s.push('\\'),
+                    '/' => s.push('/'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => s.push(self.unicode_escape()?),
+                    c => return Err(self.error(&format!("invalid escape '\\{}'", c))),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// Parses the digits of a `\u` escape, including the second half of a surrogate pair.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex_digits()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return std::char::from_u32(high).ok_or_else(|| self.error("invalid unicode escape"));
+        }
+
+        if !(self.eat('\\') && self.eat('u')) {
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+        let low = self.hex_digits()?;
+        let code_point = 0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+        std::char::from_u32(code_point).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn hex_digits(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.position..self.position + 4).ok_or_else(|| self.error("truncated unicode escape"))?;
+        let value = u32::from_str_radix(digits, 16).map_err(|_| self.error(&format!("invalid unicode escape '{}'", digits)))?;
+        self.position += 4;
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_digit() || "+-.eE".contains(c)) { break; }
+            self.position += 1;
+        }
+
+        let number = &self.text[start..self.position];
+        number.parse().map(Value::Number).map_err(|_| self.error(&format!("invalid number '{}'", number)))
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Result<Value, String> {
+        if self.text[self.position..].starts_with(literal) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected word"))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() { break; }
+            self.position += c.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.eat(expected) { Ok(()) } else { Err(self.error(&format!("expected '{}'", expected))) }
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let matches = self.peek() == Some(expected);
+        if matches { self.position += expected.len_utf8(); }
+        matches
+    }
+
+    fn peek(&self) -> Option<char> { self.text[self.position..].chars().next() }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("invalid JSON at byte {}: {}", self.position, message)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => write!(fmt, "null"),
+            Value::Boolean(b) => write!(fmt, "{}", b),
+            Value::Number(n) if n.is_finite() => write!(fmt, "{}", n),
+            Value::Number(_) => write!(fmt, "null"),
+            Value::String(ref s) => write_string(s, fmt),
+            Value::Array(ref values) => {
+                write!(fmt, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 { write!(fmt, ",")?; }
+                    write!(fmt, "{}", value)?;
+                }
+                write!(fmt, "]")
+            },
+            Value::Object(ref fields) => {
+                write!(fmt, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 { write!(fmt, ",")?; }
+                    write_string(key, fmt)?;
+                    write!(fmt, ":{}", value)?;
+                }
+                write!(fmt, "}}")
+            },
+        }
+    }
+}
+
+fn write_string(s: &str, fmt: &mut fmt::Formatter) -> fmt::Result {
+    write!(fmt, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(fmt, "\\\"")?,
+            '\\' => write!(fmt, "\\\\")?,
+            '\n' => write!(fmt, "\\n")?,
+            '\r' => write!(fmt, "\\r")?,
+            '\t' => write!(fmt, "\\t")?,
+            c if (c as u32) < 0x20 => write!(fmt, "\\u{:04x}", c as u32)?,
+            c => write!(fmt, "{}", c)?,
+        }
+    }
+    write!(fmt, "\"")
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self { Value::Boolean(b) }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self { Value::Number(n) }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self { Value::Number(n as f64) }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self { Value::Number(n as f64) }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self { Value::Number(n as f64) }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Self { Value::Number(n as f64) }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self { Value::String(s) }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Self { Value::String(s.to_owned()) }
+}
+
+impl<T> From<Option<T>> for Value where T: Into<Value> {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+impl<T> From<Vec<T>> for Value where T: Into<Value> {
+    fn from(values: Vec<T>) -> Self {
+        Value::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serializes_nested_values() {
+        let value = Value::object()
+            .field("name", "a \"quoted\"\nline")
+            .field("count", 3usize)
+            .field("items", vec![Value::Null, Value::Boolean(true)]);
+
+        assert_eq!(value.to_string(), r#"{"name":"a \"quoted\"\nline","count":3,"items":[null,true]}"#);
+    }
+
+    #[test]
+    fn parses_what_it_serializes() {
+        let value = Value::object()
+            .field("name", "tab\tquote\" \u{1F600}")
+            .field("numbers", vec![Value::Number(-1.5e3), Value::Number(0.0)])
+            .field("nested", Value::object().field("empty", Vec::<Value>::new()).field("flag", false));
+
+        assert_eq!(Value::parse(&value.to_string()), Ok(value.clone()));
+        assert_eq!(Value::parse(" { \"a\" : [ 1 , null ] } ").unwrap().get("a").and_then(Value::as_array).map(<[_]>::len), Some(2));
+        assert_eq!(Value::parse(r#""\ud83d\ude00""#), Ok(Value::String("\u{1F600}".to_owned())));
+        assert_eq!(Value::parse("[1,]").unwrap_err(), "invalid JSON at byte 3: unexpected ']'");
+        assert!(Value::parse("{} x").is_err());
+    }
+
+    #[test]
+    fn follows_paths() {
+        let value = Value::parse(r#"{"items":[{"name":"a"},{"display name":"b","n":1}]}"#).unwrap();
+        let (path, rest) = path_prefix(r#"$.items[1]["display name"] == "b""#).unwrap();
+
+        assert_eq!(path, vec![PathSegment::Key("items".to_owned()), PathSegment::Index(1), PathSegment::Key("display name".to_owned())]);
+        assert_eq!(rest, r#" == "b""#);
+        assert_eq!(value.at(&path), Some(&Value::String("b".to_owned())));
+        assert_eq!(value.at(&path_prefix("$.items[2]").unwrap().0), None);
+        assert_eq!(value.at(&path_prefix("$").unwrap().0), Some(&value));
+        assert!(path_prefix("items").unwrap_err().contains("expected '$'"));
+        assert!(path_prefix("$.items[x]").unwrap_err().contains("expected an index"));
+
+        let reordered = Value::parse(r#"{"items":[{"name":"a"},{"n":1.0,"display name":"b"}]}"#).unwrap();
+        assert!(value.equals_ignoring_key_order(&reordered) && value != reordered);
+    }
+}
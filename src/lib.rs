@@ -4,8 +4,12 @@
 //!
 //! This crate contains both a reusable library for creating test tools and
 //! an executable with generalized command line interface for manual usage.
+//!
+//! The command line interface, colored output, artifact saving and JSON output are
+//! behind the `cli`, `color`, `artifacts` and `json` cargo features, all enabled by default.
 
 pub use self::config::Config;
+pub use self::suite::Suite;
 
 pub use self::errors::*;
 pub use self::vars::{Variables, VariablesExt};
@@ -17,11 +21,26 @@ pub const INTEGRATION_TEST_FILE_EXTENSIONS: &'static [&'static str] = &[
 ];
 
 pub mod config;
+mod diff;
+pub mod doctor;
+pub mod embed;
 mod errors;
 pub mod event_handler;
-mod model;
+pub mod executor;
+pub mod format;
+pub mod helpers;
+pub mod init;
+mod json;
+pub mod matcher;
+pub mod messages;
+pub mod model;
 mod parse;
+pub mod plugin;
+pub mod report;
 pub mod run;
+pub mod suite;
+pub mod test_source;
+mod terminal;
 mod util;
 mod vars;
 
@@ -16,10 +16,16 @@ fn parse_cmdline() -> ArgMatches<'static> {
 }
 
 fn main() {
+    lit::helpers::run_if_invoked();
+
     let arg_matches = parse_cmdline();
 
-    lit::run::tests(lit::event_handler::Default::default(), |config| {
-        config.add_search_path("integration-tests/");
+    let result = lit::run::tests(lit::event_handler::Default::default(), |config| {
+        // The integration tests of lit itself, when run from its repository.
+        if std::path::Path::new("integration-tests/").is_dir() {
+            config.add_search_path("integration-tests/");
+        }
+        config.helper_executable = std::env::current_exe().ok();
         for ext in lit::INTEGRATION_TEST_FILE_EXTENSIONS {
             config.add_extension(ext);
         }
@@ -28,5 +34,11 @@ fn main() {
         config.constants.insert("os".to_owned(), consts::OS.to_owned());
 
         lit::config::clap::parse_arguments(&arg_matches, config);
-    }).unwrap()
+    });
+
+    match result {
+        Ok(()) => (),
+        Err(lit::run::SuiteError::TimedOut { .. }) => std::process::exit(lit::run::SUITE_TIMEOUT_EXIT_CODE),
+        Err(_) => std::process::exit(1),
+    }
 }
@@ -0,0 +1,234 @@
+//! Engines that find the text matched by the patterns of check directives.
+//!
+//! Patterns are regexes by default, as described in the README. A check can name another
+//! engine in brackets, as in `CHECK[exact]: 1 + 1 = [2]`, and `Config::default_matcher`
+//! changes the engine of the checks that do not name one. Engines other than regexes are
+//! given the text of the pattern after its variables are substituted, so the pattern cannot
+//! contain regexes, and nothing is captured.
+//!
+//! The builtin engines are:
+//!
+//! * `regex` - the default.
+//! * `exact` - matches the text of the pattern exactly.
+//! * `case-insensitive` - matches the text of the pattern in any case.
+//! * `whitespace` - matches the text of the pattern with any amount of whitespace wherever
+//!   it has whitespace.
+//!
+//! Embedders can register their own engines, such as semantic matchers, under other names.
+//!
+//! ```
+//! use lit::{Config, matcher::Matcher};
+//! use std::ops::Range;
+//!
+//! /// Matches whichever of several alternatives, separated by ` | `, appears first.
+//! #[derive(Debug)]
+//! struct OneOf;
+//!
+//! impl Matcher for OneOf {
+//!     fn name(&self) -> &str { "one-of" }
+//!
+//!     fn find_at(&self, needle: &str, text: &str, start: usize) -> Option<Range<usize>> {
+//!         needle.split(" | ")
+//!             .filter_map(|alternative| text[start..].find(alternative).map(|idx| (start + idx)..(start + idx + alternative.len())))
+//!             .min_by_key(|range| range.start)
+//!     }
+//! }
+//!
+//! let mut config = Config::default();
+//! config.register_matcher(OneOf);
+//!
+//! let one_of = lit::matcher::find("one-of", &config).unwrap().unwrap();
+//! assert_eq!(one_of.find_at("passed | skipped", "3 skipped, 2 passed", 0), Some(2..9));
+//! ```
+
+use crate::Config;
+use regex::{Regex, RegexBuilder};
+use std::{collections::HashMap, fmt, ops::Range, sync::{Arc, Mutex}};
+
+/// The name of the default engine, which matches patterns as regexes.
+pub const REGEX: &str = "regex";
+
+/// The names of the builtin engines, other than regexes.
+pub const BUILTIN_MATCHERS: &[&str] = &["exact", "case-insensitive", "whitespace"];
+
+/// The number of patterns that an engine keeps the compiled regexes of.
+const MAX_COMPILED_PATTERNS: usize = 256;
+
+lazy_static! {
+    /// The builtin engines, shared by all checks so that each pattern is compiled once.
+    static ref CASE_INSENSITIVE: Arc<CaseInsensitive> = Arc::new(CaseInsensitive::default());
+    static ref WHITESPACE: Arc<Whitespace> = Arc::new(Whitespace::default());
+}
+
+/// An engine that finds the text matched by a pattern.
+pub trait Matcher: fmt::Debug + Send + Sync {
+    /// The name that checks select the engine by, as in `CHECK[name]:`.
+    fn name(&self) -> &str;
+
+    /// Finds the first match of a pattern in a piece of text, starting at a byte index.
+    ///
+    /// The pattern is the text of a check, with its variables substituted. Returns the byte
+    /// range of the match within the whole text, which must not begin before `start` and must
+    /// lie on character boundaries, or the check is reported as an error.
+    fn find_at(&self, needle: &str, text: &str, start: usize) -> Option<Range<usize>>;
+}
+
+/// Matches the text of a pattern exactly.
+#[derive(Debug)]
+pub struct Exact;
+
+/// Matches the text of a pattern in any case.
+#[derive(Debug, Default)]
+pub struct CaseInsensitive {
+    compiled: CompiledPatterns,
+}
+
+/// Matches the text of a pattern, allowing any amount of whitespace wherever it has whitespace.
+#[derive(Debug, Default)]
+pub struct Whitespace {
+    compiled: CompiledPatterns,
+}
+
+/// The regexes that an engine compiled from the text of patterns, keyed on that text.
+#[derive(Debug, Default)]
+struct CompiledPatterns {
+    regexes: Mutex<HashMap<String, Regex>>,
+}
+
+impl Config {
+    /// Registers an engine that checks can select by its name, or that can be made the
+    /// default with `Config::default_matcher`.
+    ///
+    /// An engine with the same name as a builtin engine takes its place.
+    pub fn register_matcher(&mut self, matcher: impl Matcher + 'static) {
+        self.matchers.push(Arc::new(matcher));
+    }
+}
+
+/// Finds the engine with a name, which is either registered with the config or builtin.
+///
+/// Returns `Ok(None)` for regexes, which are matched by lit itself.
+pub fn find(name: &str, config: &Config) -> Result<Option<Arc<dyn Matcher>>, String> {
+    if let Some(matcher) = config.matchers.iter().rev().find(|matcher| matcher.name() == name) {
+        return Ok(Some(matcher.clone()));
+    }
+
+    match name {
+        REGEX => Ok(None),
+        "exact" => Ok(Some(Arc::new(Exact))),
+        "case-insensitive" => Ok(Some(CASE_INSENSITIVE.clone())),
+        "whitespace" => Ok(Some(WHITESPACE.clone())),
+        _ => {
+            let known_matchers = std::iter::once(REGEX).chain(BUILTIN_MATCHERS.iter().cloned()).chain(config.matchers.iter().map(|matcher| matcher.name()));
+
+            match crate::util::closest_match(name, known_matchers) {
+                Some(suggestion) => Err(format!("matcher '{}' not known, did you mean '{}'?", name, suggestion)),
+                None => Err(format!("matcher '{}' not known", name)),
+            }
+        },
+    }
+}
+
+impl Matcher for Exact {
+    fn name(&self) -> &str { "exact" }
+
+    fn find_at(&self, needle: &str, text: &str, start: usize) -> Option<Range<usize>> {
+        text[start..].find(needle).map(|idx| (start + idx)..(start + idx + needle.len()))
+    }
+}
+
+impl Matcher for CaseInsensitive {
+    fn name(&self) -> &str { "case-insensitive" }
+
+    fn find_at(&self, needle: &str, text: &str, start: usize) -> Option<Range<usize>> {
+        let regex = self.compiled.get_or_compile(needle, |needle| RegexBuilder::new(&regex::escape(needle)).case_insensitive(true).build())?;
+        regex.find_at(text, start).map(|regex_match| regex_match.range())
+    }
+}
+
+impl Matcher for Whitespace {
+    fn name(&self) -> &str { "whitespace" }
+
+    fn find_at(&self, needle: &str, text: &str, start: usize) -> Option<Range<usize>> {
+        let regex = self.compiled.get_or_compile(needle, |needle| {
+            let words: Vec<_> = needle.split_whitespace().map(regex::escape).collect();
+            Regex::new(&words.join(r"\s+"))
+        })?;
+        regex.find_at(text, start).map(|regex_match| regex_match.range())
+    }
+}
+
+impl CompiledPatterns {
+    /// Gets the regex compiled from the text of a pattern, compiling it if it is not known yet.
+    ///
+    /// Returns `None` if the regex is too large to compile.
+    fn get_or_compile(&self, needle: &str, compile: impl FnOnce(&str) -> Result<Regex, regex::Error>) -> Option<Regex> {
+        let mut regexes = self.regexes.lock().unwrap();
+
+        if let Some(regex) = regexes.get(needle) {
+            return Some(regex.clone());
+        }
+
+        let regex = compile(needle).ok()?;
+        // Patterns that substitute variables can produce new text on every run.
+        if regexes.len() >= MAX_COMPILED_PATTERNS {
+            regexes.clear();
+        }
+        regexes.insert(needle.to_owned(), regex.clone());
+        Some(regex)
+    }
+}
+
+/// Checks that the range an engine found is within the text searched, and on character boundaries.
+pub(crate) fn validate_range(matcher: &dyn Matcher, range: Range<usize>, text: &str, start: usize) -> Result<Range<usize>, String> {
+    let is_valid = start <= range.start && range.start <= range.end && range.end <= text.len() &&
+        text.is_char_boundary(range.start) && text.is_char_boundary(range.end);
+
+    if is_valid {
+        Ok(range)
+    } else {
+        Err(format!("matcher '{}' found the byte range {:?}, which is not within the {} bytes of output searched from byte {}",
+                    matcher.name(), range, text.len(), start))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builtin_matchers_find_byte_ranges_in_the_whole_text() {
+        let text = "Total:  3 ITEMS\ntotal: 3 items\n";
+
+        assert_eq!(Exact.find_at("total: 3", text, 0), Some(16..24));
+        assert_eq!(CaseInsensitive::default().find_at("TOTAL:  3 items", text, 0), Some(0..15));
+        assert_eq!(CaseInsensitive::default().find_at("TOTAL: 3 items", text, 0), Some(16..30));
+        assert_eq!(Whitespace::default().find_at("Total: 3 ITEMS", text, 0), Some(0..15));
+        assert_eq!(Whitespace::default().find_at("total:\t3", text, 1), Some(16..24));
+
+        assert!(find("regex", &Config::default()).unwrap().is_none());
+        assert_eq!(find("exct", &Config::default()).unwrap_err(), "matcher 'exct' not known, did you mean 'exact'?");
+    }
+
+    #[test]
+    fn patterns_are_compiled_once_per_engine() {
+        let matcher = Whitespace::default();
+        matcher.find_at("a b", "a  b", 0);
+        matcher.find_at("a b", "a\tb", 0);
+        matcher.find_at("c", "c", 0);
+
+        assert_eq!(matcher.compiled.regexes.lock().unwrap().len(), 2);
+        assert!(Arc::ptr_eq(&find("whitespace", &Config::default()).unwrap().unwrap(), &find("whitespace", &Config::default()).unwrap().unwrap()));
+    }
+
+    #[test]
+    fn ranges_outside_of_the_searched_text_are_rejected() {
+        let text = "héllo";
+
+        assert_eq!(validate_range(&Exact, 1..3, text, 1), Ok(1..3));
+        assert!(validate_range(&Exact, 0..3, text, 1).is_err());
+        assert!(validate_range(&Exact, 1..2, text, 1).is_err(), "the range ends within a character");
+        assert_eq!(validate_range(&Exact, 3..9, text, 0).unwrap_err(),
+                   "matcher 'exact' found the byte range 3..9, which is not within the 6 bytes of output searched from byte 0");
+    }
+}
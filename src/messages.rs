@@ -0,0 +1,181 @@
+//! User-facing messages, identified by stable IDs.
+//!
+//! Every message has an English template, which may refer to its arguments as `{0}`,
+//! `{1}` and so on. Embedders can reword or localize messages by overriding templates
+//! in `Config::messages`, or via the `[messages]` table of a config file.
+//!
+//! ```
+//! use lit::messages::MessageId;
+//!
+//! let mut config = lit::Config::default();
+//! config.messages.set(MessageId::CheckFailed, "texte attendu '{0}' introuvable");
+//!
+//! assert_eq!(config.messages.format(MessageId::CheckFailed, &[&"bonjour"]), "texte attendu 'bonjour' introuvable");
+//! ```
+
+use std::{collections::HashMap, fmt, fmt::Write};
+
+macro_rules! messages {
+    ($( $(#[$attr:meta])* $variant:ident => $id:expr, $template:expr; )*) => {
+        /// Identifies a user-facing message.
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum MessageId {
+            $( $(#[$attr])* $variant, )*
+        }
+
+        impl MessageId {
+            /// All messages, in declaration order.
+            pub const ALL: &[MessageId] = &[ $( MessageId::$variant, )* ];
+
+            /// Gets the stable ID of the message, such as `fail.check-failed`.
+            pub fn as_str(self) -> &'static str {
+                match self { $( MessageId::$variant => $id, )* }
+            }
+
+            /// Gets the English template of the message.
+            pub fn default_template(self) -> &'static str {
+                match self { $( MessageId::$variant => $template, )* }
+            }
+        }
+    };
+}
+
+messages! {
+    SummaryUnsuccessfulExecution => "summary.unsuccessful-execution", "unsuccessful program execution whilst running test";
+    SummaryUnexpectedSuccessfulExecution => "summary.unexpected-successful-execution",
+        "program execution succeeded whilst running test, but it was expected to fail";
+    SummaryOutputLimitExceeded => "summary.output-limit-exceeded", "program wrote more output than allowed whilst running test";
+    SummaryTimedOut => "summary.timed-out", "program ran for longer than allowed whilst running test";
+    SummaryCheckFailed => "summary.check-failed", "test checked for text that did not exist in the output";
+    SummaryDurationExceeded => "summary.duration-exceeded", "program took longer than expected whilst running test";
+    SummaryRssExceeded => "summary.rss-exceeded", "program used more memory than expected whilst running test";
+    SummaryStderrWritten => "summary.stderr-written", "program wrote to stderr whilst running test";
+    SummarySkippedPastEndOfOutput => "summary.skipped-past-end-of-output", "test skipped more lines than the output has";
+    SummaryReferenceOutputMismatch => "summary.reference-output-mismatch", "output did not match the output of the reference command";
+    SummaryGoldenFileMismatch => "summary.golden-file-mismatch", "output did not match the golden file";
+    SummaryForbiddenTextFound => "summary.forbidden-text-found", "test found text in the output that it forbids";
+    SummaryJsonMismatch => "summary.json-mismatch", "a value in the JSON output did not have the expected value";
+    SummaryInvalidJsonOutput => "summary.invalid-json-output", "test checked JSON output, but the output was not valid JSON";
+
+    /// Arguments: the command line, and the exit code.
+    UnsuccessfulExecution => "fail.unsuccessful-execution", "command '{0}' exited with code '{1}'";
+    /// Arguments: the command line.
+    UnexpectedSuccessfulExecution => "fail.unexpected-successful-execution", "command '{0}' was expected to fail, but exited with code '0'";
+    /// Arguments: the command line, and the limit in bytes.
+    OutputLimitExceeded => "fail.output-limit-exceeded", "command '{0}' was killed after writing more than {1} bytes of output";
+    /// Arguments: the command line, the timeout in seconds, and `terminated` or `killed`, depending on
+    /// whether the command exited when asked to, or had to be killed.
+    TimedOut => "fail.timed-out", "command '{0}' was {2} after running for longer than {1}s";
+    /// Arguments: the expected pattern.
+    CheckFailed => "fail.check-failed", "expected text '{0}' but that was not found";
+    /// Arguments: the command line, the expected limit, and how long the command took in seconds.
+    DurationExceeded => "fail.duration-exceeded", "command '{0}' took {2}s, but was expected to take less than {1}";
+    /// Arguments: the command line, the expected limit, and the peak resident set size in bytes.
+    RssExceeded => "fail.rss-exceeded", "command '{0}' used {2} bytes of memory at its peak, but was expected to use less than {1}";
+    /// Arguments: the command line.
+    StderrWritten => "fail.stderr-written", "command '{0}' exited successfully, but wrote to stderr";
+    /// Arguments: the number of lines to skip, and the number of lines that remained.
+    SkippedPastEndOfOutput => "fail.skipped-past-end-of-output", "expected to skip {0} lines of output, but only {1} remained";
+    /// Arguments: the reference command line.
+    ReferenceOutputMismatch => "fail.reference-output-mismatch", "output did not match the output of reference command '{0}'";
+    /// Arguments: the path of the golden file, and the number of the first line that differs.
+    GoldenFileMismatch => "fail.golden-file-mismatch", "output differs from golden file '{0}' on line {1}";
+    /// Arguments: the forbidden pattern, and the pattern ending its window.
+    ForbiddenTextFound => "fail.forbidden-text-found", "found forbidden text '{0}' before '{1}'";
+    /// Arguments: the JSON path, the expected value, and the actual value.
+    JsonMismatch => "fail.json-mismatch", "expected '{0}' to be {1}, but it was {2}";
+    /// Arguments: the JSON path, and the expected value.
+    JsonValueMissing => "fail.json-value-missing", "expected '{0}' to be {1}, but the output has no such value";
+    /// Arguments: the parse error.
+    InvalidJsonOutput => "fail.invalid-json-output", "output is not valid JSON: {0}";
+    UnexpectedPass => "fail.unexpected-pass", "test passed but it was declared with XFAIL";
+
+    NoTestsFound => "warning.no-tests-found", "could not find any tests";
+    /// Arguments: the revision.
+    NoChangedTests => "warning.no-changed-tests", "no tests are affected by changes since '{0}'";
+    /// Arguments: the one-based shard index, and the shard count.
+    EmptyShard => "warning.empty-shard", "shard {0}/{1} has no tests";
+    /// Arguments: the path of the test file.
+    TestFileModified => "warning.test-file-modified", "test file '{0}' was modified while the suite was running, so its results may be misleading";
+    /// Arguments: the warning, the number of times it was raised, and where it was first raised.
+    RepeatedWarning => "warning.repeated", "warning '{0}' occurred {1} times (first at {2})";
+    /// Arguments: the build command line, and its exit code.
+    BuildFailed => "error.build-failed", "build command '{0}' exited with code '{1}'";
+    /// Arguments: the value, such as `tools.python3`, its required value, and its actual value.
+    EnvironmentMismatch => "error.environment-mismatch", "the environment differs from the required environment: '{0}' is '{2}', but '{1}' is required";
+    /// Arguments: the quoted names of the missing features.
+    MissingFeatures => "warning.missing-features", "skipped, as the required features {0} are not available";
+}
+
+/// The templates of all messages, including any overrides.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Catalog {
+    overrides: HashMap<MessageId, String>,
+}
+
+impl MessageId {
+    /// Finds a message by its stable ID.
+    pub fn parse(id: &str) -> Option<MessageId> {
+        MessageId::ALL.iter().cloned().find(|m| m.as_str() == id)
+    }
+}
+
+impl Catalog {
+    /// Overrides the template of a message.
+    pub fn set(&mut self, id: MessageId, template: impl Into<String>) {
+        self.overrides.insert(id, template.into());
+    }
+
+    /// Gets the template of a message.
+    pub fn template(&self, id: MessageId) -> &str {
+        self.overrides.get(&id).map(|t| &t[..]).unwrap_or_else(|| id.default_template())
+    }
+
+    /// Formats a message, replacing each `{N}` in its template with the `N`th argument.
+    ///
+    /// Placeholders without a matching argument are left as they are.
+    pub fn format(&self, id: MessageId, arguments: &[&dyn fmt::Display]) -> String {
+        let mut remaining = self.template(id);
+        let mut formatted = String::new();
+
+        while let Some(open_idx) = remaining.find('{') {
+            formatted.push_str(&remaining[..open_idx]);
+            remaining = &remaining[open_idx..];
+
+            let placeholder = remaining.find('}').and_then(|close_idx| {
+                let argument = arguments.get(remaining[1..close_idx].parse::<usize>().ok()?)?;
+                Some((argument, close_idx))
+            });
+
+            match placeholder {
+                Some((argument, close_idx)) => {
+                    write!(formatted, "{}", argument).unwrap();
+                    remaining = &remaining[close_idx + 1..];
+                },
+                None => {
+                    formatted.push('{');
+                    remaining = &remaining[1..];
+                },
+            }
+        }
+
+        formatted.push_str(remaining);
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_overridden_templates() {
+        let mut catalog = Catalog::default();
+        assert_eq!(catalog.format(MessageId::EmptyShard, &[&2, &4]), "shard 2/4 has no tests");
+
+        catalog.set(MessageId::EmptyShard, "{1} shards, {0} is empty {2} {x}");
+        assert_eq!(catalog.format(MessageId::EmptyShard, &[&2, &4]), "4 shards, 2 is empty {2} {x}");
+        assert_eq!(MessageId::parse("warning.empty-shard"), Some(MessageId::EmptyShard));
+        assert!(MessageId::ALL.iter().all(|&id| MessageId::parse(id.as_str()) == Some(id)));
+    }
+}
@@ -1,13 +1,17 @@
-use crate::{run, util, Config, Variables};
-use std::{fmt, path::PathBuf};
+use crate::{diff, format::{self, OutputOptions, TruncateDirection}, json, messages::{self, MessageId}, run, Config, Variables};
+use std::{fmt, path::PathBuf, sync::Arc, time::Duration};
 use std::fmt::Write;
 
 /// A tool invocation.
 #[derive(Clone,Debug,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Invocation
 {
     /// The original command string.
     pub original_command: String,
+    /// Whether the command is expected to exit unsuccessfully, declared via `RUN: !<command>`
+    /// or `NOT-RUN`.
+    pub negated: bool,
 }
 
 // TODO: rename to TestFile
@@ -16,6 +20,26 @@ pub struct TestFile
 {
     pub path: TestFilePath,
     pub commands: Vec<Command>,
+    /// The settings declared in the front matter block at the top of the file, if any.
+    pub front_matter: FrontMatter,
+}
+
+/// Per-test settings declared in a front matter block at the top of a test file.
+///
+/// See `parse::front_matter` for the syntax.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrontMatter {
+    /// Environment variables set for every command of the test.
+    pub env: Variables,
+    /// How long each command of the test may run for before it is killed.
+    pub timeout: Option<Duration>,
+    /// Free-form labels, such as `slow`.
+    pub tags: Vec<String>,
+    /// Features that must be listed in `Config::available_features` for the test to run.
+    /// Otherwise the test is skipped.
+    pub requires: Vec<String>,
+    /// Constants visible only to this test, taking precedence over the constants of the config.
+    pub constants: Variables,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -26,37 +50,141 @@ pub struct TestFilePath {
 }
 
 #[derive(Clone,Debug,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Command
 {
     pub line_number: u32,
     pub kind: CommandKind,
+    /// The byte span of the directive within the test file.
+    pub span: Span,
+    /// Where the directive was written, if it was included from another file with `INCLUDE`.
+    ///
+    /// The line number and span of an included directive are those of the `INCLUDE` directive.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub origin: Option<Origin>,
+}
+
+/// The file and line that a directive included from another file was written on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Origin {
+    /// The path of the included file, found relative to the file that included it.
+    pub path: PathBuf,
+    pub line_number: u32,
+}
+
+/// A range of bytes within a piece of text.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    /// The index of the first byte.
+    pub start: usize,
+    /// The index one past the last byte.
+    pub end: usize,
 }
 
 #[derive(Clone,Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CommandKind
 {
     /// Run an external tool.
     Run(Invocation),
+    /// Spawn a long-lived external tool in the background, killed once the test finishes.
+    RunBackground(Invocation),
+    /// Wait until the output of the most recent background tool matches an expression.
+    WaitFor(TextPattern),
+    /// Hold a named resource for the duration of the test.
+    Resource(String),
+    /// Declare that the test depends on a file or directory, relative to the test file.
+    Depends(String),
+    /// Declare the person or team responsible for the test.
+    Owner(String),
+    /// Splice the directives of another file, relative to the test file, into the test.
+    ///
+    /// Includes are expanded as test files are parsed, so parsed tests never hold them.
+    Include(String),
     /// Verify that the output text matches an expression.
     Check(TextPattern),
     /// Verify that the very next output line matches an expression.
     CheckNext(TextPattern),
+    /// Verify that the output text matches a multi-line expression.
+    CheckBlock(TextPattern),
+    /// Verify that the output is identical to the output of a reference command.
+    CheckSameAs(Invocation),
+    /// Verify that the output is identical to the contents of a golden file, relative to the test file.
+    CheckGolden(String),
+    /// Verify that a value in the JSON output has the expected value.
+    CheckJson(JsonCheck),
+    /// Verify that an expression does not match the output before the next `CHECK-END`.
+    CheckNotBetween(TextPattern),
+    /// Verify that the output matches an expression, closing a `CHECK-NOT-BETWEEN` window.
+    CheckEnd(TextPattern),
+    /// Skip exactly a number of output lines before the next check.
+    CheckSkip(usize),
+    /// Verify that a command took less than a duration to run.
+    ExpectDurationUnder(Duration),
+    /// Verify that the peak resident set size of a command stayed below a number of bytes.
+    ExpectRssUnder(u64),
+    /// Clear a variable captured by an earlier check.
+    Undef(String),
+    /// Enable a builtin output normalizer preset for the test, such as `timestamps`.
+    NormalizePreset(String),
+    /// A directive defined by a plugin.
+    Custom(CustomDirective),
     /// Mark the test as supposed to fail.
     XFail,
+    /// Allow the `CHECK` directives of the test to match the output in any order.
+    CheckUnordered,
+    /// Run the `RUN` commands of the test attached to a pseudo-terminal.
+    Pty,
+    /// Allow the `RUN` commands of the test to write to stderr, regardless of `Config::fail_on_stderr`.
+    AllowStderr,
+}
+
+/// A `CHECK-JSON` directive, such as `CHECK-JSON: $.items[0].name == "lit"`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JsonCheck {
+    /// The path of the checked value within the output, such as `$.items[0].name`.
+    pub path: String,
+    /// The expected value, as JSON text.
+    pub expected: String,
+}
+
+/// A directive that is evaluated by a plugin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CustomDirective {
+    /// The name of the directive, such as `CHECK-YAML`.
+    pub name: String,
+    /// The text after the colon of the directive.
+    pub argument: String,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TextPattern {
     pub components: Vec<PatternComponent>,
+    /// The name of the engine that matches the pattern, as in `CHECK[exact]:`, or `None`
+    /// to use `Config::default_matcher`. See `lit::matcher`.
+    pub matcher: Option<String>,
+    /// Whether the pattern only matches at the start of a line, as in `CHECK{^}:`, rather
+    /// than anywhere in the output. See also `Config::anchor_checks_to_line_start`.
+    pub anchored: bool,
 }
 
 /// A component in a text pattern.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PatternComponent {
     Text(String),
     Variable(String),
     Regex(String),
     NamedRegex { name: String, regex: String },
+    /// A number printed in a `printf`-style format, such as `[[#%.3f, 1.234 +- 0.001]]`.
+    ///
+    /// The values are kept as written, and are validated when the pattern is resolved.
+    Numeric { format: String, value: String, tolerance: Option<String> },
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -72,6 +200,8 @@ pub enum TestResultKind
     /// The test failed.
     Fail {
         reason: TestFailReason,
+        /// A sentence explaining the failure, which may be followed by lines comparing what
+        /// was expected with the output, prefixed by `-` and `+` like a diff.
         hint: Option<String>,
     },
     /// The test was expected to fail and it did.
@@ -81,6 +211,9 @@ pub enum TestResultKind
     EmptyTest,
     /// The test was skipped.
     Skip,
+    /// The test was never started, as the suite ran out of time or was cancelled. See
+    /// `Config::suite_timeout` and `Config::cancellation_token`.
+    NotRun,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -89,92 +222,424 @@ pub enum TestFailReason {
         program_command_line: String,
         exit_status: i32,
     },
+    /// A negated command, such as `RUN: !<command>`, exited successfully.
+    UnexpectedSuccessfulExecution {
+        program_command_line: String,
+    },
+    /// A command wrote more than `Config::max_output_bytes` to stdout or stderr, and was killed.
+    OutputLimitExceeded {
+        program_command_line: String,
+        limit: usize,
+    },
+    /// A command ran for longer than the `timeout` declared in the front matter of its test, and was killed.
+    TimedOut {
+        program_command_line: String,
+        timeout: Duration,
+        /// Whether the program had to be killed, as it did not exit within the
+        /// grace period after it was asked to terminate.
+        killed: bool,
+    },
     CheckFailed(CheckFailureInfo),
+    /// A command took longer to run than an `EXPECT-DURATION-UNDER` directive allows.
+    DurationExceeded {
+        program_command_line: String,
+        limit: Duration,
+        duration: Duration,
+    },
+    /// The peak resident set size of a command was above what an `EXPECT-RSS-UNDER` directive allows.
+    RssExceeded {
+        program_command_line: String,
+        limit_bytes: u64,
+        max_rss_bytes: u64,
+    },
+    /// A command exited successfully but wrote to stderr, and `Config::fail_on_stderr` is set.
+    StderrWritten {
+        program_command_line: String,
+        stderr: String,
+    },
+    /// A `CHECK-SKIP` directive skipped more lines than were left in the output.
+    SkippedPastEndOfOutput {
+        line_count: usize,
+        remaining_line_count: usize,
+    },
+    /// The output differed from the output of a `CHECK-SAME-AS` reference command.
+    ReferenceOutputMismatch {
+        reference_command_line: String,
+        expected_output: String,
+        actual_output: String,
+    },
+    /// The output differed from the contents of a `CHECK-GOLDEN` file.
+    GoldenFileMismatch {
+        path: String,
+        /// The one-based number of the first line that differs.
+        line_number: usize,
+        /// The line of the golden file, or `None` if the file ended before it.
+        expected_line: Option<String>,
+        /// The line of the output, or `None` if the output ended before it.
+        actual_line: Option<String>,
+    },
+    /// A value in the JSON output differed from the value expected by a `CHECK-JSON` directive.
+    JsonMismatch {
+        path: String,
+        expected: String,
+        /// The actual value as JSON text, or `None` if the output has no value at the path.
+        actual: Option<String>,
+    },
+    /// The output was not valid JSON, but a `CHECK-JSON` directive needed it to be.
+    InvalidJsonOutput {
+        error: String,
+        output: String,
+    },
+    /// Text forbidden by a `CHECK-NOT-BETWEEN` directive appeared before the end of its window.
+    ForbiddenTextFound {
+        forbidden_pattern: TextPattern,
+        window_end_pattern: TextPattern,
+        /// The output between the start of the window and the `CHECK-END` match.
+        window_text: String,
+        /// The one-based output line that the window starts on.
+        window_start_line_number: usize,
+    },
 }
 
 impl TestFailReason {
+    /// Gets the kind of directive that failed.
+    fn directive_name(&self) -> &'static str {
+        match *self {
+            TestFailReason::UnsuccessfulExecution { .. } |
+                TestFailReason::UnexpectedSuccessfulExecution { .. } |
+                TestFailReason::OutputLimitExceeded { .. } |
+                TestFailReason::TimedOut { .. } |
+                TestFailReason::StderrWritten { .. } => "RUN",
+            TestFailReason::CheckFailed(..) => "CHECK",
+            TestFailReason::SkippedPastEndOfOutput { .. } => "CHECK-SKIP",
+            TestFailReason::DurationExceeded { .. } => "EXPECT-DURATION-UNDER",
+            TestFailReason::RssExceeded { .. } => "EXPECT-RSS-UNDER",
+            TestFailReason::ReferenceOutputMismatch { .. } => "CHECK-SAME-AS",
+            TestFailReason::GoldenFileMismatch { .. } => "CHECK-GOLDEN",
+            TestFailReason::JsonMismatch { .. } | TestFailReason::InvalidJsonOutput { .. } => "CHECK-JSON",
+            TestFailReason::ForbiddenTextFound { .. } => "CHECK-NOT-BETWEEN",
+        }
+    }
+
+    /// Gets the message summarizing this kind of failure.
+    pub fn summary_id(&self) -> MessageId {
+        match *self {
+            TestFailReason::UnsuccessfulExecution { .. } => MessageId::SummaryUnsuccessfulExecution,
+            TestFailReason::UnexpectedSuccessfulExecution { .. } => MessageId::SummaryUnexpectedSuccessfulExecution,
+            TestFailReason::OutputLimitExceeded { .. } => MessageId::SummaryOutputLimitExceeded,
+            TestFailReason::TimedOut { .. } => MessageId::SummaryTimedOut,
+            TestFailReason::CheckFailed(..) => MessageId::SummaryCheckFailed,
+            TestFailReason::SkippedPastEndOfOutput { .. } => MessageId::SummarySkippedPastEndOfOutput,
+            TestFailReason::DurationExceeded { .. } => MessageId::SummaryDurationExceeded,
+            TestFailReason::RssExceeded { .. } => MessageId::SummaryRssExceeded,
+            TestFailReason::StderrWritten { .. } => MessageId::SummaryStderrWritten,
+            TestFailReason::ReferenceOutputMismatch { .. } => MessageId::SummaryReferenceOutputMismatch,
+            TestFailReason::GoldenFileMismatch { .. } => MessageId::SummaryGoldenFileMismatch,
+            TestFailReason::JsonMismatch { .. } => MessageId::SummaryJsonMismatch,
+            TestFailReason::InvalidJsonOutput { .. } => MessageId::SummaryInvalidJsonOutput,
+            TestFailReason::ForbiddenTextFound { .. } => MessageId::SummaryForbiddenTextFound,
+        }
+    }
+
+    /// Gets the single-line message describing the failure.
+    pub fn message_id(&self) -> MessageId {
+        match *self {
+            TestFailReason::UnsuccessfulExecution { .. } => MessageId::UnsuccessfulExecution,
+            TestFailReason::UnexpectedSuccessfulExecution { .. } => MessageId::UnexpectedSuccessfulExecution,
+            TestFailReason::OutputLimitExceeded { .. } => MessageId::OutputLimitExceeded,
+            TestFailReason::TimedOut { .. } => MessageId::TimedOut,
+            TestFailReason::CheckFailed(..) => MessageId::CheckFailed,
+            TestFailReason::SkippedPastEndOfOutput { .. } => MessageId::SkippedPastEndOfOutput,
+            TestFailReason::DurationExceeded { .. } => MessageId::DurationExceeded,
+            TestFailReason::RssExceeded { .. } => MessageId::RssExceeded,
+            TestFailReason::StderrWritten { .. } => MessageId::StderrWritten,
+            TestFailReason::ReferenceOutputMismatch { .. } => MessageId::ReferenceOutputMismatch,
+            TestFailReason::GoldenFileMismatch { .. } => MessageId::GoldenFileMismatch,
+            TestFailReason::JsonMismatch { actual: Some(..), .. } => MessageId::JsonMismatch,
+            TestFailReason::JsonMismatch { actual: None, .. } => MessageId::JsonValueMissing,
+            TestFailReason::InvalidJsonOutput { .. } => MessageId::InvalidJsonOutput,
+            TestFailReason::ForbiddenTextFound { .. } => MessageId::ForbiddenTextFound,
+        }
+    }
+
     pub fn human_summary(&self) -> &'static str {
+        self.summary_id().default_template()
+    }
+
+    /// Gets a single-line description of the failure, in English.
+    pub fn short_message(&self) -> String {
+        self.short_message_in(&messages::Catalog::default())
+    }
+
+    /// Gets a single-line description of the failure, using the templates of a message catalog.
+    pub fn short_message_in(&self, messages: &messages::Catalog) -> String {
         match *self {
-            TestFailReason::UnsuccessfulExecution { .. } => {
-                "unsuccessful program execution whilst running test"
+            TestFailReason::UnsuccessfulExecution { ref program_command_line, exit_status } => {
+                messages.format(self.message_id(), &[program_command_line, &exit_status])
+            },
+            TestFailReason::UnexpectedSuccessfulExecution { ref program_command_line } => {
+                messages.format(self.message_id(), &[program_command_line])
+            },
+            TestFailReason::OutputLimitExceeded { ref program_command_line, limit } => {
+                messages.format(self.message_id(), &[program_command_line, &limit])
+            },
+            TestFailReason::TimedOut { ref program_command_line, timeout, killed } => {
+                let stopped = if killed { "killed" } else { "terminated" };
+                messages.format(self.message_id(), &[program_command_line, &timeout.as_secs_f64(), &stopped])
+            },
+            TestFailReason::CheckFailed(ref check_failure_info) => {
+                messages.format(self.message_id(), &[&check_failure_info.expected_pattern])
+            },
+            TestFailReason::DurationExceeded { ref program_command_line, limit, duration } => {
+                let duration = format!("{:.3}", duration.as_secs_f64());
+                messages.format(self.message_id(), &[program_command_line, &crate::util::format_duration(limit), &duration])
             },
-            TestFailReason::CheckFailed(..) => {
-                "test checked for text that did not exist in the output"
+            TestFailReason::RssExceeded { ref program_command_line, limit_bytes, max_rss_bytes } => {
+                messages.format(self.message_id(), &[program_command_line, &crate::util::format_byte_size(limit_bytes), &max_rss_bytes])
+            },
+            TestFailReason::StderrWritten { ref program_command_line, .. } => messages.format(self.message_id(), &[program_command_line]),
+            TestFailReason::SkippedPastEndOfOutput { line_count, remaining_line_count } => {
+                messages.format(self.message_id(), &[&line_count, &remaining_line_count])
+            },
+            TestFailReason::ReferenceOutputMismatch { ref reference_command_line, .. } => {
+                messages.format(self.message_id(), &[reference_command_line])
+            },
+            TestFailReason::GoldenFileMismatch { ref path, line_number, .. } => {
+                messages.format(self.message_id(), &[path, &line_number])
+            },
+            TestFailReason::JsonMismatch { ref path, ref expected, ref actual } => {
+                messages.format(self.message_id(), &[path, expected, actual.as_ref().unwrap_or(&String::new())])
+            },
+            TestFailReason::InvalidJsonOutput { ref error, .. } => messages.format(self.message_id(), &[error]),
+            TestFailReason::ForbiddenTextFound { ref forbidden_pattern, ref window_end_pattern, .. } => {
+                messages.format(self.message_id(), &[forbidden_pattern, window_end_pattern])
             },
         }
     }
 
     pub fn human_detail_message(&self, config: &Config) -> String {
         match *self {
-            TestFailReason::UnsuccessfulExecution { ref program_command_line, exit_status } => {
-                format!("command '{}' exited with code '{}'", program_command_line, exit_status)
-            },
+            TestFailReason::UnsuccessfulExecution { .. } |
+                TestFailReason::UnexpectedSuccessfulExecution { .. } |
+                TestFailReason::OutputLimitExceeded { .. } |
+                TestFailReason::TimedOut { .. } |
+                TestFailReason::DurationExceeded { .. } |
+                TestFailReason::RssExceeded { .. } |
+                TestFailReason::SkippedPastEndOfOutput { .. } |
+                TestFailReason::JsonMismatch { .. } => self.short_message_in(&config.messages),
             TestFailReason::CheckFailed(ref check_failure_info) => {
                 let mut buf = String::new();
-                writeln!(&mut buf, "expected text '{}' but that was not found", check_failure_info.expected_pattern).unwrap();
+                writeln!(&mut buf, "{}", self.short_message_in(&config.messages)).unwrap();
                 writeln!(&mut buf).unwrap();
 
                 // Write the successfully checked output.
-                writeln!(&mut buf, "{}", format_test_output("successfully checked output",
-                        check_failure_info.successfully_checked_text(), 1, util::TruncateDirection::Top,
-                        config)).unwrap();
+                writeln!(&mut buf, "{}", format::test_output("successfully checked output",
+                        check_failure_info.successfully_checked_text(),
+                        &OutputOptions::for_config(config, TruncateDirection::Top))).unwrap();
 
                 writeln!(&mut buf).unwrap();
 
                 // Write the remaining unchecked output.
-                writeln!(&mut buf, "{}", format_test_output("remaining unchecked output",
+                writeln!(&mut buf, "{}", format::test_output("remaining unchecked output",
                         check_failure_info.remaining_text(),
-                        check_failure_info.successfully_checked_upto_line_number(), util::TruncateDirection::Bottom,
-                        config)).unwrap();
+                        &OutputOptions::for_config(config, TruncateDirection::Bottom)
+                            .starting_at_line(check_failure_info.successfully_checked_upto_line_number()))).unwrap();
 
                 buf
             },
-        }
-    }
-}
+            TestFailReason::ReferenceOutputMismatch { ref expected_output, ref actual_output, .. } => {
+                let mut buf = String::new();
+                writeln!(&mut buf, "{}", self.short_message_in(&config.messages)).unwrap();
+                writeln!(&mut buf).unwrap();
 
-pub(crate) fn format_test_output(
-    output_label: &str,
-    unformatted_output: &str,
-    output_base_line_number: usize,
-    truncate_direction: util::TruncateDirection,
-    config: &Config) -> String {
-    let mut formatted_output = util::decorate_with_line_numbers(unformatted_output, output_base_line_number);
+                let mut rendered_diff = diff::render(&crate::terminal::present(expected_output, config.control_sequences_in_context),
+                                                     &crate::terminal::present(actual_output, config.control_sequences_in_context));
+                if let Some(max_line_count) = config.truncate_output_context_to_number_of_lines {
+                    let truncate_direction = format::truncate_direction(TruncateDirection::Bottom, config);
+                    rendered_diff = format::truncate_to_max_lines(&rendered_diff, max_line_count, truncate_direction);
+                }
+                // N.B. not using `format::indent` here, it would trim the diff markers.
+                let rendered_diff = rendered_diff.lines().map(|l| format!("  {}", l)).collect::<Vec<_>>().join("\n");
+                writeln!(&mut buf, "<diff (- reference, + actual)>:\n\n{}\n</diff>", rendered_diff).unwrap();
 
-    if let Some(max_line_count) = config.truncate_output_context_to_number_of_lines {
-        formatted_output = util::truncate_to_max_lines(&formatted_output, max_line_count, truncate_direction);
-    }
-    let formatted_output = util::indent(&formatted_output, 1);
+                buf
+            },
+            TestFailReason::GoldenFileMismatch { ref expected_line, ref actual_line, .. } => {
+                let mut buf = String::new();
+                writeln!(&mut buf, "{}", self.short_message_in(&config.messages)).unwrap();
+                writeln!(&mut buf).unwrap();
+
+                let present = |line: &Option<String>| match *line {
+                    Some(ref line) => format!("'{}'", crate::terminal::present(line, config.control_sequences_in_context)),
+                    None => "<end of file>".to_owned(),
+                };
+                writeln!(&mut buf, "  expected: {}", present(expected_line)).unwrap();
+                writeln!(&mut buf, "  actual:   {}", present(actual_line)).unwrap();
 
-    format!("<{}>:\n\n{}\n</{}>", output_label, formatted_output, output_label)
+                buf
+            },
+            TestFailReason::StderrWritten { ref stderr, .. } => {
+                let mut buf = String::new();
+                writeln!(&mut buf, "{}", self.short_message_in(&config.messages)).unwrap();
+                writeln!(&mut buf).unwrap();
+
+                writeln!(&mut buf, "{}", format::test_output("stderr", stderr, &OutputOptions::for_config(config, TruncateDirection::Bottom))).unwrap();
+
+                buf
+            },
+            TestFailReason::InvalidJsonOutput { ref output, .. } => {
+                let mut buf = String::new();
+                writeln!(&mut buf, "{}", self.short_message_in(&config.messages)).unwrap();
+                writeln!(&mut buf).unwrap();
+
+                writeln!(&mut buf, "{}", format::test_output("output", output, &OutputOptions::for_config(config, TruncateDirection::Bottom))).unwrap();
+
+                buf
+            },
+            TestFailReason::ForbiddenTextFound { ref window_text, window_start_line_number, .. } => {
+                let mut buf = String::new();
+                writeln!(&mut buf, "{}", self.short_message_in(&config.messages)).unwrap();
+                writeln!(&mut buf).unwrap();
+
+                writeln!(&mut buf, "{}", format::test_output("checked window", window_text,
+                        &OutputOptions::for_config(config, TruncateDirection::Bottom).starting_at_line(window_start_line_number))).unwrap();
+
+                buf
+            },
+        }
+    }
 }
 
 /// Information about a failed check in a test.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct CheckFailureInfo {
-    pub complete_output_text: String,
+    /// The complete output of the program, shared with the `ProgramOutput`.
+    pub complete_output_text: Arc<str>,
     pub successfully_checked_until_byte_index: usize,
     pub expected_pattern: TextPattern,
 }
 
 /// Results from executing a test.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TestResult
 {
     /// A path to the test.
     pub path: TestFilePath,
     /// The kind of result.
     pub overall_result: TestResultKind,
-    pub individual_run_results: Vec<(TestResultKind, Invocation, run::CommandLine, ProgramOutput)>,
+    pub individual_run_results: Vec<RunResult>,
+    /// How long it took to execute all `RUN` commands of the test.
+    pub duration: Duration,
+    /// The people or teams responsible for the test, if known.
+    pub owners: Vec<String>,
+    /// The history of the test including this run, if a timing history is kept.
+    pub history: Option<run::TestHistory>,
+    /// The shell the test was run with, if the suite is run across a shell matrix.
+    pub shell: Option<String>,
+    /// The absolute path of the directory the artifacts of the test were saved to, if any.
+    pub artifacts_directory: Option<PathBuf>,
+}
+
+/// The result of executing a single `RUN` command of a test.
+#[derive(Clone, Debug)]
+pub struct RunResult
+{
+    /// The kind of result.
+    pub kind: TestResultKind,
+    /// The `RUN` directive that was executed.
+    pub invocation: Invocation,
+    /// The command line after variable resolution.
+    pub command_line: run::CommandLine,
+    /// The output of the program.
+    pub output: ProgramOutput,
+    /// Variables whose values were only decided at run time, such as `@free_port`.
+    pub runtime_variables: Variables,
+    /// The environment the command was executed with.
+    pub environment: Variables,
+    /// The line of the directive responsible for the result.
+    ///
+    /// This is the failing directive when one is known, otherwise the `RUN` directive itself.
+    pub directive_line_number: u32,
+    /// The output regions matched by each passing check, in order.
+    pub check_matches: Vec<CheckMatch>,
+    /// Problems with the test that did not affect its result.
+    pub warnings: Vec<String>,
+    /// The absolute path of the directory the artifacts of the run were saved to, if any.
+    pub artifacts_directory: Option<PathBuf>,
+    /// How long each phase of the run took.
+    pub phase_durations: PhaseDurations,
+    /// The resources used by the program, if it exited and the platform reports them.
+    pub resource_usage: Option<ResourceUsage>,
 }
 
+/// The resources used by the program of a run, including any processes it waited for,
+/// for telling CPU-bound slow tests apart from those that wait on I/O. Only reported on Unix.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceUsage
+{
+    /// The CPU time spent executing the program itself.
+    pub user_time: Duration,
+    /// The CPU time the operating system spent on behalf of the program.
+    pub system_time: Duration,
+    /// The peak resident set size of the program, in bytes.
+    pub max_rss_bytes: u64,
+}
+
+/// How long each phase of a run took, for finding out where the time of a slow test goes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PhaseDurations
+{
+    /// Starting the program.
+    pub spawn: Duration,
+    /// Waiting for the program to exit and reading its output.
+    ///
+    /// For runs whose checks are evaluated while the program is still writing output, this
+    /// includes the checks.
+    pub wait: Duration,
+    /// Evaluating the checks against the output of the program.
+    pub check: Duration,
+}
+
+/// The region of program output that satisfied a passing check.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct ProgramOutput {
-    pub stdout: String,
-    pub stderr: String,
+pub struct CheckMatch
+{
+    /// The line of the check directive in the test file.
+    pub directive_line_number: u32,
+    /// The matched bytes within stdout.
+    pub span: Span,
+    /// The one-based line of stdout the match starts on.
+    pub start_line: usize,
+    /// The one-based line of stdout the match ends on, inclusive.
+    pub end_line: usize,
 }
 
+/// A test that behaved differently under the comparison config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigurationDifference
+{
+    /// A path to the test.
+    pub path: TestFilePath,
+    /// The result under the primary config.
+    pub primary_result: TestResultKind,
+    /// The result under the comparison config.
+    pub comparison_result: TestResultKind,
+    /// Whether the normalized program output differed between the configs.
+    pub output_differs: bool,
+}
 
+/// The output streams of an executed program.
+///
+/// The streams are reference counted so that they can be cheaply shared
+/// with check failure information without duplicating large outputs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramOutput {
+    pub stdout: Arc<str>,
+    pub stderr: Arc<str>,
+}
+
+/// Results from executing a test suite.
+#[deprecated(note = "results are reported through `EventHandler::on_test_finished`, this type is never constructed by lit")]
 #[derive(Debug)]
 pub struct Results
 {
@@ -185,9 +650,30 @@ impl PartialEq for CommandKind {
     fn eq(&self, other: &CommandKind) -> bool {
         match *self {
             CommandKind::Run(ref a) => if let CommandKind::Run(ref b) = *other { a == b } else { false },
-            CommandKind::Check(ref a) => if let CommandKind::Check(ref b) = *other { a.to_string() == b.to_string() } else { false },
-            CommandKind::CheckNext(ref a) => if let CommandKind::CheckNext(ref b) = *other { a.to_string() == b.to_string() } else { false },
-            CommandKind::XFail => *other == CommandKind::XFail,
+            CommandKind::RunBackground(ref a) => if let CommandKind::RunBackground(ref b) = *other { a == b } else { false },
+            CommandKind::Resource(ref a) => if let CommandKind::Resource(ref b) = *other { a == b } else { false },
+            CommandKind::Depends(ref a) => if let CommandKind::Depends(ref b) = *other { a == b } else { false },
+            CommandKind::Owner(ref a) => if let CommandKind::Owner(ref b) = *other { a == b } else { false },
+            CommandKind::Include(ref a) => if let CommandKind::Include(ref b) = *other { a == b } else { false },
+            CommandKind::WaitFor(ref a) => if let CommandKind::WaitFor(ref b) = *other { a == b } else { false },
+            CommandKind::Check(ref a) => if let CommandKind::Check(ref b) = *other { a == b } else { false },
+            CommandKind::CheckNext(ref a) => if let CommandKind::CheckNext(ref b) = *other { a == b } else { false },
+            CommandKind::CheckBlock(ref a) => if let CommandKind::CheckBlock(ref b) = *other { a == b } else { false },
+            CommandKind::CheckSameAs(ref a) => if let CommandKind::CheckSameAs(ref b) = *other { a == b } else { false },
+            CommandKind::CheckGolden(ref a) => if let CommandKind::CheckGolden(ref b) = *other { a == b } else { false },
+            CommandKind::CheckJson(ref a) => if let CommandKind::CheckJson(ref b) = *other { a == b } else { false },
+            CommandKind::CheckNotBetween(ref a) => if let CommandKind::CheckNotBetween(ref b) = *other { a == b } else { false },
+            CommandKind::CheckEnd(ref a) => if let CommandKind::CheckEnd(ref b) = *other { a == b } else { false },
+            CommandKind::CheckSkip(a) => if let CommandKind::CheckSkip(b) = *other { a == b } else { false },
+            CommandKind::ExpectDurationUnder(a) => if let CommandKind::ExpectDurationUnder(b) = *other { a == b } else { false },
+            CommandKind::ExpectRssUnder(a) => if let CommandKind::ExpectRssUnder(b) = *other { a == b } else { false },
+            CommandKind::Undef(ref a) => if let CommandKind::Undef(ref b) = *other { a == b } else { false },
+            CommandKind::NormalizePreset(ref a) => if let CommandKind::NormalizePreset(ref b) = *other { a == b } else { false },
+            CommandKind::Custom(ref a) => if let CommandKind::Custom(ref b) = *other { a == b } else { false },
+            CommandKind::XFail => matches!(*other, CommandKind::XFail),
+            CommandKind::CheckUnordered => matches!(*other, CommandKind::CheckUnordered),
+            CommandKind::Pty => matches!(*other, CommandKind::Pty),
+            CommandKind::AllowStderr => matches!(*other, CommandKind::AllowStderr),
         }
     }
 }
@@ -202,6 +688,8 @@ impl fmt::Display for TextPattern {
                 PatternComponent::Variable(ref name) => write!(fmt, "$${}", name)?,
                 PatternComponent::Regex(ref regex) => write!(fmt, "[[{}]]", regex)?,
                 PatternComponent::NamedRegex { ref name, ref regex } => write!(fmt, "[[{}:{}]]", name, regex)?,
+                PatternComponent::Numeric { ref format, ref value, tolerance: Some(ref tolerance) } => write!(fmt, "[[#{}, {} +- {}]]", format, value, tolerance)?,
+                PatternComponent::Numeric { ref format, ref value, tolerance: None } => write!(fmt, "[[#{}, {}]]", format, value)?,
             }
         }
 
@@ -209,10 +697,131 @@ impl fmt::Display for TextPattern {
     }
 }
 
+impl fmt::Display for CommandKind {
+    /// Formats the command as the directive it was parsed from, without any comment leader.
+    ///
+    /// `parse::command_kind` parses the text back into an identical command.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let argument = match *self {
+            CommandKind::Run(ref invocation) if invocation.negated => format!("!{}", invocation.original_command),
+            CommandKind::Run(ref invocation) | CommandKind::RunBackground(ref invocation) |
+                CommandKind::CheckSameAs(ref invocation) => invocation.original_command.clone(),
+            CommandKind::WaitFor(ref pattern) | CommandKind::Check(ref pattern) | CommandKind::CheckNext(ref pattern) |
+                CommandKind::CheckNotBetween(ref pattern) | CommandKind::CheckEnd(ref pattern) => pattern.to_string(),
+            CommandKind::Resource(ref name) | CommandKind::Depends(ref name) | CommandKind::Owner(ref name) |
+                CommandKind::Include(ref name) | CommandKind::CheckGolden(ref name) | CommandKind::Undef(ref name) | CommandKind::NormalizePreset(ref name) => name.clone(),
+            CommandKind::CheckJson(ref check) => format!("{} == {}", check.path, check.expected),
+            CommandKind::CheckSkip(line_count) => line_count.to_string(),
+            CommandKind::ExpectDurationUnder(limit) => crate::util::format_duration(limit),
+            CommandKind::ExpectRssUnder(limit) => crate::util::format_byte_size(limit),
+            CommandKind::Custom(ref directive) => directive.argument.clone(),
+            CommandKind::CheckBlock(..) | CommandKind::XFail | CommandKind::CheckUnordered | CommandKind::Pty | CommandKind::AllowStderr => String::new(),
+        };
+
+        write!(fmt, "{}", self.directive_name())?;
+        match *self {
+            CommandKind::Check(ref pattern) | CommandKind::CheckNext(ref pattern) | CommandKind::CheckBlock(ref pattern) |
+                CommandKind::CheckNotBetween(ref pattern) | CommandKind::CheckEnd(ref pattern) => {
+                if let Some(ref matcher) = pattern.matcher {
+                    write!(fmt, "[{}]", matcher)?;
+                }
+                if pattern.anchored {
+                    write!(fmt, "{{^}}")?;
+                }
+            },
+            _ => (),
+        }
+        write!(fmt, ":")?;
+        if !argument.is_empty() {
+            write!(fmt, " {}", argument)?;
+        }
+
+        if let CommandKind::CheckBlock(ref pattern) = *self {
+            for line in pattern.to_string().split('\n') {
+                match line {
+                    "" => writeln!(fmt)?,
+                    line => write!(fmt, "\n  {}", line)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for CommandKind {
+    type Err = String;
+
+    /// Parses the text of a single builtin directive.
+    fn from_str(s: &str) -> Result<Self, String> {
+        crate::parse::command_kind(s, &[])
+    }
+}
+
+impl CommandKind {
+    /// Gets the name of the directive for this command, such as `CHECK-NEXT`.
+    pub fn directive_name(&self) -> &str {
+        match *self {
+            CommandKind::Run(..) => "RUN",
+            CommandKind::RunBackground(..) => "RUN-BACKGROUND",
+            CommandKind::WaitFor(..) => "WAIT-FOR",
+            CommandKind::Resource(..) => "RESOURCE",
+            CommandKind::Depends(..) => "DEPENDS",
+            CommandKind::Owner(..) => "OWNER",
+            CommandKind::Include(..) => "INCLUDE",
+            CommandKind::Check(..) => "CHECK",
+            CommandKind::CheckNext(..) => "CHECK-NEXT",
+            CommandKind::CheckBlock(..) => "CHECK-BLOCK",
+            CommandKind::CheckSameAs(..) => "CHECK-SAME-AS",
+            CommandKind::CheckGolden(..) => "CHECK-GOLDEN",
+            CommandKind::CheckJson(..) => "CHECK-JSON",
+            CommandKind::CheckNotBetween(..) => "CHECK-NOT-BETWEEN",
+            CommandKind::CheckEnd(..) => "CHECK-END",
+            CommandKind::CheckSkip(..) => "CHECK-SKIP",
+            CommandKind::ExpectDurationUnder(..) => "EXPECT-DURATION-UNDER",
+            CommandKind::ExpectRssUnder(..) => "EXPECT-RSS-UNDER",
+            CommandKind::Undef(..) => "UNDEF",
+            CommandKind::NormalizePreset(..) => "NORMALIZE-PRESET",
+            CommandKind::Custom(ref directive) => &directive.name,
+            CommandKind::XFail => "XFAIL",
+            CommandKind::CheckUnordered => "CHECK-UNORDERED",
+            CommandKind::Pty => "PTY",
+            CommandKind::AllowStderr => "ALLOW-STDERR",
+        }
+    }
+}
+
 impl Command
 {
     pub fn new(kind: CommandKind, line_number: u32) -> Self {
-        Command { kind, line_number }
+        Command { kind, line_number, span: Span::default(), origin: None }
+    }
+}
+
+impl TestResult {
+    /// Gets the name the test is reported under, which includes the shell if it is part of a shell matrix.
+    ///
+    /// See `Config::test_name`.
+    pub fn display_name(&self, config: &Config) -> String {
+        match self.shell {
+            Some(ref shell) => format!("{} [{}]", config.test_name(&self.path), shell),
+            None => config.test_name(&self.path),
+        }
+    }
+
+    /// Gets a normalized description of why the test did not pass, if it did not.
+    ///
+    /// Tests that failed in the same way, such as the same kind of directive failing with
+    /// the same exit code, have identical signatures. The path of the test is replaced
+    /// with `@file` so that signatures can be compared across tests.
+    pub fn failure_signature(&self) -> Option<String> {
+        let signature = match self.overall_result {
+            TestResultKind::Fail { ref reason, .. } => format!("{}: {}", reason.directive_name(), reason.short_message()),
+            ref kind => kind.short_failure_message()?,
+        };
+        let signature = signature.replace(&self.path.absolute.display().to_string(), "@file");
+
+        Some(signature.split_whitespace().collect::<Vec<_>>().join(" "))
     }
 }
 
@@ -223,7 +832,64 @@ impl TestResultKind {
 
         match *self {
             UnexpectedPass | Error { .. } | Fail { .. } => true,
-            Pass | Skip | ExpectedFailure { .. } | EmptyTest => false,
+            Pass | Skip | NotRun | ExpectedFailure { .. } | EmptyTest => false,
+        }
+    }
+
+    /// Gets a short, stable identifier for the kind of result, for machine-readable output.
+    pub fn machine_label(&self) -> &'static str {
+        use self::TestResultKind::*;
+
+        match *self {
+            Pass => "pass",
+            UnexpectedPass => "unexpected-pass",
+            Error { .. } => "error",
+            Fail { .. } => "fail",
+            ExpectedFailure { .. } => "expected-failure",
+            EmptyTest => "empty",
+            Skip => "skip",
+            NotRun => "not-run",
+        }
+    }
+
+    /// Gets a description of why the test did not pass, if it did not.
+    pub fn failure_message(&self, config: &Config) -> Option<String> {
+        use self::TestResultKind::*;
+
+        match *self {
+            Error { ref message } => Some(message.clone()),
+            Fail { ref reason, .. } => Some(reason.human_detail_message(config)),
+            UnexpectedPass => Some(config.messages.format(MessageId::UnexpectedPass, &[])),
+            Pass | Skip | NotRun | ExpectedFailure { .. } | EmptyTest => None,
+        }
+    }
+
+    /// Gets the ID of the message describing why the test did not pass, if the
+    /// message comes from the message catalog.
+    pub fn message_id(&self) -> Option<MessageId> {
+        match *self {
+            TestResultKind::Fail { ref reason, .. } => Some(reason.message_id()),
+            TestResultKind::UnexpectedPass => Some(MessageId::UnexpectedPass),
+            _ => None,
+        }
+    }
+
+    /// Gets a single-line description of why the test did not pass, if it did not, in English.
+    pub fn short_failure_message(&self) -> Option<String> {
+        self.short_failure_message_in(&messages::Catalog::default())
+    }
+
+    /// Gets a single-line description of why the test did not pass, if it did not, using
+    /// the templates of a message catalog.
+    pub fn short_failure_message_in(&self, messages: &messages::Catalog) -> Option<String> {
+        use self::TestResultKind::*;
+
+        match *self {
+            Error { ref message } => Some(message.lines().next().unwrap_or("").to_owned()),
+            Fail { ref reason, hint: Some(ref hint) } => Some(format!("{} ({})", reason.short_message_in(messages), hint.lines().next().unwrap_or(""))),
+            Fail { ref reason, hint: None } => Some(reason.short_message_in(messages)),
+            UnexpectedPass => Some(messages.format(MessageId::UnexpectedPass, &[])),
+            Pass | Skip | NotRun | ExpectedFailure { .. } | EmptyTest => None,
         }
     }
 
@@ -244,6 +910,7 @@ impl TestResultKind {
             ExpectedFailure { .. } => "Expected failures",
             EmptyTest { .. } => "Empty tests",
             Skip => "Skipped tests",
+            NotRun => "Tests not run",
         }
     }
 }
@@ -268,6 +935,31 @@ impl CheckFailureInfo {
 
 impl TestFile
 {
+    /// Creates a test file without any commands.
+    ///
+    /// The test file does not need to exist on disk, but `@file` will still refer to its path.
+    pub fn new(path: TestFilePath) -> Self {
+        TestFile { path, commands: Vec::new(), front_matter: FrontMatter::default() }
+    }
+
+    /// Parses a test file from text, rather than reading it from disk.
+    pub fn parse(path: TestFilePath, text: &str) -> Result<Self, String> {
+        crate::parse::test_file(path, text)
+    }
+
+    /// Appends a command to the test file, numbering it as the next line.
+    pub fn add_command(&mut self, kind: CommandKind) -> &mut Self {
+        let line_number = self.commands.last().map(|c| c.line_number + 1).unwrap_or(1);
+        self.commands.push(Command::new(kind, line_number));
+        self
+    }
+
+    /// Appends a command to the test file, numbering it as the next line.
+    pub fn with_command(mut self, kind: CommandKind) -> Self {
+        self.add_command(kind);
+        self
+    }
+
     /// Extra test-specific variables.
     pub fn variables(&self) -> Variables {
         let mut v = Variables::new();
@@ -275,10 +967,42 @@ impl TestFile
         v
     }
 
-    /// Gets an iterator over all `RUN` commands in the test file.
-    pub fn run_command_invocations(&self) -> impl Iterator<Item=&Invocation> {
+    /// Gets the names of all resources declared by `RESOURCE` directives.
+    pub fn resources(&self) -> impl Iterator<Item=&str> {
         self.commands.iter().filter_map(|c| match c.kind {
-            CommandKind::Run(ref invocation) => Some(invocation),
+            CommandKind::Resource(ref name) => Some(&name[..]),
+            _ => None,
+        })
+    }
+
+    /// Gets the paths declared by `DEPENDS` directives, relative to the test file.
+    pub fn dependencies(&self) -> impl Iterator<Item=&str> {
+        self.commands.iter().filter_map(|c| match c.kind {
+            CommandKind::Depends(ref path) => Some(&path[..]),
+            _ => None,
+        })
+    }
+
+    /// Gets the owners declared by `OWNER` directives.
+    pub fn owners(&self) -> impl Iterator<Item=&str> {
+        self.commands.iter().filter_map(|c| match c.kind {
+            CommandKind::Owner(ref owner) => Some(&owner[..]),
+            _ => None,
+        })
+    }
+
+    /// Gets the names of the normalizer presets enabled by `NORMALIZE-PRESET` directives.
+    pub fn normalizer_presets(&self) -> impl Iterator<Item=&str> {
+        self.commands.iter().filter_map(|c| match c.kind {
+            CommandKind::NormalizePreset(ref name) => Some(&name[..]),
+            _ => None,
+        })
+    }
+
+    /// Gets an iterator over all `RUN` commands in the test file, along with their line numbers.
+    pub fn run_command_invocations(&self) -> impl Iterator<Item=(u32, &Invocation)> {
+        self.commands.iter().filter_map(|c| match c.kind {
+            CommandKind::Run(ref invocation) => Some((c.line_number, invocation)),
             _ => None,
         })
     }
@@ -287,12 +1011,84 @@ impl TestFile
     pub fn is_expected_failure(&self) -> bool {
         self.commands.iter().any(|c| if let CommandKind::XFail = c.kind { true } else { false })
     }
+
+    /// Can the `CHECK` directives of this test match the output in any order.
+    pub fn has_unordered_checks(&self) -> bool {
+        self.commands.iter().any(|c| c.kind == CommandKind::CheckUnordered)
+    }
+
+    /// Are the `RUN` commands of this test attached to a pseudo-terminal, regardless of `Config::use_pty`.
+    pub fn uses_pty(&self) -> bool {
+        self.commands.iter().any(|c| c.kind == CommandKind::Pty)
+    }
+
+    /// Can the `RUN` commands of this test write to stderr, regardless of `Config::fail_on_stderr`.
+    pub fn allows_stderr(&self) -> bool {
+        self.commands.iter().any(|c| c.kind == CommandKind::AllowStderr)
+    }
+}
+
+impl TestFilePath {
+    /// Creates a test file path.
+    ///
+    /// The relative path is used when presenting the test to the user.
+    pub fn new(absolute: impl Into<PathBuf>, relative: impl Into<PathBuf>) -> Self {
+        TestFilePath { absolute: absolute.into(), relative: relative.into() }
+    }
+
+    /// Gets the group the test is reported in, which is the directory it is in, such as `run-pass/nested`.
+    ///
+    /// Tests directly inside the suite are not in a group.
+    pub fn group(&self) -> Option<String> {
+        let components: Vec<_> = self.relative.parent()?.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+
+        if components.is_empty() { None } else { Some(components.join("/")) }
+    }
+}
+
+impl Invocation {
+    /// Creates an invocation of a shell command line, which may refer to variables.
+    pub fn new(command_line: impl Into<String>) -> Self {
+        Invocation { original_command: command_line.into(), negated: false }
+    }
+
+    /// Expects the command to exit unsuccessfully, like LLVM's `not` tool.
+    pub fn negated(self) -> Self {
+        Invocation { negated: true, ..self }
+    }
+}
+
+impl TextPattern {
+    /// Parses a pattern using the same syntax as in test file directives.
+    pub fn parse(pattern: &str) -> Self {
+        crate::parse::text_pattern(pattern)
+    }
+
+    /// Creates a pattern that matches text literally.
+    pub fn literal(text: impl Into<String>) -> Self {
+        PatternComponent::Text(text.into()).into()
+    }
+
+    /// Creates a pattern from a regex.
+    pub fn regex(regex: impl Into<String>) -> Self {
+        PatternComponent::Regex(regex.into()).into()
+    }
+
+    /// Matches the pattern with another engine, such as `exact`. See `lit::matcher`.
+    pub fn with_matcher(self, matcher: impl Into<String>) -> Self {
+        TextPattern { matcher: Some(matcher.into()), ..self }
+    }
+
+    /// Only matches the pattern at the start of a line.
+    pub fn anchored(self) -> Self {
+        TextPattern { anchored: true, ..self }
+    }
 }
 
 /// Build a text pattern from a single component.
 impl From<PatternComponent> for TextPattern {
     fn from(component: PatternComponent) -> Self {
-        TextPattern { components: vec![component] }
+        TextPattern { components: vec![component], matcher: None, anchored: false }
     }
 }
 
@@ -334,7 +1130,53 @@ fn convert_bytes_to_str(bytes: &[u8]) -> &str {
 
 impl ProgramOutput {
     pub fn empty() -> Self {
-        ProgramOutput { stdout: String::new(), stderr: String::new() }
+        ProgramOutput { stdout: Arc::from(""), stderr: Arc::from("") }
     }
 }
 
+
+impl CheckMatch {
+    /// Describes a match of the given span of output.
+    pub fn new(directive_line_number: u32, output: &str, span: Span) -> Self {
+        let matched_text = &output[span.start..span.end];
+        // A match ending in a newline does not extend onto the following line.
+        let matched_text = matched_text.strip_suffix('\n').unwrap_or(matched_text);
+
+        let start_line = output[..span.start].matches('\n').count() + 1;
+        let end_line = start_line + matched_text.matches('\n').count();
+
+        CheckMatch { directive_line_number, span, start_line, end_line }
+    }
+
+    /// Describes the match as a JSON object, for tooling.
+    pub(crate) fn to_json(&self) -> json::Value {
+        json::Value::object()
+            .field("line", self.directive_line_number as usize)
+            .field("start_byte", self.span.start)
+            .field("end_byte", self.span.end)
+            .field("start_line", self.start_line)
+            .field("end_line", self.end_line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identical_failures_in_different_tests_share_a_signature() {
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let failing_test = |name: &str| TestFile::new(TestFilePath::new(format!("/nonexistent/{}", name), name))
+            .with_command(CommandKind::Run(Invocation::new("cat @file;  exit 3")));
+
+        let signatures: Vec<_> = ["a.txt", "b.txt"].iter()
+            .map(|name| crate::run::execute_test_file(&failing_test(name), &config).failure_signature())
+            .collect();
+        assert_eq!(signatures[0], Some("RUN: command 'cat @file; exit 3' exited with code '3'".to_owned()));
+        assert_eq!(signatures[0], signatures[1]);
+
+        let passing_test = TestFile::new(TestFilePath::new("/nonexistent/c.txt", "c.txt"))
+            .with_command(CommandKind::Run(Invocation::new("true")));
+        assert_eq!(crate::run::execute_test_file(&passing_test, &config).failure_signature(), None);
+    }
+}
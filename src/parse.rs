@@ -1,39 +1,534 @@
-use crate::model::*;
+use crate::{Config, config::CommentSyntax, model::*};
 
 use regex::Regex;
-use std::mem;
+use std::{borrow::Cow, fs, mem, path::{Path, PathBuf}};
 
 lazy_static! {
-    static ref DIRECTIVE_REGEX: Regex = Regex::new("([A-Z-]+):(.*)").unwrap();
+    static ref DIRECTIVE_REGEX: Regex = Regex::new(&format!("({}):(.*)", DIRECTIVE_NAME_REGEX)).unwrap();
     static ref IDENTIFIER_REGEX: Regex = Regex::new("^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
 }
 
-/// Parses a test file
-pub fn test_file<I>(path: TestFilePath, chars: I) -> Result<TestFile, String>
-    where I: Iterator<Item=char> {
+/// Matches the name of a directive, along with the engine and anchor of a check, as in `CHECK[exact]{^}`.
+const DIRECTIVE_NAME_REGEX: &str = r"[A-Z-]+(?:\[[a-z][a-z0-9-]*\])?(?:\{\^\})?";
+
+/// The names of all directives that are built into lit.
+pub const BUILTIN_DIRECTIVES: &[&str] = &[
+    "RUN", "NOT-RUN", "RUN-BACKGROUND", "RESOURCE", "DEPENDS", "OWNER", "WAIT-FOR", "CHECK", "CHECK-NEXT", "CHECK-BLOCK",
+    "CHECK-NOT-BETWEEN", "CHECK-END", "CHECK-SAME-AS", "CHECK-GOLDEN", "CHECK-JSON", "UNDEF", "NORMALIZE-PRESET", "XFAIL",
+    "CHECK-UNORDERED", "PTY", "CHECK-DIAG", "EXPECT-DURATION-UNDER", "EXPECT-RSS-UNDER",
+    "ALLOW-STDERR", "CHECK-SKIP", "INCLUDE",
+];
+
+/// Parses a test file.
+///
+/// Directives are parsed straight out of the borrowed test text, and each
+/// parsed command records the byte span of its directive within the text.
+pub fn test_file(path: TestFilePath, text: &str) -> Result<TestFile, String> {
+    self::test_file_with_custom_directives(path, text, &[], &DIRECTIVE_REGEX)
+}
+
+/// Parses a test file, which may also use the directives defined by the plugins of a config.
+///
+/// If the config has a comment syntax for the extension of the file, only directives
+/// inside comments are found.
+pub fn test_file_for_config(path: TestFilePath, text: &str, config: &Config) -> Result<TestFile, String> {
+    let custom_directives: Vec<_> = config.plugins.iter().flat_map(|p| p.directives().iter().cloned()).collect();
+    let directive_regex = self::directive_regex(config.directive_separator_of(&path.relative));
+
+    match config.comment_syntax_of(&path.absolute) {
+        Some(syntax) => self::test_file_with_custom_directives(path, &self::comments_only(text, syntax), &custom_directives, &directive_regex),
+        None => self::test_file_with_custom_directives(path, text, &custom_directives, &directive_regex),
+    }
+}
+
+/// Gets a function that parses a single line, such as a line of program output, as a directive
+/// of a test file would be parsed under a config.
+///
+/// The function returns `None` if the line has no directive.
+pub fn line_directive_parser<'a>(path: &TestFilePath, config: &'a Config) -> impl Fn(&str) -> Option<Result<Command, String>> + 'a {
+    let custom_directives: Vec<_> = config.plugins.iter().flat_map(|p| p.directives().iter().cloned()).collect();
+    let directive_regex = self::directive_regex(config.directive_separator_of(&path.relative)).into_owned();
+
+    move |line| self::possible_command(line, 0, &custom_directives, &directive_regex)
+}
+
+/// Blanks out everything in a piece of text that is not inside a comment.
+///
+/// Line comment leaders are kept so that `CHECK-BLOCK` directives can find their blocks,
+/// but block comment delimiters are blanked out too. Byte offsets and lines are preserved.
+///
+/// Character literals such as `'"'` and raw strings such as `r#"\"#` are recognized, so that
+/// the delimiters within them do not start or end strings.
+fn comments_only(text: &str, syntax: &CommentSyntax) -> String {
+    enum Region<'a> { Code, String(char), RawString(usize), LineComment, BlockComment(&'a str) }
+
+    fn blank(text: &str, masked: &mut String) {
+        masked.extend(text.chars().map(|c| if c == '\n' || c == '\r' { c.to_string() } else { " ".repeat(c.len_utf8()) }));
+    }
+
+    /// Gets the length of a character literal, such as `'"'` or `'\''`, without its opening quote.
+    fn char_literal_len(text: &str) -> Option<usize> {
+        let mut chars = text.char_indices();
+        let (_, c) = chars.next()?;
+        if c == '\\' { chars.next()?; }
+
+        match chars.next()? {
+            (idx, '\'') => Some(idx + 1),
+            _ => None,
+        }
+    }
+
+    let is_identifier_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    let mut masked = String::with_capacity(text.len());
+    let mut region = Region::Code;
+    let mut idx = 0;
+
+    while let Some(c) = text[idx..].chars().next() {
+        let remaining = &text[idx..];
+        let mut consumed = c.len_utf8();
+
+        match region {
+            Region::Code => {
+                if let Some(leader) = syntax.line_comments.iter().find(|leader| remaining.starts_with(&leader[..])) {
+                    masked.push_str(leader);
+                    consumed = leader.len();
+                    region = Region::LineComment;
+                } else if let Some((open, close)) = syntax.block_comments.iter().find(|(open, _)| remaining.starts_with(&open[..])) {
+                    blank(open, &mut masked);
+                    consumed = open.len();
+                    region = Region::BlockComment(close);
+                } else if is_identifier_char(c) {
+                    // Whole identifiers are skipped, so that raw string prefixes are only found at their start.
+                    let word_len = remaining.find(|c| !is_identifier_char(c)).unwrap_or(remaining.len());
+                    let hashes = remaining[word_len..].chars().take_while(|&c| c == '#').count();
+                    let is_raw_string = matches!(&remaining[..word_len], "r" | "br") && syntax.string_delimiters.contains(&'"') &&
+                        remaining[word_len + hashes..].starts_with('"');
+
+                    consumed = word_len;
+                    if is_raw_string {
+                        consumed += hashes + 1;
+                        region = Region::RawString(hashes);
+                    }
+                    blank(&remaining[..consumed], &mut masked);
+                } else if c == '\'' && !syntax.string_delimiters.contains(&c) {
+                    consumed += char_literal_len(&remaining[1..]).unwrap_or(0);
+                    blank(&remaining[..consumed], &mut masked);
+                } else {
+                    if syntax.string_delimiters.contains(&c) { region = Region::String(c); }
+                    blank(&remaining[..consumed], &mut masked);
+                }
+            },
+            Region::String(delimiter) => {
+                if c == '\\' {
+                    // Skip over the escaped character, so that escaped delimiters do not end the string.
+                    consumed += remaining[1..].chars().next().map(char::len_utf8).unwrap_or(0);
+                } else if c == delimiter {
+                    region = Region::Code;
+                }
+                blank(&remaining[..consumed], &mut masked);
+            },
+            Region::RawString(hashes) => {
+                // Raw strings have no escapes, and end at a quote followed by as many `#` as they started with.
+                if c == '"' && remaining[1..].chars().take(hashes).filter(|&c| c == '#').count() == hashes {
+                    consumed += hashes;
+                    region = Region::Code;
+                }
+                blank(&remaining[..consumed], &mut masked);
+            },
+            Region::LineComment => {
+                if c == '\n' { region = Region::Code; }
+                masked.push(c);
+            },
+            Region::BlockComment(close) => {
+                if remaining.starts_with(close) {
+                    blank(close, &mut masked);
+                    consumed = close.len();
+                    region = Region::Code;
+                } else {
+                    masked.push(c);
+                }
+            },
+        }
+
+        idx += consumed;
+    }
+
+    masked
+}
+
+/// Gets the regex that finds directives whose names are followed by the given separator.
+fn directive_regex(separator: char) -> Cow<'static, Regex> {
+    if separator == crate::config::DEFAULT_DIRECTIVE_SEPARATOR {
+        Cow::Borrowed(&DIRECTIVE_REGEX)
+    } else {
+        Cow::Owned(Regex::new(&format!("({}){}(.*)", DIRECTIVE_NAME_REGEX, regex::escape(&separator.to_string()))).unwrap())
+    }
+}
+
+fn test_file_with_custom_directives(path: TestFilePath, text: &str, custom_directives: &[&str], directive_regex: &Regex) -> Result<TestFile, String> {
+    let commands = self::commands(text, custom_directives, directive_regex)?;
+    let mut include_chain = vec![(canonical_path(&path.absolute), path.relative.display().to_string())];
+    let commands = self::expand_includes(commands, &path.absolute, &mut include_chain, custom_directives, directive_regex)?;
+    self::check_not_between_windows(&commands)?;
+    let front_matter = self::front_matter(text).map_err(|e| format!("{}: front matter {}", path.relative.display(), e))?;
+
+    Ok(TestFile {
+        path,
+        commands: commands,
+        front_matter,
+    })
+}
+
+/// Replaces `INCLUDE` directives with the directives of the files they name, which are
+/// found relative to the file holding the directive.
+///
+/// Included files are parsed whole, rather than only their comments. Their commands take
+/// the line number and span of the `INCLUDE` directive, so that failures point into the
+/// test file, and record the file and line they were written on as their `origin`. Errors
+/// in an included file name the file and its own line numbers.
+fn expand_includes(commands: Vec<Command>, including_path: &Path, include_chain: &mut Vec<(PathBuf, String)>,
+                   custom_directives: &[&str], directive_regex: &Regex) -> Result<Vec<Command>, String> {
+    let mut expanded = Vec::with_capacity(commands.len());
+
+    for command in commands {
+        let relative_path = match command.kind {
+            CommandKind::Include(ref relative_path) => relative_path.clone(),
+            _ => {
+                expanded.push(command);
+                continue;
+            },
+        };
+
+        let path = including_path.parent().unwrap_or_else(|| Path::new("")).join(&relative_path);
+        let canonical = canonical_path(&path);
+
+        if include_chain.iter().any(|(included, _)| *included == canonical) {
+            let chain: Vec<_> = include_chain.iter().map(|(_, name)| &name[..]).chain(std::iter::once(&relative_path[..])).collect();
+            return Err(format!("INCLUDE directive on line {} includes '{}' within itself: {}", command.line_number, relative_path, chain.join(" -> ")));
+        }
+
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("INCLUDE directive on line {} could not read '{}': {}", command.line_number, path.display(), e))?;
+
+        include_chain.push((canonical, relative_path.clone()));
+        let included = self::commands(&text, custom_directives, directive_regex)
+            .and_then(|included| self::expand_includes(included, &path, include_chain, custom_directives, directive_regex))
+            .map_err(|e| format!("in '{}', included on line {}: {}", relative_path, command.line_number, e))?;
+        include_chain.pop();
+
+        expanded.extend(included.into_iter().map(|included| {
+            // Directives from nested includes keep the file they were written in.
+            let origin = included.origin.clone().unwrap_or_else(|| Origin { path: path.clone(), line_number: included.line_number });
+            Command { line_number: command.line_number, span: command.span, origin: Some(origin), ..included }
+        }));
+    }
+
+    Ok(expanded)
+}
+
+/// Resolves symlinks and relative components of a path, if it exists, so that cycles of
+/// includes are found however the files are named.
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+/// Parses the front matter block at the top of a test file, if there is one.
+///
+/// The block is delimited by `---` lines and holds TOML-style settings. Every line
+/// of the block may start with a comment leader such as `#` or `//`, as long as
+/// the opening `---` starts with the same leader. A leading `#!` line is ignored.
+///
+/// ``` text
+/// # ---
+/// # timeout = 30
+/// # tags = ["slow"]
+/// # requires = ["docker"]
+/// #
+/// # [env]
+/// # RUST_LOG = "debug"
+/// #
+/// # [constants]
+/// # greeting = "hello"
+/// # ---
+/// # RUN: sh -c 'echo @greeting'
+/// ```
+///
+/// A block whose lines are not settings, such as the start of a YAML document, is left
+/// alone as ordinary test text. A block of settings that is never closed is an error.
+pub fn front_matter(text: &str) -> Result<FrontMatter, String> {
+    let lines: Vec<_> = text.lines().map(strip_carriage_return).collect();
+    let opening_idx = if lines.first().map(|l| l.starts_with("#!")).unwrap_or(false) { 1 } else { 0 };
+
+    let leader = match lines.get(opening_idx).and_then(|line| line.trim_end().strip_suffix("---")) {
+        // The leader must be separated from the delimiter, so that a line such as `------` is not mistaken for one.
+        Some(leader) if leader.is_empty() || (leader.ends_with(char::is_whitespace) && leader.chars().all(|c| !c.is_alphanumeric())) => leader.trim_end(),
+        _ => return Ok(FrontMatter::default()),
+    };
+
+    // Keep the lines before the block, blanked out, so that errors refer to the right line.
+    let mut settings = vec![""; opening_idx + 1];
+    let mut terminated = false;
+    for line in lines.iter().skip(opening_idx + 1) {
+        let line = match line.trim_end().strip_prefix(leader) {
+            Some(line) => line,
+            None => return Ok(FrontMatter::default()),
+        };
+
+        if line.trim() == "---" {
+            terminated = true;
+            break;
+        }
+        settings.push(line);
+    }
+
+    let entries = match crate::config::file::parse(&settings.join("\n")) {
+        Ok(entries) => entries,
+        Err(..) => return Ok(FrontMatter::default()),
+    };
+
+    if !terminated {
+        if entries.is_empty() {
+            return Ok(FrontMatter::default());
+        }
+
+        let closing_line = if leader.is_empty() { "---".to_owned() } else { format!("{} ---", leader) };
+        return Err(format!("line {}: unterminated front matter, expected a closing '{}'", opening_idx + 1, closing_line));
+    }
+
+    let mut front_matter = FrontMatter::default();
+    for entry in entries {
+        self::front_matter_entry(&entry, &mut front_matter).map_err(|e| format!("line {}: {}", entry.line_number, e))?;
+    }
+
+    Ok(front_matter)
+}
+
+fn front_matter_entry(entry: &crate::config::file::Entry, front_matter: &mut FrontMatter) -> Result<(), String> {
+    let strings = |value: &crate::config::file::Value| -> Result<Vec<String>, String> {
+        value.as_array()?.iter().map(|v| v.as_str().map(ToOwned::to_owned)).collect()
+    };
+
+    match (&entry.table[..], &entry.key[..]) {
+        ("", "timeout") => {
+            front_matter.timeout = match entry.value.as_integer()? {
+                seconds if seconds > 0 => Some(std::time::Duration::from_secs(seconds as u64)),
+                seconds => return Err(format!("invalid timeout: '{}'", seconds)),
+            };
+        },
+        ("", "tags") => front_matter.tags = strings(&entry.value)?,
+        ("", "requires") => front_matter.requires = strings(&entry.value)?,
+        ("env", name) => { front_matter.env.insert(name.to_owned(), entry.value.as_str()?.to_owned()); },
+        ("constants", name) => {
+            crate::vars::validate_constant_name(name)?;
+            front_matter.constants.insert(name.to_owned(), entry.value.as_str()?.to_owned());
+        },
+        (table, key) => {
+            let qualified_key = if table.is_empty() { key.to_owned() } else { format!("{}.{}", table, key) };
+            return Err(format!("unknown front matter key '{}'", qualified_key));
+        },
+    }
+
+    Ok(())
+}
+
+/// Parses the text of a single directive, such as `CHECK: hello` or a `CHECK-BLOCK` with
+/// its following lines.
+///
+/// This is the inverse of the `Display` implementation of `CommandKind`.
+pub fn command_kind(text: &str, custom_directives: &[&str]) -> Result<CommandKind, String> {
+    let mut commands = self::commands(text, custom_directives, &DIRECTIVE_REGEX)?;
+
+    match commands.len() {
+        1 => Ok(commands.remove(0).kind),
+        0 => Err(format!("'{}' does not contain a directive", text)),
+        count => Err(format!("'{}' contains {} directives, but only one was expected", text, count)),
+    }
+}
+
+/// Parses all commands in a piece of text, without validating how they relate to each other.
+fn commands(text: &str, custom_directives: &[&str], directive_regex: &Regex) -> Result<Vec<Command>, String> {
     let mut commands = Vec::new();
-    let test_body: String = chars.collect();
+    let raw_lines: Vec<_> = text.split('\n').collect();
+    let mut line_byte_offsets = Vec::with_capacity(raw_lines.len());
+    let mut line_byte_offset = 0;
+    for raw_line in raw_lines.iter() {
+        line_byte_offsets.push(line_byte_offset);
+        line_byte_offset += raw_line.len() + 1; // account for the new line.
+    }
 
-    for (line_idx, line) in test_body.lines().enumerate() {
+    let mut line_idx = 0;
+    while line_idx < raw_lines.len() {
         let line_number = line_idx + 1;
+        let line = strip_carriage_return(raw_lines[line_idx]);
+        let line_byte_offset = line_byte_offsets[line_idx];
+
+        match self::possible_command(line, line_number as _, custom_directives, directive_regex) {
+            Some(Ok(mut command)) => {
+                let mut span_end = command.span.end + line_byte_offset;
+
+                if let CommandKind::CheckBlock(..) = command.kind {
+                    let (block_pattern, block_line_count) = self::check_block(&line[..command.span.start], &raw_lines[line_idx + 1..], line_number as _)
+                        .map_err(|e| format!("could not parse command: {}", e))?;
 
-        match self::possible_command(line, line_number as _) {
-            Some(Ok(command)) => commands.push(command),
+                    line_idx += block_line_count;
+                    span_end = line_byte_offsets[line_idx] + strip_carriage_return(raw_lines[line_idx]).len();
+                    if let CommandKind::CheckBlock(ref pattern) = command.kind {
+                        command.kind = CommandKind::CheckBlock(TextPattern { matcher: pattern.matcher.clone(), anchored: pattern.anchored, ..block_pattern });
+                    }
+                }
+
+                command.span = Span { start: command.span.start + line_byte_offset, end: span_end };
+                commands.push(command);
+            },
             Some(Err(e)) => {
                 return Err(format!(
                     "could not parse command: {}", e)
                 );
             },
-            None => continue,
+            None => (),
         }
+
+        line_idx += 1;
     }
 
-    Ok(TestFile {
-        path,
-        commands: commands,
-    })
+    Ok(commands)
+}
+
+fn strip_carriage_return(line: &str) -> &str {
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// Gathers the block of a `CHECK-BLOCK` directive from the lines following it.
+///
+/// Block lines start with the same comment leader as the directive, but are indented
+/// further. Returns the pattern for the block and the number of lines it spans.
+fn check_block(directive_prefix: &str, following_lines: &[&str], line: u32)
+    -> Result<(TextPattern, usize), String> {
+    let leader = directive_prefix.trim_end();
+    let directive_indentation = directive_prefix.len() - leader.len();
+    let indentation_of = |s: &str| s.len() - s.trim_start_matches([' ', '\t']).len();
+
+    let mut block_lines = Vec::new();
+    // The number of lines up to and including the last non-blank block line.
+    let mut block_line_count = 0;
+    for (idx, raw_line) in following_lines.iter().enumerate() {
+        let rest = match strip_carriage_return(raw_line).strip_prefix(leader) {
+            Some(rest) => rest,
+            None => break,
+        };
+
+        if rest.trim().is_empty() {
+            block_lines.push("");
+            continue;
+        }
+        if indentation_of(rest) <= directive_indentation {
+            break;
+        }
+
+        block_lines.push(rest);
+        block_line_count = idx + 1;
+    }
+    block_lines.truncate(block_line_count);
+
+    let block_indentation = match block_lines.iter().filter(|l| !l.is_empty()).map(|l| indentation_of(l)).min() {
+        Some(indentation) => indentation,
+        None => return Err(format!("CHECK-BLOCK directive on line {} must be followed by indented lines", line)),
+    };
+
+    let block_text = block_lines.iter().map(|l| if l.is_empty() { "" } else { &l[block_indentation..] }).collect::<Vec<_>>().join("\n");
+    Ok((self::text_pattern(&block_text), block_line_count))
+}
+
+/// Verifies that every `CHECK-NOT-BETWEEN` is directly followed by a `CHECK-END`
+/// closing its window, optionally after further `CHECK-NOT-BETWEEN` directives.
+fn check_not_between_windows(commands: &[Command]) -> Result<(), String> {
+    let mut open_window_line = None;
+
+    for command in commands {
+        match (&command.kind, open_window_line) {
+            (CommandKind::CheckNotBetween(..), None) => open_window_line = Some(command.line_number),
+            (CommandKind::CheckNotBetween(..), Some(..)) => (),
+            (CommandKind::CheckEnd(..), Some(..)) => open_window_line = None,
+            (CommandKind::CheckEnd(..), None) => {
+                return Err(format!("CHECK-END on line {} does not close a CHECK-NOT-BETWEEN", command.line_number));
+            },
+            (CommandKind::Check(..), Some(line)) | (CommandKind::CheckNext(..), Some(line)) |
+                (CommandKind::CheckBlock(..), Some(line)) | (CommandKind::CheckSameAs(..), Some(line)) |
+                (CommandKind::CheckGolden(..), Some(line)) => {
+                return Err(format!("CHECK-NOT-BETWEEN on line {} must be closed by CHECK-END before line {}", line, command.line_number));
+            },
+            _ => (),
+        }
+    }
+
+    match open_window_line {
+        Some(line) => Err(format!("CHECK-NOT-BETWEEN on line {} is never closed by CHECK-END", line)),
+        None => Ok(()),
+    }
+}
+
+/// Parses the argument of a `CHECK-JSON` directive, such as `$.items[0] == "lit"`.
+fn json_check(text: &str) -> Result<JsonCheck, String> {
+    let (_, rest) = crate::json::path_prefix(text)?;
+    let path = &text[..text.len() - rest.len()];
+
+    let expected = match rest.trim_start().strip_prefix("==") {
+        Some(expected) => expected.trim(),
+        None => return Err(format!("expected '==' after the path '{}'", path)),
+    };
+    crate::json::Value::parse(expected).map_err(|e| format!("invalid expected value '{}': {}", expected, e))?;
+
+    Ok(JsonCheck { path: path.to_owned(), expected: expected.to_owned() })
 }
 
+/// Parses the argument of a `CHECK-DIAG` directive, such as `error@+1: unknown type name '[[\w+]]'`,
+/// into a pattern matching a compiler diagnostic such as `main.c:4:9: error: unknown type name 'foo'`.
+///
+/// The line of the diagnostic is the line of the directive itself, unless it is given after an `@`,
+/// either relative to the directive, such as `@-1`, or absolute, such as `@12`.
+fn diagnostic_check(text: &str, directive_line: u32) -> Result<TextPattern, String> {
+    let (location, message) = match text.find(':') {
+        Some(idx) => (text[..idx].trim(), text[idx + 1..].trim()),
+        None => return Err("expected '<severity>@<line>: <message>'".to_owned()),
+    };
+    let (severity, line) = match location.find('@') {
+        Some(idx) => (&location[..idx], Some(&location[idx + 1..])),
+        None => (location, None),
+    };
+
+    if severity.is_empty() || !severity.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!("invalid diagnostic severity '{}', expected a word such as 'error' or 'warning'", severity));
+    }
+    if message.is_empty() {
+        return Err("expected a message pattern after the severity".to_owned());
+    }
+
+    let line = match line {
+        None => directive_line as i64,
+        Some(line) if line.starts_with('+') || line.starts_with('-') => match line.parse::<i64>() {
+            Ok(offset) => directive_line as i64 + offset,
+            Err(..) => return Err(format!("invalid relative line '{}'", line)),
+        },
+        Some(line) => line.parse::<i64>().map_err(|_| format!("invalid line '{}', expected a number such as '12' or '+1'", line))?,
+    };
+    if line < 1 {
+        return Err(format!("the diagnostic line {} is before the start of the file", line));
+    }
+
+    // Diagnostics start on their own lines, and their paths may not contain colons, other than
+    // after a Windows drive letter, so that the column of a diagnostic is never mistaken for its line.
+    let mut components = vec![
+        PatternComponent::Regex(r"(?m:^)(?:[a-zA-Z]:)?[^:\n]*".to_owned()),
+        PatternComponent::Text(format!(":{}:", line)),
+        PatternComponent::Regex(r"(?:\d+:)?".to_owned()),
+        PatternComponent::Text(format!(" {}: ", severity)),
+    ];
+    components.extend(self::text_pattern(message).components);
+
+    // Diagnostics are found by regexes, whichever matcher is the default.
+    Ok(TextPattern { components, matcher: Some(crate::matcher::REGEX.to_owned()), anchored: false })
+}
 
 /// Parses a tool invocation.
 ///
@@ -47,7 +542,21 @@ pub fn invocation<'a,I>(words: I) -> Result<Invocation, String>
     let parts: Vec<_> = words.collect();
     let original_command = parts.join(" ");
 
-    Ok(Invocation { original_command })
+    Ok(Invocation::new(original_command))
+}
+
+/// Parses the inside of a numeric pattern component, such as `%.3f, 1.234 +- 0.001`.
+fn numeric_component(s: &str) -> PatternComponent {
+    let (format, expected) = match s.find(',') {
+        Some(comma_idx) => (&s[..comma_idx], &s[comma_idx + 1..]),
+        None => (s, ""),
+    };
+    let (value, tolerance) = match expected.find("+-") {
+        Some(idx) => (&expected[..idx], Some(expected[idx + 2..].trim().to_owned())),
+        None => (expected, None),
+    };
+
+    PatternComponent::Numeric { format: format.trim().to_owned(), value: value.trim().to_owned(), tolerance }
 }
 
 pub fn text_pattern(s: &str) -> TextPattern {
@@ -69,10 +578,26 @@ pub fn text_pattern(s: &str) -> TextPattern {
                 complete_text(&mut current_text, &mut components);
                 chars.next(); // Eat second '$'.
 
-                let name: String = chars.clone()
-                                        .take_while(|c| c.is_alphanumeric())
-                                        .collect();
-                chars.nth(name.len() - 1); // Skip the variable name.
+                let mut name = self::variable_name(chars.clone());
+                if name.is_empty() {
+                    current_text.extend(&['$', '$']);
+                    continue;
+                }
+                chars.nth(name.chars().count() - 1); // Skip the variable name.
+
+                // Names qualified with a namespace, such as '$$cap:name'.
+                if crate::vars::NAMESPACES.contains(&&name[..]) {
+                    let mut lookahead = chars.clone();
+                    if lookahead.next() == Some(':') {
+                        let unqualified_name = self::variable_name(lookahead);
+
+                        if !unqualified_name.is_empty() {
+                            chars.nth(unqualified_name.chars().count()); // Skip the colon and the name.
+                            name = crate::vars::qualified_name(&name, &unqualified_name);
+                        }
+                    }
+                }
+
                 components.push(PatternComponent::Variable(name));
             },
             // Named or unnamed regex.
@@ -105,6 +630,11 @@ pub fn text_pattern(s: &str) -> TextPattern {
 
                 let regex: String = current_regex.into_iter().collect();
 
+                if regex.starts_with("#%") {
+                    components.push(self::numeric_component(&regex[1..]));
+                    continue;
+                }
+
                 let first_colon_idx = regex.chars().position(|c| c == ':');
                 let (name, regex): (Option<&str>, &str) = match first_colon_idx {
                     Some(first_colon_idx) => {
@@ -135,30 +665,152 @@ pub fn text_pattern(s: &str) -> TextPattern {
         }
     }
 
-    TextPattern { components: components }
+    TextPattern { components: components, matcher: None, anchored: false }
+}
+
+/// Reads the name of a variable from the start of some text.
+///
+/// Names are made up of alphanumeric characters and underscores, but trailing underscores
+/// are not part of the name, so that `$$name_` is the variable `name` followed by `_`.
+fn variable_name(text: impl Iterator<Item=char>) -> String {
+    let name: String = text.take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+    name.trim_end_matches('_').to_owned()
 }
 
 /// Parses a possible command, if a string defines one.
 ///
+/// The span of the returned command is relative to the start of the string.
+///
 /// Returns `None` if no command is specified.
-pub fn possible_command(string: &str, line: u32)
+pub fn possible_command(string: &str, line: u32, custom_directives: &[&str], directive_regex: &Regex)
     -> Option<Result<Command, String>> {
-    if !DIRECTIVE_REGEX.is_match(string) { return None; }
+    let captures = directive_regex.captures(string)?;
+    let directive_match = captures.get(0).unwrap();
+    // Trailing whitespace is excluded, such as where the delimiter of a block comment was blanked out.
+    let span = Span { start: directive_match.start(), end: directive_match.start() + directive_match.as_str().trim_end().len() };
 
-    let captures = DIRECTIVE_REGEX.captures(string).unwrap();
-    let command_str = captures.get(1).unwrap().as_str().trim();
+    let command = self::directive(&captures, line, custom_directives)?;
+    Some(command.map(|command| Command { span, ..command }))
+}
+
+fn directive(captures: &regex::Captures, line: u32, custom_directives: &[&str])
+    -> Option<Result<Command, String>> {
+    let name = captures.get(1).unwrap().as_str().trim();
+    let (command_str, anchored) = match name.strip_suffix("{^}") {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    let (command_str, matcher) = self::split_matcher(command_str);
     let after_command_str = captures.get(2).unwrap().as_str().trim();
 
+    let mut command = self::named_directive(command_str, after_command_str, line, custom_directives)?;
+    if let Some(matcher) = matcher {
+        command = command.and_then(|command| self::with_matcher(command, command_str, matcher));
+    }
+    if anchored {
+        command = command.and_then(|command| self::anchored(command, command_str));
+    }
+    Some(command)
+}
+
+/// Splits the engine named in brackets off the name of a directive, as in `CHECK[exact]`.
+fn split_matcher(name: &str) -> (&str, Option<&str>) {
+    match name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+        Some((name, matcher)) => (name, Some(matcher)),
+        None => (name, None),
+    }
+}
+
+/// Matches the pattern of a check directive with the named engine.
+fn with_matcher(mut command: Command, directive_name: &str, matcher: &str) -> Result<Command, String> {
+    match command.kind {
+        CommandKind::Check(ref mut pattern) | CommandKind::CheckNext(ref mut pattern) | CommandKind::CheckBlock(ref mut pattern) |
+            CommandKind::CheckNotBetween(ref mut pattern) | CommandKind::CheckEnd(ref mut pattern) if pattern.matcher.is_none() => {
+            pattern.matcher = Some(matcher.to_owned());
+            Ok(command)
+        },
+        _ => Err(format!("{} directive on line {} does not take a matcher, but got '[{}]'", directive_name, command.line_number, matcher)),
+    }
+}
+
+/// Only matches the pattern of a check directive at the start of a line.
+fn anchored(mut command: Command, directive_name: &str) -> Result<Command, String> {
+    match command.kind {
+        CommandKind::Check(ref mut pattern) | CommandKind::CheckNext(ref mut pattern) | CommandKind::CheckBlock(ref mut pattern) |
+            CommandKind::CheckNotBetween(ref mut pattern) | CommandKind::CheckEnd(ref mut pattern) => {
+            pattern.anchored = true;
+            Ok(command)
+        },
+        _ => Err(format!("{} directive on line {} cannot be anchored to the start of a line", directive_name, command.line_number)),
+    }
+}
+
+fn named_directive(command_str: &str, after_command_str: &str, line: u32, custom_directives: &[&str])
+    -> Option<Result<Command, String>> {
     match command_str {
         // FIXME: better message if we have 'RUN :'
-        "RUN" => {
+        "RUN" | "NOT-RUN" => {
+            // 'RUN: !<command>' is shorthand for 'NOT-RUN: <command>'.
+            let (negated, command_str) = match after_command_str.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (command_str == "NOT-RUN", after_command_str),
+            };
+            if negated && command_str.trim().is_empty() {
+                return Some(Err(format!("negated RUN directive on line {} requires a command", line)));
+            }
+
+            let inner_words = command_str.split_whitespace();
+            let invocation = match self::invocation(inner_words) {
+                Ok(i) => i,
+                Err(e) => return Some(Err(e)),
+            };
+
+            Some(Ok(Command::new(CommandKind::Run(Invocation { negated, ..invocation }), line)))
+        },
+        "RUN-BACKGROUND" => {
             let inner_words = after_command_str.split_whitespace();
             let invocation = match self::invocation(inner_words) {
                 Ok(i) => i,
                 Err(e) => return Some(Err(e)),
             };
 
-            Some(Ok(Command::new(CommandKind::Run(invocation), line)))
+            Some(Ok(Command::new(CommandKind::RunBackground(invocation), line)))
+        },
+        "RESOURCE" => {
+            let name = after_command_str.trim();
+            if name.is_empty() {
+                return Some(Err(format!("RESOURCE directive on line {} requires a resource name", line)));
+            }
+
+            Some(Ok(Command::new(CommandKind::Resource(name.to_owned()), line)))
+        },
+        "DEPENDS" => {
+            let path = after_command_str.trim();
+            if path.is_empty() {
+                return Some(Err(format!("DEPENDS directive on line {} requires a path", line)));
+            }
+
+            Some(Ok(Command::new(CommandKind::Depends(path.to_owned()), line)))
+        },
+        "INCLUDE" => {
+            let path = after_command_str.trim();
+            if path.is_empty() {
+                return Some(Err(format!("INCLUDE directive on line {} requires a path", line)));
+            }
+
+            Some(Ok(Command::new(CommandKind::Include(path.to_owned()), line)))
+        },
+        "OWNER" => {
+            let owner = after_command_str.trim();
+            if owner.is_empty() {
+                return Some(Err(format!("OWNER directive on line {} requires an owner", line)));
+            }
+
+            Some(Ok(Command::new(CommandKind::Owner(owner.to_owned()), line)))
+        },
+        "WAIT-FOR" => {
+            let text_pattern = self::text_pattern(after_command_str);
+            Some(Ok(Command::new(CommandKind::WaitFor(text_pattern), line)))
         },
         "CHECK" => {
             let text_pattern = self::text_pattern(after_command_str);
@@ -168,11 +820,98 @@ pub fn possible_command(string: &str, line: u32)
             let text_pattern = self::text_pattern(after_command_str);
             Some(Ok(Command::new(CommandKind::CheckNext(text_pattern), line)))
         },
+        "CHECK-BLOCK" => {
+            if !after_command_str.is_empty() {
+                return Some(Err(format!("CHECK-BLOCK directive on line {} expects its block on the following indented lines", line)));
+            }
+
+            // The block itself is gathered from the following lines by the caller.
+            Some(Ok(Command::new(CommandKind::CheckBlock(TextPattern { components: Vec::new(), matcher: None, anchored: false }), line)))
+        },
+        "CHECK-NOT-BETWEEN" => {
+            let text_pattern = self::text_pattern(after_command_str);
+            Some(Ok(Command::new(CommandKind::CheckNotBetween(text_pattern), line)))
+        },
+        "CHECK-END" => {
+            let text_pattern = self::text_pattern(after_command_str);
+            Some(Ok(Command::new(CommandKind::CheckEnd(text_pattern), line)))
+        },
+        "CHECK-SAME-AS" => {
+            let inner_words = after_command_str.split_whitespace();
+            let invocation = match self::invocation(inner_words) {
+                Ok(i) => i,
+                Err(e) => return Some(Err(e)),
+            };
+
+            Some(Ok(Command::new(CommandKind::CheckSameAs(invocation), line)))
+        },
+        "CHECK-GOLDEN" => {
+            let path = after_command_str.trim();
+            if path.is_empty() {
+                return Some(Err(format!("CHECK-GOLDEN directive on line {} requires the path of a golden file", line)));
+            }
+
+            Some(Ok(Command::new(CommandKind::CheckGolden(path.to_owned()), line)))
+        },
+        "CHECK-DIAG" => {
+            let text_pattern = self::diagnostic_check(after_command_str, line).map_err(|e| format!("CHECK-DIAG directive on line {}: {}", line, e));
+            Some(text_pattern.map(|text_pattern| Command::new(CommandKind::Check(text_pattern), line)))
+        },
+        "CHECK-JSON" => {
+            let check = self::json_check(after_command_str).map_err(|e| format!("CHECK-JSON directive on line {}: {}", line, e));
+            Some(check.map(|check| Command::new(CommandKind::CheckJson(check), line)))
+        },
+        "CHECK-SKIP" => {
+            let line_count = after_command_str.trim().parse().map_err(|_| format!("CHECK-SKIP directive on line {}: expected a number of lines, but got '{}'", line, after_command_str.trim()));
+            Some(line_count.map(|line_count| Command::new(CommandKind::CheckSkip(line_count), line)))
+        },
+        "EXPECT-DURATION-UNDER" => {
+            let limit = crate::util::parse_duration(after_command_str).map_err(|e| format!("EXPECT-DURATION-UNDER directive on line {}: {}", line, e));
+            Some(limit.map(|limit| Command::new(CommandKind::ExpectDurationUnder(limit), line)))
+        },
+        "EXPECT-RSS-UNDER" => {
+            let limit = crate::util::parse_byte_size(after_command_str).map_err(|e| format!("EXPECT-RSS-UNDER directive on line {}: {}", line, e));
+            Some(limit.map(|limit| Command::new(CommandKind::ExpectRssUnder(limit), line)))
+        },
+        "UNDEF" => {
+            let name = after_command_str.trim();
+            if !IDENTIFIER_REGEX.is_match(name) {
+                return Some(Err(format!("UNDEF directive on line {} requires a variable name, but got '{}'", line, name)));
+            }
+
+            Some(Ok(Command::new(CommandKind::Undef(name.to_owned()), line)))
+        },
+        "NORMALIZE-PRESET" => {
+            let name = after_command_str.trim();
+            if let Err(e) = crate::config::presets::validate(name) {
+                return Some(Err(format!("NORMALIZE-PRESET directive on line {}: {}", line, e)));
+            }
+
+            Some(Ok(Command::new(CommandKind::NormalizePreset(name.to_owned()), line)))
+        },
         "XFAIL" => {
             Some(Ok(Command::new(CommandKind::XFail, line)))
         },
+        "CHECK-UNORDERED" => {
+            Some(Ok(Command::new(CommandKind::CheckUnordered, line)))
+        },
+        "PTY" => {
+            Some(Ok(Command::new(CommandKind::Pty, line)))
+        },
+        "ALLOW-STDERR" => {
+            Some(Ok(Command::new(CommandKind::AllowStderr, line)))
+        },
+        name if custom_directives.contains(&name) => {
+            let directive = CustomDirective { name: name.to_owned(), argument: after_command_str.to_owned() };
+            Some(Ok(Command::new(CommandKind::Custom(directive), line)))
+        },
         _ => {
-            Some(Err(format!("command '{}' not known", command_str)))
+            let known_directives = BUILTIN_DIRECTIVES.iter().chain(custom_directives.iter()).cloned();
+
+            match crate::util::closest_match(command_str, known_directives) {
+                Some(suggestion) => Some(Err(format!("command '{}' not known, did you mean '{}'?", command_str, suggestion))),
+                None => Some(Err(format!("command '{}' not known", command_str))),
+            }
         },
     }
 }
@@ -213,3 +952,229 @@ mod test {
     }
 }
 
+#[cfg(test)]
+mod text_pattern_test {
+    use super::*;
+
+    #[test]
+    fn parses_single_text() {
+        assert_eq!(text_pattern("hello world").components,
+                   vec![PatternComponent::Text("hello world".to_owned())]);
+    }
+
+    #[test]
+    fn keeps_text_unescaped() {
+        assert_eq!(text_pattern("hello()").components,
+                   vec![PatternComponent::Text("hello()".to_owned())]);
+    }
+
+    #[test]
+    fn correctly_picks_up_single_regex() {
+        assert_eq!(text_pattern("[[\\d]]").components,
+                   vec![PatternComponent::Text(String::new()), PatternComponent::Regex("\\d".to_owned()), PatternComponent::Text(String::new())]);
+    }
+
+    #[test]
+    fn correctly_picks_up_regex_between_text() {
+        assert_eq!(text_pattern("1[[\\d]]3").components,
+                   vec![PatternComponent::Text("1".to_owned()), PatternComponent::Regex("\\d".to_owned()), PatternComponent::Text("3".to_owned())]);
+    }
+
+    #[test]
+    fn correctly_picks_up_named_regex() {
+        assert_eq!(text_pattern("[[num:\\d]]").components,
+                   vec![PatternComponent::Text(String::new()), PatternComponent::NamedRegex { name: "num".to_owned(), regex: "\\d".to_owned() }, PatternComponent::Text(String::new())]);
+    }
+}
+
+
+#[cfg(test)]
+mod test_file_test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn records_byte_spans_of_directives() {
+        let path = TestFilePath { absolute: PathBuf::from("/test.txt"), relative: PathBuf::from("test.txt") };
+        let text = "; RUN: cat @file\r\n\n; CHECK: hello\n";
+        let test_file = test_file(path, text).unwrap();
+
+        let spans: Vec<_> = test_file.commands.iter().map(|c| &text[c.span.start..c.span.end]).collect();
+        assert_eq!(spans, vec!["RUN: cat @file", "CHECK: hello"]);
+        assert_eq!(test_file.commands[1].line_number, 3);
+    }
+
+    #[test]
+    fn gathers_indented_check_block_lines() {
+        let path = TestFilePath { absolute: PathBuf::from("/test.txt"), relative: PathBuf::from("test.txt") };
+        let text = "# CHECK-BLOCK:\n#   fn main() {\n#\n#     body\n#   }\n#\n# CHECK: after\n";
+        let test_file = test_file(path, text).unwrap();
+
+        assert_eq!(test_file.commands.len(), 2);
+        match test_file.commands[0].kind {
+            CommandKind::CheckBlock(ref pattern) => assert_eq!(pattern.to_string(), "fn main() {\n\n  body\n}"),
+            ref kind => panic!("expected a CHECK-BLOCK but got {:?}", kind),
+        }
+        assert_eq!(&text[test_file.commands[0].span.start..test_file.commands[0].span.end], "CHECK-BLOCK:\n#   fn main() {\n#\n#     body\n#   }");
+        assert_eq!(test_file.commands[1].line_number, 7);
+    }
+
+    #[test]
+    fn displayed_commands_parse_back_identically() {
+        let path = TestFilePath { absolute: PathBuf::from("/test.txt"), relative: PathBuf::from("test.txt") };
+        let text = "RUN: cc @file -o $$out\nRUN: !false\nOWNER: core\nCHECK: [[x:\\d+]] then $$cap:x\nCHECK-NOT-BETWEEN: warning\n\
+                    CHECK-END: done\nCHECK-BLOCK:\n  fn main() {\n\n    body\n  }\nUNDEF: x\nNORMALIZE-PRESET: uuids\nXFAIL:\nPTY:\nALLOW-STDERR:\nCHECK-SKIP: 2\nCHECK[exact]: 1 + 1\nCHECK{^}: start\nCHECK-JSON: $.a[0] == {\"b\": 1}\nCHECK-YAML: {}\n";
+        let test_file = test_file_with_custom_directives(path, text, &["CHECK-YAML"], &DIRECTIVE_REGEX).unwrap();
+
+        for command in test_file.commands {
+            let displayed = command.kind.to_string();
+            assert_eq!(command_kind(&displayed, &["CHECK-YAML"]), Ok(command.kind), "'{}' did not round trip", displayed);
+        }
+
+        assert_eq!("CHECK:  hello ".parse::<CommandKind>().map(|k| k.to_string()), Ok("CHECK: hello".to_owned()));
+        assert!("CHECK: a\nCHECK: b".parse::<CommandKind>().is_err());
+        assert_eq!("NOT-RUN: false".parse(), Ok(CommandKind::Run(Invocation::new("false").negated())));
+        assert_eq!("INCLUDE: common.txt".parse::<CommandKind>().map(|k| k.to_string()), Ok("INCLUDE: common.txt".to_owned()));
+        assert_ne!("CHECK[exact]: a".parse::<CommandKind>(), "CHECK: a".parse::<CommandKind>());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn commands_round_trip_through_serde() {
+        let path = TestFilePath { absolute: PathBuf::from("/test.txt"), relative: PathBuf::from("test.txt") };
+        let text = "RUN: !cc @file\nCHECK[exact]{^}: [[x:\\d+]] $$x [[#%d, 3]]\nCHECK-BLOCK:\n  a\n  b\nCHECK-JSON: $.a == 1\n\
+                    EXPECT-DURATION-UNDER: 2s\nCHECK-YAML: {}\nXFAIL:\n";
+        let test_file = test_file_with_custom_directives(path, text, &["CHECK-YAML"], &DIRECTIVE_REGEX).unwrap();
+
+        for command in test_file.commands {
+            let json = serde_json::to_string(&command).unwrap();
+            assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), command, "{} did not round trip", json);
+        }
+    }
+
+    #[test]
+    fn rejects_unclosed_check_not_between_windows() {
+        let path = TestFilePath { absolute: PathBuf::from("/test.txt"), relative: PathBuf::from("test.txt") };
+
+        assert!(test_file(path.clone(), "CHECK-NOT-BETWEEN: a\nCHECK-NOT-BETWEEN: b\nCHECK-END: c\n").is_ok());
+        assert_eq!(test_file(path.clone(), "CHECK-NOT-BETWEEN: a\nCHECK: b\n").unwrap_err(),
+                   "CHECK-NOT-BETWEEN on line 1 must be closed by CHECK-END before line 2");
+        assert_eq!(test_file(path.clone(), "CHECK-NOT-BETWEEN: a\n").unwrap_err(),
+                   "CHECK-NOT-BETWEEN on line 1 is never closed by CHECK-END");
+        assert_eq!(test_file(path, "CHECK-END: a\n").unwrap_err(),
+                   "CHECK-END on line 1 does not close a CHECK-NOT-BETWEEN");
+    }
+
+    #[test]
+    fn parses_commented_front_matter() {
+        let text = "#!/bin/sh\n# ---\n# timeout = 5\n# tags = [\"slow\"]\n#\n# [env]\n# LANG = \"C\"\n# ---\n# RUN: true\n";
+        let parsed = front_matter(text).unwrap();
+
+        assert_eq!(parsed.timeout, Some(std::time::Duration::from_secs(5)));
+        assert_eq!(parsed.tags, vec!["slow"]);
+        assert_eq!(parsed.env["LANG"], "C");
+        assert_eq!(front_matter("---\ntimeout = 1\n---\n").unwrap().timeout, Some(std::time::Duration::from_secs(1)));
+        assert_eq!(front_matter("# -------\n# RUN: true\n"), Ok(FrontMatter::default()));
+
+        assert_eq!(front_matter("# ---\n# colour = \"red\"\n# ---\n").unwrap_err(), "line 2: unknown front matter key 'colour'");
+        assert_eq!(front_matter("// ---\n// timeout = 1\n").unwrap_err(), "line 1: unterminated front matter, expected a closing '// ---'");
+        assert_eq!(front_matter("#!/bin/sh\n---\ntimeout = 1\n").unwrap_err(), "line 2: unterminated front matter, expected a closing '---'");
+        assert_eq!(front_matter("# ---\ntimeout = 1\n# ---\n"), Ok(FrontMatter::default()));
+    }
+
+    #[test]
+    fn yaml_documents_are_not_front_matter() {
+        let path = TestFilePath { absolute: PathBuf::from("/doc.yaml"), relative: PathBuf::from("doc.yaml") };
+        let text = "---\n# RUN: cat @file\n# CHECK: name: lit\nname: lit\n";
+        let test_file = test_file(path.clone(), text).unwrap();
+
+        assert_eq!(test_file.front_matter, FrontMatter::default());
+        assert_eq!(test_file.commands.iter().map(|c| c.kind.directive_name()).collect::<Vec<_>>(), vec!["RUN", "CHECK"]);
+        assert_eq!(self::test_file(path.clone(), "---\nname: lit\n---\n# RUN: cat @file\n").unwrap().front_matter, FrontMatter::default());
+        assert_eq!(self::test_file(path, "---\ntimeout = -1\n---\n").unwrap_err(), "doc.yaml: front matter line 2: invalid timeout: '-1'");
+    }
+
+    #[test]
+    fn only_finds_directives_in_comments_for_configured_extensions() {
+        let mut config = Config::default();
+        config.set_comment_syntax("rs", CommentSyntax::line("//"));
+        config.set_comment_syntax("ml", CommentSyntax::block("(*", "*)"));
+        let parse = |file_name: &str, text: &str| {
+            let path = TestFilePath { absolute: PathBuf::from("/").join(file_name), relative: PathBuf::from(file_name) };
+            let test_file = test_file_for_config(path, text, &config).unwrap();
+            test_file.commands.iter().map(|c| (c.line_number, text[c.span.start..c.span.end].to_owned())).collect::<Vec<_>>()
+        };
+
+        let rust = "// RUN: rustc @file\nlet s = \"// CHECK: no \\\" // CHECK: no\";\nlet t = 1; // CHECK: yes\n";
+        assert_eq!(parse("test.rs", rust), vec![(1, "RUN: rustc @file".to_owned()), (3, "CHECK: yes".to_owned())]);
+        assert_eq!(parse("test.txt", rust).len(), 3);
+        let literals = "let q = '\"'; // CHECK: after char\nlet e = '\\''; let l: &'a str = r\"C:\\\"; // CHECK: after raw\n\
+                        let h = r#\"\" // CHECK: no\"#; // CHECK: after hashes\nlet bar\"// CHECK: no\";\n";
+        assert_eq!(parse("test.rs", literals).into_iter().map(|(line, _)| line).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let ocaml = "(* RUN: ocaml @file *)\nlet s = \"CHECK: no\"\n(*\n  CHECK: yes\n*)\n";
+        assert_eq!(parse("test.ml", ocaml), vec![(1, "RUN: ocaml @file".to_owned()), (4, "CHECK: yes".to_owned())]);
+    }
+
+    #[test]
+    fn parses_json_checks_and_performance_limits() {
+        assert_eq!(command_kind("CHECK-JSON: $.a[0] == {\"b\": 1}", &[]),
+                   Ok(CommandKind::CheckJson(JsonCheck { path: "$.a[0]".to_owned(), expected: "{\"b\": 1}".to_owned() })));
+        assert_eq!(command_kind("CHECK-JSON: $.a ==", &[]).unwrap_err(),
+                   "could not parse command: CHECK-JSON directive on line 1: invalid expected value '': invalid JSON at byte 0: unexpected end of document");
+        assert_eq!(command_kind("CHECK-JSON: a == 1", &[]).unwrap_err(),
+                   "could not parse command: CHECK-JSON directive on line 1: invalid JSON path 'a == 1' at byte 0: expected '$'");
+
+        assert_eq!(command_kind("EXPECT-DURATION-UNDER: 90s", &[]), Ok(CommandKind::ExpectDurationUnder(std::time::Duration::from_secs(90))));
+        assert_eq!(command_kind("EXPECT-RSS-UNDER: 1GB", &[]), Ok(CommandKind::ExpectRssUnder(1_000_000_000)));
+        assert_eq!(command_kind("EXPECT-DURATION-UNDER: soon", &[]).unwrap_err(),
+                   "could not parse command: EXPECT-DURATION-UNDER directive on line 1: invalid duration 'soon', expected a number followed by 'ms', 's', 'm' or 'h'");
+        assert_eq!(command_kind("EXPECT-RSS-UNDER: lots", &[]).unwrap_err(),
+                   "could not parse command: EXPECT-RSS-UNDER directive on line 1: invalid size 'lots', expected a number followed by 'B', 'KB', 'MB', 'GB', 'KiB', 'MiB' or 'GiB'");
+    }
+
+    #[test]
+    fn diagnostic_checks_are_relative_to_their_own_line() {
+        let path = TestFilePath { absolute: PathBuf::from("/diag.c"), relative: PathBuf::from("diag.c") };
+        let test_file = test_file(path.clone(), "RUN: cc @file\nCHECK-DIAG: warning@-1: unused\nCHECK-DIAG: error: expected ;\n").unwrap();
+        let patterns: Vec<_> = test_file.commands[1..].iter().map(|c| c.kind.to_string()).collect();
+
+        // The diagnostic lines are the `:1:` and `:3:` between the file name and the message.
+        assert!(patterns[0].contains("]]:1:[[") && patterns[0].ends_with("]] warning: unused"), "{}", patterns[0]);
+        assert!(patterns[1].contains("]]:3:[[") && patterns[1].ends_with("]] error: expected ;"), "{}", patterns[1]);
+        assert_eq!(self::test_file(path.clone(), "CHECK-DIAG: error@-1: oops\n").unwrap_err(),
+                   "could not parse command: CHECK-DIAG directive on line 1: the diagnostic line 0 is before the start of the file");
+        assert_eq!(self::test_file(path, "CHECK-DIAG: 42: oops\n").unwrap_err(),
+                   "could not parse command: CHECK-DIAG directive on line 1: invalid diagnostic severity '42', expected a word such as 'error' or 'warning'");
+    }
+
+    #[test]
+    fn included_directives_are_spliced_into_tests() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("common")).unwrap();
+        std::fs::write(dir.path().join("common/prologue.txt"), "RUN: printf 'hello\\nworld\\n'\nINCLUDE: checks.txt\n").unwrap();
+        std::fs::write(dir.path().join("common/checks.txt"), "CHECK: hello\n").unwrap();
+        std::fs::write(dir.path().join("common/broken.txt"), "\nCHECK-SKIP: many\n").unwrap();
+        std::fs::write(dir.path().join("common/cycle.txt"), "INCLUDE: ../test.txt\n").unwrap();
+        std::fs::write(dir.path().join("test.txt"), "INCLUDE: common/cycle.txt\n").unwrap();
+        let parse = |text: &str| test_file(TestFilePath { absolute: dir.path().join("test.txt"), relative: PathBuf::from("test.txt") }, text);
+
+        let test_file = parse("INCLUDE: common/prologue.txt\nCHECK-NEXT: world\n").unwrap();
+        assert_eq!(test_file.commands.iter().map(|c| (c.kind.to_string(), c.line_number)).collect::<Vec<_>>(), vec![
+            ("RUN: printf 'hello\\nworld\\n'".to_owned(), 1),
+            ("CHECK: hello".to_owned(), 1),
+            ("CHECK-NEXT: world".to_owned(), 2),
+        ]);
+        assert_eq!(test_file.commands.iter().map(|c| c.origin.clone()).collect::<Vec<_>>(), vec![
+            Some(Origin { path: dir.path().join("common/prologue.txt"), line_number: 1 }),
+            Some(Origin { path: dir.path().join("common/checks.txt"), line_number: 1 }),
+            None,
+        ]);
+
+        assert_eq!(parse("RUN: true\nINCLUDE: common/broken.txt\n").unwrap_err(),
+                   "in 'common/broken.txt', included on line 2: could not parse command: CHECK-SKIP directive on line 2: expected a number of lines, but got 'many'");
+        assert_eq!(parse("INCLUDE: common/cycle.txt\n").unwrap_err(),
+                   "in 'common/cycle.txt', included on line 1: INCLUDE directive on line 1 includes '../test.txt' within itself: test.txt -> common/cycle.txt -> ../test.txt");
+        assert!(parse("INCLUDE: common/missing.txt\n").unwrap_err().starts_with("INCLUDE directive on line 1 could not read"));
+    }
+}
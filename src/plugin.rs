@@ -0,0 +1,135 @@
+//! Bundles of customizations that can be shared between test suites.
+//!
+//! A plugin is registered with `Config::register_plugin`, which lets a single
+//! crate customize lit consistently across many repositories.
+//!
+//! ```
+//! use lit::{Config, Variables, model::{CustomDirective, ProgramOutput, TestResultKind}, plugin::Plugin};
+//!
+//! #[derive(Debug)]
+//! struct LineCount;
+//!
+//! impl Plugin for LineCount {
+//!     fn name(&self) -> &str { "line-count" }
+//!
+//!     fn directives(&self) -> &[&str] { &["CHECK-LINE-COUNT"] }
+//!
+//!     fn evaluate_directive(&self, directive: &CustomDirective, output: &ProgramOutput, _: &Variables) -> TestResultKind {
+//!         match directive.argument.parse::<usize>() {
+//!             Ok(count) if output.stdout.lines().count() == count => TestResultKind::Pass,
+//!             _ => TestResultKind::Error { message: format!("expected {} lines of output", directive.argument) },
+//!         }
+//!     }
+//! }
+//!
+//! let mut config = Config::default();
+//! config.register_plugin(LineCount);
+//! ```
+
+use crate::{Config, Variables, config::OutputNormalizer, event_handler::EventHandler};
+use crate::model::{CustomDirective, ProgramOutput, TestResultKind};
+use std::fmt;
+
+/// A set of optional hooks that customize lit.
+pub trait Plugin: fmt::Debug + Send + Sync {
+    /// The name of the plugin, used in diagnostics.
+    fn name(&self) -> &str;
+
+    /// The names of the extra directives evaluated by this plugin, such as `CHECK-YAML`.
+    fn directives(&self) -> &[&str] { &[] }
+
+    /// Evaluates one of the directives of this plugin against the output of a `RUN` command.
+    fn evaluate_directive(&self, directive: &CustomDirective, _output: &ProgramOutput, _variables: &Variables) -> TestResultKind {
+        TestResultKind::Error { message: format!("plugin '{}' cannot evaluate directive '{}'", self.name(), directive.name) }
+    }
+
+    /// Resolves a variable that is not otherwise defined.
+    fn lookup_variable(&self, _name: &str) -> Option<String> { None }
+
+    /// Substitutions applied to outputs before they are compared, added to
+    /// `Config::output_normalizers` when the plugin is registered.
+    fn output_normalizers(&self) -> Vec<OutputNormalizer> { Vec::new() }
+
+    /// Creates an event handler that is notified of all events, alongside the
+    /// event handler given to `run::tests`.
+    fn event_handler(&self) -> Option<Box<dyn EventHandler>> { None }
+}
+
+impl Config {
+    /// Registers a plugin, applying its customizations.
+    ///
+    /// Panics if the plugin defines a directive that is already defined.
+    pub fn register_plugin(&mut self, plugin: impl Plugin + 'static) {
+        for &directive in plugin.directives() {
+            if crate::parse::BUILTIN_DIRECTIVES.contains(&directive) {
+                panic!("plugin '{}' cannot redefine the builtin directive '{}'", plugin.name(), directive);
+            }
+            if let Some(other) = self.plugin_for_directive(directive) {
+                panic!("plugin '{}' cannot define directive '{}', as it is already defined by plugin '{}'", plugin.name(), directive, other.name());
+            }
+        }
+
+        self.output_normalizers.extend(plugin.output_normalizers());
+        self.plugins.push(std::sync::Arc::new(plugin));
+    }
+
+    /// Gets the plugin that evaluates a directive, if any.
+    pub fn plugin_for_directive(&self, directive: &str) -> Option<&dyn Plugin> {
+        self.plugins.iter().find(|p| p.directives().contains(&directive)).map(|p| &**p)
+    }
+}
+
+/// Evaluates a directive defined by a plugin.
+pub(crate) fn evaluate_directive(directive: &CustomDirective, output: &ProgramOutput, variables: &Variables, config: &Config) -> TestResultKind {
+    match config.plugin_for_directive(&directive.name) {
+        Some(plugin) => plugin.evaluate_directive(directive, output, variables),
+        None => TestResultKind::Error { message: format!("no plugin defines the directive '{}'", directive.name) },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Dummy(&'static [&'static str]);
+
+    impl Plugin for Dummy {
+        fn name(&self) -> &str { "dummy" }
+        fn directives(&self) -> &[&str] { self.0 }
+        fn evaluate_directive(&self, directive: &CustomDirective, output: &ProgramOutput, _: &Variables) -> TestResultKind {
+            if output.stdout.trim() == directive.argument { TestResultKind::Pass } else { TestResultKind::Skip }
+        }
+        fn lookup_variable(&self, name: &str) -> Option<String> {
+            if name == "answer" { Some("42".to_owned()) } else { None }
+        }
+    }
+
+    #[test]
+    fn registered_plugins_resolve_directives_and_variables() {
+        let mut config = Config::default();
+        config.register_plugin(Dummy(&["CHECK-DUMMY"]));
+
+        assert_eq!(config.plugin_for_directive("CHECK-DUMMY").map(|p| p.name()), Some("dummy"));
+        assert!(config.plugin_for_directive("CHECK-OTHER").is_none());
+        assert_eq!(config.try_lookup_variable("answer", &mut Variables::new()), Some("42"));
+    }
+
+    #[test]
+    fn custom_directives_are_evaluated_by_plugins() {
+        let mut config = Config { shell: "sh".to_owned(), ..Config::default() };
+        config.register_plugin(Dummy(&["CHECK-DUMMY"]));
+
+        let path = crate::model::TestFilePath::new("/nonexistent/plugin.txt", "plugin.txt");
+        let test_file = crate::parse::test_file_for_config(path.clone(), "RUN: echo @answer\nCHECK-DUMMY: 42\n", &config).unwrap();
+        assert_eq!(crate::run::execute_test_file(&test_file, &config).overall_result, TestResultKind::Pass);
+
+        assert!(crate::parse::test_file(path, "CHECK-DUMMY: 42\n").is_err(), "directives should only be known with the plugin");
+    }
+
+    #[test]
+    #[should_panic(expected = "builtin directive 'CHECK'")]
+    fn plugins_cannot_redefine_builtin_directives() {
+        Config::default().register_plugin(Dummy(&["CHECK"]));
+    }
+}
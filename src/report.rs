@@ -0,0 +1,264 @@
+//! Reading machine-readable results back into Rust, such as for tools that compare runs.
+//!
+//! The formats are described by the JSON schemas in the `schema` directory of the
+//! repository, which are also available as `RESULTS_SCHEMA` and `OWNER_REPORT_SCHEMA`.
+//!
+//! ```no_run
+//! let results = lit::report::parse_results("results.jsonl").unwrap();
+//!
+//! for test in results.tests.iter().filter(|t| t.result == "fail") {
+//!     println!("{} failed: {}", test.path.display(), test.message.as_deref().unwrap_or(""));
+//! }
+//! ```
+
+use crate::json;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The version of the machine-readable formats, recorded in their `format_version` fields.
+///
+/// This is increased whenever the formats change in a way that existing consumers could misread.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// The JSON schema of each line of `--message-format json-lines` output.
+pub const RESULTS_SCHEMA: &str = include_str!("../schema/results.schema.json");
+/// The JSON schema of the `owners/<owner>.json` test artifacts.
+pub const OWNER_REPORT_SCHEMA: &str = include_str!("../schema/owner-report.schema.json");
+
+/// The results of a test suite, as read from `--message-format json-lines` output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Results {
+    /// The version of the format the results were written in.
+    pub format_version: u64,
+    /// The number of test files in the suite.
+    pub test_file_count: usize,
+    /// The directory artifacts were saved to, if any.
+    pub artifacts_directory: Option<PathBuf>,
+    /// The seed substituted for `@seed`, if recorded.
+    pub seed: Option<u64>,
+    /// The environment the suite ran in, such as the versions of its shells, if recorded.
+    pub environment: crate::run::environment::Environment,
+    /// Whether the suite passed, or `None` if the output ends before the suite finished.
+    pub passed: Option<bool>,
+    /// Every finished test, in the order they finished.
+    pub tests: Vec<TestReport>,
+}
+
+/// A finished test.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestReport {
+    /// The path of the test, relative to the suite.
+    pub path: PathBuf,
+    /// The name the test was reported under, if it was recorded.
+    pub name: Option<String>,
+    /// The directory the test is grouped under, such as `run-pass`, if it is in one.
+    pub group: Option<String>,
+    /// The shell the test was run with, when the suite has a shell matrix.
+    pub shell: Option<String>,
+    /// The result, such as `pass` or `fail`.
+    pub result: String,
+    /// A description of why the test did not pass, if it did not.
+    pub message: Option<String>,
+    /// The stable ID of the message, such as `fail.check-failed`.
+    pub message_id: Option<String>,
+    /// The signature shared by tests that failed in the same way.
+    pub failure_signature: Option<String>,
+    /// How long the test took.
+    pub duration: Duration,
+    /// The owners of the test.
+    pub owners: Vec<String>,
+    /// The directory the artifacts of the test were saved to, if any.
+    pub artifacts_directory: Option<PathBuf>,
+    /// The result of each `RUN` command.
+    pub runs: Vec<RunReport>,
+}
+
+/// A single `RUN` command of a finished test.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunReport {
+    /// The command line after variable resolution.
+    pub command_line: String,
+    /// The line of the directive responsible for the result.
+    pub line: u32,
+    /// The result, such as `pass` or `fail`.
+    pub result: String,
+    /// A description of why the run did not pass, if it did not.
+    pub message: Option<String>,
+    /// The stable ID of the message.
+    pub message_id: Option<String>,
+    /// The directory the artifacts of the run were saved to, if any.
+    pub artifacts_directory: Option<PathBuf>,
+    /// The resources used by the program, if they were reported.
+    pub resource_usage: Option<crate::model::ResourceUsage>,
+}
+
+/// Reads results written by `--message-format json-lines`.
+///
+/// Events that do not affect the results, such as `test-started`, are skipped. Fails if
+/// the results were written in a newer format than this version of lit understands.
+pub fn parse_results(path: impl AsRef<Path>) -> Result<Results, String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read results '{}': {}", path.display(), e))?;
+
+    self::results(&text).map_err(|e| format!("invalid results '{}': {}", path.display(), e))
+}
+
+fn results(text: &str) -> Result<Results, String> {
+    let mut results: Option<Results> = None;
+
+    for (line_idx, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let event = json::Value::parse(line).map_err(|e| format!("line {}: {}", line_idx + 1, e))?;
+        self::apply_event(&event, &mut results).map_err(|e| format!("line {}: {}", line_idx + 1, e))?;
+    }
+
+    results.ok_or_else(|| "no 'suite-started' event found".to_owned())
+}
+
+fn apply_event(event: &json::Value, results: &mut Option<Results>) -> Result<(), String> {
+    match string(event, "type")?.as_str() {
+        "suite-started" => {
+            let format_version = number(event, "format_version")? as u64;
+            if format_version > FORMAT_VERSION {
+                return Err(format!("format version {} is newer than the supported version {}", format_version, FORMAT_VERSION));
+            }
+
+            *results = Some(Results {
+                format_version,
+                test_file_count: number(event, "test_file_count")? as usize,
+                artifacts_directory: optional_string(event, "artifacts_directory")?.map(PathBuf::from),
+                seed: optional_string(event, "seed")?.map(|seed| seed.parse().map_err(|_| format!("invalid seed '{}'", seed))).transpose()?,
+                environment: match event.get("environment") {
+                    Some(json::Value::Object(fields)) => fields.iter()
+                        .map(|(key, value)| value.as_str().map(|value| (key.clone(), value.to_owned())).ok_or_else(|| format!("expected environment value '{}' to be a string", key)))
+                        .collect::<Result<_, _>>()?,
+                    Some(..) => return Err("expected 'environment' to be an object".to_owned()),
+                    None => Default::default(),
+                },
+                passed: None,
+                tests: Vec::new(),
+            });
+        },
+        "suite-finished" => {
+            let passed = field(event, "passed")?.as_bool().ok_or("expected 'passed' to be a boolean")?;
+            self::started(results)?.passed = Some(passed);
+        },
+        "test-finished" => {
+            let test = self::test_report(event)?;
+            self::started(results)?.tests.push(test);
+        },
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn started(results: &mut Option<Results>) -> Result<&mut Results, String> {
+    results.as_mut().ok_or_else(|| "expected a 'suite-started' event first".to_owned())
+}
+
+fn test_report(event: &json::Value) -> Result<TestReport, String> {
+    let runs = array(event, "runs")?.iter().map(|run| {
+        Ok(RunReport {
+            command_line: string(run, "command_line")?,
+            line: number(run, "line")? as u32,
+            result: string(run, "result")?,
+            message: optional_string(run, "message")?,
+            message_id: optional_string(run, "message_id")?,
+            artifacts_directory: optional_string(run, "artifacts_directory")?.map(PathBuf::from),
+            resource_usage: match (optional_number(run, "user_time_ms")?, optional_number(run, "system_time_ms")?, optional_number(run, "max_rss_bytes")?) {
+                (Some(user_time_ms), Some(system_time_ms), Some(max_rss_bytes)) => Some(crate::model::ResourceUsage {
+                    user_time: Duration::from_secs_f64(user_time_ms / 1000.0),
+                    system_time: Duration::from_secs_f64(system_time_ms / 1000.0),
+                    max_rss_bytes: max_rss_bytes as u64,
+                }),
+                _ => None,
+            },
+        })
+    }).collect::<Result<_, String>>()?;
+
+    Ok(TestReport {
+        path: PathBuf::from(string(event, "path")?),
+        name: optional_string(event, "name")?,
+        group: optional_string(event, "group")?,
+        shell: optional_string(event, "shell")?,
+        result: string(event, "result")?,
+        message: optional_string(event, "message")?,
+        message_id: optional_string(event, "message_id")?,
+        failure_signature: optional_string(event, "failure_signature")?,
+        duration: Duration::from_secs_f64(number(event, "duration_ms")? / 1000.0),
+        owners: array(event, "owners")?.iter().map(|owner| owner.as_str().map(ToOwned::to_owned).ok_or("expected owners to be strings".to_owned())).collect::<Result<_, _>>()?,
+        artifacts_directory: optional_string(event, "artifacts_directory")?.map(PathBuf::from),
+        runs,
+    })
+}
+
+fn field<'a>(object: &'a json::Value, key: &str) -> Result<&'a json::Value, String> {
+    object.get(key).ok_or_else(|| format!("missing field '{}'", key))
+}
+
+fn string(object: &json::Value, key: &str) -> Result<String, String> {
+    field(object, key)?.as_str().map(ToOwned::to_owned).ok_or_else(|| format!("expected '{}' to be a string", key))
+}
+
+/// Gets a string field that may be null, or missing from results written by older versions.
+fn optional_string(object: &json::Value, key: &str) -> Result<Option<String>, String> {
+    match object.get(key) {
+        None | Some(json::Value::Null) => Ok(None),
+        Some(value) => value.as_str().map(|s| Some(s.to_owned())).ok_or_else(|| format!("expected '{}' to be a string", key)),
+    }
+}
+
+fn number(object: &json::Value, key: &str) -> Result<f64, String> {
+    field(object, key)?.as_f64().filter(|n| *n >= 0.0).ok_or_else(|| format!("expected '{}' to be a non-negative number", key))
+}
+
+fn optional_number(object: &json::Value, key: &str) -> Result<Option<f64>, String> {
+    match object.get(key) {
+        None | Some(json::Value::Null) => Ok(None),
+        Some(..) => number(object, key).map(Some),
+    }
+}
+
+fn array<'a>(object: &'a json::Value, key: &str) -> Result<&'a [json::Value], String> {
+    field(object, key)?.as_array().ok_or_else(|| format!("expected '{}' to be an array", key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Config, model::*};
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn reads_back_what_the_json_lines_handler_writes() {
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/greeting.txt", "greeting.txt"))
+            .with_command(CommandKind::Run(Invocation::new("echo hello")))
+            .with_command(CommandKind::Check(TextPattern::literal("goodbye")));
+        let result = crate::run::execute_test_file(&test_file, &config);
+
+        let text = format!("{}\n{}\n{}\n",
+            json::Value::object().field("type", "suite-started").field("format_version", FORMAT_VERSION).field("test_file_count", 1usize)
+                .field("seed", u64::MAX.to_string()),
+            crate::event_handler::json_lines::test_finished_event(&result, &config),
+            json::Value::object().field("type", "suite-finished").field("passed", false));
+        let results = self::results(&text).unwrap();
+
+        assert_eq!(results.passed, Some(false));
+        assert_eq!(results.seed, Some(u64::MAX));
+        assert_eq!(results.tests.len(), 1);
+        assert_eq!(results.tests[0].path, PathBuf::from("greeting.txt"));
+        assert_eq!(results.tests[0].result, "fail");
+        assert_eq!(results.tests[0].message_id.as_deref(), Some("fail.check-failed"));
+        assert_eq!(results.tests[0].runs[0].command_line, "echo hello");
+    }
+
+    #[test]
+    fn rejects_newer_formats() {
+        let newer = format!("{{\"type\":\"suite-started\",\"format_version\":{},\"test_file_count\":0}}", FORMAT_VERSION + 1);
+
+        assert!(self::results(&newer).unwrap_err().contains("newer than the supported version"));
+        assert_eq!(self::results("{\"type\":\"test-started\",\"path\":\"a.txt\"}").unwrap_err(), "no 'suite-started' event found");
+        assert!(json::Value::parse(RESULTS_SCHEMA).is_ok() && json::Value::parse(OWNER_REPORT_SCHEMA).is_ok());
+    }
+}
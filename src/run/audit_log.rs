@@ -0,0 +1,126 @@
+//! The `events.log` test artifact, recording how long each phase of each test took.
+//!
+//! Every event is appended as soon as it happens, so the log of a suite that was killed
+//! still shows how far it got.
+
+use crate::{config::AuditLogFormat, json, model::*};
+use std::{fs, io::Write, path::Path, sync::Mutex, time::{Duration, Instant}};
+
+/// The file in the artifacts directory that the audit log is appended to.
+pub const AUDIT_LOG_PATH: &str = "events.log";
+
+/// An append-only log of the events of a test suite.
+pub struct AuditLog {
+    file: Option<Mutex<fs::File>>,
+    format: AuditLogFormat,
+    /// When the suite started, which event times are relative to.
+    start_time: Instant,
+}
+
+impl AuditLog {
+    /// Creates a log that writes nothing.
+    pub fn disabled() -> Self {
+        AuditLog { file: None, format: AuditLogFormat::Text, start_time: Instant::now() }
+    }
+
+    /// Opens the log in an artifacts directory, appending to any events already in it.
+    pub fn open(artifacts_dir: &Path, format: AuditLogFormat) -> Result<Self, String> {
+        let path = artifacts_dir.join(AUDIT_LOG_PATH);
+        let file = fs::create_dir_all(artifacts_dir)
+            .and_then(|()| fs::OpenOptions::new().create(true).append(true).open(&path))
+            .map_err(|e| format!("could not open audit log '{}': {}", path.display(), e))?;
+
+        Ok(AuditLog { file: Some(Mutex::new(file)), format, start_time: Instant::now() })
+    }
+
+    /// Records that a test file was parsed.
+    pub fn parsed(&self, path: &TestFilePath, duration: Duration) {
+        self.record("parse", path, vec![("duration_ms", milliseconds(duration))]);
+    }
+
+    /// Records that a test file was started, and the resources it holds while it runs.
+    pub fn scheduled(&self, path: &TestFilePath, resources: &[String], running_test_count: usize) {
+        self.record("schedule", path, vec![
+            ("resources", resources.to_vec().into()),
+            ("running_tests", running_test_count.into()),
+        ]);
+    }
+
+    /// Records how long each run of a finished test took.
+    pub fn finished(&self, result: &TestResult) {
+        for run_result in result.individual_run_results.iter() {
+            let PhaseDurations { spawn, wait, check } = run_result.phase_durations;
+
+            self.record("run", &result.path, vec![
+                ("shell", result.shell.clone().into()),
+                ("command_line", run_result.command_line.0.clone().into()),
+                ("spawn_ms", milliseconds(spawn)),
+                ("wait_ms", milliseconds(wait)),
+                ("check_ms", milliseconds(check)),
+                ("user_ms", run_result.resource_usage.map(|usage| milliseconds(usage.user_time)).into()),
+                ("system_ms", run_result.resource_usage.map(|usage| milliseconds(usage.system_time)).into()),
+                ("max_rss_bytes", run_result.resource_usage.map(|usage| usage.max_rss_bytes).into()),
+            ]);
+        }
+
+        self.record("finish", &result.path, vec![
+            ("shell", result.shell.clone().into()),
+            ("result", result.overall_result.machine_label().into()),
+            ("duration_ms", milliseconds(result.duration)),
+        ]);
+    }
+
+    fn record(&self, event: &str, path: &TestFilePath, fields: Vec<(&str, json::Value)>) {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return,
+        };
+        let elapsed = self.start_time.elapsed();
+
+        let line = match self.format {
+            AuditLogFormat::Text => {
+                let fields: Vec<_> = fields.into_iter().filter(|(_, value)| *value != json::Value::Null)
+                    .map(|(key, value)| format!("{}={}", key, value)).collect();
+                format!("[{:>10.3}s] {:<8} {} {}", elapsed.as_secs_f64(), event, path.relative.display(), fields.join(" "))
+            },
+            AuditLogFormat::JsonLines => {
+                let object = json::Value::object()
+                    .field("elapsed_ms", milliseconds(elapsed))
+                    .field("event", event)
+                    .field("path", path.relative.display().to_string());
+                fields.into_iter().fold(object, |object, (key, value)| object.field(key, value)).to_string()
+            },
+        };
+
+        // Losing a line of the log is not worth failing the suite over.
+        writeln!(file.lock().unwrap(), "{}", line).ok();
+    }
+}
+
+fn milliseconds(duration: Duration) -> json::Value {
+    json::Value::Number((duration.as_secs_f64() * 1_000_000.0).round() / 1000.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn appends_events_in_either_format() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let path = TestFilePath::new("/nonexistent/slow.txt", "slow.txt");
+
+        AuditLog::open(artifacts_dir.path(), AuditLogFormat::Text).unwrap().parsed(&path, Duration::from_micros(1500));
+        AuditLog::open(artifacts_dir.path(), AuditLogFormat::JsonLines).unwrap().scheduled(&path, &["gpu".to_owned()], 2);
+        AuditLog::disabled().parsed(&path, Duration::from_secs(1));
+
+        let log = fs::read_to_string(artifacts_dir.path().join(AUDIT_LOG_PATH)).unwrap();
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", log);
+        assert!(lines[0].ends_with("] parse    slow.txt duration_ms=1.5"), "{}", lines[0]);
+
+        let event = json::Value::parse(lines[1]).unwrap();
+        assert_eq!(event.get("event").and_then(json::Value::as_str), Some("schedule"));
+        assert_eq!(event.get("resources"), Some(&json::Value::Array(vec!["gpu".into()])));
+    }
+}
@@ -0,0 +1,72 @@
+//! Running the preparatory `Config::build_command` before any tests.
+
+use crate::{Config, messages::MessageId};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+/// Runs a build command through the configured shell, passing each line of its
+/// stdout and stderr to `on_output` as it is written.
+///
+/// Fails with a description of the problem if the command cannot be run, or if it
+/// exits unsuccessfully.
+pub fn run(command_line: &str, config: &Config, mut on_output: impl FnMut(&str)) -> Result<(), String> {
+    let mut child = Command::new(&config.shell)
+        .arg("-c").arg(command_line)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run build command '{}': {}", command_line, e))?;
+
+    let (sender, receiver) = mpsc::channel();
+    let readers: Vec<_> = vec![forward_lines(child.stdout.take(), sender.clone()), forward_lines(child.stderr.take(), sender)]
+        .into_iter().flatten().collect();
+
+    // The channel closes once both streams have been read to the end.
+    for line in receiver {
+        on_output(&line);
+    }
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    let status = child.wait().map_err(|e| format!("could not wait for build command '{}': {}", command_line, e))?;
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(config.messages.format(MessageId::BuildFailed, &[&command_line, &code])),
+        // Killed by a signal.
+        None => Err(config.messages.format(MessageId::BuildFailed, &[&command_line, &status])),
+    }
+}
+
+fn forward_lines(stream: Option<impl Read + Send + 'static>, sender: mpsc::Sender<String>) -> Option<thread::JoinHandle<()>> {
+    let stream = stream?;
+
+    Some(thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            match line {
+                Ok(line) => if sender.send(line).is_err() { break },
+                Err(..) => break,
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn forwards_output_and_reports_failures() {
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let mut lines = Vec::new();
+
+        assert_eq!(run("echo compiling; echo warning >&2", &config, |line| lines.push(line.to_owned())), Ok(()));
+        lines.sort();
+        assert_eq!(lines, vec!["compiling", "warning"]);
+
+        assert_eq!(run("exit 3", &config, |_| ()), Err("build command 'exit 3' exited with code '3'".to_owned()));
+    }
+}
@@ -0,0 +1,70 @@
+//! Bundling of the artifacts of failing tests into a single archive, as done by `--bundle-failures`,
+//! so that they can be attached to a bug report.
+//!
+//! The archive is a gzipped tarball written by the `tar` program, holding the artifacts directory
+//! of every failing test, at the same paths as inside the artifacts directory.
+
+use std::{fs, path::{Path, PathBuf}, process};
+
+/// Packs the artifacts directories of failing tests into an archive.
+///
+/// Nothing is written if there are no failing tests with artifacts.
+pub fn failures(archive_path: &Path, artifacts_directory: &Path, failed_test_directories: &[PathBuf]) -> Result<(), String> {
+    let mut relative_directories: Vec<_> = failed_test_directories.iter()
+        .filter(|directory| directory.is_dir())
+        .filter_map(|directory| directory.strip_prefix(artifacts_directory).ok())
+        .collect();
+    relative_directories.sort();
+    relative_directories.dedup();
+
+    if relative_directories.is_empty() {
+        return Ok(());
+    }
+
+    let fail = |message: String| format!("could not bundle failures into '{}': {}", archive_path.display(), message);
+
+    // The archive is written from inside the artifacts directory.
+    let archive_path = std::env::current_dir().map(|cwd| cwd.join(archive_path)).map_err(|e| fail(e.to_string()))?;
+    if let Some(parent) = archive_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| fail(e.to_string()))?;
+    }
+
+    let output = process::Command::new("tar")
+        .arg("-czf").arg(&archive_path).arg("--")
+        .args(relative_directories)
+        .current_dir(artifacts_directory)
+        .output()
+        .map_err(|e| fail(format!("could not run tar: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(fail(String::from_utf8_lossy(&output.stderr).trim().to_owned()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_the_artifacts_of_failing_tests_are_bundled() {
+        let artifacts_directory = tempfile::tempdir().unwrap();
+        for test in ["pass.txt", "fail.txt", "nested/fail.txt"] {
+            fs::create_dir_all(artifacts_directory.path().join(test)).unwrap();
+            fs::write(artifacts_directory.path().join(test).join("stdout.txt"), "").unwrap();
+        }
+        let archive_path = artifacts_directory.path().join("bundles/failures.tar.gz");
+
+        failures(&archive_path, artifacts_directory.path(), &[]).unwrap();
+        assert!(!archive_path.exists());
+
+        let failed_test_directories = vec![artifacts_directory.path().join("nested/fail.txt"), artifacts_directory.path().join("fail.txt")];
+        failures(&archive_path, artifacts_directory.path(), &failed_test_directories).unwrap();
+
+        let listing = process::Command::new("tar").arg("-tzf").arg(&archive_path).output().unwrap();
+        let mut members: Vec<_> = String::from_utf8_lossy(&listing.stdout).lines().map(|line| line.trim_end_matches('/').to_owned()).collect();
+        members.sort();
+        assert_eq!(members, vec!["fail.txt", "fail.txt/stdout.txt", "nested/fail.txt", "nested/fail.txt/stdout.txt"]);
+    }
+}
@@ -0,0 +1,41 @@
+//! Stopping a running suite from another thread, such as when lit runs inside a larger
+//! service or a GUI test runner.
+
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+/// A handle that stops a suite from starting any more tests once it is cancelled.
+///
+/// The token is given to the suite through `Config::cancellation_token`, and clones of it
+/// share the same state. Tests that are already running are allowed to finish, and those that
+/// were never started are reported as `TestResultKind::NotRun`, after which `run::tests`
+/// returns `run::SuiteError::Cancelled`.
+///
+/// ```
+/// use lit::{Config, run::CancellationToken};
+///
+/// let token = CancellationToken::new();
+/// let config = Config { cancellation_token: token.clone(), ..Config::default() };
+///
+/// // Such as when the user presses a stop button.
+/// token.cancel();
+/// assert!(config.cancellation_token.is_cancelled());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a token that has not been cancelled.
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Stops the suites using the token from starting any more tests.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
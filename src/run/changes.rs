@@ -0,0 +1,131 @@
+//! Selection of the tests affected by changes in version control.
+
+use crate::{Config, model::{TestFile, TestFilePath}, parse};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Gets the absolute paths of all files changed since a git revision.
+///
+/// This includes uncommitted changes and untracked files.
+pub fn changed_files(revision: &str, working_directory: &Path) -> Result<Vec<PathBuf>, String> {
+    let repository_root = PathBuf::from(self::git(&["rev-parse", "--show-toplevel"], working_directory)?.trim());
+
+    let changed = self::git(&["diff", "--name-only", revision, "--"], working_directory)?;
+    let untracked = self::git(&["ls-files", "--others", "--exclude-standard", "--full-name"], &repository_root)?;
+
+    Ok(changed.lines().chain(untracked.lines())
+        .filter(|line| !line.is_empty())
+        .map(|relative_path| repository_root.join(relative_path))
+        .collect())
+}
+
+/// Explains why a test is affected by a set of changed files, if it is.
+pub fn reason_for_selection(test_file: &TestFile, changed_files: &[PathBuf]) -> Option<String> {
+    if changed_files.iter().any(|changed| changed == &test_file.path.absolute) {
+        return Some("the test file was modified".to_owned());
+    }
+
+    let test_directory = test_file.path.absolute.parent().unwrap_or(Path::new(""));
+    for dependency in test_file.dependencies() {
+        let dependency_path = normalize(&test_directory.join(dependency));
+
+        // A dependency on a directory covers all files beneath it.
+        if let Some(changed) = changed_files.iter().find(|changed| changed.starts_with(&dependency_path)) {
+            return Some(format!("it depends on '{}', and '{}' was modified", dependency, changed.display()));
+        }
+    }
+
+    None
+}
+
+/// Selects the tests affected by the changes since `Config::changed_since`, if set.
+pub fn select(test_paths: Vec<TestFilePath>, config: &Config) -> Result<Vec<TestFilePath>, String> {
+    let revision = match config.changed_since {
+        Some(ref revision) => revision,
+        None => return Ok(test_paths),
+    };
+
+    let mut selected_paths = Vec::new();
+    for (test_path, reason) in self::reasons_for_selection(test_paths, revision, config)? {
+        let selection_log = match reason? {
+            Some(reason) => {
+                let selection_log = format!("selected '{}' because {}", test_path.relative.display(), reason);
+                selected_paths.push(test_path);
+                selection_log
+            },
+            None => format!("skipped '{}' because it is unaffected by changes since '{}'", test_path.relative.display(), revision),
+        };
+
+        debug!("{}", selection_log);
+        if config.dump_test_selection {
+            eprintln!("[info] {}", selection_log);
+        }
+    }
+
+    Ok(selected_paths)
+}
+
+/// Why a test is affected by changes, `None` if it is not, or the error that prevented the test
+/// from being read or parsed.
+pub type Reason = Result<Option<String>, String>;
+
+/// Explains why each test is affected by the changes since a revision, in the order given.
+pub fn reasons_for_selection(test_paths: Vec<TestFilePath>, revision: &str, config: &Config) -> Result<Vec<(TestFilePath, Reason)>, String> {
+    let working_directory = std::env::current_dir().map_err(|e| e.to_string())?;
+    let changed_files = self::changed_files(revision, &working_directory)?;
+
+    Ok(test_paths.into_iter().map(|test_path| {
+        let reason = config.read_test(&test_path)
+            .and_then(|text| parse::test_file_for_config(test_path.clone(), &text, config))
+            .map(|test_file| self::reason_for_selection(&test_file, &changed_files));
+        (test_path, reason)
+    }).collect())
+}
+
+fn git(arguments: &[&str], working_directory: &Path) -> Result<String, String> {
+    let output = process::Command::new("git").args(arguments).current_dir(working_directory).output()
+        .map_err(|e| format!("could not run git: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("'git {}' failed: {}", arguments.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Removes `.` and `..` components from a path, without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => (),
+            std::path::Component::ParentDir => { normalized.pop(); },
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::CommandKind;
+
+    #[test]
+    fn selects_modified_tests_and_tests_with_modified_dependencies() {
+        let changed_files = vec![PathBuf::from("/repo/tests/modified.txt"), PathBuf::from("/repo/src/lexer/mod.rs")];
+        let test_file = |name: &str| TestFile::new(TestFilePath::new(format!("/repo/tests/{}", name), name));
+
+        assert_eq!(reason_for_selection(&test_file("modified.txt"), &changed_files), Some("the test file was modified".to_owned()));
+        assert_eq!(reason_for_selection(&test_file("untouched.txt"), &changed_files), None);
+
+        let dependent = test_file("lexer.txt").with_command(CommandKind::Depends("../src/lexer".to_owned()));
+        assert_eq!(reason_for_selection(&dependent, &changed_files),
+                   Some("it depends on '../src/lexer', and '/repo/src/lexer/mod.rs' was modified".to_owned()));
+
+        let independent = test_file("parser.txt").with_command(CommandKind::Depends("../src/parser".to_owned()));
+        assert_eq!(reason_for_selection(&independent, &changed_files), None);
+    }
+}
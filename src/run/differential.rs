@@ -0,0 +1,38 @@
+//! Differential testing of a test file under two configs.
+
+use super::{fixtures::Fixtures, test_evaluator};
+use crate::{Config, model::*};
+
+/// Runs a test under the comparison config, returning the difference in
+/// behaviour compared to the primary result, if any.
+pub fn compare(
+    test_file: &TestFile,
+    primary_result: &TestResult,
+    config: &Config,
+    comparison_config: &Config,
+    fixtures: &Fixtures,
+) -> Option<ConfigurationDifference> {
+    let comparison_results = test_evaluator::execute_tests(test_file, comparison_config, fixtures);
+    let comparison_result = super::overall_result(comparison_results.iter().map(|r| &r.kind));
+
+    let primary_output = normalized_stdout(primary_result.individual_run_results.iter().map(|r| &r.output), test_file, config);
+    let comparison_output = normalized_stdout(comparison_results.iter().map(|r| &r.output), test_file, config);
+
+    let output_differs = primary_output != comparison_output;
+    let status_differs = primary_result.overall_result.is_erroneous() != comparison_result.is_erroneous();
+
+    if output_differs || status_differs {
+        Some(ConfigurationDifference {
+            path: test_file.path.clone(),
+            primary_result: primary_result.overall_result.clone(),
+            comparison_result,
+            output_differs,
+        })
+    } else {
+        None
+    }
+}
+
+fn normalized_stdout<'a>(outputs: impl Iterator<Item=&'a ProgramOutput>, test_file: &TestFile, config: &Config) -> Vec<String> {
+    outputs.map(|output| config.normalize_test_output(test_file, &output.stdout)).collect()
+}
@@ -0,0 +1,129 @@
+//! Stamping of the environment a suite runs in, such as the versions of its shells
+//! and the binaries of its tools, so that differences between machines are caught
+//! by lit rather than showing up as confusing test failures.
+//!
+//! Environments are saved in the format of configuration files, so that the
+//! `environment.txt` artifact of one run can be required by later runs.
+//!
+//! ``` toml
+//! lit = "0.3.0"
+//! os = "linux"
+//! arch = "x86_64"
+//!
+//! [shells]
+//! bash = "GNU bash, version 5.2.15(1)-release (x86_64-pc-linux-gnu)"
+//!
+//! [tools]
+//! python3 = "fnv1a:5d2e1c0f3a9b7e64"
+//! ```
+
+use crate::Config;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// The values recorded for an environment, keyed by `<table>.<key>` for values inside tables.
+pub type Environment = BTreeMap<String, String>;
+
+/// Describes the environment that tests run in with a config.
+pub fn capture(config: &Config) -> Environment {
+    let mut environment = Environment::new();
+    environment.insert("lit".to_owned(), env!("CARGO_PKG_VERSION").to_owned());
+    environment.insert("os".to_owned(), std::env::consts::OS.to_owned());
+    environment.insert("arch".to_owned(), std::env::consts::ARCH.to_owned());
+
+    // Shells without a `--version` flag, such as dash, are identified by their binaries.
+    for shell in std::iter::once(&config.shell).chain(config.shell_matrix.iter()) {
+        let description = crate::doctor::find_executable(shell, config)
+            .map(|path| crate::doctor::version(&path).or_else(|| self::hash_file(&path)).unwrap_or_else(|| "unreadable".to_owned()))
+            .unwrap_or_else(|| "missing".to_owned());
+        environment.insert(format!("shells.{}", shell), description);
+    }
+
+    for tool in config.executor.required_tools().iter().chain(config.required_tools.iter()) {
+        let description = crate::doctor::find_executable(tool, config)
+            .map(|path| self::hash_file(&path).unwrap_or_else(|| "unreadable".to_owned()))
+            .unwrap_or_else(|| "missing".to_owned());
+        environment.insert(format!("tools.{}", tool), description);
+    }
+
+    environment
+}
+
+/// Loads an environment saved by `render`.
+pub fn load(path: &Path) -> Result<Environment, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read environment '{}': {}", path.display(), e))?;
+
+    crate::config::file::parse(&text).and_then(|entries| entries.into_iter().map(|entry| {
+        let value = entry.value.as_str().map_err(|e| format!("line {}: {}", entry.line_number, e))?.to_owned();
+        let key = if entry.table.is_empty() { entry.key } else { format!("{}.{}", entry.table, entry.key) };
+        Ok((key, value))
+    }).collect()).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Renders an environment in the format of configuration files.
+pub fn render(environment: &Environment) -> String {
+    let quote = crate::config::file::quote;
+    let mut text = String::new();
+    let mut current_table = "";
+
+    // Top-level values sort before those inside tables, as they cannot follow a table header.
+    let mut entries: Vec<_> = environment.iter().map(|(key, value)| match key.find('.') {
+        Some(idx) => (&key[..idx], &key[idx + 1..], value),
+        None => ("", &key[..], value),
+    }).collect();
+    entries.sort_by_key(|&(table, key, _)| (table, key));
+
+    for (table, key, value) in entries {
+        if table != current_table {
+            text.push_str(&format!("\n[{}]\n", table));
+            current_table = table;
+        }
+        text.push_str(&format!("{} = {}\n", quote(key), quote(value)));
+    }
+    text
+}
+
+/// Finds the values of a required environment that differ from an actual environment.
+///
+/// Values missing from the required environment are not checked, so that it can list only
+/// the values that matter. Returns the keys along with the required and actual values.
+pub fn mismatches<'a>(required: &'a Environment, actual: &'a Environment) -> Vec<(&'a str, &'a str, &'a str)> {
+    required.iter()
+        .map(|(key, required_value)| (&key[..], &required_value[..], actual.get(key).map(|v| &v[..]).unwrap_or("missing")))
+        .filter(|(_, required_value, actual_value)| required_value != actual_value)
+        .collect()
+}
+
+/// Identifies the content of a file by its FNV-1a hash, which is stable across platforms and
+/// versions of lit, unlike the hashers of the standard library.
+fn hash_file(path: &Path) -> Option<String> {
+    let content = fs::read(path).ok()?;
+    let hash = content.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+
+    Some(format!("fnv1a:{:016x}", hash))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rendered_environments_load_back_and_are_compared_by_required_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = dir.path().join("tool");
+        fs::write(&tool, "#!/bin/sh\n").unwrap();
+        let config = Config { shell: "sh".to_owned(), required_tools: vec![tool.display().to_string(), "lit-nonexistent-tool".to_owned()], ..Config::default() };
+
+        let environment = capture(&config);
+        assert_eq!(environment[&format!("tools.{}", tool.display())], "fnv1a:2b26cf13f555773b");
+        assert_eq!(environment["tools.lit-nonexistent-tool"], "missing");
+
+        let path = dir.path().join("environment.txt");
+        fs::write(&path, render(&environment)).unwrap();
+        assert_eq!(load(&path).unwrap(), environment);
+
+        let mut required = Environment::new();
+        required.insert("os".to_owned(), std::env::consts::OS.to_owned());
+        required.insert("tools.lit-nonexistent-tool".to_owned(), "fnv1a:0000000000000000".to_owned());
+        assert_eq!(mismatches(&required, &environment), vec![("tools.lit-nonexistent-tool", "fnv1a:0000000000000000", "missing")]);
+    }
+}
@@ -4,10 +4,20 @@ use crate::{Config, model::TestFilePath};
 
 use std;
 use std::path::Path;
-use walkdir::WalkDir;
 
-/// Recursively finds tests for the given paths.
+/// Finds the tests from all test sources - on disk, and any registered via `Config::add_test_source`.
 pub fn with_config(config: &Config) -> Result<Vec<TestFilePath>, String> {
+    let mut test_paths = self::on_disk(config)?;
+
+    for test_source in config.test_sources.iter() {
+        test_paths.extend(test_source.test_paths(config)?);
+    }
+
+    Ok(test_paths)
+}
+
+/// Recursively finds tests on disk for the given paths.
+pub fn on_disk(config: &Config) -> Result<Vec<TestFilePath>, String> {
     let mut absolute_paths = Vec::new();
 
     for path in config.test_paths.iter() {
@@ -17,14 +27,30 @@ pub fn with_config(config: &Config) -> Result<Vec<TestFilePath>, String> {
         absolute_paths.extend(test_paths.into_iter().map(|p| Path::new(&p).to_owned()));
     }
 
-    let test_paths = absolute_paths.into_iter().map(|absolute_path| {
+    Ok(self::test_file_paths(absolute_paths, config))
+}
+
+/// Recursively finds the files on disk underneath the test directories that are not tests,
+/// as their extensions are not supported.
+pub fn unsupported_on_disk(config: &Config) -> Result<Vec<TestFilePath>, String> {
+    let mut absolute_paths = Vec::new();
+
+    for path in config.test_paths.iter().filter(|path| path.is_dir()) {
+        let mut files = parallel_walk::files_matching(path, |path| !is_test_file(path, config))?;
+        files.sort();
+        absolute_paths.extend(files);
+    }
+
+    Ok(self::test_file_paths(absolute_paths, config))
+}
+
+fn test_file_paths(absolute_paths: Vec<std::path::PathBuf>, config: &Config) -> Vec<TestFilePath> {
+    absolute_paths.into_iter().map(|absolute_path| {
         let absolute_path = std::fs::canonicalize(absolute_path).unwrap();
         let relative_path =  relative_path::compute(&absolute_path, config).expect("could not compute relative path");
 
         TestFilePath { absolute: absolute_path, relative: relative_path }
-    }).collect();
-
-    Ok(test_paths)
+    }).collect()
 }
 
 pub fn in_path(path: &str,
@@ -215,31 +241,168 @@ mod relative_path {
 
 fn tests_in_dir(path: &str,
                 config: &Config) -> Result<Vec<String>,String> {
-    let tests = files_in_dir(path)?.into_iter()
-                     .filter(|f| {
-                         let path = std::path::Path::new(f);
-                         path.extension().map(|ext| config.is_extension_supported(ext.to_str().unwrap())).unwrap_or(false)
-                     })
-                     .collect();
-    Ok(tests)
+    let mut tests = parallel_walk::files_matching(Path::new(path), |path| is_test_file(path, config))?;
+
+    // Directories are walked in parallel, sort the paths so the test order is deterministic.
+    tests.sort();
+
+    Ok(tests.into_iter().map(|p| p.to_str().unwrap().to_owned()).collect())
+}
+
+fn is_test_file(path: &Path, config: &Config) -> bool {
+    path.file_name().and_then(|name| name.to_str())
+        .map(|name| config.is_test_file_name(name) && !super::fixtures::is_fixture(name, config))
+        .unwrap_or(false)
 }
 
-fn files_in_dir(path: &str) -> Result<Vec<String>,String> {
-    let mut dir_tests = Vec::new();
+/// A directory walker that visits directories concurrently on all available cores.
+mod parallel_walk {
+    use std::path::{Path, PathBuf};
+    use std::sync::{Condvar, Mutex};
+    use std::{fs, thread};
+
+    struct WorkQueue {
+        /// Directories that still need to be visited.
+        pending_directories: Vec<PathBuf>,
+        /// The number of directories currently being visited by a worker.
+        directories_in_progress: usize,
+    }
+
+    struct Walk<'a> {
+        queue: Mutex<WorkQueue>,
+        queue_changed: Condvar,
+        matching_files: Mutex<Vec<PathBuf>>,
+        first_error: Mutex<Option<String>>,
+        filter: &'a (dyn Fn(&Path) -> bool + Sync),
+    }
 
-    for entry in WalkDir::new(path) {
-        let entry = entry.unwrap();
+    /// Recursively finds all files underneath a directory that match a filter.
+    ///
+    /// The filter is applied during traversal, so non-matching files are never collected.
+    pub fn files_matching(root: &Path, filter: impl Fn(&Path) -> bool + Sync) -> Result<Vec<PathBuf>, String> {
+        let walk = Walk {
+            queue: Mutex::new(WorkQueue { pending_directories: vec![root.to_owned()], directories_in_progress: 0 }),
+            queue_changed: Condvar::new(),
+            matching_files: Mutex::new(Vec::new()),
+            first_error: Mutex::new(None),
+            filter: &filter,
+        };
+
+        let thread_count = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        thread::scope(|scope| {
+            for _ in 0..thread_count {
+                scope.spawn(|| worker(&walk));
+            }
+        });
 
-        // don't go into an infinite loop
-        if entry.path().to_str().unwrap() == path {
-            continue;
+        match walk.first_error.into_inner().unwrap() {
+            Some(error) => Err(error),
+            None => Ok(walk.matching_files.into_inner().unwrap()),
         }
+    }
+
+    /// A directory being visited by a worker.
+    ///
+    /// Its subdirectories are queued once it is dropped, which also happens if the filter
+    /// panics, so that the other workers never wait for it forever.
+    struct DirectoryInProgress<'a, 'f> {
+        walk: &'a Walk<'f>,
+        subdirectories: Vec<PathBuf>,
+    }
+
+    fn worker(walk: &Walk) {
+        while let Some(directory) = next_directory(walk) {
+            let mut in_progress = DirectoryInProgress { walk, subdirectories: Vec::new() };
 
-        if entry.metadata().unwrap().is_file() {
-            dir_tests.push(entry.path().to_str().unwrap().to_owned());
+            match visit(&directory, walk, &mut in_progress.subdirectories) {
+                Ok(matching_files) => walk.matching_files.lock().unwrap().extend(matching_files),
+                Err(e) => {
+                    walk.first_error.lock().unwrap().get_or_insert(format!("could not read '{}': {}", directory.display(), e));
+                },
+            }
         }
     }
 
-    Ok(dir_tests)
-}
+    impl<'a, 'f> Drop for DirectoryInProgress<'a, 'f> {
+        fn drop(&mut self) {
+            let mut queue = self.walk.queue.lock().unwrap_or_else(|e| e.into_inner());
+            queue.pending_directories.append(&mut self.subdirectories);
+            queue.directories_in_progress -= 1;
+            self.walk.queue_changed.notify_all();
+        }
+    }
+
+    /// Waits for the next directory to visit, returning `None` once the walk is complete.
+    fn next_directory(walk: &Walk) -> Option<PathBuf> {
+        let mut queue = walk.queue.lock().unwrap_or_else(|e| e.into_inner());
+
+        loop {
+            if let Some(directory) = queue.pending_directories.pop() {
+                queue.directories_in_progress += 1;
+                return Some(directory);
+            }
+
+            if queue.directories_in_progress == 0 {
+                return None;
+            }
+
+            queue = walk.queue_changed.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn visit(directory: &Path, walk: &Walk, subdirectories: &mut Vec<PathBuf>) -> std::io::Result<Vec<PathBuf>> {
+        let mut matching_files = Vec::new();
+
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            // N.B. symbolic links are not followed.
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                subdirectories.push(entry.path());
+            } else if file_type.is_file() && (walk.filter)(&entry.path()) {
+                matching_files.push(entry.path());
+            }
+        }
+
+        Ok(matching_files)
+    }
+
+    #[cfg(test)]
+    mod test {
+        use std::fs;
+
+        #[test]
+        fn finds_matching_files_in_nested_directories() {
+            let root = tempfile::tempdir().unwrap();
+            fs::create_dir_all(root.path().join("a/b/c")).unwrap();
+            for path in &["top.txt", "a/one.txt", "a/b/skip.rs", "a/b/c/two.txt"] {
+                fs::write(root.path().join(path), "").unwrap();
+            }
+
+            let mut files = super::files_matching(root.path(), |p| p.extension().map(|e| e == "txt").unwrap_or(false)).unwrap();
+            files.sort();
 
+            let relative: Vec<_> = files.iter().map(|f| f.strip_prefix(root.path()).unwrap().to_owned()).collect();
+            assert_eq!(relative, vec![
+                std::path::PathBuf::from("a/b/c/two.txt"),
+                std::path::PathBuf::from("a/one.txt"),
+                std::path::PathBuf::from("top.txt"),
+            ]);
+        }
+
+        #[test]
+        fn panicking_filters_do_not_hang_the_other_workers() {
+            let root = tempfile::tempdir().unwrap();
+            for directory in 0..8 {
+                fs::create_dir_all(root.path().join(directory.to_string())).unwrap();
+                fs::write(root.path().join(format!("{}/test.txt", directory)), "").unwrap();
+            }
+
+            let walk = std::panic::catch_unwind(|| {
+                super::files_matching(root.path(), |p| if p.ends_with("3/test.txt") { panic!("bad filter") } else { true })
+            });
+            assert!(walk.is_err());
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Fixtures, which prepare outputs shared by all tests in a directory.
+//!
+//! A fixture is a file named `Config::fixture_file_name`, such as `fixture.txt`. Its `RUN`
+//! commands run once, before the first test in its directory or beneath it that is run,
+//! and write to a directory given to them, and to those tests, as `@fixture_out`. Tests
+//! use the fixture closest to them. Tests that start while the fixture is still running
+//! wait for it to finish, and if it fails, they fail without running. Fixtures are run
+//! again by every run of a suite.
+//!
+//! ``` text
+//! # corpus/fixture.txt
+//! RUN: generate-corpus --size 10000 --out @fixture_out
+//!
+//! # corpus/parse.txt
+//! RUN: parse @fixture_out/corpus.bin
+//! ```
+
+use super::test_evaluator;
+use crate::{Config, model::TestFilePath, parse};
+use std::{collections::HashMap, fs, path::PathBuf, sync::{Arc, Mutex, OnceLock}};
+
+/// The variable holding the directory that the fixture of a test writes its outputs to.
+pub const OUTPUT_DIRECTORY_VARIABLE: &str = "fixture_out";
+
+/// The outcome of a fixture, which is either the directory of its outputs, or why it failed.
+type Outcome = Arc<OnceLock<Result<PathBuf, String>>>;
+
+/// The fixtures run by a single run of a suite.
+#[derive(Default)]
+pub struct Fixtures {
+    /// The fixtures run so far, by their path and the shell they were run with.
+    outcomes: Mutex<HashMap<(PathBuf, String), Outcome>>,
+}
+
+impl Fixtures {
+    /// Runs the fixture of a test unless it was already run, waiting for it to finish if it is
+    /// running on another thread.
+    ///
+    /// Returns the directory of the outputs of the fixture, or `None` if the test has no fixture.
+    pub fn prepare(&self, test_path: &TestFilePath, config: &Config) -> Result<Option<PathBuf>, String> {
+        let fixture_path = match self::find(test_path, config) {
+            Some(fixture_path) => fixture_path,
+            None => return Ok(None),
+        };
+
+        let outcome = self.outcomes.lock().unwrap()
+            .entry((fixture_path.absolute.clone(), config.shell.clone()))
+            .or_default()
+            .clone();
+
+        outcome.get_or_init(|| self::run(&fixture_path, self, config)).clone().map(Some)
+    }
+
+    /// Removes the outputs of all fixtures that were run, unless temporary files are kept.
+    pub fn finish(self, config: &Config) {
+        for (_, outcome) in self.outcomes.into_inner().unwrap() {
+            if let (true, Some(Ok(output_directory))) = (config.cleanup_temporary_files, outcome.get()) {
+                fs::remove_dir_all(output_directory).ok();
+            }
+        }
+    }
+}
+
+/// Checks if a file is a fixture, rather than a test.
+pub fn is_fixture(file_name: &str, config: &Config) -> bool {
+    config.fixture_file_name.as_ref().map(|fixture_file_name| fixture_file_name == file_name).unwrap_or(false)
+}
+
+/// Finds the fixture closest to a test, in its directory or any parent beneath the test root.
+fn find(test_path: &TestFilePath, config: &Config) -> Option<TestFilePath> {
+    let fixture_file_name = config.fixture_file_name.as_ref()?;
+    if test_path.absolute.file_name().map(|name| name == &fixture_file_name[..]).unwrap_or(false) {
+        return None; // fixtures have no fixtures of their own.
+    }
+
+    let relative_directories = test_path.relative.ancestors().skip(1);
+    let absolute_directories = test_path.absolute.ancestors().skip(1);
+
+    relative_directories.zip(absolute_directories)
+        .map(|(relative, absolute)| TestFilePath { absolute: absolute.join(fixture_file_name), relative: relative.join(fixture_file_name) })
+        .find(|fixture_path| fixture_path.absolute.is_file())
+}
+
+fn run(fixture_path: &TestFilePath, fixtures: &Fixtures, config: &Config) -> Result<PathBuf, String> {
+    let fail = |message: String| format!("fixture '{}' failed: {}", fixture_path.relative.display(), message);
+
+    let text = fs::read_to_string(&fixture_path.absolute).map_err(|e| fail(e.to_string()))?;
+    let fixture = parse::test_file_for_config(fixture_path.clone(), &text, config).map_err(fail)?;
+
+    let output_directory = match config.temp_root {
+        Some(ref temp_root) => tempfile::Builder::new().prefix("lit-fixture-").tempdir_in(temp_root),
+        None => tempfile::Builder::new().prefix("lit-fixture-").tempdir(),
+    };
+    let output_directory = output_directory.map_err(|e| fail(format!("could not create its output directory: {}", e)))?.keep();
+
+    let mut fixture_config = config.clone();
+    fixture_config.constants.insert(OUTPUT_DIRECTORY_VARIABLE.to_owned(), output_directory.display().to_string());
+
+    let run_results = test_evaluator::execute_tests(&fixture, &fixture_config, fixtures);
+    match run_results.iter().find(|run_result| run_result.kind.is_erroneous()) {
+        Some(run_result) => {
+            fs::remove_dir_all(&output_directory).ok();
+            Err(fail(run_result.kind.short_failure_message_in(&config.messages).unwrap_or_default()))
+        },
+        None => Ok(output_directory),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fixtures_run_once_for_the_tests_beneath_them() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("nested")).unwrap();
+        fs::write(root.path().join("fixture.txt"), "RUN: echo run >> @fixture_out/log\n").unwrap();
+
+        let config = Config { shell: "sh".to_owned(), fixture_file_name: Some("fixture.txt".to_owned()), ..Config::default() };
+        let test_path = |relative: &str| TestFilePath::new(root.path().join(relative), relative);
+
+        let fixtures = Fixtures::default();
+        let output_directory = fixtures.prepare(&test_path("a.txt"), &config).unwrap().unwrap();
+        assert_eq!(fixtures.prepare(&test_path("nested/b.txt"), &config), Ok(Some(output_directory.clone())));
+        assert_eq!(fs::read_to_string(output_directory.join("log")).unwrap(), "run\n");
+        assert_eq!(fixtures.prepare(&test_path("fixture.txt"), &config), Ok(None));
+
+        fs::write(root.path().join("nested/fixture.txt"), "RUN: false\n").unwrap();
+        let error = fixtures.prepare(&test_path("nested/c.txt"), &config).unwrap_err();
+        assert!(error.starts_with("fixture 'nested/fixture.txt' failed: command 'false' exited with code '1'"), "{}", error);
+
+        fixtures.finish(&config);
+        assert!(!output_directory.exists());
+    }
+
+    #[test]
+    fn executing_a_single_test_removes_the_outputs_of_its_fixture() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("fixture.txt"), "RUN: echo run >> @fixture_out/log\n").unwrap();
+
+        let config = Config { shell: "sh".to_owned(), fixture_file_name: Some("fixture.txt".to_owned()), ..Config::default() };
+        let test_file = parse::test_file(TestFilePath::new(root.path().join("a.txt"), "a.txt"), "RUN: echo @fixture_out; cat @fixture_out/log\n").unwrap();
+
+        let output_directories: Vec<_> = (0..2).map(|_| {
+            let result = crate::run::execute_test_file(&test_file, &config);
+            let stdout = result.individual_run_results[0].output.stdout.to_string();
+            assert_eq!(stdout.lines().nth(1), Some("run"));
+            PathBuf::from(stdout.lines().next().unwrap())
+        }).collect();
+
+        // Each execution runs the fixture afresh, and cleans up after it.
+        assert_ne!(output_directories[0], output_directories[1]);
+        assert!(output_directories.iter().all(|directory| !directory.exists()));
+    }
+}
@@ -0,0 +1,260 @@
+//! The history of tests from previous runs.
+
+use crate::model::{TestFile, TestResultKind};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How each test behaved the last times it was executed.
+///
+/// Stored on disk as one `<microseconds>\t<streak>\t<last failed>\t<failing since>\t<relative test path>`
+/// line per test, where the timestamps are in seconds since the Unix epoch, or `-` if unknown.
+/// Files that only hold `<microseconds>\t<relative test path>` lines are also accepted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TimingHistory {
+    tests: HashMap<PathBuf, TestHistory>,
+}
+
+/// The history of a single test.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TestHistory {
+    /// How long the test took the last time it ran.
+    pub duration: Duration,
+    /// The number of consecutive runs with the same outcome, positive for passes and
+    /// negative for failures.
+    pub streak: i64,
+    /// When the test last failed, in seconds since the Unix epoch.
+    pub last_failed: Option<u64>,
+    /// When the current streak of failures started, in seconds since the Unix epoch.
+    pub failing_since: Option<u64>,
+}
+
+impl TimingHistory {
+    /// Loads a timing history file, treating a missing file as an empty history.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(TimingHistory::default()),
+            Err(e) => Err(format!("could not read timing history '{}': {}", path.display(), e)),
+        }
+    }
+
+    /// Parses the text of a timing history file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut tests = HashMap::new();
+
+        for (line_idx, line) in text.lines().enumerate() {
+            if line.trim().is_empty() { continue; }
+
+            let line_number = line_idx + 1;
+            let parts: Vec<_> = line.splitn(5, '\t').collect();
+            let (micros, relative_path) = match parts.len() {
+                2 | 5 => (parts[0], parts[parts.len() - 1]),
+                _ => return Err(format!("line {}: expected '<microseconds>\\t<streak>\\t<last failed>\\t<failing since>\\t<path>'", line_number)),
+            };
+            let micros: u64 = micros.parse().map_err(|_| format!("line {}: invalid duration '{}'", line_number, micros))?;
+
+            let mut test_history = TestHistory { duration: Duration::from_micros(micros), ..TestHistory::default() };
+            if parts.len() == 5 {
+                test_history.streak = parts[1].parse().map_err(|_| format!("line {}: invalid streak '{}'", line_number, parts[1]))?;
+                test_history.last_failed = parse_timestamp(parts[2]).map_err(|e| format!("line {}: {}", line_number, e))?;
+                test_history.failing_since = parse_timestamp(parts[3]).map_err(|e| format!("line {}: {}", line_number, e))?;
+            }
+
+            tests.insert(PathBuf::from(relative_path), test_history);
+        }
+
+        Ok(TimingHistory { tests })
+    }
+
+    /// Writes the history to disk.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_string())
+            .map_err(|e| format!("could not write timing history '{}': {}", path.display(), e))
+    }
+
+    /// Gets how long a test took the last time it ran, if it has run before.
+    pub fn expected_duration(&self, relative_path: &Path) -> Option<Duration> {
+        self.tests.get(relative_path).map(|t| t.duration)
+    }
+
+    /// Whether a test failed the last time it ran.
+    pub fn failed_last_time(&self, relative_path: &Path) -> bool {
+        self.tests.get(relative_path).map(|t| t.streak < 0).unwrap_or(false)
+    }
+
+    /// Moves the tests that failed the last time they ran before all others, as done by
+    /// `--fail-first`, keeping the order of the tests otherwise.
+    ///
+    /// Tests run under a matrix of shells are recorded once per shell, and are moved if they
+    /// failed under any of them.
+    pub fn order_failures_first(&self, test_files: &mut [TestFile], shells: &[String]) {
+        test_files.sort_by_key(|test_file| {
+            let relative_path = &test_file.path.relative;
+            let failed = match shells {
+                [] => self.failed_last_time(relative_path),
+                shells => shells.iter().any(|shell| self.failed_last_time(&Path::new(&super::shell_directory_name(shell)).join(relative_path))),
+            };
+            !failed
+        });
+    }
+
+    /// Records the outcome of a test, returning its updated history.
+    ///
+    /// Skipped tests, tests that were not run and expected failures say nothing about
+    /// whether a test passes, so they leave its streak unchanged.
+    pub fn record(&mut self, relative_path: &Path, duration: Duration, result: &TestResultKind) -> TestHistory {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let passed = match *result {
+            TestResultKind::Skip | TestResultKind::NotRun | TestResultKind::ExpectedFailure { .. } => None,
+            ref result => Some(!result.is_erroneous()),
+        };
+
+        self.record_at(relative_path, duration, passed, now)
+    }
+
+    fn record_at(&mut self, relative_path: &Path, duration: Duration, passed: Option<bool>, now: u64) -> TestHistory {
+        let test_history = self.tests.entry(relative_path.to_owned()).or_default();
+        let passed = match passed {
+            Some(passed) => passed,
+            None => return test_history.clone(),
+        };
+        test_history.duration = duration;
+
+        match (passed, test_history.streak) {
+            (true, streak) if streak > 0 => test_history.streak += 1,
+            (true, _) => {
+                test_history.streak = 1;
+                test_history.failing_since = None;
+            },
+            (false, streak) if streak < 0 => test_history.streak -= 1,
+            (false, _) => {
+                test_history.streak = -1;
+                test_history.failing_since = Some(now);
+            },
+        }
+        if !passed {
+            test_history.last_failed = Some(now);
+        }
+
+        test_history.clone()
+    }
+}
+
+impl TestHistory {
+    /// Describes how long a failing test has been failing for, for triage.
+    pub fn failure_annotation(&self) -> Option<String> {
+        match self.streak {
+            -1 => Some("newly failing".to_owned()),
+            streak if streak < -1 => Some(match self.failing_since {
+                Some(since) => format!("still failing since {}, {} runs", format_date(since), -streak),
+                None => format!("still failing, {} runs", -streak),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TimingHistory {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let timestamp = |t: Option<u64>| t.map(|t| t.to_string()).unwrap_or_else(|| "-".to_owned());
+
+        let mut entries: Vec<_> = self.tests.iter().collect();
+        entries.sort_by_key(|&(relative_path, _)| relative_path);
+
+        for (relative_path, test_history) in entries {
+            writeln!(fmt, "{}\t{}\t{}\t{}\t{}", test_history.duration.as_micros(), test_history.streak,
+                     timestamp(test_history.last_failed), timestamp(test_history.failing_since), relative_path.display())?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_timestamp(s: &str) -> Result<Option<u64>, String> {
+    match s {
+        "-" => Ok(None),
+        s => s.parse().map(Some).map_err(|_| format!("invalid timestamp '{}'", s)),
+    }
+}
+
+/// Formats seconds since the Unix epoch as a `YYYY-MM-DD` date in UTC.
+fn format_date(unix_seconds: u64) -> String {
+    // Converts days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (unix_seconds / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::{TestFailReason, TestFilePath};
+
+    #[test]
+    fn round_trips_through_text() {
+        let mut history = TimingHistory::default();
+        history.record_at(Path::new("a/b.txt"), Duration::from_millis(1500), Some(true), 100);
+        history.record_at(Path::new("c.sh"), Duration::from_micros(12), Some(false), 200);
+
+        assert_eq!(history.to_string(), "1500000\t1\t-\t-\ta/b.txt\n12\t-1\t200\t200\tc.sh\n");
+        assert_eq!(TimingHistory::parse(&history.to_string()), Ok(history));
+        assert_eq!(TimingHistory::parse("12\tc.sh\n").unwrap().expected_duration(Path::new("c.sh")), Some(Duration::from_micros(12)));
+    }
+
+    #[test]
+    fn distinguishes_new_failures_from_chronic_failures() {
+        let mut history = TimingHistory::default();
+        let path = Path::new("flaky.txt");
+        let day = 86400;
+
+        assert_eq!(history.record_at(path, Duration::default(), Some(true), 0).failure_annotation(), None);
+        assert_eq!(history.record_at(path, Duration::default(), Some(false), 19_000 * day).failure_annotation(), Some("newly failing".to_owned()));
+        assert_eq!(history.record_at(path, Duration::default(), Some(false), 19_001 * day).failure_annotation(),
+                   Some("still failing since 2022-01-08, 2 runs".to_owned()));
+
+        let test_history = history.record_at(path, Duration::default(), Some(true), 19_002 * day);
+        assert_eq!((test_history.streak, test_history.last_failed, test_history.failing_since), (1, Some(19_001 * day), None));
+    }
+
+    #[test]
+    fn skipped_tests_and_expected_failures_keep_their_streak() {
+        let mut history = TimingHistory::default();
+        let path = Path::new("flaky.txt");
+
+        history.record(path, Duration::default(), &TestResultKind::Error { message: "oops".to_owned() });
+        history.record(path, Duration::default(), &TestResultKind::Skip);
+        history.record(path, Duration::default(), &TestResultKind::ExpectedFailure { actual_reason: TestFailReason::UnsuccessfulExecution { program_command_line: "false".to_owned(), exit_status: 1 } });
+        assert_eq!(history.record(path, Duration::default(), &TestResultKind::NotRun).streak, -1);
+        assert_eq!(history.record(path, Duration::default(), &TestResultKind::Error { message: "oops".to_owned() }).streak, -2);
+    }
+
+    #[test]
+    fn previously_failing_tests_are_ordered_first() {
+        let mut history = TimingHistory::default();
+        history.record_at(Path::new("b.txt"), Duration::default(), Some(false), 0);
+        history.record_at(Path::new("c.txt"), Duration::default(), Some(false), 0);
+        history.record_at(Path::new("c.txt"), Duration::default(), Some(true), 1);
+        history.record_at(Path::new("bash/d.txt"), Duration::default(), Some(false), 0);
+
+        let names = |test_files: &[TestFile]| test_files.iter().map(|t| t.path.relative.display().to_string()).collect::<Vec<_>>();
+        let test_file = |name: &str| TestFile::new(TestFilePath::new(Path::new("/tests").join(name), name));
+        let mut test_files: Vec<_> = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"].iter().map(|name| test_file(name)).collect();
+
+        history.order_failures_first(&mut test_files, &[]);
+        assert_eq!(names(&test_files), ["b.txt", "a.txt", "c.txt", "d.txt", "e.txt"]);
+
+        history.order_failures_first(&mut test_files, &["sh".to_owned(), "bash".to_owned()]);
+        assert_eq!(names(&test_files), ["d.txt", "b.txt", "a.txt", "c.txt", "e.txt"]);
+    }
+}
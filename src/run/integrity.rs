@@ -0,0 +1,75 @@
+//! Detection of test files that change while the suite is running.
+//!
+//! This is commonly caused by a tool that updates expectations running at the
+//! same time, and makes the results of the run misleading.
+
+use crate::model::{TestFile, TestFilePath};
+use std::{collections::hash_map::DefaultHasher, fs, hash::{Hash, Hasher}};
+
+/// The state of every on-disk test file at the start of a run.
+pub struct Snapshot {
+    /// The paths of the test files, along with the hashes of their content.
+    files: Vec<(TestFilePath, Option<u64>)>,
+}
+
+impl Snapshot {
+    /// Records the state of the test files that exist on disk.
+    ///
+    /// Test files synthesized at runtime, or provided by other test sources, are not tracked.
+    pub fn take(test_files: &[TestFile]) -> Self {
+        let files = test_files.iter()
+            .filter(|test_file| test_file.path.absolute.is_file())
+            .map(|test_file| (test_file.path.clone(), self::content_hash(&test_file.path)))
+            .collect();
+
+        Snapshot { files }
+    }
+
+    /// Gets the test files whose content changed, or that were removed, since the snapshot was taken.
+    ///
+    /// Files that were only touched, without their content changing, are not considered modified.
+    pub fn modified_files(&self) -> Vec<&TestFilePath> {
+        self.files.iter()
+            .filter(|(path, hash_before)| self::content_hash(path) != *hash_before)
+            .map(|(path, _)| path)
+            .collect()
+    }
+}
+
+/// Hashes the content of a file, or returns `None` if it cannot be read.
+///
+/// Modification times are not used, as they are too coarse on some file systems to
+/// notice quick successive writes.
+fn content_hash(path: &TestFilePath) -> Option<u64> {
+    let content = fs::read(&path.absolute).ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_test_files_whose_content_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_file = |name: &str| {
+            let absolute = dir.path().join(name);
+            fs::write(&absolute, "RUN: true\n").unwrap();
+            TestFile::new(TestFilePath::new(absolute, name))
+        };
+        let test_files = vec![test_file("changed.txt"), test_file("touched.txt"), test_file("removed.txt"), test_file("untouched.txt")];
+        let synthesized = TestFile::new(TestFilePath::new(dir.path().join("synthesized.txt"), "synthesized.txt"));
+
+        let snapshot = Snapshot::take(&[test_files, vec![synthesized]].concat());
+
+        fs::write(dir.path().join("changed.txt"), "RUN: false\n").unwrap();
+        fs::write(dir.path().join("touched.txt"), "RUN: true\n").unwrap();
+        fs::remove_file(dir.path().join("removed.txt")).unwrap();
+
+        let modified: Vec<_> = snapshot.modified_files().iter().map(|p| p.relative.display().to_string()).collect();
+        assert_eq!(modified, vec!["changed.txt", "removed.txt"]);
+    }
+}
@@ -1,16 +1,55 @@
 //! Routines for running tests.
 
+mod audit_log;
+mod build;
+mod bundle;
+pub mod cancellation;
+mod changes;
+mod differential;
+pub mod environment;
 pub(crate) mod find_files;
+pub mod fixtures;
+mod history;
+mod integrity;
+pub mod nesting;
+pub mod rerun;
+mod scheduler;
+pub mod selection;
+pub(crate) mod sharding;
 mod test_evaluator;
+pub mod upload;
+mod warnings;
 
+pub use self::cancellation::CancellationToken;
+pub use self::history::TestHistory;
 pub use self::test_evaluator::CommandLine;
 
-use crate::{Config, event_handler::{EventHandler, TestSuiteDetails}};
+use crate::{Config, config::{MessageFormat, ModifiedTestFiles}, event_handler::{self, EventHandler, SuiteProgress, TestSuiteDetails}, messages::MessageId};
 use crate::model::*;
+use std::{fmt, path::Path};
+
+/// The exit code of the lit binary if `Config::suite_timeout` passed before every test was
+/// started, the same as that of the `timeout` program.
+pub const SUITE_TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// The reason a suite run by `run::tests` was unsuccessful.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SuiteError {
+    /// No tests were run, such as because the build command failed or no tests were found.
+    NotStarted,
+    /// Tests failed, or the suite failed for another reason, such as warnings in strict mode.
+    Failed { failed_count: usize },
+    /// `Config::suite_timeout` passed before every test was started.
+    TimedOut { failed_count: usize, not_run_count: usize },
+    /// `Config::cancellation_token` was cancelled before every test was started.
+    Cancelled { failed_count: usize, not_run_count: usize },
+}
 
 /// Runs all tests according to a given config.
 ///
-/// Return `Ok` if all tests pass, and `Err` otherwise.
+/// Return `Ok` if all tests pass, and `Err` otherwise, with `SuiteError::TimedOut` if
+/// `Config::suite_timeout` passed before every test was started, and `SuiteError::Cancelled`
+/// if the suite was stopped by `Config::cancellation_token`.
 ///
 /// # Parameters
 ///
@@ -18,106 +57,471 @@ use crate::model::*;
 /// * `event_handler` is an object which presents the user interface to the user.
 ///
 pub fn tests<F>(
-    mut event_handler: impl EventHandler,
+    event_handler: impl EventHandler,
     config_fn: F,
-    ) -> Result<(), ()>
+    ) -> Result<(), SuiteError>
     where F: Fn(&mut Config) {
     let mut config = Config::default();
     config_fn(&mut config);
 
+    // Every test in the run sees the same seed, so that the run can be replayed with it.
+    let seed = *config.seed.get_or_insert_with(crate::vars::random_seed);
+
+    // The given event handler keeps receiving events in other formats, although the default
+    // one stays quiet in them.
+    let mut event_handler: Box<dyn EventHandler> = match config.message_format {
+        MessageFormat::Human => Box::new(event_handler),
+        #[cfg(feature = "json")]
+        MessageFormat::JsonLines => Box::new(event_handler::Broadcast::new(vec![Box::new(event_handler), Box::new(event_handler::JsonLines::new())])),
+        #[cfg(not(feature = "json"))]
+        MessageFormat::JsonLines => util::abort("JSON output requires lit to be built with the 'json' feature"),
+    };
+
+    let plugin_event_handlers: Vec<_> = config.plugins.iter().filter_map(|p| p.event_handler()).collect();
+    if !plugin_event_handlers.is_empty() {
+        let event_handlers = std::iter::once(event_handler).chain(plugin_event_handlers).collect();
+        event_handler = Box::new(event_handler::Broadcast::new(event_handlers));
+    }
+
+    // In strict mode, any warning fails the suite.
+    let mut warnings = warnings::Counter::default();
+
+    // The tests of a suite built on lit may run lit, which must not save its artifacts over ours.
+    if let Some(warning) = nesting::parent().and_then(|parent| nesting::separate_artifacts(&parent, &mut config)) {
+        warnings.note(&mut *event_handler, &warning);
+    }
+
     // Used for storing artifacts generated during testing.
-    let artifact_config = save_artifacts::Config {
-        artifacts_dir: config.save_artifacts_to_directory.clone(),
+    let artifact_config = save_artifacts::Config::new(&config);
+
+    if config.save_artifacts_to_directory.is_some() && artifact_config.artifacts_dir.is_none() {
+        warnings.note(&mut *event_handler, "not saving artifacts, as lit was built without the 'artifacts' feature");
+    }
+
+    let audit_log = match (config.audit_log_format, artifact_config.artifacts_dir.as_ref()) {
+        (Some(format), Some(artifacts_dir)) => {
+            let audit_log = audit_log::AuditLog::open(artifacts_dir, format).unwrap_or_else(|e| util::abort(e));
+            artifact_config.record_saved_file(artifacts_dir.join(audit_log::AUDIT_LOG_PATH));
+            audit_log
+        },
+        (Some(..), None) => {
+            warnings.note(&mut *event_handler, "not writing an audit log, as artifacts are not being saved");
+            audit_log::AuditLog::disabled()
+        },
+        (None, _) => audit_log::AuditLog::disabled(),
     };
 
-    if config.test_paths.is_empty() {
+    if config.bundle_failures_to.is_some() && artifact_config.artifacts_dir.is_none() {
+        warnings.note(&mut *event_handler, "not bundling failures, as artifacts are not being saved");
+    }
+
+    if config.test_paths.is_empty() && config.test_sources.is_empty() {
         util::abort("no test paths given to lit")
     }
 
+    if let Some(ref temp_root) = config.temp_root {
+        if let Err(e) = std::fs::create_dir_all(temp_root) {
+            util::abort(format!("could not create temp root '{}': {}", temp_root.display(), e));
+        }
+    }
+
+    if let Some(ref build_command) = config.build_command {
+        if let Err(e) = build::run(build_command, &config, |line| event_handler.on_build_output(line, &config)) {
+            event_handler.note_warning(&e);
+            return Err(SuiteError::NotStarted);
+        }
+    }
+
+    // Tools may be produced by the build command, so the environment is checked after it.
+    let environment = environment::capture(&config);
+    if let Some(ref required_environment) = config.required_environment {
+        let required_environment = environment::load(required_environment).unwrap_or_else(|e| util::abort(e));
+        let mismatches = environment::mismatches(&required_environment, &environment);
+
+        for &(key, required_value, actual_value) in mismatches.iter() {
+            event_handler.note_warning(&config.messages.format(MessageId::EnvironmentMismatch, &[&key, &required_value, &actual_value]));
+        }
+        if !mismatches.is_empty() {
+            return Err(SuiteError::NotStarted);
+        }
+    }
+
+    let discovery_start_time = std::time::Instant::now();
     let test_paths = match find_files::with_config(&config) {
         Ok(paths) => paths,
         Err(e) => util::abort(format!("could not find test files: {}", e)),
     };
 
+    let discovery_log = format!("discovered {} test files in {:?}", test_paths.len(), discovery_start_time.elapsed());
+    debug!("{}", discovery_log);
+    if config.dump_discovery_time {
+        eprintln!("[info] {}", discovery_log);
+    }
+
     if test_paths.is_empty() {
-        event_handler.note_warning("could not find any tests");
-        return Err(());
+        event_handler.note_warning(&config.messages.format(MessageId::NoTestsFound, &[]));
+        return Err(SuiteError::NotStarted);
+    }
+
+    let test_paths = match changes::select(test_paths, &config) {
+        Ok(paths) => paths,
+        Err(e) => util::abort(format!("could not select changed tests: {}", e)),
+    };
+
+    if let (true, Some(revision)) = (test_paths.is_empty(), config.changed_since.as_ref()) {
+        event_handler.note_warning(&config.messages.format(MessageId::NoChangedTests, &[revision]));
+        return if config.strict { Err(SuiteError::NotStarted) } else { Ok(()) };
+    }
+
+    let test_paths = match sharding::select(test_paths, &config) {
+        Ok(paths) => paths,
+        Err(e) => util::abort(format!("could not plan shards: {}", e)),
+    };
+
+    if let (true, Some(shard)) = (test_paths.is_empty(), config.shard.as_ref()) {
+        event_handler.note_warning(&config.messages.format(MessageId::EmptyShard, &[&(shard.index + 1), &shard.count]));
+        return if config.strict { Err(SuiteError::NotStarted) } else { Ok(()) };
     }
 
     let test_suite_details = TestSuiteDetails {
         number_of_test_files: test_paths.len(),
+        artifacts_directory: artifact_config.artifacts_dir.clone(),
+        seed,
+        environment,
     };
 
     event_handler.on_test_suite_started(&test_suite_details, &config);
 
+    let mut test_files: Vec<_> = test_paths.into_iter().map(|path| {
+        let parse_start_time = std::time::Instant::now();
+        let test_file = util::parse_test(path, &config);
+
+        audit_log.parsed(&test_file.path, parse_start_time.elapsed());
+        test_file
+    }).collect();
+
+    let mut timing_history = config.timing_history_file.as_ref().map(|path| {
+        history::TimingHistory::load(path).unwrap_or_else(|e| util::abort(e))
+    });
+
+    if config.fail_first {
+        match timing_history {
+            Some(ref timing_history) => timing_history.order_failures_first(&mut test_files, &config.shell_matrix),
+            None => {
+                warnings.note(&mut *event_handler, "not ordering failing tests first, as there is no timing history");
+            },
+        }
+    }
+
+    let integrity_snapshot = match config.modified_test_files {
+        ModifiedTestFiles::Ignore => None,
+        ModifiedTestFiles::Warn | ModifiedTestFiles::Fail => Some(integrity::Snapshot::take(&test_files)),
+    };
+
     let mut has_failure = false;
-    for test_file_path in test_paths {
-        let test_file = util::parse_test(test_file_path).unwrap();
-        let is_successful = self::single_file(&test_file, &mut event_handler, &config, &artifact_config);
+    let mut configuration_differences = Vec::new();
+    let mut failures_by_owner = std::collections::BTreeMap::new();
+    // The artifacts directories of failing tests, packed into an archive by `--bundle-failures`.
+    let mut failed_artifact_directories = Vec::new();
+    // Each shell of the matrix gets its own config, and its own artifacts directory.
+    let shell_configs: Vec<_> = config.shell_matrix.iter().map(|shell| {
+        let shell_config = Config { shell: shell.clone(), ..config.clone() };
+        let shell_artifact_config = save_artifacts::Config {
+            artifacts_dir: artifact_config.artifacts_dir.as_ref().map(|dir| dir.join(self::shell_directory_name(shell))),
+            ..artifact_config.clone()
+        };
+        (shell_config, shell_artifact_config)
+    }).collect();
+
+    let mut running_test_count = 0;
+    // The tests never started, as the suite ran out of time or was cancelled.
+    let mut not_run_count = 0;
+    let mut repeated_warnings = warnings::Repeated::new(config.max_repeated_warnings);
+    let mut progress = SuiteProgress {
+        completed: 0,
+        remaining: test_files.len() * shell_configs.len().max(1),
+        failures: 0,
+        elapsed: std::time::Duration::from_secs(0),
+    };
+    // Fixtures are shared by the tests of this run only.
+    let fixtures = fixtures::Fixtures::default();
+    let execution_start_time = std::time::Instant::now();
+    scheduler::run(&test_files, &config, |test_file| {
+        if shell_configs.is_empty() {
+            return vec![self::single_file(test_file, &config, &artifact_config, &fixtures)];
+        }
 
-        if !is_successful { has_failure = true; }
+        shell_configs.iter().map(|(shell_config, shell_artifact_config)| {
+            self::single_file(test_file, shell_config, shell_artifact_config, &fixtures)
+        }).collect()
+    }, |event| match event {
+        scheduler::Event::Started(test_file) => {
+            audit_log.scheduled(&test_file.path, &scheduler::resources(test_file, &config), running_test_count);
+            running_test_count += 1;
+
+            event_handler.on_test_started(&test_file.path, &config);
+        },
+        scheduler::Event::StillRunning(test_file, elapsed) => event_handler.on_test_still_running(&test_file.path, elapsed, &config),
+        scheduler::Event::Finished(results) => {
+            running_test_count -= 1;
+
+            for (mut result, difference) in results {
+                event_handler.on_test_result_pending(&mut result, &config);
+                audit_log.finished(&result);
+
+                if result.overall_result.is_erroneous() {
+                    has_failure = true;
+
+                    for owner in result.owners.iter() {
+                        failures_by_owner.entry(owner.clone()).or_insert_with(Vec::new).push(save_artifacts::failure_json(&result, &config));
+                    }
+                    failed_artifact_directories.extend(result.artifacts_directory.clone());
+                }
+                configuration_differences.extend(difference);
+
+                if let Some(timing_history) = timing_history.as_mut() {
+                    let key = match result.shell {
+                        Some(ref shell) => Path::new(&self::shell_directory_name(shell)).join(&result.path.relative),
+                        None => result.path.relative.clone(),
+                    };
+                    result.history = Some(timing_history.record(&key, result.duration, &result.overall_result));
+                }
+
+                // Every RUN command usually raises the same warnings.
+                let mut run_warnings: Vec<(&String, u32)> = Vec::new();
+                for run_result in result.individual_run_results.iter() {
+                    for warning in run_result.warnings.iter() {
+                        if !run_warnings.iter().any(|&(w, _)| w == warning) { run_warnings.push((warning, run_result.directive_line_number)); }
+                    }
+                }
+                for (warning, line_number) in run_warnings {
+                    warnings.note_repeated(&mut *event_handler, &mut repeated_warnings, warning, &format!("{}:{}", result.path.relative.display(), line_number),
+                                           &format!("{}: {}", result.display_name(&config), warning));
+                }
+
+                let is_empty = result.individual_run_results.is_empty() || result.overall_result == TestResultKind::EmptyTest;
+                if config.strict && is_empty {
+                    warnings.note_repeated(&mut *event_handler, &mut repeated_warnings, "test is empty", &result.path.relative.display().to_string(),
+                                           &format!("{}: test is empty", result.display_name(&config)));
+                }
+
+                progress.completed += 1;
+                progress.remaining -= 1;
+                if result.overall_result.is_erroneous() { progress.failures += 1; }
+                progress.elapsed = execution_start_time.elapsed();
+
+                event_handler.on_test_finished(result, &progress, &config);
+            }
+        },
+        scheduler::Event::NotRun(test_file) => {
+            let shells: Vec<_> = if shell_configs.is_empty() {
+                vec![None]
+            } else {
+                shell_configs.iter().map(|(shell_config, _)| Some(shell_config.shell.clone())).collect()
+            };
+
+            for shell in shells {
+                not_run_count += 1;
+                progress.completed += 1;
+                progress.remaining -= 1;
+                progress.elapsed = execution_start_time.elapsed();
+
+                let result = config.transform_result(TestResult { shell, ..self::not_run(test_file, &config) });
+                event_handler.on_test_finished(result, &progress, &config);
+            }
+        },
+    });
+
+    let is_cancelled = config.cancellation_token.is_cancelled();
+    if not_run_count > 0 {
+        match config.suite_timeout {
+            Some(suite_timeout) if !is_cancelled => event_handler.note_warning(&format!("stopped starting tests after the suite timeout of {}, so {} tests were not run",
+                                                                                        crate::util::format_duration(suite_timeout), not_run_count)),
+            _ => event_handler.note_warning(&format!("the suite was cancelled, so {} tests were not run", not_run_count)),
+        }
+        has_failure = true;
     }
-    let is_successful = !has_failure;
 
-    event_handler.on_test_suite_finished(is_successful, &config);
+    for summary in repeated_warnings.summaries(&config.messages) {
+        event_handler.note_warning(&summary);
+    }
+
+    if let (Some(timing_history), Some(path)) = (timing_history, config.timing_history_file.as_ref()) {
+        if let Err(e) = timing_history.save(path) {
+            warnings.note(&mut *event_handler, &e);
+        }
+    }
+
+    if let Some(integrity_snapshot) = integrity_snapshot {
+        for path in integrity_snapshot.modified_files() {
+            warnings.note(&mut *event_handler, &config.messages.format(MessageId::TestFileModified, &[&path.relative.display()]));
+
+            if config.modified_test_files == ModifiedTestFiles::Fail { has_failure = true; }
+        }
+    }
+
+    if config.comparison_config.is_some() {
+        event_handler.on_configuration_differences(&configuration_differences, &config);
+
+        if !configuration_differences.is_empty() { has_failure = true; }
+    }
+    fixtures.finish(&config);
+    // The status saved with the artifacts cannot account for warnings raised while bundling or uploading them.
+    let fails_strictly = config.strict && warnings.count() > 0;
+    let is_successful = !has_failure && !fails_strictly;
     save_artifacts::suite_status(is_successful, &artifact_config);
+    save_artifacts::seed(seed, &artifact_config);
+    save_artifacts::environment(&test_suite_details.environment, &artifact_config);
+    save_artifacts::owner_reports(failures_by_owner, &artifact_config);
+
+    if let Some(ref artifacts_dir) = artifact_config.artifacts_dir {
+        if let Some(ref archive_path) = config.bundle_failures_to {
+            if let Err(e) = bundle::failures(archive_path, artifacts_dir, &failed_artifact_directories) {
+                warnings.note(&mut *event_handler, &e);
+            }
+        }
+
+        if let Err(e) = upload::run(artifacts_dir, &artifact_config.saved_files(), is_successful, &config) {
+            warnings.note(&mut *event_handler, &e);
+        }
+    }
+
+    if config.strict && warnings.count() > 0 {
+        event_handler.note_warning(&format!("failing the suite in strict mode, as {} warnings were raised", warnings.count()));
+        has_failure = true;
+    }
+    event_handler.on_test_suite_finished(!has_failure, &config);
+
+    let failed_count = progress.failures;
+    match (has_failure, not_run_count) {
+        (false, _) => Ok(()),
+        (true, 0) => Err(SuiteError::Failed { failed_count }),
+        (true, not_run_count) if is_cancelled => Err(SuiteError::Cancelled { failed_count, not_run_count }),
+        (true, not_run_count) => Err(SuiteError::TimedOut { failed_count, not_run_count }),
+    }
+}
 
-    if !has_failure { Ok(()) } else { Err(()) }
+impl fmt::Display for SuiteError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SuiteError::NotStarted => write!(fmt, "the suite could not be run"),
+            SuiteError::Failed { failed_count: 0 } => write!(fmt, "the suite failed, although no tests did"),
+            SuiteError::Failed { failed_count } => write!(fmt, "{} tests failed", failed_count),
+            SuiteError::TimedOut { failed_count, not_run_count } => write!(fmt, "the suite timed out, so {} tests were not run, and {} tests failed", not_run_count, failed_count),
+            SuiteError::Cancelled { failed_count, not_run_count } => write!(fmt, "the suite was cancelled, so {} tests were not run, and {} tests failed", not_run_count, failed_count),
+        }
+    }
+}
+
+impl std::error::Error for SuiteError { }
+
+/// Executes a single test file, which does not need to exist on disk.
+///
+/// This allows tests to be synthesized at runtime via the `model` constructors. No event
+/// handler is notified - the result can be passed to `EventHandler::on_test_finished` if
+/// it should be reported. Artifacts are saved if configured, and the outputs of the fixture
+/// of the test, if any, are removed afterwards.
+pub fn execute_test_file(test_file: &TestFile, config: &Config) -> TestResult {
+    let artifact_config = save_artifacts::Config::new(config);
+    let fixtures = fixtures::Fixtures::default();
+
+    let result = self::single_file(test_file, config, &artifact_config, &fixtures).0;
+    fixtures.finish(config);
+    result
 }
 
 /// Executes a single, parsed test file.
 ///
-/// Returns `true` if all the tests in the file succeeded.
+/// The result transformers of the config are applied before the artifacts of the test are
+/// saved, so that the artifacts match what event handlers see. Also returns how the test
+/// behaved differently under the comparison config, if any.
 fn single_file(
     test_file: &TestFile,
-    event_handler: &mut dyn EventHandler,
     config: &Config,
     artifact_config: &save_artifacts::Config,
-    ) -> bool {
-    let test_results = test_evaluator::execute_tests(test_file, config);
-
-    // The overall result is failure if there are any failures, otherwise it is a pass.
-    let overall_result = test_results.iter().map(|(r, _, _, _)| r).filter(|r| match *r {
-        TestResultKind::Pass { .. } => false,
-        _ => true,
-    }).next().cloned().unwrap_or(TestResultKind::Pass);
+    fixtures: &fixtures::Fixtures,
+    ) -> (TestResult, Option<ConfigurationDifference>) {
+    let start_time = std::time::Instant::now();
+    let test_results = test_evaluator::execute_tests(test_file, config, fixtures);
+    let overall_result = self::overall_result(test_results.iter().map(|r| &r.kind));
 
     let result = TestResult {
         path: test_file.path.clone(),
         overall_result,
-        individual_run_results: test_results.into_iter().map(|(a, b, c, d)| (a, b.clone(), c, d)).collect(),
+        individual_run_results: test_results,
+        duration: start_time.elapsed(),
+        owners: config.owners_of(test_file),
+        history: None,
+        // Each shell of a matrix runs the test under its own config.
+        shell: if config.shell_matrix.is_empty() { None } else { Some(config.shell.clone()) },
+        artifacts_directory: None,
     };
+    let mut result = config.transform_result(result);
+
+    save_artifacts::run_results(&mut result, test_file, config, artifact_config);
 
-    save_artifacts::run_results(&result, test_file, artifact_config);
+    let difference = config.comparison_config.as_ref().and_then(|comparison_config| {
+        differential::compare(test_file, &result, config, comparison_config, fixtures)
+    });
 
-    let is_erroneous = result.overall_result.is_erroneous();
+    (result, difference)
+}
 
-    event_handler.on_test_finished(result, config);
+/// The result of a test that was never started, as the suite ran out of time or was cancelled.
+fn not_run(test_file: &TestFile, config: &Config) -> TestResult {
+    TestResult {
+        path: test_file.path.clone(),
+        overall_result: TestResultKind::NotRun,
+        individual_run_results: Vec::new(),
+        duration: std::time::Duration::default(),
+        owners: config.owners_of(test_file),
+        history: None,
+        shell: None,
+        artifacts_directory: None,
+    }
+}
 
-    !is_erroneous
+/// Computes the overall result of a test from the results of its individual runs.
+///
+/// The overall result is failure if there are any failures, otherwise it is a pass.
+fn overall_result<'a>(run_results: impl Iterator<Item=&'a TestResultKind>) -> TestResultKind {
+    run_results.filter(|r| match *r {
+        TestResultKind::Pass { .. } => false,
+        _ => true,
+    }).next().cloned().unwrap_or(TestResultKind::Pass)
+}
+
+/// Gets the directory that holds the artifacts and timing history of a shell of `Config::shell_matrix`.
+///
+/// Shells may be given as paths, such as `/bin/bash`, which must stay within the artifacts directory.
+fn shell_directory_name(shell: &str) -> String {
+    let name: String = shell.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '_' }).collect();
+
+    match name.trim_start_matches(['_', '.']) {
+        "" => "shell".to_owned(),
+        name => name.to_owned(),
+    }
 }
 
 mod util
 {
     use crate::model::*;
-    use crate::parse;
+    use crate::{parse, Config};
 
-    use std::{io::Read, path::Path};
     use std;
 
-    pub fn parse_test(path: TestFilePath) -> Result<TestFile, String> {
-        let mut text = String::new();
-        open_file(&path.absolute).read_to_string(&mut text).unwrap();
-        parse::test_file(path, text.chars())
-    }
+    pub fn parse_test(path: TestFilePath, config: &Config) -> TestFile {
+        let text = match config.read_test(&path) {
+            Ok(text) => text,
+            Err(e) => abort(e),
+        };
 
-    fn open_file(path: &Path) -> std::fs::File {
-        match std::fs::File::open(path) {
-            Ok(f) => f,
-            Err(e) => abort(format!("could not open {}: {}",
-                                    path.display(), e.to_string())),
+        match parse::test_file_for_config(path, &text, config) {
+            Ok(test_file) => test_file,
+            Err(e) => abort(e),
         }
     }
+
     pub fn abort<S>(msg: S) -> !
         where S: Into<String> {
         eprintln!("error: {}", msg.into());
@@ -127,16 +531,41 @@ mod util
 }
 
 mod save_artifacts {
-    use super::CommandLine;
-    use crate::model::*;
-    use std::path::{Path, PathBuf};
+    use crate::{json, model::*, VariablesExt};
+    use std::{path::{Path, PathBuf}, sync::{Arc, Mutex}};
     use std::fs;
 
     const SUITE_STATUS_PATH: &'static str = "suite-status.txt";
+    const SEED_PATH: &str = "seed.txt";
+    const ENVIRONMENT_PATH: &str = "environment.txt";
 
     #[derive(Clone, Debug)]
     pub struct Config {
         pub artifacts_dir: Option<PathBuf>,
+        /// The absolute paths of the artifacts saved so far, shared by the configs of every shell.
+        pub saved_files: Arc<Mutex<Vec<PathBuf>>>,
+    }
+
+    impl Config {
+        /// Gets the artifact settings of a config, which are ignored unless the
+        /// `artifacts` feature is enabled.
+        pub fn new(config: &crate::Config) -> Self {
+            let artifacts_dir = if cfg!(feature = "artifacts") { config.save_artifacts_to_directory.clone() } else { None };
+            // Artifact paths are reported to event handlers, which should not depend on the working directory.
+            let artifacts_dir = artifacts_dir.map(|dir| std::env::current_dir().map(|cwd| cwd.join(&dir)).unwrap_or(dir));
+
+            Config { artifacts_dir, saved_files: Arc::default() }
+        }
+
+        /// Gets the absolute paths of the artifacts saved so far.
+        pub fn saved_files(&self) -> Vec<PathBuf> {
+            self.saved_files.lock().unwrap().clone()
+        }
+
+        /// Records an artifact that was saved other than through this module, such as the audit log.
+        pub fn record_saved_file(&self, absolute_path: PathBuf) {
+            self.saved_files.lock().unwrap().push(absolute_path);
+        }
     }
 
     pub fn suite_status(is_successful: bool, config: &Config) {
@@ -149,17 +578,57 @@ mod save_artifacts {
         });
     }
 
-    pub fn run_results(test_result: &TestResult, test_file: &TestFile, artifact_config: &Config) {
+    pub fn seed(seed: u64, config: &Config) {
+        save(Path::new(SEED_PATH), config, || format!("{}\n", seed));
+    }
+
+    /// Saves the environment of the suite, which later runs can require via `--require-environment`.
+    pub fn environment(environment: &crate::run::environment::Environment, config: &Config) {
+        save(Path::new(ENVIRONMENT_PATH), config, || crate::run::environment::render(environment));
+    }
+
+    /// Saves one `owners/<owner>.json` report per owner of a failing test.
+    pub fn owner_reports(failures_by_owner: std::collections::BTreeMap<String, Vec<json::Value>>, config: &Config) {
+        for (owner, failures) in failures_by_owner {
+            let file_name: String = owner.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
+
+            save(&Path::new("owners").join(format!("{}.json", file_name)), config, || {
+                format!("{}\n", json::Value::object()
+                    .field("format_version", crate::report::FORMAT_VERSION)
+                    .field("owner", owner)
+                    .field("failures", failures))
+            });
+        }
+    }
+
+    /// Describes a failing test for an owner report.
+    pub fn failure_json(test_result: &TestResult, config: &crate::Config) -> json::Value {
+        json::Value::object()
+            .field("path", test_result.path.relative.display().to_string())
+            .field("name", config.test_name(&test_result.path))
+            .field("result", test_result.overall_result.machine_label())
+            .field("message", test_result.overall_result.failure_message(&untruncated(config)))
+            .field("failure_signature", test_result.failure_signature())
+    }
+
+    /// Saves the artifacts of every run of a test, recording where they were saved in the result.
+    pub fn run_results(test_result: &mut TestResult, test_file: &TestFile, test_config: &crate::Config, artifact_config: &Config) {
         let only_one_run_command = test_result.individual_run_results.len() == 1;
 
-        for (i, (result_kind, _, command_line, output)) in test_result.individual_run_results.iter().enumerate() {
+        for (i, run_result) in test_result.individual_run_results.iter_mut().enumerate() {
             let run_number = if only_one_run_command { None } else { Some(i + 1) };
-            self::individual_run_result(run_number, result_kind, command_line, output, test_file, artifact_config);
+            let dir_run_result = self::individual_run_result(run_number, run_result, test_file, test_config, artifact_config);
+
+            run_result.artifacts_directory = artifact_config.artifacts_dir.as_ref().map(|dir| dir.join(dir_run_result));
         }
+        test_result.artifacts_directory = artifact_config.artifacts_dir.as_ref().map(|dir| dir.join(&test_file.path.relative));
+
+        save(&test_file.path.relative.join(crate::run::rerun::MANIFEST_FILE_NAME), artifact_config, || crate::run::rerun::render(test_file, test_config));
     }
 
-    pub fn individual_run_result(run_number: Option<usize>, result_kind: &TestResultKind, command_line: &CommandLine, output: &ProgramOutput, test_file: &TestFile, config: &Config) {
-        let test_file_extension = test_file.path.absolute.extension().and_then(|s| s.to_str()).unwrap_or("txt");
+    /// Saves the artifacts of a single run, returning the directory they were saved to, relative to the artifacts directory.
+    pub fn individual_run_result(run_number: Option<usize>, run_result: &RunResult, test_file: &TestFile, test_config: &crate::Config, config: &Config) -> PathBuf {
+        let test_file_suffix = self::file_name_suffix(&test_file.path.absolute);
 
         let dir_run_result = match run_number {
             Some(run_number) => test_file.path.relative.join(format!("run-command-{}", run_number)),
@@ -167,16 +636,71 @@ mod save_artifacts {
         };
 
         save(&dir_run_result.join("result.txt"), config, || {
-            format!("{:#?}\n", result_kind)
+            format!("{:#?}\n", run_result.kind)
+        });
+
+        if run_result.kind.is_erroneous() {
+            save(&dir_run_result.join("failure-detail.txt"), config, || self::failure_detail(run_result, test_file, test_config));
+        }
+
+        save(&dir_run_result.join("stdout.txt"), config, || &run_result.output.stdout[..]);
+        save(&dir_run_result.join("stderr.txt"), config, || &run_result.output.stderr[..]);
+        save(&dir_run_result.join("command-line.txt"), config, || format!("{}\n", run_result.command_line.0));
+        save(&dir_run_result.join("runtime-variables.txt"), config, || run_result.runtime_variables.dump());
+        save(&dir_run_result.join("env.txt"), config, || {
+            run_result.environment.sorted_pairs().into_iter().map(|(name, value)| {
+                let is_secret = test_config.environment_redactions.iter().any(|r| r.is_match(name));
+                format!("{}={}\n", name, if is_secret { "<redacted>" } else { value })
+            }).collect::<String>()
+        });
+        save(&dir_run_result.join("check-matches.json"), config, || {
+            let check_matches: Vec<_> = run_result.check_matches.iter().map(CheckMatch::to_json).collect();
+            format!("{}\n", json::Value::from(check_matches))
         });
 
-        save(&dir_run_result.join("stdout.txt"), config, || &output.stdout[..]);
-        save(&dir_run_result.join("stderr.txt"), config, || &output.stderr[..]);
-        save(&dir_run_result.join("command-line.txt"), config, || format!("{}\n", command_line.0));
+        // Test files synthesized at runtime do not exist on disk.
+        if test_file.path.absolute.is_file() {
+            save(&dir_run_result.join(&format!("copy-of-test-case{}", test_file_suffix)), config, || std::fs::read(&test_file.path.absolute).unwrap());
 
-        save(&dir_run_result.join(&format!("copy-of-test-case.{}", test_file_extension)), config, || std::fs::read(&test_file.path.absolute).unwrap());
+            create_symlink(&test_file.path.absolute, &dir_run_result.join(&format!("symlink-to-test-case{}", test_file_suffix)), config)
+        }
 
-        create_symlink(&test_file.path.absolute, &dir_run_result.join(&format!("symlink-to-test-case.{}", test_file_extension)), config)
+        dir_run_result
+    }
+
+    /// Gets everything from the first dot of a file name, such as `.lit.txt` for `basic.lit.txt`,
+    /// so that copies of a test keep all of its extensions. Names without a dot have no suffix.
+    fn file_name_suffix(path: &Path) -> String {
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+        // The dot of a hidden file does not start an extension.
+        match file_name.char_indices().skip(1).find(|&(_, c)| c == '.') {
+            Some((index, _)) => file_name[index..].to_owned(),
+            None => String::new(),
+        }
+    }
+
+    /// Renders a full report of a failing run, which is never truncated like console output is.
+    fn failure_detail(run_result: &RunResult, test_file: &TestFile, test_config: &crate::Config) -> String {
+        let untruncated_config = self::untruncated(test_config);
+
+        let mut detail = format!("test: {}\nline: {}\ncommand: {}\nresult: {}\n",
+                                 test_config.test_name(&test_file.path), run_result.directive_line_number,
+                                 run_result.command_line.0, run_result.kind.machine_label());
+
+        if let Some(message) = run_result.kind.failure_message(&untruncated_config) {
+            detail.push_str(&format!("\n{}\n", message.trim_end()));
+        }
+        if let TestResultKind::Fail { hint: Some(ref hint), .. } = run_result.kind {
+            detail.push_str(&format!("\nhint: {}\n", hint));
+        }
+
+        detail
+    }
+
+    /// Gets a copy of a config that shows output context in full, for use in artifacts.
+    fn untruncated(config: &crate::Config) -> crate::Config {
+        crate::Config { truncate_output_context_to_number_of_lines: None, ..config.clone() }
     }
 
     fn save<C>(relative_path: &Path, config: &Config, render: impl FnOnce() -> C )
@@ -188,7 +712,8 @@ mod save_artifacts {
             let file_content = render();
 
             fs::create_dir_all(parent_directory).unwrap();
-            fs::write(absolute_path, file_content).unwrap();
+            fs::write(&absolute_path, file_content).unwrap();
+            config.record_saved_file(absolute_path);
         }
     }
 
@@ -206,7 +731,126 @@ mod save_artifacts {
                 fs::remove_file(&dst).unwrap(); // Remove the symlink.
             }
             create_symlink_impl(src, &dst).unwrap();
+            if dst.symlink_metadata().is_ok() {
+                config.record_saved_file(dst);
+            }
         }
 
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn executes_synthesized_test_files() {
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/synthesized.txt", "synthesized.txt"))
+            .with_command(CommandKind::Run(Invocation::new("echo hello world")))
+            .with_command(CommandKind::Check(TextPattern::parse("hello [[\\w+]]")));
+
+        let result = execute_test_file(&test_file, &config);
+        assert_eq!(result.overall_result, TestResultKind::Pass);
+
+        let failing_test_file = test_file.with_command(CommandKind::CheckNext(TextPattern::literal("goodbye")));
+        let result = execute_test_file(&failing_test_file, &config);
+        assert!(result.overall_result.is_erroneous());
+        assert_eq!(result.individual_run_results[0].directive_line_number, 3);
+    }
+
+    #[test]
+    #[cfg(feature = "artifacts")]
+    fn failure_details_in_artifacts_are_not_truncated() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            shell: "sh".to_owned(),
+            truncate_output_context_to_number_of_lines: Some(1),
+            save_artifacts_to_directory: Some(artifacts_dir.path().to_owned()),
+            ..Config::default()
+        };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/truncated.txt", "truncated.txt"))
+            .with_command(CommandKind::Run(Invocation::new("seq 1 20")))
+            .with_command(CommandKind::Check(TextPattern::literal("twenty")));
+
+        assert!(execute_test_file(&test_file, &config).overall_result.is_erroneous());
+
+        let detail = std::fs::read_to_string(artifacts_dir.path().join("truncated.txt/failure-detail.txt")).unwrap();
+        assert!(detail.starts_with("test: truncated.txt\nline: 2\ncommand: seq 1 20\nresult: fail\n"), "{}", detail);
+        assert!(detail.contains("expected text 'twenty'"));
+        assert!(detail.contains("  1 |") && detail.contains("20|"), "the full output should be shown: {}", detail);
+    }
+
+    #[test]
+    #[cfg(feature = "artifacts")]
+    fn results_refer_to_the_directories_of_their_artifacts() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            shell: "sh".to_owned(),
+            save_artifacts_to_directory: Some(artifacts_dir.path().to_owned()),
+            ..Config::default()
+        };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/two-runs.txt", "two-runs.txt"))
+            .with_command(CommandKind::Run(Invocation::new("true")))
+            .with_command(CommandKind::Run(Invocation::new("false")));
+
+        let result = execute_test_file(&test_file, &config);
+        let run_directories: Vec<_> = result.individual_run_results.iter().map(|r| r.artifacts_directory.clone().unwrap()).collect();
+        assert_eq!(result.artifacts_directory, Some(artifacts_dir.path().join("two-runs.txt")));
+        assert_eq!(run_directories, vec![artifacts_dir.path().join("two-runs.txt/run-command-1"), artifacts_dir.path().join("two-runs.txt/run-command-2")]);
+        assert!(run_directories.iter().all(|dir| dir.is_dir()));
+
+        assert_eq!(execute_test_file(&test_file, &Config { shell: "sh".to_owned(), ..Config::default() }).artifacts_directory, None);
+    }
+
+    #[test]
+    fn shells_given_as_paths_get_relative_directories() {
+        assert_eq!(shell_directory_name("bash"), "bash");
+        assert_eq!(shell_directory_name("/bin/bash"), "bin_bash");
+        assert_eq!(shell_directory_name("../zsh-5.9"), "zsh-5.9");
+        assert_eq!(shell_directory_name("/"), "shell");
+    }
+
+    #[test]
+    #[cfg(feature = "artifacts")]
+    fn copies_of_tests_in_artifacts_keep_every_extension() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let config = Config { shell: "sh".to_owned(), save_artifacts_to_directory: Some(artifacts_dir.path().to_owned()), ..Config::default() };
+
+        for name in ["basic.lit.txt", "Makefile"] {
+            std::fs::write(test_dir.path().join(name), "RUN: true\n").unwrap();
+            let test_file = TestFile::new(TestFilePath::new(test_dir.path().join(name), name))
+                .with_command(CommandKind::Run(Invocation::new("true")));
+            execute_test_file(&test_file, &config);
+        }
+
+        assert!(artifacts_dir.path().join("basic.lit.txt/copy-of-test-case.lit.txt").is_file());
+        assert!(artifacts_dir.path().join("Makefile/copy-of-test-case").is_file());
+    }
+
+    #[test]
+    #[cfg(feature = "artifacts")]
+    fn environment_artifacts_include_injected_paths_and_hide_secrets() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            shell: "sh".to_owned(),
+            save_artifacts_to_directory: Some(artifacts_dir.path().to_owned()),
+            ..Config::default()
+        };
+        config.add_executable_search_path("/opt/lit-test-bin");
+        config.environment_redactions.push(regex::Regex::new("^PATH$").unwrap());
+
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/env.txt", "env.txt"))
+            .with_command(CommandKind::Run(Invocation::new("true")));
+        execute_test_file(&test_file, &config);
+
+        let environment = std::fs::read_to_string(artifacts_dir.path().join("env.txt/env.txt")).unwrap();
+        assert!(environment.lines().any(|l| l == "PATH=<redacted>"));
+
+        config.environment_redactions.clear();
+        execute_test_file(&test_file, &config);
+        let environment = std::fs::read_to_string(artifacts_dir.path().join("env.txt/env.txt")).unwrap();
+        assert!(environment.lines().any(|l| l.starts_with("PATH=") && l.contains("/opt/lit-test-bin:")));
+    }
+}
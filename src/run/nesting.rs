@@ -0,0 +1,128 @@
+//! Detection of lit runs started by the tests of another lit run, such as the
+//! integration tests of a test suite that is itself built on lit.
+//!
+//! Every command lit runs is told how deeply it is nested, and where the run that
+//! started it saves its artifacts, so that a nested run does not clobber them.
+
+use crate::{Config, model::TestFile};
+use std::{env, path::PathBuf, process};
+
+/// The number of lit runs that a command is nested in, such as `1` for the commands of a top-level run.
+pub const DEPTH_VARIABLE: &str = "LIT_NESTING_DEPTH";
+/// The absolute path of the artifacts directory of the run that started a command, if it saves artifacts.
+pub const ARTIFACTS_DIRECTORY_VARIABLE: &str = "LIT_PARENT_ARTIFACTS_DIRECTORY";
+/// The absolute path of the artifacts directory of the test that started a command, if its run saves artifacts.
+pub const TEST_ARTIFACTS_DIRECTORY_VARIABLE: &str = "LIT_PARENT_TEST_ARTIFACTS_DIRECTORY";
+
+/// The run of lit that started the current one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Parent {
+    /// How deeply the current run is nested, starting from `1`.
+    pub depth: usize,
+    pub artifacts_directory: Option<PathBuf>,
+    /// The artifacts directory of the test that started the current run.
+    pub test_artifacts_directory: Option<PathBuf>,
+}
+
+/// Finds the run of lit that started the current one, if any.
+pub fn parent() -> Option<Parent> {
+    let depth = env::var(DEPTH_VARIABLE).ok()?.parse().ok().filter(|&depth| depth > 0)?;
+    let artifacts_directory = env::var_os(ARTIFACTS_DIRECTORY_VARIABLE).map(PathBuf::from);
+    let test_artifacts_directory = env::var_os(TEST_ARTIFACTS_DIRECTORY_VARIABLE).map(PathBuf::from);
+
+    Some(Parent { depth, artifacts_directory, test_artifacts_directory })
+}
+
+/// Moves the artifacts of a nested run into a directory of their own if they would otherwise be
+/// saved over the artifacts of the parent run, describing what was done.
+///
+/// The artifacts are moved into a `nested` directory among the artifacts of the test that started
+/// the run, so that nested runs started by different tests do not share a directory.
+pub fn separate_artifacts(parent: &Parent, config: &mut Config) -> Option<String> {
+    let artifacts_directory = self::absolute(config.save_artifacts_to_directory.clone()?);
+    if parent.artifacts_directory.as_ref() != Some(&artifacts_directory) {
+        return None;
+    }
+
+    let nested_artifacts_directory = parent.test_artifacts_directory.as_ref().unwrap_or(&artifacts_directory).join("nested");
+    let warning = format!("lit is running inside the tests of another lit run that saves artifacts to '{}', so artifacts are saved to '{}' instead",
+                          artifacts_directory.display(), nested_artifacts_directory.display());
+
+    config.save_artifacts_to_directory = Some(nested_artifacts_directory);
+    Some(warning)
+}
+
+/// Tells a command which lit run, and which test of it, started it.
+pub fn mark(command: &mut process::Command, test_file: &TestFile, config: &Config) {
+    let depth = self::parent().map(|parent| parent.depth).unwrap_or(0) + 1;
+    command.env(DEPTH_VARIABLE, depth.to_string());
+
+    match config.save_artifacts_to_directory.clone() {
+        Some(artifacts_directory) => {
+            let artifacts_directory = self::absolute(artifacts_directory);
+            // Under a shell matrix, each shell saves its artifacts to a directory of its own.
+            let test_artifacts_directory = match config.shell_matrix.is_empty() {
+                true => artifacts_directory.join(&test_file.path.relative),
+                false => artifacts_directory.join(super::shell_directory_name(&config.shell)).join(&test_file.path.relative),
+            };
+
+            command.env(ARTIFACTS_DIRECTORY_VARIABLE, artifacts_directory);
+            command.env(TEST_ARTIFACTS_DIRECTORY_VARIABLE, test_artifacts_directory)
+        },
+        None => command.env_remove(ARTIFACTS_DIRECTORY_VARIABLE).env_remove(TEST_ARTIFACTS_DIRECTORY_VARIABLE),
+    };
+}
+
+fn absolute(path: PathBuf) -> PathBuf {
+    env::current_dir().map(|cwd| cwd.join(&path)).unwrap_or(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{model::*, run::execute_test_file};
+
+    #[test]
+    fn nested_runs_saving_artifacts_to_the_same_directory_are_separated() {
+        let directory = env::temp_dir().join("lit-artifacts");
+        let parent = Parent { depth: 1, artifacts_directory: Some(directory.clone()), test_artifacts_directory: Some(directory.join("a.txt")) };
+
+        let mut config = Config { save_artifacts_to_directory: Some(directory.clone()), ..Config::default() };
+        assert!(separate_artifacts(&parent, &mut config).is_some());
+        assert_eq!(config.save_artifacts_to_directory, Some(directory.join("a.txt/nested")));
+
+        // Nested runs started by different tests at the same depth get different directories.
+        let sibling = Parent { test_artifacts_directory: Some(directory.join("b.txt")), ..parent.clone() };
+        let mut config = Config { save_artifacts_to_directory: Some(directory.clone()), ..Config::default() };
+        separate_artifacts(&sibling, &mut config);
+        assert_eq!(config.save_artifacts_to_directory, Some(directory.join("b.txt/nested")));
+
+        let mut config = Config { save_artifacts_to_directory: Some(env::temp_dir().join("other")), ..Config::default() };
+        assert_eq!(separate_artifacts(&parent, &mut config), None);
+        assert_eq!(separate_artifacts(&parent, &mut Config::default()), None);
+    }
+
+    #[test]
+    fn commands_are_told_how_deeply_lit_is_nested() {
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/nesting.txt", "nesting.txt"))
+            .with_command(CommandKind::Run(Invocation::new(format!("echo ${}:${{{}-none}}", DEPTH_VARIABLE, ARTIFACTS_DIRECTORY_VARIABLE))))
+            .with_command(CommandKind::CheckNext(TextPattern::literal("1:none")));
+
+        assert_eq!(execute_test_file(&test_file, &config).overall_result, TestResultKind::Pass);
+    }
+
+    #[test]
+    fn commands_are_told_the_artifacts_directory_of_their_test() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/nesting.txt", "dir/nesting.txt"))
+            .with_command(CommandKind::Run(Invocation::new(format!("echo ${}", TEST_ARTIFACTS_DIRECTORY_VARIABLE))));
+        let stdout_with = |shell_matrix: Vec<String>| {
+            let config = Config { shell: "sh".to_owned(), shell_matrix, save_artifacts_to_directory: Some(artifacts_dir.path().to_owned()), ..Config::default() };
+            execute_test_file(&test_file, &config).individual_run_results[0].output.stdout.to_string()
+        };
+
+        assert_eq!(stdout_with(Vec::new()), format!("{}\n", artifacts_dir.path().join("dir/nesting.txt").display()));
+        assert_eq!(stdout_with(vec!["sh".to_owned()]), format!("{}\n", artifacts_dir.path().join("sh/dir/nesting.txt").display()));
+    }
+}
@@ -0,0 +1,171 @@
+//! Replaying a single test from an earlier run, as done by `lit rerun <test>`.
+//!
+//! Every test saves a `rerun.txt` manifest in its artifacts directory, in the format of
+//! configuration files. It records what a later run needs to execute the test exactly as
+//! before - the seed, shell, features, constants and environment variables.
+//!
+//! ``` toml
+//! shell = "bash"
+//! features = ["x86"]
+//!
+//! [rerun]
+//! test = "/home/user/project/tests/parser/empty.txt"
+//! seed = "8231470613520437119"
+//!
+//! [constants]
+//! "arch" = "x86_64"
+//!
+//! [environment]
+//! "PATH" = "/usr/local/bin:/usr/bin:/bin"
+//! ```
+
+use crate::{Config, VariablesExt, config::file::{self, quote}, model::TestFile};
+use std::{collections::BTreeMap, env, fs, path::{Path, PathBuf}};
+
+/// Saved environment variables, by name.
+pub type Environment = BTreeMap<String, String>;
+
+/// The name of the manifest saved in the artifacts directory of every test.
+pub const MANIFEST_FILE_NAME: &str = "rerun.txt";
+
+/// Renders the manifest that replays a test under a config.
+///
+/// Environment variables matching `Config::environment_redactions` are left out, so the
+/// values of the machine that reruns the test are used instead. So are variables whose name
+/// or value is not valid Unicode, as the manifest cannot hold them.
+pub fn render(test_file: &TestFile, config: &Config) -> String {
+    let mut text = format!("shell = {}\n", quote(&config.shell));
+    let features: Vec<_> = config.available_features.iter().map(|feature| quote(feature)).collect();
+    text.push_str(&format!("features = [{}]\n", features.join(", ")));
+
+    text.push_str(&format!("\n[rerun]\ntest = {}\n", quote(&test_file.path.absolute.display().to_string())));
+    if let Some(seed) = config.seed {
+        // Seeds are saved as strings, as they do not fit in the signed integers of configuration files.
+        text.push_str(&format!("seed = {}\n", quote(&seed.to_string())));
+    }
+
+    text.push_str("\n[constants]\n");
+    for (name, value) in config.constants.sorted_pairs() {
+        text.push_str(&format!("{} = {}\n", quote(name), quote(value)));
+    }
+
+    let mut variables: Vec<_> = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| !config.environment_redactions.iter().any(|r| r.is_match(name)))
+        .collect();
+    variables.sort();
+    text.push_str("\n[environment]\n");
+    for (name, value) in variables {
+        text.push_str(&format!("{} = {}\n", quote(&name), quote(&value)));
+    }
+
+    text
+}
+
+/// Configures a run of a single test from the manifest saved for it in an artifacts directory.
+///
+/// Artifacts are not saved, so that the artifacts of the earlier run are kept. Returns the
+/// saved environment variables, which should be applied with `set_environment`.
+pub fn configure(artifacts_directory: &Path, test: &Path, config: &mut Config) -> Result<Environment, String> {
+    let manifest_path = artifacts_directory.join(test).join(MANIFEST_FILE_NAME);
+    let text = fs::read_to_string(&manifest_path)
+        .map_err(|e| format!("could not read the rerun manifest '{}': {}, was the test run with '--save-artifacts-to {}'?", manifest_path.display(), e, artifacts_directory.display()))?;
+    let entries = file::parse(&text).map_err(|e| format!("{}: {}", manifest_path.display(), e))?;
+
+    let mut test_path = None;
+    let mut environment = Environment::new();
+    config.available_features.clear();
+    config.seed = None;
+
+    for entry in entries.iter() {
+        let result = match (&entry.table[..], &entry.key[..]) {
+            ("rerun", "test") => entry.value.as_str().map(|path| test_path = Some(PathBuf::from(path))),
+            ("rerun", "seed") => entry.value.as_str().and_then(|seed| {
+                seed.parse().map(|seed| config.seed = Some(seed)).map_err(|_| format!("invalid seed: '{}'", seed))
+            }),
+            ("environment", name) => entry.value.as_str().map(|value| { environment.insert(name.to_owned(), value.to_owned()); }),
+            _ => file::apply_entry(entry, config),
+        };
+        result.map_err(|e| format!("{}: line {}: {}", manifest_path.display(), entry.line_number, e))?;
+    }
+
+    let test_path = test_path.ok_or_else(|| format!("{}: the path of the test is missing", manifest_path.display()))?;
+    if !test_path.is_file() {
+        return Err(format!("cannot rerun '{}', as '{}' no longer exists", test.display(), test_path.display()));
+    }
+
+    // Only the one test runs, regardless of how tests were selected before.
+    config.test_paths = vec![test_path];
+    config.test_sources.clear();
+    config.shell_matrix.clear();
+    config.shard = None;
+    config.changed_since = None;
+    config.save_artifacts_to_directory = None;
+
+    // Output is shown in full, as when running with `-v`.
+    config.always_show_stderr = true;
+    config.truncate_output_context_to_number_of_lines = None;
+    Ok(environment)
+}
+
+/// Replaces the environment variables of lit with saved ones, other than those that were redacted.
+///
+/// This must be done before any tests run, as no other threads may read the environment while it changes.
+pub fn set_environment(environment: &Environment, config: &Config) {
+    for (name, _) in env::vars_os() {
+        if !config.environment_redactions.iter().any(|r| r.is_match(&name.to_string_lossy())) {
+            env::remove_var(name);
+        }
+    }
+    for (name, value) in environment {
+        env::set_var(name, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::TestFilePath;
+
+    #[test]
+    fn configures_a_run_of_the_saved_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_path = dir.path().join("math.txt");
+        fs::write(&test_path, "RUN: true\n").unwrap();
+
+        let mut config = Config { shell: "bash".to_owned(), seed: Some(u64::MAX), available_features: vec!["x86".to_owned()], ..Config::default() };
+        config.constants.insert("cc".to_owned(), "clang \"-O2\"".to_owned());
+        let test_file = TestFile::new(TestFilePath::new(&test_path, "math.txt"));
+
+        fs::create_dir_all(dir.path().join("artifacts/math.txt")).unwrap();
+        fs::write(dir.path().join("artifacts/math.txt").join(MANIFEST_FILE_NAME), render(&test_file, &config)).unwrap();
+
+        let mut rerun_config = Config { shell_matrix: vec!["sh".to_owned(), "bash".to_owned()], ..Config::default() };
+        let environment = configure(&dir.path().join("artifacts"), Path::new("math.txt"), &mut rerun_config).unwrap();
+
+        assert_eq!(rerun_config.test_paths, vec![test_path]);
+        assert_eq!(rerun_config.shell, "bash");
+        assert!(rerun_config.shell_matrix.is_empty());
+        assert_eq!(rerun_config.seed, Some(u64::MAX));
+        assert_eq!(rerun_config.available_features, vec!["x86".to_owned()]);
+        assert_eq!(rerun_config.constants["cc"], "clang \"-O2\"");
+        assert!(rerun_config.always_show_stderr);
+        assert_eq!(environment.get("PATH"), env::var("PATH").ok().as_ref());
+
+        let error = configure(&dir.path().join("artifacts"), Path::new("missing.txt"), &mut rerun_config).unwrap_err();
+        assert!(error.starts_with("could not read the rerun manifest"), "{}", error);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn environment_variables_that_are_not_unicode_are_left_out() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        env::set_var("LIT_RERUN_NOT_UNICODE", OsStr::from_bytes(b"\xff"));
+        let manifest = render(&TestFile::new(TestFilePath::new("/nonexistent/a.txt", "a.txt")), &Config::default());
+        env::remove_var("LIT_RERUN_NOT_UNICODE");
+
+        assert!(!manifest.contains("LIT_RERUN_NOT_UNICODE"));
+        assert!(manifest.contains("\"PATH\" = "));
+    }
+}
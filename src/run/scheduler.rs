@@ -0,0 +1,368 @@
+//! Concurrent execution of test files, respecting resource limits.
+
+use crate::{Config, model::TestFile};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{mpsc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The state shared between all worker threads.
+struct Queue {
+    /// Indices of the test files that have not been started yet, in order.
+    pending: VecDeque<usize>,
+    /// The number of running tests holding each resource.
+    holders: HashMap<String, usize>,
+    /// The number of running instances of each file, by the index of its first instance.
+    running_instances: HashMap<usize, usize>,
+    /// When no more tests may be started, as given by `Config::suite_timeout`.
+    deadline: Option<Instant>,
+}
+
+/// Something that happened to a test file during scheduling.
+pub enum Event<'a, R> {
+    /// The test file has started executing.
+    Started(&'a TestFile),
+    /// The test file has been executing for the given time, and has not produced an
+    /// event for at least `Config::heartbeat_interval`.
+    StillRunning(&'a TestFile, Duration),
+    /// A test file has finished executing with the given result.
+    Finished(R),
+    /// The test file was never started, as `Config::suite_timeout` passed or the suite was cancelled first.
+    ///
+    /// Raised for every such test once all started tests have finished.
+    NotRun(&'a TestFile),
+}
+
+/// Executes all test files on up to `Config::jobs` threads.
+///
+/// A test file is only started once every resource it needs has a free slot,
+/// so tests contending for the same resource are serialized. Test files sharing a path
+/// are instances of the same file, of which at most `Config::max_concurrent_instances_per_file`
+/// run at the same time. `on_event` is
+/// called on the current thread. `Event::Started` and `Event::StillRunning` are raised as they
+/// happen, but `Event::Finished` and `Event::NotRun` are raised in the order of the test files,
+/// so that results are reported deterministically however many jobs there are.
+///
+/// If `Config::heartbeat_interval` is set, `Event::StillRunning` is raised for
+/// every test that has been quiet for that long. No tests are started once `Config::suite_timeout`
+/// passes or `Config::cancellation_token` is cancelled, and `Event::NotRun` is raised for those left over.
+pub fn run<R>(test_files: &[TestFile],
+              config: &Config,
+              execute: impl Fn(&TestFile) -> R + Sync,
+              mut on_event: impl FnMut(Event<R>))
+    where R: Send {
+    let resources: Vec<Vec<String>> = test_files.iter().map(|test_file| self::resources(test_file, config)).collect();
+    let instances_of = self::instances_of(test_files);
+
+    let queue = Mutex::new(Queue {
+        pending: (0..test_files.len()).collect(),
+        holders: HashMap::new(),
+        running_instances: HashMap::new(),
+        deadline: config.suite_timeout.map(|suite_timeout| Instant::now() + suite_timeout),
+    });
+    let queue_changed = Condvar::new();
+    let worker_count = config.jobs.max(1).min(test_files.len().max(1));
+
+    // The results of test files that finished before an earlier test file, by index.
+    let mut finished: BTreeMap<usize, R> = BTreeMap::new();
+    let mut next_to_report = 0;
+
+    thread::scope(|scope| {
+        let (event_sender, event_receiver) = mpsc::channel();
+
+        for _ in 0..worker_count {
+            let event_sender = event_sender.clone();
+            let (queue, queue_changed, resources, instances_of, execute) = (&queue, &queue_changed, &resources, &instances_of, &execute);
+
+            scope.spawn(move || {
+                while let Some(index) = self::acquire_next(queue, queue_changed, resources, instances_of, config) {
+                    let reservation = Reservation { index, queue, queue_changed, resources, instances_of };
+                    if event_sender.send((index, None)).is_err() { break; }
+
+                    let result = execute(&test_files[index]);
+                    drop(reservation);
+
+                    if event_sender.send((index, Some(result))).is_err() { break; }
+                }
+            });
+        }
+        drop(event_sender);
+
+        // When each running test started, and when it last raised an event.
+        let mut running: HashMap<usize, (Instant, Instant)> = HashMap::new();
+
+        loop {
+            let next_heartbeat = config.heartbeat_interval.and_then(|interval| {
+                running.values().map(|&(_, last_event)| last_event + interval).min()
+            });
+            let received = match next_heartbeat {
+                Some(deadline) => event_receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => event_receiver.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+
+            match received {
+                Ok((index, None)) => {
+                    running.insert(index, (Instant::now(), Instant::now()));
+                    on_event(Event::Started(&test_files[index]));
+                },
+                Ok((index, Some(result))) => {
+                    running.remove(&index);
+                    finished.insert(index, result);
+
+                    while let Some(result) = finished.remove(&next_to_report) {
+                        on_event(Event::Finished(result));
+                        next_to_report += 1;
+                    }
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            if let Some(interval) = config.heartbeat_interval {
+                let now = Instant::now();
+                let mut quiet: Vec<_> = running.iter_mut().filter(|(_, (_, last_event))| now - *last_event >= interval).collect();
+                quiet.sort_by_key(|&(&index, _)| index);
+
+                for (&index, (started, last_event)) in quiet {
+                    *last_event = now;
+                    on_event(Event::StillRunning(&test_files[index], now - *started));
+                }
+            }
+        }
+    });
+
+    // Tests that were never started hold back the results of later tests until now.
+    for (index, test_file) in test_files.iter().enumerate().skip(next_to_report) {
+        match finished.remove(&index) {
+            Some(result) => on_event(Event::Finished(result)),
+            None => on_event(Event::NotRun(test_file)),
+        }
+    }
+}
+
+/// Gets the resources a test file needs, from its own directives and from the config.
+pub fn resources(test_file: &TestFile, config: &Config) -> Vec<String> {
+    let mut resources: Vec<String> = test_file.resources().map(ToOwned::to_owned).collect();
+    resources.extend(config.resources_for_test(&test_file.path.absolute));
+    resources.sort();
+    resources.dedup();
+    resources
+}
+
+/// Maps every test file to the index of the first test file with the same path.
+fn instances_of(test_files: &[TestFile]) -> Vec<usize> {
+    let mut first_instances = HashMap::new();
+
+    test_files.iter().enumerate()
+        .map(|(index, test_file)| *first_instances.entry(&test_file.path.absolute).or_insert(index))
+        .collect()
+}
+
+/// Blocks until a pending test can be started, reserving its resources.
+///
+/// Returns `None` once all tests have been started, or once the deadline of the suite has passed
+/// or the suite has been cancelled.
+fn acquire_next(queue: &Mutex<Queue>,
+                queue_changed: &Condvar,
+                resources: &[Vec<String>],
+                instances_of: &[usize],
+                config: &Config) -> Option<usize> {
+    let mut queue = queue.lock().unwrap_or_else(|e| e.into_inner());
+
+    loop {
+        if queue.pending.is_empty() { return None; }
+        if queue.deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false) { return None; }
+        if config.cancellation_token.is_cancelled() { return None; }
+
+        let runnable_position = queue.pending.iter().position(|&index| {
+            let running_instances = queue.running_instances.get(&instances_of[index]).cloned().unwrap_or(0);
+            let instance_available = config.max_concurrent_instances_per_file.map(|max| running_instances < max.max(1)).unwrap_or(true);
+
+            instance_available && resources[index].iter().all(|resource| {
+                queue.holders.get(resource).cloned().unwrap_or(0) < config.resource_limit(resource)
+            })
+        });
+
+        if let Some(position) = runnable_position {
+            let index = queue.pending.remove(position).unwrap();
+            for resource in resources[index].iter() {
+                *queue.holders.entry(resource.clone()).or_insert(0) += 1;
+            }
+            *queue.running_instances.entry(instances_of[index]).or_insert(0) += 1;
+            return Some(index);
+        }
+
+        queue = queue_changed.wait(queue).unwrap_or_else(|e| e.into_inner());
+    }
+}
+
+/// The resources reserved for a running test, released once it is dropped.
+///
+/// This also happens if the test panics, so that the other workers are not left waiting for them.
+struct Reservation<'a> {
+    index: usize,
+    queue: &'a Mutex<Queue>,
+    queue_changed: &'a Condvar,
+    resources: &'a [Vec<String>],
+    instances_of: &'a [usize],
+}
+
+impl<'a> Drop for Reservation<'a> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        for resource in self.resources[self.index].iter() {
+            *queue.holders.get_mut(resource).unwrap() -= 1;
+        }
+        *queue.running_instances.get_mut(&self.instances_of[self.index]).unwrap() -= 1;
+        self.queue_changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::{Command, CommandKind, TestFilePath};
+    use std::{path::PathBuf, sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+
+    fn test_file(name: &str, resources: &[&str]) -> TestFile {
+        TestFile {
+            path: TestFilePath { absolute: name.into(), relative: name.into() },
+            commands: resources.iter().map(|r| Command::new(CommandKind::Resource(r.to_string()), 1)).collect(),
+            front_matter: Default::default(),
+        }
+    }
+
+    #[test]
+    fn exclusive_resources_are_never_held_concurrently() {
+        let test_files: Vec<_> = (0..8).map(|i| test_file(&format!("test{}", i), &["gpu"])).collect();
+        let config = Config { jobs: 4, ..Config::default() };
+        let (current_holders, max_holders) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+        let mut finished = 0;
+        run(&test_files, &config, |_| {
+            let holders = current_holders.fetch_add(1, Ordering::SeqCst) + 1;
+            max_holders.fetch_max(holders, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            current_holders.fetch_sub(1, Ordering::SeqCst);
+        }, |event| if let Event::Finished(..) = event { finished += 1 });
+
+        assert_eq!(finished, 8);
+        assert_eq!(max_holders.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn results_are_reported_in_the_order_of_the_test_files() {
+        let test_files: Vec<_> = (0..8).map(|i| test_file(&format!("test{}", i), &[])).collect();
+        let config = Config { jobs: 4, ..Config::default() };
+
+        let mut finished = Vec::new();
+        run(&test_files, &config, |test_file| {
+            let index: u64 = test_file.path.relative.to_str().unwrap()["test".len()..].parse().unwrap();
+            thread::sleep(Duration::from_millis((8 - index) * 5));
+            index
+        }, |event| if let Event::Finished(index) = event { finished.push(index) });
+
+        assert_eq!(finished, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn panicking_tests_release_their_resources() {
+        let test_files: Vec<_> = (0..4).map(|i| test_file(&format!("test{}", i), &["gpu"])).collect();
+        let config = Config { jobs: 2, ..Config::default() };
+
+        let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            run(&test_files, &config, |test_file| {
+                if test_file.path.relative.ends_with("test1") { panic!("test crashed"); }
+            }, |_| ());
+        }));
+        assert!(outcome.is_err());
+    }
+
+    #[test]
+    fn resource_limits_allow_multiple_holders() {
+        let test_files: Vec<_> = (0..8).map(|i| test_file(&format!("test{}", i), &["db"])).collect();
+        let mut config = Config { jobs: 8, ..Config::default() };
+        config.resource_limits.insert("db".to_owned(), 2);
+        let (current_holders, max_holders) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+        run(&test_files, &config, |_| {
+            let holders = current_holders.fetch_add(1, Ordering::SeqCst) + 1;
+            max_holders.fetch_max(holders, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            current_holders.fetch_sub(1, Ordering::SeqCst);
+        }, |_| ());
+
+        assert!(max_holders.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn instances_of_the_same_file_are_limited() {
+        let test_files: Vec<_> = (0..8).map(|i| test_file(if i % 2 == 0 { "shared" } else { "other" }, &[])).collect();
+        let config = Config { jobs: 8, max_concurrent_instances_per_file: Some(2), ..Config::default() };
+        let running = Mutex::new(HashMap::new());
+        let max_running = AtomicUsize::new(0);
+
+        run(&test_files, &config, |test_file| {
+            let instances = {
+                let mut running = running.lock().unwrap();
+                let instances = running.entry(test_file.path.relative.clone()).or_insert(0);
+                *instances += 1;
+                *instances
+            };
+            max_running.fetch_max(instances, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            *running.lock().unwrap().get_mut(&test_file.path.relative).unwrap() -= 1;
+        }, |_| ());
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn quiet_tests_raise_heartbeats() {
+        let test_files = vec![test_file("slow", &[]), test_file("fast", &[])];
+        let config = Config { jobs: 2, heartbeat_interval: Some(Duration::from_millis(20)), ..Config::default() };
+
+        let mut heartbeats = Vec::new();
+        run(&test_files, &config, |test_file| {
+            if test_file.path.relative.ends_with("slow") { thread::sleep(Duration::from_millis(110)); }
+        }, |event| if let Event::StillRunning(test_file, elapsed) = event { heartbeats.push((test_file.path.relative.clone(), elapsed)) });
+
+        assert!(heartbeats.len() >= 3, "{:?}", heartbeats);
+        assert!(heartbeats.iter().all(|(path, _)| path.ends_with("slow")));
+        assert!(heartbeats.windows(2).all(|w| w[0].1 < w[1].1), "elapsed times should increase: {:?}", heartbeats);
+    }
+
+    #[test]
+    fn tests_are_not_started_after_the_suite_timeout() {
+        let test_files = vec![test_file("a", &[]), test_file("b", &[]), test_file("c", &[])];
+        let config = Config { jobs: 1, suite_timeout: Some(Duration::from_millis(30)), ..Config::default() };
+
+        let (mut finished, mut not_run) = (Vec::new(), Vec::new());
+        run(&test_files, &config, |test_file| {
+            thread::sleep(Duration::from_millis(60));
+            test_file.path.relative.clone()
+        }, |event| match event {
+            Event::Finished(path) => finished.push(path),
+            Event::NotRun(test_file) => not_run.push(test_file.path.relative.clone()),
+            _ => (),
+        });
+
+        assert_eq!(finished, vec![PathBuf::from("a")]);
+        assert_eq!(not_run, vec![PathBuf::from("b"), PathBuf::from("c")]);
+    }
+
+    #[test]
+    fn tests_are_not_started_once_cancelled() {
+        let test_files = vec![test_file("a", &[]), test_file("b", &[]), test_file("c", &[])];
+        let config = Config { jobs: 1, ..Config::default() };
+
+        let mut not_run = Vec::new();
+        run(&test_files, &config, |test_file| {
+            if test_file.path.relative.ends_with("b") { config.cancellation_token.cancel(); }
+        }, |event| if let Event::NotRun(test_file) = event { not_run.push(test_file.path.relative.clone()) });
+
+        assert_eq!(not_run, vec![PathBuf::from("c")]);
+    }
+}
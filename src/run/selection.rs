@@ -0,0 +1,230 @@
+//! Explanations of why each test is run or not, as shown by `--explain-selection`.
+//!
+//! Tests are selected in the same order as when they are run: by file extension, by changes
+//! since a revision, by shard, and finally by the features required in their front matter.
+
+use super::{changes, find_files, fixtures, sharding};
+use crate::{Config, model::{TestFile, TestFilePath}, parse};
+use std::fmt;
+
+/// Whether a file is run as a test.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Included,
+    /// The file is not a test in this run, and is never parsed.
+    Excluded,
+    /// The test is parsed, but skipped when it is run.
+    Skipped,
+    /// The file cannot be read or parsed, which stops the suite when it is run.
+    Error,
+}
+
+/// The rule that decided the outcome of a file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rule {
+    /// The extension or name of the file, as configured by `--add-file-extension` and `--add-file-name-pattern`.
+    Extension,
+    /// The file is a fixture, as configured by `--fixture-file-name`, which runs before the tests beneath it.
+    Fixture,
+    /// The changes since the revision given by `--changed-since`.
+    ChangedSince,
+    /// The shard given by `--shard`.
+    Shard,
+    /// The features required by the `requires` key of the front matter of the test.
+    Requires,
+}
+
+/// How a discovered file was selected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub path: TestFilePath,
+    pub outcome: Outcome,
+    /// The rule that decided the outcome, or `None` if no rule applied to an included test.
+    pub rule: Option<Rule>,
+    /// Why the rule decided as it did.
+    pub reason: String,
+}
+
+/// Decides which of the discovered files are run, explaining the rule that decided each.
+///
+/// Files go through the same stages as when the suite is run. A file that cannot be read or
+/// parsed, which would stop the run, is reported as an error rather than failing the explanation.
+pub fn explain(config: &Config) -> Result<Vec<Decision>, String> {
+    let mut decisions: Vec<_> = find_files::unsupported_on_disk(config)?.into_iter().map(|path| {
+        let is_fixture = path.absolute.file_name().and_then(|name| name.to_str()).map(|name| fixtures::is_fixture(name, config)).unwrap_or(false);
+        let (rule, reason) = match path.absolute.extension() {
+            _ if is_fixture => (Rule::Fixture, "runs before the tests in its directory, rather than being a test".to_owned()),
+            Some(extension) => (Rule::Extension, format!("'.{}' is not a supported file extension, and no file name pattern matches", extension.to_string_lossy())),
+            None => (Rule::Extension, "the file has no extension, and no file name pattern matches".to_owned()),
+        };
+        Decision { path, outcome: Outcome::Excluded, rule: Some(rule), reason }
+    }).collect();
+
+    let mut candidates = find_files::with_config(config)?;
+
+    if let Some(ref revision) = config.changed_since {
+        let mut affected = Vec::new();
+
+        for (path, reason) in changes::reasons_for_selection(candidates, revision, config)? {
+            match reason {
+                Ok(Some(reason)) => {
+                    decisions.push(Decision { path: path.clone(), outcome: Outcome::Included, rule: Some(Rule::ChangedSince), reason });
+                    affected.push(path);
+                },
+                Ok(None) => decisions.push(Decision {
+                    path,
+                    outcome: Outcome::Excluded,
+                    rule: Some(Rule::ChangedSince),
+                    reason: format!("unaffected by changes since '{}'", revision),
+                }),
+                Err(reason) => decisions.push(Decision { path, outcome: Outcome::Error, rule: None, reason }),
+            }
+        }
+        candidates = affected;
+    }
+
+    if let Some(ref shard) = config.shard {
+        let shards = sharding::plan_with_config(candidates.clone(), config)?;
+
+        candidates.retain(|path| {
+            let shard_index = shards.iter().position(|plan| plan.test_paths.contains(path)).unwrap_or(shard.index);
+            if shard_index == shard.index {
+                return true;
+            }
+
+            decisions.push(Decision {
+                path: path.clone(),
+                outcome: Outcome::Excluded,
+                rule: Some(Rule::Shard),
+                reason: format!("belongs to shard {}/{}, not {}/{}", shard_index + 1, shard.count, shard.index + 1, shard.count),
+            });
+            false
+        });
+    }
+
+    for path in candidates {
+        let test_file = config.read_test(&path).and_then(|text| parse::test_file_for_config(path.clone(), &text, config));
+        let decision = match test_file {
+            Ok(test_file) => {
+                let missing_features = self::missing_features(&test_file, config);
+                match missing_features.is_empty() {
+                    true => None,
+                    false => Some(Decision {
+                        path: test_file.path,
+                        outcome: Outcome::Skipped,
+                        rule: Some(Rule::Requires),
+                        reason: format!("requires missing features {}", missing_features.join(", ")),
+                    }),
+                }
+            },
+            Err(reason) => Some(Decision { path: path.clone(), outcome: Outcome::Error, rule: None, reason }),
+        };
+
+        match decision {
+            Some(decision) => {
+                decisions.retain(|d| d.path != decision.path);
+                decisions.push(decision);
+            },
+            None if !decisions.iter().any(|decision| decision.path == path) => {
+                decisions.push(Decision { path, outcome: Outcome::Included, rule: None, reason: String::new() });
+            },
+            None => (),
+        }
+    }
+
+    decisions.sort_by(|a, b| a.path.relative.cmp(&b.path.relative));
+    Ok(decisions)
+}
+
+/// Gets the features required by a test that are not available, quoted, in the order they are required.
+pub(crate) fn missing_features(test_file: &TestFile, config: &Config) -> Vec<String> {
+    test_file.front_matter.requires.iter()
+        .filter(|feature| !config.available_features.contains(feature))
+        .map(|feature| format!("'{}'", feature))
+        .collect()
+}
+
+/// Renders decisions as a table with a row per file.
+pub fn render(decisions: &[Decision]) -> String {
+    let rows: Vec<[String; 4]> = decisions.iter().map(|decision| [
+        decision.outcome.to_string(),
+        decision.rule.map(|rule| rule.to_string()).unwrap_or_else(|| "-".to_owned()),
+        decision.path.relative.display().to_string(),
+        decision.reason.clone(),
+    ]).collect();
+    let header = ["OUTCOME", "RULE", "TEST", "REASON"].map(str::to_owned);
+
+    let widths: Vec<usize> = (0..3).map(|column| {
+        rows.iter().chain(std::iter::once(&header)).map(|row| row[column].chars().count()).max().unwrap_or(0)
+    }).collect();
+
+    std::iter::once(&header).chain(rows.iter()).map(|row| {
+        let line = format!("{:w0$}  {:w1$}  {:w2$}  {}", row[0], row[1], row[2], row[3], w0 = widths[0], w1 = widths[1], w2 = widths[2]);
+        line.trim_end().to_owned() + "\n"
+    }).collect()
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Included => "included",
+            Outcome::Excluded => "excluded",
+            Outcome::Skipped => "skipped",
+            Outcome::Error => "error",
+        }.fmt(fmt)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rule::Extension => "extension",
+            Rule::Fixture => "fixture",
+            Rule::ChangedSince => "changed-since",
+            Rule::Shard => "shard",
+            Rule::Requires => "requires",
+        }.fmt(fmt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Shard;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn explains_the_rule_deciding_each_file() {
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(directory.path().join("a.txt"), "RUN: true\n").unwrap();
+        fs::write(directory.path().join("b.txt"), "RUN: true\n").unwrap();
+        fs::write(directory.path().join("gpu.txt"), "---\nrequires = [\"gpu\"]\n---\nRUN: true\n").unwrap();
+        fs::write(directory.path().join("notes.md"), "RUN: true\n").unwrap();
+
+        let mut config = Config { shard: Some(Shard { index: 0, count: 2 }), ..Config::default() };
+        config.add_extension("txt");
+        config.add_search_path(directory.path().to_str().unwrap());
+
+        let decisions = explain(&config).unwrap();
+        let summary: Vec<_> = decisions.iter().map(|d| (d.path.relative.display().to_string(), d.outcome, d.rule)).collect();
+        assert_eq!(summary, vec![
+            ("a.txt".to_owned(), Outcome::Included, None),
+            ("b.txt".to_owned(), Outcome::Excluded, Some(Rule::Shard)),
+            ("gpu.txt".to_owned(), Outcome::Skipped, Some(Rule::Requires)),
+            ("notes.md".to_owned(), Outcome::Excluded, Some(Rule::Extension)),
+        ]);
+        assert_eq!(decisions[1].reason, "belongs to shard 2/2, not 1/2");
+
+        let table = render(&decisions);
+        assert!(table.starts_with("OUTCOME   RULE       TEST      REASON\nincluded  -          a.txt\n"), "{}", table);
+
+        // Files that cannot be parsed get a row of their own, rather than failing the explanation.
+        fs::write(directory.path().join("broken.txt"), "RUN: true\nCHECK-SKIP: lots\n").unwrap();
+        config.shard = None;
+
+        let decisions = explain(&config).unwrap();
+        let broken = decisions.iter().find(|d| d.path.relative == Path::new("broken.txt")).unwrap();
+        assert_eq!((broken.outcome, broken.rule), (Outcome::Error, None));
+        assert_eq!(decisions.iter().filter(|d| d.outcome == Outcome::Included).count(), 2);
+    }
+}
@@ -0,0 +1,125 @@
+//! Partitioning of the test suite into shards, for splitting a run across machines.
+
+use super::history::TimingHistory;
+use crate::{Config, model::TestFilePath};
+use std::time::Duration;
+
+/// The tests assigned to a single shard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShardPlan {
+    /// The tests in the shard, in discovery order.
+    pub test_paths: Vec<TestFilePath>,
+    /// The total duration of the tests according to the timing history, if one was available.
+    pub expected_duration: Option<Duration>,
+}
+
+/// Partitions tests into `shard_count` shards.
+///
+/// With a timing history, the tests are balanced so that all shards are expected to
+/// take roughly the same time. Tests without a recorded duration are assumed to take
+/// the average duration. Without a history, tests are distributed evenly by count.
+pub fn plan(test_paths: Vec<TestFilePath>, shard_count: usize, history: Option<&TimingHistory>) -> Vec<ShardPlan> {
+    let shard_count = shard_count.max(1);
+
+    let history = match history {
+        Some(history) => history,
+        None => {
+            let mut shards = vec![Vec::new(); shard_count];
+            for (i, test_path) in test_paths.into_iter().enumerate() {
+                shards[i % shard_count].push(test_path);
+            }
+            return shards.into_iter().map(|test_paths| ShardPlan { test_paths, expected_duration: None }).collect();
+        },
+    };
+
+    let known_durations: Vec<_> = test_paths.iter().filter_map(|p| history.expected_duration(&p.relative)).collect();
+    let average_duration = if known_durations.is_empty() {
+        Duration::from_secs(1)
+    } else {
+        known_durations.iter().sum::<Duration>() / known_durations.len() as u32
+    };
+
+    // Longest processing time first - assign the slowest remaining test to the least loaded shard.
+    let mut tests: Vec<_> = test_paths.into_iter().enumerate().map(|(discovery_index, test_path)| {
+        let duration = history.expected_duration(&test_path.relative).unwrap_or(average_duration);
+        (discovery_index, test_path, duration)
+    }).collect();
+    tests.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+
+    let mut shards: Vec<(Duration, Vec<(usize, TestFilePath)>)> = vec![(Duration::default(), Vec::new()); shard_count];
+    for (discovery_index, test_path, duration) in tests {
+        let shard = shards.iter_mut().min_by_key(|(total, _)| *total).unwrap();
+        shard.0 += duration;
+        shard.1.push((discovery_index, test_path));
+    }
+
+    shards.into_iter().map(|(expected_duration, mut tests)| {
+        tests.sort_by_key(|(discovery_index, _)| *discovery_index);
+
+        ShardPlan {
+            test_paths: tests.into_iter().map(|(_, test_path)| test_path).collect(),
+            expected_duration: Some(expected_duration),
+        }
+    }).collect()
+}
+
+/// Plans the shards of the suite according to the config.
+pub fn plan_with_config(test_paths: Vec<TestFilePath>, config: &Config) -> Result<Vec<ShardPlan>, String> {
+    let shard_count = config.shard.as_ref().map(|s| s.count).unwrap_or(1);
+
+    let history = match config.timing_history_file {
+        Some(ref path) => Some(TimingHistory::load(path)?),
+        None => None,
+    };
+
+    Ok(self::plan(test_paths, shard_count, history.as_ref()))
+}
+
+/// Selects the tests belonging to the shard configured via `Config::shard`.
+pub fn select(test_paths: Vec<TestFilePath>, config: &Config) -> Result<Vec<TestFilePath>, String> {
+    match config.shard {
+        Some(ref shard) => {
+            let mut shards = self::plan_with_config(test_paths, config)?;
+            Ok(shards.swap_remove(shard.index).test_paths)
+        },
+        None => Ok(test_paths),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::TestResultKind;
+    use std::path::{Path, PathBuf};
+
+    fn test_paths(names: &[&str]) -> Vec<TestFilePath> {
+        names.iter().map(|name| TestFilePath { absolute: PathBuf::from("/").join(name), relative: PathBuf::from(name) }).collect()
+    }
+
+    fn names(plan: &ShardPlan) -> Vec<String> {
+        plan.test_paths.iter().map(|p| p.relative.display().to_string()).collect()
+    }
+
+    #[test]
+    fn distributes_by_count_without_history() {
+        let shards = plan(test_paths(&["a", "b", "c"]), 2, None);
+
+        assert_eq!(names(&shards[0]), vec!["a", "c"]);
+        assert_eq!(names(&shards[1]), vec!["b"]);
+    }
+
+    #[test]
+    fn balances_by_expected_duration() {
+        let mut history = TimingHistory::default();
+        history.record(Path::new("slow"), Duration::from_secs(10), &TestResultKind::Pass);
+        history.record(Path::new("a"), Duration::from_secs(3), &TestResultKind::Pass);
+        history.record(Path::new("b"), Duration::from_secs(3), &TestResultKind::Pass);
+        history.record(Path::new("c"), Duration::from_secs(4), &TestResultKind::Pass);
+
+        let shards = plan(test_paths(&["a", "b", "c", "slow"]), 2, Some(&history));
+
+        assert_eq!(names(&shards[0]), vec!["slow"]);
+        assert_eq!(names(&shards[1]), vec!["a", "b", "c"]);
+        assert_eq!(shards[1].expected_duration, Some(Duration::from_secs(10)));
+    }
+}
@@ -1,85 +1,321 @@
 use crate::{
-    model::{CommandKind, Invocation, TestFile, TestResultKind, TestFailReason, ProgramOutput},
+    model::{Command, CommandKind, Invocation, JsonCheck, PhaseDurations, ResourceUsage, TestFile, TestResultKind, TestFailReason, ProgramOutput, RunResult},
     Config,
+    helpers,
+    json,
+    messages::MessageId,
+    parse,
+    plugin,
     vars,
+    Variables,
     VariablesExt,
 };
 use self::state::TestRunState;
-use std::{collections::HashMap, env, fs, process};
+use std::{collections::HashMap, env, fs, io::{self, BufRead, Read, Write}, process, sync::{mpsc, Arc}, thread, time::{Duration, Instant}};
 
+mod background;
+mod pty;
 mod state;
 #[cfg(test)] mod state_tests;
+mod streaming;
 
-/// Responsible for evaluating specific tests and collecting
-/// the results.
-#[derive(Clone)]
-pub struct TestEvaluator
-{
-    pub invocation: Invocation,
-}
-
-pub fn execute_tests<'test>(test_file: &'test TestFile, config: &Config) -> Vec<(TestResultKind, &'test Invocation, CommandLine, ProgramOutput)> {
-    test_file.run_command_invocations().map(|invocation| {
-        let initial_variables = {
-            let mut vars = HashMap::new();
-            vars.extend(config.constants.clone());
-            vars.extend(test_file.variables());
-            vars
-        };
+pub fn execute_tests(test_file: &TestFile, config: &Config, fixtures: &super::fixtures::Fixtures) -> Vec<RunResult> {
+    // Values such as '@free_port' are decided once so that all commands in the file agree on them.
+    let runtime_variables = vars::runtime_variables();
+    let mut test_variables = {
+        let mut vars = helpers::variables(config);
+        let constants = config.constants_for_test(&test_file.path.absolute);
+        vars.extend(constants.iter().map(|(name, value)| (vars::qualified_name(vars::CONFIG_NAMESPACE, name), value.clone())));
+        vars.extend(constants);
+        vars.extend(test_file.front_matter.constants.clone());
+        vars.extend(test_file.variables());
+        vars.extend(config.executor.variables());
+        vars.extend(config.seed.map(|seed| ("seed".to_owned(), seed.to_string())));
+        vars.extend(runtime_variables.clone());
+        vars
+    };
 
-        let mut test_run_state = TestRunState::new(initial_variables);
-        let (command, command_line) = self::build_command(invocation, test_file, config);
+    let run_commands: Vec<_> = test_file.run_command_invocations().collect();
+    let unexecuted = |kind: TestResultKind, directive_line_number: Option<u32>, warnings: Vec<String>| {
+        run_commands.iter().map(|&(run_line_number, invocation)| RunResult {
+            kind: kind.clone(),
+            invocation: invocation.clone(),
+            command_line: CommandLine(invocation.original_command.clone()),
+            output: ProgramOutput::empty(),
+            runtime_variables: runtime_variables.clone(),
+            environment: Variables::new(),
+            directive_line_number: directive_line_number.unwrap_or(run_line_number),
+            check_matches: Vec::new(),
+            warnings: warnings.clone(),
+            artifacts_directory: None,
+            phase_durations: PhaseDurations::default(),
+            resource_usage: None,
+        }).collect::<Vec<_>>()
+    };
 
-        let (program_output, execution_result) = self::collect_output(command, command_line.clone(), config);
+    let missing_features = super::selection::missing_features(test_file, config);
+    if !missing_features.is_empty() {
+        let warning = config.messages.format(MessageId::MissingFeatures, &[&missing_features.join(", ")]);
+        return unexecuted(TestResultKind::Skip, None, vec![warning]);
+    }
 
-        test_run_state.append_program_output(&program_output.stdout);
-        test_run_state.append_program_stderr(&program_output.stderr);
+    match fixtures.prepare(&test_file.path, config) {
+        Ok(Some(fixture_output_directory)) => {
+            test_variables.insert(super::fixtures::OUTPUT_DIRECTORY_VARIABLE.to_owned(), fixture_output_directory.display().to_string());
+        },
+        Ok(None) => (),
+        Err(message) => return unexecuted(TestResultKind::Error { message }, None, Vec::new()),
+    }
 
-        if execution_result.is_erroneous() {
-            return (execution_result, invocation, command_line, program_output);
-        }
+    let variables_log = format!("variables for '{}':\n{}", test_file.path.relative.display(), test_variables.dump());
+    debug!("{}", variables_log);
+    if config.dump_variable_resolution {
+        eprint!("[info] {}", variables_log);
+    }
+
+    // Background processes live for the duration of all RUN commands in the file.
+    let _background_processes = match background::start(test_file, &test_variables, config) {
+        Ok(processes) => processes,
+        Err((directive_line_number, message)) => {
+            return unexecuted(TestResultKind::Error { message }, Some(directive_line_number), Vec::new());
+        },
+    };
+
+    run_commands.iter().map(|&(run_line_number, invocation)| {
+        let mut test_run_state = TestRunState::new(test_variables.clone());
+        test_run_state.set_unordered_checks(test_file.has_unordered_checks());
+        let (command, command_line) = self::build_command(invocation, test_file, &test_variables, config);
+        let environment = self::effective_environment(&command);
+        let mut phase_durations = PhaseDurations::default();
+
+        // Output limits, timeouts and stderr are only checked when running commands to completion.
+        let use_pty = config.use_pty || test_file.uses_pty();
+        let fails_on_stderr = config.fail_on_stderr && !invocation.negated && !test_file.allows_stderr();
+        let streams = config.early_exit_on_check_completion && config.max_output_bytes.is_none() &&
+            test_file.front_matter.timeout.is_none() && !use_pty && !fails_on_stderr && !config.skip_directive_lines_in_output &&
+            streaming::supports(test_file);
+        let (kind, program_output, failing_line_number, resource_usage) = if streams {
+            let streaming_start_time = Instant::now();
+            let (program_output, result_kind, failing_line_number, resource_usage) = streaming::run(command, command_line.clone(), &mut test_run_state, test_file, config);
+            phase_durations.wait = streaming_start_time.elapsed();
+
+            (result_kind, program_output, failing_line_number, resource_usage)
+        } else {
+            let (program_output, execution_result, resource_usage) = self::collect_output(command, command_line.clone(), invocation.negated,
+                test_file.front_matter.timeout, use_pty, &mut phase_durations, config);
+
+            test_run_state.set_program_output(&program_output);
+            if config.skip_directive_lines_in_output {
+                let parse_directive = parse::line_directive_parser(&test_file.path, config);
+                test_run_state.blank_lines(|line| parse_directive(line).is_some());
+            }
 
-        let overall_test_result_kind = run_test_checks(&mut test_run_state, test_file, config);
-        (overall_test_result_kind, invocation, command_line, program_output)
+            if execution_result.is_erroneous() {
+                (execution_result, program_output, None, resource_usage)
+            } else if fails_on_stderr && !program_output.stderr.is_empty() {
+                let reason = TestFailReason::StderrWritten { program_command_line: command_line.0.clone(), stderr: program_output.stderr.to_string() };
+                (TestResultKind::Fail { reason, hint: None }, program_output, None, resource_usage)
+            } else {
+                let check_start_time = Instant::now();
+                let metrics = RunMetrics { command_line: &command_line, duration: phase_durations.spawn + phase_durations.wait, resource_usage };
+                let (overall_test_result_kind, failing_line_number) = run_test_checks(&mut test_run_state, test_file, &metrics, config);
+                phase_durations.check = check_start_time.elapsed();
+
+                let (global_hits, global_misses) = vars::resolve::global_regex_cache_statistics();
+                let regex_cache_log = format!("regex cache for '{}': {} hits, {} misses (global cache: {} hits, {} misses)",
+                    test_file.path.relative.display(), test_run_state.regex_cache().hits, test_run_state.regex_cache().misses,
+                    global_hits, global_misses);
+                debug!("{}", regex_cache_log);
+
+                if config.dump_regex_cache_statistics {
+                    eprintln!("[info] {}", regex_cache_log);
+                }
+
+                (overall_test_result_kind, program_output, failing_line_number, resource_usage)
+            }
+        };
+
+        let directive_line_number = match kind {
+            TestResultKind::UnexpectedPass => test_file.commands.iter()
+                .find(|c| c.kind == CommandKind::XFail).map(|c| c.line_number).unwrap_or(run_line_number),
+            _ => failing_line_number.unwrap_or(run_line_number),
+        };
+
+        RunResult {
+            kind,
+            invocation: invocation.clone(),
+            command_line,
+            output: program_output,
+            runtime_variables: runtime_variables.clone(),
+            environment,
+            directive_line_number,
+            check_matches: test_run_state.check_matches().to_vec(),
+            warnings: test_run_state.warnings().to_vec(),
+            artifacts_directory: None,
+            phase_durations,
+            resource_usage,
+        }
     }).collect()
 }
 
+/// What was measured whilst running a program, as checked by `EXPECT-DURATION-UNDER` and `EXPECT-RSS-UNDER`.
+struct RunMetrics<'a> {
+    command_line: &'a CommandLine,
+    /// How long the program took to start and to exit.
+    duration: Duration,
+    resource_usage: Option<ResourceUsage>,
+}
+
+/// Evaluates all checks against the output of a program.
+///
+/// Also returns the line of the directive that failed, if any.
 fn run_test_checks(
     test_run_state: &mut TestRunState,
     test_file: &TestFile,
+    metrics: &RunMetrics,
     config: &Config,
-) -> TestResultKind {
+) -> (TestResultKind, Option<u32>) {
     let mut check_result = TestResultKind::EmptyTest;
+    let mut failing_line_number = None;
+    let mut failing_command = None;
+    // Patterns forbidden until the next `CHECK-END`.
+    let mut forbidden_patterns = Vec::new();
 
     for command in test_file.commands.iter() {
+        let mut failing_directive_line_number = command.line_number;
+
         let test_result = match command.kind {
             CommandKind::Run(..) | // RUN commands are already handled above, in the loop.
-                CommandKind::XFail => { // XFAIL commands are handled separately too.
+                CommandKind::RunBackground(..) | CommandKind::WaitFor(..) | CommandKind::Resource(..) | // So are background and resource commands.
+                CommandKind::Depends(..) | CommandKind::Owner(..) | // Metadata only affects selection and reporting.
+                CommandKind::NormalizePreset(..) | // Presets are applied wherever output is normalized.
+                CommandKind::Include(..) | // Includes are expanded when the test is parsed.
+                CommandKind::XFail | CommandKind::CheckUnordered | CommandKind::Pty | CommandKind::AllowStderr => { // XFAIL, CHECK-UNORDERED, PTY and ALLOW-STDERR commands are handled separately too.
                     TestResultKind::Pass
                 },
-            CommandKind::Check(ref text_pattern) => test_run_state.check(text_pattern, config),
+            CommandKind::Check(ref text_pattern) | CommandKind::CheckBlock(ref text_pattern) => test_run_state.check(text_pattern, config),
             CommandKind::CheckNext(ref text_pattern) => test_run_state.check_next(text_pattern, config),
-        };
-
-        if config.cleanup_temporary_files {
-            let tempfile_paths = test_run_state.variables().tempfile_paths();
+            CommandKind::CheckSkip(line_count) => test_run_state.skip_lines(line_count),
+            CommandKind::CheckSameAs(ref reference_invocation) => check_same_as(test_run_state, test_file, reference_invocation, config),
+            CommandKind::CheckGolden(ref path) => check_golden(test_run_state, test_file, path, config),
+            CommandKind::CheckJson(ref json_check) => check_json(test_run_state, test_file, json_check, config),
+            CommandKind::Custom(ref directive) => {
+                plugin::evaluate_directive(directive, &test_run_state.program_output(), test_run_state.variables(), config)
+            },
+            CommandKind::ExpectDurationUnder(limit) => match metrics.duration < limit {
+                true => TestResultKind::Pass,
+                false => TestResultKind::Fail {
+                    reason: TestFailReason::DurationExceeded { program_command_line: metrics.command_line.0.clone(), limit, duration: metrics.duration },
+                    hint: None,
+                },
+            },
+            CommandKind::ExpectRssUnder(limit_bytes) => match metrics.resource_usage {
+                Some(usage) if usage.max_rss_bytes >= limit_bytes => TestResultKind::Fail {
+                    reason: TestFailReason::RssExceeded { program_command_line: metrics.command_line.0.clone(), limit_bytes, max_rss_bytes: usage.max_rss_bytes },
+                    hint: None,
+                },
+                Some(..) => TestResultKind::Pass,
+                None => {
+                    test_run_state.warn("EXPECT-RSS-UNDER was not checked, as the memory usage of the command could not be measured".to_owned());
+                    TestResultKind::Pass
+                },
+            },
+            CommandKind::Undef(ref name) => {
+                test_run_state.undefine(name);
+                TestResultKind::Pass
+            },
+            CommandKind::CheckNotBetween(ref text_pattern) => {
+                forbidden_patterns.push((command.line_number, text_pattern));
+                TestResultKind::Pass
+            },
+            CommandKind::CheckEnd(ref text_pattern) => {
+                let (result, forbidding_line_number) = test_run_state.check_end(text_pattern, &forbidden_patterns, config);
+                forbidden_patterns.clear();
 
-            for tempfile in tempfile_paths {
-                // Ignore errors, these are tempfiles, they go away anyway.
-                fs::remove_file(tempfile).ok();
-            }
-        }
+                failing_directive_line_number = forbidding_line_number.unwrap_or(command.line_number);
+                result
+            },
+        };
 
+        cleanup_temporary_files(test_run_state, config);
 
         // Early return for failures.
         if test_result.is_erroneous() {
             check_result = test_result;
+            failing_command = Some(command);
+            failing_line_number = Some(failing_directive_line_number);
             break;
         } else {
+            test_run_state.record_match(command.line_number);
             check_result = TestResultKind::Pass;
         }
     }
 
+    let check_result = explain_self_match(test_run_state, check_result, test_file, config);
+    let check_result = match failing_command {
+        Some(command) => explain_origin(check_result, command),
+        None => check_result,
+    };
+    (apply_expected_failure(check_result, test_file), failing_line_number)
+}
+
+/// Names the file and line that a failing directive was written on, if it was included from
+/// another file, as the line number of the failure is that of the `INCLUDE` directive.
+fn explain_origin(result: TestResultKind, command: &Command) -> TestResultKind {
+    let origin = match command.origin {
+        Some(ref origin) => format!("the failing directive is on line {} of '{}'", origin.line_number, origin.path.display()),
+        None => return result,
+    };
+
+    match result {
+        TestResultKind::Fail { reason, hint } => TestResultKind::Fail {
+            reason,
+            hint: Some(hint.map(|hint| format!("{}\n{}", origin, hint)).unwrap_or(origin)),
+        },
+        TestResultKind::Error { message } => TestResultKind::Error { message: format!("{} ({})", message, origin) },
+        result => result,
+    }
+}
+
+/// Hints at the cause of a failed check if an earlier check matched a line of output holding one of
+/// the directives of the test itself, such as when the test checks its own source with `cat @file`.
+fn explain_self_match(test_run_state: &TestRunState, result: TestResultKind, test_file: &TestFile, config: &Config) -> TestResultKind {
+    let reason = match result {
+        TestResultKind::Fail { reason: reason @ TestFailReason::CheckFailed(..), hint: None } => reason,
+        result => return result,
+    };
+
+    let parse_directive = parse::line_directive_parser(&test_file.path, config);
+    let output_lines: Vec<&str> = test_run_state.complete_output_stream().lines().collect();
+    let self_match = test_run_state.check_matches().iter().find_map(|check_match| {
+        let matched_lines = output_lines.get(check_match.start_line - 1..check_match.end_line)?;
+        matched_lines.iter()
+            .filter_map(|line| parse_directive(line)?.ok())
+            .find_map(|parsed| test_file.commands.iter().find(|command| command.kind == parsed.kind))
+            .map(|directive| (check_match.directive_line_number, directive.line_number))
+    });
+
+    let hint = self_match.map(|(check_line_number, directive_line_number)| {
+        format!("the check on line {} matched the directive on line {} of the test itself, rather than the output it was meant to",
+                check_line_number, directive_line_number)
+    });
+    TestResultKind::Fail { reason, hint }
+}
+
+/// Removes any tempfiles referred to by the test variables, if configured.
+fn cleanup_temporary_files(test_run_state: &TestRunState, config: &Config) {
+    if config.cleanup_temporary_files {
+        let tempfile_paths = test_run_state.variables().tempfile_paths();
+
+        for tempfile in tempfile_paths {
+            // Ignore errors, these are tempfiles, they go away anyway.
+            fs::remove_file(tempfile).ok();
+        }
+    }
+}
+
+/// Converts check failures into expected failures for `XFAIL` tests.
+fn apply_expected_failure(check_result: TestResultKind, test_file: &TestFile) -> TestResultKind {
     match check_result {
         TestResultKind::Fail { reason, hint } => {
             if test_file.is_expected_failure() {
@@ -92,41 +328,503 @@ fn run_test_checks(
     }
 }
 
+/// Runs a reference command and verifies the test output is identical to its output.
+fn check_same_as(
+    test_run_state: &TestRunState,
+    test_file: &TestFile,
+    reference_invocation: &Invocation,
+    config: &Config,
+) -> TestResultKind {
+    let (command, command_line) = self::build_command(reference_invocation, test_file, test_run_state.variables(), config);
+    // The reference command counts towards the time spent checking the test.
+    let (reference_output, execution_result, _) = self::collect_output(command, command_line.clone(), false,
+        test_file.front_matter.timeout, false, &mut PhaseDurations::default(), config);
+
+    if execution_result.is_erroneous() {
+        return execution_result;
+    }
+
+    let expected_output = config.normalize_test_output(test_file, &reference_output.stdout);
+    let actual_output = config.normalize_test_output(test_file, test_run_state.complete_output_stream());
+
+    if expected_output == actual_output {
+        TestResultKind::Pass
+    } else {
+        TestResultKind::Fail {
+            reason: TestFailReason::ReferenceOutputMismatch {
+                reference_command_line: command_line.0,
+                expected_output,
+                actual_output,
+            },
+            hint: None,
+        }
+    }
+}
+
+/// The most bytes of a golden file line shown when the output differs from it.
+const MAX_GOLDEN_LINE_BYTES: u64 = 4096;
+
+/// Verifies that the output is identical to the contents of a golden file.
+///
+/// The golden file is streamed in chunks rather than read into memory, so that large
+/// reference outputs can be compared without holding a second copy of them. Output
+/// normalizers apply to the output of the test, but not to the golden file.
+fn check_golden(test_run_state: &TestRunState, test_file: &TestFile, path: &str, config: &Config) -> TestResultKind {
+    let golden_path = test_file.path.absolute.parent().map(|dir| dir.join(path)).unwrap_or_else(|| path.into());
+    let error = |e: io::Error| TestResultKind::Error { message: format!("could not read golden file '{}': {}", path, e) };
+    let mut golden = match fs::File::open(&golden_path) {
+        Ok(file) => io::BufReader::new(file),
+        Err(e) => return error(e),
+    };
+    let actual = config.normalize_test_output(test_file, test_run_state.complete_output_stream());
+    let actual = actual.as_bytes();
+
+    // Find the offset of the first byte that differs, if any.
+    let mut offset = 0;
+    let mismatch_offset = loop {
+        let chunk = match golden.fill_buf() {
+            Ok(chunk) => chunk,
+            Err(e) => return error(e),
+        };
+        if chunk.is_empty() {
+            break if offset == actual.len() { None } else { Some(offset) };
+        }
+
+        let remaining = &actual[offset..];
+        let compared_len = chunk.len().min(remaining.len());
+        if let Some(i) = (0..compared_len).find(|&i| chunk[i] != remaining[i]) {
+            break Some(offset + i);
+        }
+        if compared_len < chunk.len() {
+            break Some(actual.len());
+        }
+
+        offset += compared_len;
+        golden.consume(compared_len);
+    };
+
+    let mismatch_offset = match mismatch_offset {
+        Some(mismatch_offset) => mismatch_offset,
+        None => return TestResultKind::Pass,
+    };
+
+    // Everything before the mismatch is shared, so only the rest of the golden line needs reading.
+    let line_start = actual[..mismatch_offset].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let consumed = mismatch_offset - offset;
+    golden.consume(consumed);
+    let mut expected_rest = Vec::new();
+    if let Err(e) = (&mut golden).take(MAX_GOLDEN_LINE_BYTES).read_until(b'\n', &mut expected_rest) {
+        return error(e);
+    }
+
+    let line = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim_end_matches(['\r', '\n']).to_owned();
+    let prefix = &actual[line_start..mismatch_offset];
+    let expected_line = match (prefix.is_empty(), expected_rest.is_empty()) {
+        (true, true) => None,
+        _ => Some(line(&[prefix, &expected_rest].concat())),
+    };
+    let actual_line = match actual[line_start..].split(|&b| b == b'\n').next() {
+        Some(bytes) if line_start < actual.len() => Some(line(bytes)),
+        _ => None,
+    };
+
+    TestResultKind::Fail {
+        reason: TestFailReason::GoldenFileMismatch {
+            path: path.to_owned(),
+            line_number: actual[..line_start].iter().filter(|&&b| b == b'\n').count() + 1,
+            expected_line,
+            actual_line,
+        },
+        hint: None,
+    }
+}
+
+/// Verifies that a value in the JSON output of a program has the expected value.
+///
+/// The key order of objects does not matter, and numbers are compared by value.
+fn check_json(test_run_state: &TestRunState, test_file: &TestFile, json_check: &JsonCheck, config: &Config) -> TestResultKind {
+    let fail = |reason| TestResultKind::Fail { reason, hint: None };
+    let output = config.normalize_test_output(test_file, test_run_state.complete_output_stream());
+
+    let document = match json::Value::parse(output.trim()) {
+        Ok(document) => document,
+        Err(error) => return fail(TestFailReason::InvalidJsonOutput { error, output }),
+    };
+    // Both were validated when the directive was parsed.
+    let (path, _) = json::path_prefix(&json_check.path).unwrap();
+    let expected = json::Value::parse(&json_check.expected).unwrap();
+
+    match document.at(&path) {
+        Some(actual) if actual.equals_ignoring_key_order(&expected) => TestResultKind::Pass,
+        actual => fail(TestFailReason::JsonMismatch {
+            path: json_check.path.clone(),
+            expected: json_check.expected.clone(),
+            actual: actual.map(ToString::to_string),
+        }),
+    }
+}
+
 fn collect_output(
-    mut command: process::Command,
+    command: process::Command,
     command_line: CommandLine,
+    negated: bool,
+    timeout: Option<Duration>,
+    use_pty: bool,
+    phase_durations: &mut PhaseDurations,
     config: &Config,
-) -> (ProgramOutput, TestResultKind) {
+) -> (ProgramOutput, TestResultKind, Option<ResourceUsage>) {
     let mut test_result_kind = TestResultKind::Pass;
 
-    let output = match command.output() {
+    let output = match (config.max_output_bytes, timeout) {
+        (None, None) if !config.forward_output && !use_pty => {
+            self::output(command, phase_durations, config).map(|(output, resource_usage)| (output, None, resource_usage))
+        },
+        (max_output_bytes, timeout) => self::output_with_limits(command, max_output_bytes, timeout, use_pty, phase_durations, config),
+    };
+    let (output, exceeded_limit, resource_usage) = match output {
         Ok(o) => o,
         Err(e) => {
-            let error_message = match e.kind() {
-                std::io::ErrorKind::NotFound => format!("shell '{}' does not exist", &config.shell).into(),
-                _ => e.to_string(),
+            return (ProgramOutput::empty(), self::spawn_error(e, config), None);
+        },
+    };
+
+    let program_output = ProgramOutput {
+        stdout: Arc::from(String::from_utf8_lossy(&output.stdout)),
+        stderr: Arc::from(String::from_utf8_lossy(&output.stderr)),
+    };
+
+    match exceeded_limit {
+        Some(ExceededLimit::OutputBytes(limit)) => {
+            let reason = TestFailReason::OutputLimitExceeded { program_command_line: command_line.0, limit };
+            return (program_output, TestResultKind::Fail { reason, hint: None }, resource_usage);
+        },
+        Some(ExceededLimit::Time { timeout, killed }) => {
+            let reason = TestFailReason::TimedOut { program_command_line: command_line.0, timeout, killed };
+            return (program_output, TestResultKind::Fail { reason, hint: None }, resource_usage);
+        },
+        None => (),
+    }
+
+    // Like LLVM's 'not' tool, negated commands that crash still fail.
+    let crashed = output.status.code().is_none();
+    match (output.status.success(), negated) {
+        (false, false) => test_result_kind = self::unsuccessful_execution(output.status, command_line),
+        (false, true) if crashed => test_result_kind = self::unsuccessful_execution(output.status, command_line),
+        (true, true) => {
+            test_result_kind = TestResultKind::Fail {
+                reason: TestFailReason::UnexpectedSuccessfulExecution { program_command_line: command_line.0 },
+                hint: None,
             };
+        },
+        _ => (),
+    }
+
+    (program_output, test_result_kind, resource_usage)
+}
+
+/// A limit that a program was killed for exceeding.
+enum ExceededLimit {
+    OutputBytes(usize),
+    /// The program ran for longer than the timeout, and was killed if it did not exit when asked to.
+    Time { timeout: Duration, killed: bool },
+}
+
+/// Runs a program to completion, like `Command::output`, recording how long it took to start and to exit.
+fn output(mut command: process::Command,
+          phase_durations: &mut PhaseDurations,
+          config: &Config) -> std::io::Result<(process::Output, Option<ResourceUsage>)> {
+    self::isolate_streams(&mut command, config);
+
+    let spawn_start_time = Instant::now();
+    let mut child = command.spawn()?;
+    let wait_start_time = Instant::now();
+    phase_durations.spawn = wait_start_time - spawn_start_time;
+
+    let mut stderr = child.stderr.take().unwrap();
+    let stderr_reader = thread::spawn(move || {
+        let mut bytes = Vec::new();
+        stderr.read_to_end(&mut bytes).map(|_| bytes)
+    });
+    let mut stdout = Vec::new();
+    child.stdout.take().unwrap().read_to_end(&mut stdout)?;
+    let stderr = stderr_reader.join().unwrap()?;
 
-            return (ProgramOutput::empty(), TestResultKind::Error { message: error_message });
+    let (status, resource_usage) = self::wait(&mut child)?;
+    phase_durations.wait = wait_start_time.elapsed();
+    Ok((process::Output { status, stdout, stderr }, resource_usage))
+}
+
+/// Runs a program, killing it if it writes more than `max_output_bytes` to stdout or stderr,
+/// or runs for longer than `timeout`.
+///
+/// With `use_pty`, the program is attached to a pseudo-terminal, and everything it writes
+/// is treated as stdout.
+///
+/// Also returns the limit that was exceeded, if any, in which case the output is cut off at the limit.
+fn output_with_limits(mut command: process::Command,
+                      max_output_bytes: Option<usize>,
+                      timeout: Option<Duration>,
+                      use_pty: bool,
+                      phase_durations: &mut PhaseDurations,
+                      config: &Config) -> std::io::Result<(process::Output, Option<ExceededLimit>, Option<ResourceUsage>)> {
+    self::isolate_streams(&mut command, config);
+    let terminal = match use_pty {
+        true => Some(pty::attach(&mut command)?),
+        false => {
+            self::use_own_process_group(&mut command);
+            None
         },
     };
 
-    let program_output = ProgramOutput {
-        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
-        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    let spawn_start_time = Instant::now();
+    let deadline = timeout.map(|timeout| spawn_start_time + timeout);
+    let mut child = command.spawn()?;
+    let wait_start_time = Instant::now();
+    phase_durations.spawn = wait_start_time - spawn_start_time;
+    // Otherwise the terminal stays open after the program exits.
+    drop(command);
+
+    let (sender, receiver) = mpsc::channel();
+    let read_limited = |mut stream: Box<dyn Read + Send>, sender: mpsc::Sender<bool>| thread::spawn(move || {
+        let limit = max_output_bytes.unwrap_or(usize::MAX);
+        let mut bytes = Vec::new();
+        let exceeded = match stream.by_ref().take((limit as u64).saturating_add(1)).read_to_end(&mut bytes) {
+            Ok(..) => bytes.len() > limit,
+            Err(..) => false,
+        };
+        bytes.truncate(limit);
+
+        sender.send(exceeded).ok();
+        bytes
+    });
+
+    let (stdout, stderr): (Box<dyn Read + Send>, Box<dyn Read + Send>) = match terminal {
+        Some(terminal) => (Box::new(terminal), Box::new(io::empty())),
+        None => (Box::new(child.stdout.take().unwrap()), Box::new(child.stderr.take().unwrap())),
+    };
+    let stdout_reader = read_limited(self::forwarded(stdout, Stream::Stdout, config), sender.clone());
+    let stderr_reader = read_limited(self::forwarded(stderr, Stream::Stderr, config), sender);
+
+    // Wait for both streams to finish, for either of them to exceed the limit, or for the deadline.
+    let mut exceeded_limit = None;
+    let mut finished_stream_count = 0;
+    while finished_stream_count < 2 && exceeded_limit.is_none() {
+        let received = match deadline {
+            Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => receiver.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(true) => exceeded_limit = max_output_bytes.map(ExceededLimit::OutputBytes),
+            Ok(false) => finished_stream_count += 1,
+            Err(mpsc::RecvTimeoutError::Timeout) => exceeded_limit = timeout.map(|timeout| ExceededLimit::Time { timeout, killed: false }),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    // The program may close its streams without exiting.
+    let mut exited = None;
+    if let (None, Some(deadline)) = (&exceeded_limit, deadline) {
+        loop {
+            exited = self::try_wait(&mut child)?;
+            if exited.is_some() { break; }
+
+            if Instant::now() >= deadline {
+                exceeded_limit = timeout.map(|timeout| ExceededLimit::Time { timeout, killed: false });
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    if exceeded_limit.is_some() {
+        let (terminated, killed) = self::terminate_process_group(&mut child, config.termination_grace_period)?;
+        exited = Some(terminated);
+
+        if let Some(ExceededLimit::Time { killed: ref mut killed_for_timeout, .. }) = exceeded_limit {
+            *killed_for_timeout = killed;
+        }
+    }
+
+    let (status, resource_usage) = match exited {
+        Some(exited) => exited,
+        None => self::wait(&mut child)?,
     };
+    let stdout = stdout_reader.join().unwrap_or_default();
+    let stderr = stderr_reader.join().unwrap_or_default();
+    phase_durations.wait = wait_start_time.elapsed();
+
+    Ok((process::Output { status, stdout, stderr }, exceeded_limit, resource_usage))
+}
 
-    if !output.status.success() {
-        test_result_kind = TestResultKind::Fail {
-            reason: TestFailReason::UnsuccessfulExecution {
-                exit_status: output.status.code().unwrap_or_else(|| if output.status.success() { 0 } else { 1 }),
-                program_command_line: command_line.0,
+/// Waits for a program to exit, also getting the resources it used where the platform reports them.
+///
+/// Once this has been called, the `Child` must no longer be waited for or killed, as it
+/// does not know that the program has exited.
+fn wait(child: &mut process::Child) -> std::io::Result<(process::ExitStatus, Option<ResourceUsage>)> {
+    #[cfg(unix)]
+    return self::wait4(child, 0).map(|exited| exited.expect("a blocking wait returned early"));
+
+    #[cfg(not(unix))]
+    child.wait().map(|status| (status, None))
+}
+
+/// Checks if a program has exited without blocking, like `Child::try_wait`, also getting
+/// the resources it used if it has.
+fn try_wait(child: &mut process::Child) -> std::io::Result<Option<(process::ExitStatus, Option<ResourceUsage>)>> {
+    #[cfg(unix)]
+    return self::wait4(child, libc::WNOHANG);
+
+    #[cfg(not(unix))]
+    child.try_wait().map(|status| status.map(|status| (status, None)))
+}
+
+#[cfg(unix)]
+fn wait4(child: &mut process::Child, options: libc::c_int) -> std::io::Result<Option<(process::ExitStatus, Option<ResourceUsage>)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let time = |time: libc::timeval| Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64);
+    let mut status = 0;
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+
+    loop {
+        match unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, options, &mut usage) } {
+            0 => return Ok(None),
+            pid if pid > 0 => break,
+            _ => match std::io::Error::last_os_error() {
+                e if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                e => return Err(e),
             },
-            hint: None,
+        }
+    }
+
+    // Peak memory is reported in kilobytes, except on macOS, where it is in bytes.
+    let max_rss_bytes = if cfg!(target_os = "macos") { usage.ru_maxrss as u64 } else { usage.ru_maxrss as u64 * 1024 };
+    let resource_usage = ResourceUsage { user_time: time(usage.ru_utime), system_time: time(usage.ru_stime), max_rss_bytes };
+
+    Ok(Some((process::ExitStatus::from_raw(status), Some(resource_usage))))
+}
+
+/// Captures the stdout and stderr of a program under test, and keeps it away from the stdin of lit
+/// unless `Config::forward_output` is set.
+fn isolate_streams(command: &mut process::Command, config: &Config) {
+    let stdin = if config.forward_output { process::Stdio::inherit() } else { process::Stdio::null() };
+    command.stdin(stdin).stdout(process::Stdio::piped()).stderr(process::Stdio::piped());
+}
+
+/// A standard stream of lit.
+#[derive(Copy, Clone)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Copies everything read from an output stream of a program to a stream of lit.
+struct Forwarded<R> {
+    stream: R,
+    to: Stream,
+}
+
+impl<R: Read> Read for Forwarded<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let count = self.stream.read(buffer)?;
+
+        // Failing to forward output should not affect the test.
+        let _ = match self.to {
+            Stream::Stdout => io::stdout().write_all(&buffer[..count]),
+            Stream::Stderr => io::stderr().write_all(&buffer[..count]),
         };
+        Ok(count)
+    }
+}
+
+/// Reads an output stream of a program, forwarding it to the same stream of lit if
+/// `Config::forward_output` is set.
+fn forwarded(stream: impl Read + Send + 'static, to: Stream, config: &Config) -> Box<dyn Read + Send> {
+    match config.forward_output {
+        true => Box::new(Forwarded { stream, to }),
+        false => Box::new(stream),
+    }
+}
+
+/// Runs the program in its own process group, so that any processes it spawns
+/// can be killed along with it. Otherwise they could hold the output pipes open.
+fn use_own_process_group(command: &mut process::Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+/// Kills a program along with all of the processes it has spawned.
+fn kill_process_group(child: &mut process::Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+
+    #[cfg(not(unix))]
+    {
+        child.kill().ok();
     }
+}
+
+/// Asks a program, along with all of the processes it has spawned, to terminate, and kills
+/// them if the program has not exited once the grace period is over.
+///
+/// Returns how the program exited, and whether it had to be killed. Processes it spawned that
+/// are still running when it exits are killed.
+fn terminate_process_group(child: &mut process::Child, grace_period: Duration)
+    -> std::io::Result<((process::ExitStatus, Option<ResourceUsage>), bool)> {
+    #[cfg(unix)]
+    {
+        if grace_period > Duration::from_secs(0) {
+            let deadline = Instant::now() + grace_period;
+            unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGTERM); }
 
-    (program_output, test_result_kind)
+            while Instant::now() < deadline {
+                if let Some(exited) = self::try_wait(child)? {
+                    unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL); }
+                    return Ok((exited, false));
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = grace_period;
+
+    self::kill_process_group(child);
+    Ok((self::wait(child)?, true))
+}
+
+/// Builds the test result for a program that could not be spawned.
+fn spawn_error(e: std::io::Error, config: &Config) -> TestResultKind {
+    let error_message = match e.kind() {
+        std::io::ErrorKind::NotFound => format!("shell '{}' does not exist", &config.shell),
+        _ => match config.run_as {
+            Some(ref run_as) => format!("could not run the command as {}: {}", run_as.description(), e),
+            None => e.to_string(),
+        },
+    };
+
+    TestResultKind::Error { message: error_message }
+}
+
+/// Builds the test result for an unsuccessful program execution.
+fn unsuccessful_execution(status: process::ExitStatus, command_line: CommandLine) -> TestResultKind {
+    TestResultKind::Fail {
+        reason: TestFailReason::UnsuccessfulExecution {
+            exit_status: status.code().unwrap_or_else(|| if status.success() { 0 } else { 1 }),
+            program_command_line: command_line.0,
+        },
+        hint: None,
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -135,14 +833,17 @@ pub struct CommandLine(pub String);
 /// Builds a command that can be used to execute the process behind a `RUN` directive.
 fn build_command(invocation: &Invocation,
                  test_file: &TestFile,
+                 variables: &HashMap<String, String>,
                  config: &Config) -> (process::Command, CommandLine) {
-    let mut variables = config.constants.clone();
-    variables.extend(test_file.variables());
+    let mut variables = variables.clone();
 
     let command_line: String = vars::resolve::invocation(invocation, &config, &mut variables);
+    let command_line = config.command_line_rewriters.iter().fold(command_line, |command_line, rewriter| (rewriter.0)(&command_line));
 
-    let mut cmd = process::Command::new(&config.shell);
-    cmd.args(&["-c", &command_line]);
+    let mut cmd = config.executor.command(&command_line, config);
+    if let Some(ref run_as) = config.run_as {
+        cmd = run_as.apply(cmd);
+    }
 
     if !config.extra_executable_search_paths.is_empty() {
         let os_path_separator = if cfg!(windows) { ";" } else { ":" };
@@ -153,12 +854,258 @@ fn build_command(invocation: &Invocation,
 
         cmd.env("PATH", os_path_to_inject);
     }
+    if let Some(ref temp_root) = config.temp_root {
+        cmd.env(if cfg!(windows) { "TEMP" } else { "TMPDIR" }, temp_root);
+    }
+    crate::run::nesting::mark(&mut cmd, test_file, config);
+    cmd.envs(&test_file.front_matter.env);
 
     (cmd, CommandLine(command_line))
 }
 
+/// Gets the environment a command will be executed with.
+fn effective_environment(command: &process::Command) -> Variables {
+    let mut environment: Variables = env::vars_os()
+        .map(|(name, value)| (name.to_string_lossy().into_owned(), value.to_string_lossy().into_owned()))
+        .collect();
+
+    for (name, value) in command.get_envs() {
+        let name = name.to_string_lossy().into_owned();
+        match value {
+            Some(value) => { environment.insert(name, value.to_string_lossy().into_owned()); },
+            None => { environment.remove(&name); },
+        }
+    }
+
+    environment
+}
+
 impl std::fmt::Display for CommandLine {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         self.0.fmt(fmt)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{model::*, run::execute_test_file};
+
+    #[test]
+    fn command_line_rewriters_apply_in_order() {
+        let mut config = Config { shell: "sh".to_owned(), ..Config::default() };
+        config.add_command_line_rewriter(|command_line| format!("{} world", command_line));
+        config.add_command_line_rewriter(|command_line| command_line.replace("echo", "echo hello"));
+
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/rewritten.txt", "rewritten.txt"))
+            .with_command(CommandKind::Run(Invocation::new("echo")))
+            .with_command(CommandKind::Check(TextPattern::literal("hello world")));
+
+        let result = execute_test_file(&test_file, &config);
+        assert_eq!(result.overall_result, TestResultKind::Pass);
+        assert_eq!(result.individual_run_results[0].command_line.0, "echo hello world");
+    }
+
+    #[test]
+    fn seeds_are_substituted() {
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/seeded.txt", "seeded.txt"))
+            .with_command(CommandKind::Run(Invocation::new("echo seed @seed")))
+            .with_command(CommandKind::Check(TextPattern::literal("seed 1234")));
+
+        let config = Config { shell: "sh".to_owned(), seed: Some(1234), ..Config::default() };
+        assert_eq!(execute_test_file(&test_file, &config).overall_result, TestResultKind::Pass);
+    }
+
+    #[test]
+    fn programs_under_test_do_not_read_the_stdin_of_lit() {
+        let mut test_file = TestFile::new(TestFilePath::new("/nonexistent/reads-stdin.txt", "reads-stdin.txt"))
+            .with_command(CommandKind::Run(Invocation::new("cat; echo done")))
+            .with_command(CommandKind::Check(TextPattern::literal("done")));
+        test_file.front_matter.timeout = Some(std::time::Duration::from_secs(5));
+
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        assert_eq!(execute_test_file(&test_file, &config).overall_result, TestResultKind::Pass);
+    }
+
+    #[test]
+    fn commands_writing_to_stderr_fail_if_so_configured() {
+        let config = Config { shell: "sh".to_owned(), fail_on_stderr: true, ..Config::default() };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/warning.txt", "warning.txt"))
+            .with_command(CommandKind::Run(Invocation::new("echo done; echo 'warning: unused' >&2")))
+            .with_command(CommandKind::Check(TextPattern::literal("done")));
+
+        let result = execute_test_file(&test_file, &config);
+        assert!(matches!(result.overall_result, TestResultKind::Fail { reason: TestFailReason::StderrWritten { ref stderr, .. }, .. } if stderr == "warning: unused\n"));
+        assert_eq!(execute_test_file(&test_file.clone().with_command(CommandKind::AllowStderr), &config).overall_result, TestResultKind::Pass);
+        assert_eq!(execute_test_file(&test_file, &Config { fail_on_stderr: false, ..config.clone() }).overall_result, TestResultKind::Pass);
+
+        let negated_test_file = TestFile::new(TestFilePath::new("/nonexistent/error.txt", "error.txt"))
+            .with_command(CommandKind::Run(Invocation::new("echo 'error: bad input' >&2; exit 1").negated()));
+        assert_eq!(execute_test_file(&negated_test_file, &config).overall_result, TestResultKind::Pass);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn resource_usage_is_reported_for_every_run() {
+        let mut config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/busy.txt", "busy.txt"))
+            .with_command(CommandKind::Run(Invocation::new("i=0; while [ $i -lt 20000 ]; do i=$((i + 1)); done; echo $i")))
+            .with_command(CommandKind::Check(TextPattern::literal("20000")));
+
+        let usage = execute_test_file(&test_file, &config).individual_run_results[0].resource_usage.unwrap();
+        assert!(usage.user_time + usage.system_time > std::time::Duration::from_secs(0));
+        assert!(usage.max_rss_bytes > 0);
+
+        config.early_exit_on_check_completion = true;
+        assert!(execute_test_file(&test_file, &config).individual_run_results[0].resource_usage.is_some());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn commands_that_time_out_are_asked_to_terminate_before_they_are_killed() {
+        let config = Config { shell: "sh".to_owned(), termination_grace_period: std::time::Duration::from_millis(500), ..Config::default() };
+        let timed_out = |command_line: &str| {
+            let mut test_file = TestFile::new(TestFilePath::new("/nonexistent/slow.txt", "slow.txt"))
+                .with_command(CommandKind::Run(Invocation::new(command_line)));
+            test_file.front_matter.timeout = Some(std::time::Duration::from_millis(200));
+
+            let result = execute_test_file(&test_file, &config);
+            match result.overall_result {
+                TestResultKind::Fail { reason: TestFailReason::TimedOut { killed, .. }, .. } => (killed, result.individual_run_results[0].output.stdout.to_string()),
+                ref other => panic!("expected the test to time out, got {:?}", other),
+            }
+        };
+
+        assert_eq!(timed_out("trap 'echo cleaned up; exit 1' TERM; sleep 10; :"), (false, "cleaned up\n".to_owned()));
+        assert_eq!(timed_out("trap '' TERM; sleep 10"), (true, String::new()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn commands_writing_too_much_output_are_killed() {
+        let config = Config { shell: "sh".to_owned(), max_output_bytes: Some(100), ..Config::default() };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/noisy.txt", "noisy.txt"))
+            .with_command(CommandKind::Run(Invocation::new("yes")));
+
+        let result = execute_test_file(&test_file, &config);
+        match result.overall_result {
+            TestResultKind::Fail { reason: TestFailReason::OutputLimitExceeded { limit, .. }, .. } => assert_eq!(limit, 100),
+            ref other => panic!("expected the output limit to be exceeded, got {:?}", other),
+        }
+        assert_eq!(result.individual_run_results[0].output.stdout.len(), 100);
+
+        let quiet_test_file = TestFile::new(TestFilePath::new("/nonexistent/quiet.txt", "quiet.txt"))
+            .with_command(CommandKind::Run(Invocation::new("echo hello")))
+            .with_command(CommandKind::Check(TextPattern::literal("hello")));
+        assert_eq!(execute_test_file(&quiet_test_file, &config).overall_result, TestResultKind::Pass);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn durations_and_memory_usage_of_commands_are_checked() {
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let check = |directives: &str| {
+            let test_file = crate::parse::test_file(TestFilePath::new("/nonexistent/perf.txt", "perf.txt"), &format!("RUN: sleep 0.2\n{}\n", directives)).unwrap();
+            execute_test_file(&test_file, &config).overall_result
+        };
+
+        assert_eq!(check("EXPECT-DURATION-UNDER: 10s\nEXPECT-RSS-UNDER: 1GB"), TestResultKind::Pass);
+        match check("EXPECT-DURATION-UNDER: 100ms") {
+            TestResultKind::Fail { reason: TestFailReason::DurationExceeded { limit, duration, .. }, .. } => {
+                assert_eq!(limit, std::time::Duration::from_millis(100));
+                assert!(duration >= std::time::Duration::from_millis(200));
+            },
+            result => panic!("expected the duration to be exceeded, but got {:?}", result),
+        }
+        assert!(matches!(check("EXPECT-RSS-UNDER: 1KB"), TestResultKind::Fail { reason: TestFailReason::RssExceeded { limit_bytes: 1000, .. }, .. }));
+    }
+
+    #[test]
+    fn front_matter_timeouts_kill_commands_and_requirements_skip_tests() {
+        let mut config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let mut test_file = TestFile::new(TestFilePath::new("/nonexistent/slow.txt", "slow.txt"))
+            .with_command(CommandKind::Run(Invocation::new("sleep 10")));
+        test_file.front_matter.timeout = Some(std::time::Duration::from_secs(1));
+
+        let start_time = std::time::Instant::now();
+        match execute_test_file(&test_file, &config).overall_result {
+            TestResultKind::Fail { reason: TestFailReason::TimedOut { .. }, .. } => (),
+            ref other => panic!("expected the test to time out, got {:?}", other),
+        }
+        assert!(start_time.elapsed() < std::time::Duration::from_secs(5));
+
+        test_file.front_matter.requires = vec!["time-travel".to_owned()];
+        let result = execute_test_file(&test_file, &config);
+        assert_eq!(result.overall_result, TestResultKind::Skip);
+        assert_eq!(result.individual_run_results[0].warnings, vec!["skipped, as the required features 'time-travel' are not available"]);
+
+        config.available_features.push("time-travel".to_owned());
+        assert!(execute_test_file(&test_file, &config).overall_result.is_erroneous());
+    }
+
+    #[test]
+    fn golden_files_are_compared_line_by_line() {
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(directory.path().join("expected.txt"), "alpha\nbeta\ngamma\n").unwrap();
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let run = |output: &str, golden: &str| {
+            let test_file = TestFile::new(TestFilePath::new(directory.path().join("golden.txt"), "golden.txt"))
+                .with_command(CommandKind::Run(Invocation::new(&format!("printf '{}'", output))))
+                .with_command(CommandKind::CheckGolden(golden.to_owned()));
+            execute_test_file(&test_file, &config).overall_result
+        };
+        let mismatch = |line_number, expected_line: Option<&str>, actual_line: Option<&str>| TestResultKind::Fail {
+            reason: TestFailReason::GoldenFileMismatch {
+                path: "expected.txt".to_owned(),
+                line_number,
+                expected_line: expected_line.map(ToOwned::to_owned),
+                actual_line: actual_line.map(ToOwned::to_owned),
+            },
+            hint: None,
+        };
+
+        assert_eq!(run("alpha\\nbeta\\ngamma\\n", "expected.txt"), TestResultKind::Pass);
+        assert_eq!(run("alpha\\nbeat\\ngamma\\n", "expected.txt"), mismatch(2, Some("beta"), Some("beat")));
+        assert_eq!(run("alpha\\nbeta\\n", "expected.txt"), mismatch(3, Some("gamma"), None));
+        assert_eq!(run("alpha\\nbeta\\ngamma\\ndelta\\n", "expected.txt"), mismatch(4, None, Some("delta")));
+        assert!(matches!(run("alpha\\n", "missing.txt"), TestResultKind::Error { .. }));
+    }
+
+    #[test]
+    fn failed_checks_hint_at_earlier_checks_matching_directives_of_their_own_test() {
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/self.txt", "self.txt"))
+            .with_command(CommandKind::Run(Invocation::new("printf '# CHECK: a\\na\\n'")))
+            .with_command(CommandKind::Check(TextPattern::literal("a")));
+
+        let result = execute_test_file(&test_file, &config);
+        assert_eq!(result.overall_result, TestResultKind::Pass);
+        assert!(result.individual_run_results[0].warnings.is_empty(), "passing tests should not be warned about");
+
+        let failing_test_file = test_file.clone().with_command(CommandKind::Check(TextPattern::literal("b")));
+        match execute_test_file(&failing_test_file, &config).overall_result {
+            TestResultKind::Fail { hint, .. } => assert_eq!(hint.as_deref(), Some("the check on line 2 matched the directive on line 2 of the test itself, rather than the output it was meant to")),
+            result => panic!("expected failure, got {:?}", result),
+        }
+
+        let result = execute_test_file(&test_file, &Config { skip_directive_lines_in_output: true, ..config.clone() });
+        assert_eq!(result.overall_result, TestResultKind::Pass);
+        assert_eq!(result.individual_run_results[0].check_matches[0].start_line, 2);
+        assert!(result.individual_run_results[0].warnings.is_empty());
+    }
+
+    #[test]
+    fn failures_of_included_directives_name_the_file_they_were_written_in() {
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(directory.path().join("checks.txt"), "CHECK: hello\nCHECK: goodbye\n").unwrap();
+        let path = TestFilePath::new(directory.path().join("test.txt"), "test.txt");
+        let test_file = TestFile::parse(path, "RUN: echo hello\nINCLUDE: checks.txt\n").unwrap();
+
+        let result = execute_test_file(&test_file, &Config { shell: "sh".to_owned(), ..Config::default() });
+        assert_eq!(result.individual_run_results[0].directive_line_number, 2);
+        match result.overall_result {
+            TestResultKind::Fail { hint, .. } => assert_eq!(hint, Some(format!("the failing directive is on line 2 of '{}'", directory.path().join("checks.txt").display()))),
+            result => panic!("expected failure, got {:?}", result),
+        }
+    }
+}
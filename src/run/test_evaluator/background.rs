@@ -0,0 +1,138 @@
+//! Management of `RUN-BACKGROUND` processes and `WAIT-FOR` directives.
+
+use crate::{
+    model::{CommandKind, Invocation, TestFile, TextPattern},
+    vars,
+    Config, Variables,
+};
+use std::{
+    io::Read,
+    process,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The combined stdout and stderr of a background process, as it arrives.
+#[derive(Default)]
+struct SharedOutput {
+    text: Mutex<OutputState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct OutputState {
+    text: String,
+    /// The number of output streams that have been closed.
+    closed_stream_count: usize,
+}
+
+/// A process running in the background of a test.
+struct BackgroundProcess {
+    child: process::Child,
+    command_line: String,
+    output: Arc<SharedOutput>,
+}
+
+/// All background processes of a test. The processes are killed on drop.
+pub struct BackgroundProcesses {
+    processes: Vec<BackgroundProcess>,
+    /// How long the processes are given to exit when asked to at the end of the test.
+    termination_grace_period: Duration,
+}
+
+/// Spawns all `RUN-BACKGROUND` commands in a test file, waiting on any `WAIT-FOR` directives.
+///
+/// On failure, returns the line of the responsible directive along with the error.
+pub fn start(test_file: &TestFile, variables: &Variables, config: &Config) -> Result<BackgroundProcesses, (u32, String)> {
+    let mut background_processes = BackgroundProcesses { processes: Vec::new(), termination_grace_period: config.termination_grace_period };
+
+    for command in test_file.commands.iter() {
+        match command.kind {
+            CommandKind::RunBackground(ref invocation) => {
+                let process = spawn(invocation, test_file, variables, config).map_err(|e| (command.line_number, e))?;
+                background_processes.processes.push(process);
+            },
+            CommandKind::WaitFor(ref text_pattern) => {
+                match background_processes.processes.last() {
+                    Some(process) => wait_for(process, text_pattern, variables, config).map_err(|e| (command.line_number, e))?,
+                    None => return Err((command.line_number, format!("WAIT-FOR on line {} must come after a RUN-BACKGROUND directive", command.line_number))),
+                }
+            },
+            _ => (),
+        }
+    }
+
+    Ok(background_processes)
+}
+
+fn spawn(invocation: &Invocation, test_file: &TestFile, variables: &Variables, config: &Config) -> Result<BackgroundProcess, String> {
+    let (mut command, command_line) = super::build_command(invocation, test_file, variables, config);
+    command.stdin(process::Stdio::null()).stdout(process::Stdio::piped()).stderr(process::Stdio::piped());
+    super::use_own_process_group(&mut command);
+
+    let mut child = command.spawn()
+        .map_err(|e| format!("could not spawn background command '{}': {}", command_line, e))?;
+
+    let output = Arc::new(SharedOutput::default());
+    forward_stream(child.stdout.take().unwrap(), output.clone());
+    forward_stream(child.stderr.take().unwrap(), output.clone());
+
+    Ok(BackgroundProcess { child, command_line: command_line.0, output })
+}
+
+/// Continuously appends the contents of a stream to the shared output.
+fn forward_stream(mut stream: impl Read + Send + 'static, output: Arc<SharedOutput>) {
+    thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let read_byte_count = stream.read(&mut buffer).unwrap_or(0);
+            let mut state = output.text.lock().unwrap();
+
+            if read_byte_count == 0 {
+                state.closed_stream_count += 1;
+                output.changed.notify_all();
+                break;
+            }
+
+            state.text.push_str(&String::from_utf8_lossy(&buffer[..read_byte_count]));
+            output.changed.notify_all();
+        }
+    });
+}
+
+/// Blocks until the output of a background process matches a pattern.
+fn wait_for(process: &BackgroundProcess, text_pattern: &TextPattern, variables: &Variables, config: &Config) -> Result<(), String> {
+    let mut variables = variables.clone();
+    let regex = vars::resolve::text_pattern(text_pattern, config, &mut variables, &mut vars::resolve::RegexCache::new())?;
+
+    let deadline = Instant::now() + config.background_wait_timeout;
+    let mut state = process.output.text.lock().unwrap();
+
+    loop {
+        if vars::resolve::find_at(&regex, text_pattern, &state.text, 0).is_some() {
+            return Ok(());
+        }
+
+        if state.closed_stream_count == 2 {
+            return Err(format!("background command '{}' closed its output without printing '{}'", process.command_line, text_pattern));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(format!("timed out after {:?} waiting for background command '{}' to print '{}'",
+                               config.background_wait_timeout, process.command_line, text_pattern));
+        }
+
+        state = process.output.changed.wait_timeout(state, deadline - now).unwrap().0;
+    }
+}
+
+impl Drop for BackgroundProcesses {
+    fn drop(&mut self) {
+        for process in self.processes.iter_mut() {
+            super::terminate_process_group(&mut process.child, self.termination_grace_period).ok();
+        }
+    }
+}
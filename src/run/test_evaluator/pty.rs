@@ -0,0 +1,115 @@
+//! Running programs attached to a pseudo-terminal, for testing tools that behave
+//! differently in a terminal, such as by coloring their output or showing progress bars.
+
+use std::{fs::File, io, process};
+
+/// The size of the terminal that programs see.
+#[cfg(unix)]
+const COLUMNS: u16 = 80;
+#[cfg(unix)]
+const ROWS: u16 = 24;
+
+/// Attaches the stdin, stdout and stderr of a command to a new pseudo-terminal, which
+/// also becomes the controlling terminal of the program.
+///
+/// Returns the other side of the terminal, from which everything the program writes to
+/// stdout or stderr can be read. The terminal does not translate line endings, so the
+/// output has the same line endings as it would have when written to a pipe.
+///
+/// The program is started in its own session, and hence its own process group.
+#[cfg(unix)]
+pub fn attach(command: &mut process::Command) -> io::Result<File> {
+    use std::os::unix::{io::FromRawFd, process::CommandExt};
+
+    let (mut master, mut slave) = (0, 0);
+    let size = libc::winsize { ws_row: ROWS, ws_col: COLUMNS, ws_xpixel: 0, ws_ypixel: 0 };
+
+    let (master, slave) = unsafe {
+        if libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        (File::from_raw_fd(master), File::from_raw_fd(slave))
+    };
+    // `openpty` cannot open the descriptors close-on-exec, so mark them before anything else
+    // is spawned. Otherwise programs of other tests running in parallel would inherit them,
+    // and the master would never see the end of the output while those programs are alive.
+    // The copies of the slave given to the command are installed as its stdio regardless.
+    set_close_on_exec(&master)?;
+    set_close_on_exec(&slave)?;
+
+    unsafe {
+        use std::os::unix::io::AsRawFd;
+
+        let mut attributes = std::mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut attributes) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        attributes.c_oflag &= !libc::ONLCR;
+        if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &attributes) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        command.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    command.stdin(slave.try_clone()?).stdout(slave.try_clone()?).stderr(slave);
+    Ok(master)
+}
+
+#[cfg(unix)]
+fn set_close_on_exec(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn attach(_: &mut process::Command) -> io::Result<File> {
+    Err(io::Error::new(io::ErrorKind::Other, "pseudo-terminals are only supported on Unix"))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::{model::*, run::execute_test_file, Config};
+
+    #[test]
+    fn terminals_are_not_inherited_by_other_programs() {
+        use std::os::unix::io::AsRawFd;
+
+        let is_close_on_exec = |file: &File| unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFD) & libc::FD_CLOEXEC != 0 };
+        let mut command = process::Command::new("true");
+        let master = attach(&mut command).unwrap();
+
+        assert!(is_close_on_exec(&master));
+        // The program's own copies of the terminal still become its stdio.
+        assert!(command.status().unwrap().success());
+    }
+
+    #[test]
+    fn pty_directives_attach_commands_to_a_terminal() {
+        let config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let test_file = TestFile::new(TestFilePath::new("/nonexistent/terminal.txt", "terminal.txt"))
+            .with_command(CommandKind::Run(Invocation::new("if [ -t 0 ] && [ -t 1 ]; then echo terminal; else echo pipe; fi; echo oops >&2")));
+
+        let result = execute_test_file(&test_file, &config);
+        assert_eq!(&*result.individual_run_results[0].output.stdout, "pipe\n");
+
+        let result = execute_test_file(&test_file.with_command(CommandKind::Pty), &config);
+        assert_eq!(result.overall_result, TestResultKind::Pass);
+        assert_eq!(&*result.individual_run_results[0].output.stdout, "terminal\noops\n");
+        assert_eq!(&*result.individual_run_results[0].output.stderr, "");
+    }
+}
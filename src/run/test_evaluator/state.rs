@@ -3,10 +3,11 @@
 
 use crate::{
     Config, Variables,
+    matcher,
     model::{self, TestResultKind, TestFailReason, TextPattern},
     vars,
 };
-use std::collections::HashMap;
+use std::{collections::{HashMap, HashSet}, ops::Range, sync::Arc};
 use regex::Regex;
 
 /// Byte-index relative to entire stream.
@@ -17,6 +18,12 @@ struct AbsoluteByteIndex(pub usize);
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct RelativeByteIndex(pub usize);
 
+/// A match of a pattern, along with its named captures, if it was matched as a regex.
+struct FoundMatch {
+    range: Range<usize>,
+    captures: HashMap<String, String>,
+}
+
 /// The byte range of a matched pattern.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct MatchedRange {
@@ -28,42 +35,91 @@ struct MatchedRange {
 #[derive(Debug)]
 pub struct TestRunState {
     /// All output bytes emitted by the program.
-    complete_output_stream: String,
+    complete_output_stream: Arc<str>,
     /// The current position in the stream at which all prior output has been
     /// successfully checked by the test script.
     current_stream_byte_position: AbsoluteByteIndex,
     /// The stderr portion of the command output. This does not get used by `CHECK`s.
-    complete_stderr: String,
+    complete_stderr: Arc<str>,
     /// A list of available variables to the test script.
     variables: HashMap<String, String>,
+    /// Compiled regexes for patterns that depend on variables.
+    regex_cache: vars::resolve::RegexCache,
+    /// The output matched by the most recent passing check.
+    last_match: Option<model::Span>,
+    /// The output regions matched by passing checks so far.
+    check_matches: Vec<model::CheckMatch>,
+    /// Whether `CHECK` directives may match anywhere in the output, as set by `CHECK-UNORDERED`.
+    unordered_checks: bool,
+    /// The whole lines of output already matched by checks, when checks are unordered.
+    claimed_lines: Vec<model::Span>,
+    /// The variables available before any captures were made.
+    initial_variables: HashMap<String, String>,
+    /// The names of the variables shadowed by captures that were already warned about.
+    shadowed_variable_names: HashSet<String>,
+    /// Warnings raised whilst evaluating the checks.
+    warnings: Vec<String>,
 }
 
 impl TestRunState {
     pub fn new(initial_variables: HashMap<String, String>) -> Self {
         TestRunState {
-            complete_output_stream: String::new(),
+            complete_output_stream: Arc::from(""),
             current_stream_byte_position: AbsoluteByteIndex(0),
-            complete_stderr: String::new(),
-            variables: initial_variables,
+            complete_stderr: Arc::from(""),
+            variables: initial_variables.clone(),
+            regex_cache: vars::resolve::RegexCache::new(),
+            last_match: None,
+            check_matches: Vec::new(),
+            unordered_checks: false,
+            claimed_lines: Vec::new(),
+            initial_variables,
+            shadowed_variable_names: HashSet::new(),
+            warnings: Vec::new(),
         }
     }
 
     /// Appends output from the inner program.
     pub fn append_program_output(&mut self, output: &str) {
-        self.complete_output_stream.extend(output.chars())
+        self.complete_output_stream = Arc::from(format!("{}{}", self.complete_output_stream, output));
+    }
+
+    /// Sets the complete output of the inner program, sharing the
+    /// underlying buffers rather than copying them.
+    pub fn set_program_output(&mut self, output: &model::ProgramOutput) {
+        self.complete_output_stream = output.stdout.clone();
+        self.complete_stderr = output.stderr.clone();
+        self.current_stream_byte_position = AbsoluteByteIndex(0);
+    }
+
+    /// Empties the lines of the output that a predicate holds for, keeping their line endings
+    /// so that line numbers are unchanged. Checks skip over empty lines.
+    pub fn blank_lines(&mut self, is_blanked: impl Fn(&str) -> bool) {
+        let output: String = self.complete_output_stream.split_inclusive('\n').map(|line| {
+            let content = line.trim_end_matches(&['\r', '\n'][..]);
+            if is_blanked(content) { &line[content.len()..] } else { line }
+        }).collect();
+
+        self.complete_output_stream = Arc::from(output);
     }
 
-    /// Appends stderr output.
-    pub fn append_program_stderr(&mut self, stderr: &str) {
-        self.complete_stderr.extend(stderr.chars())
+    /// Sets whether `CHECK` directives may match anywhere in the output.
+    pub fn set_unordered_checks(&mut self, unordered_checks: bool) {
+        self.unordered_checks = unordered_checks;
     }
 
     /// Verifies that a text pattern appears subsequently in the stream.
+    ///
+    /// When checks are unordered, the pattern may instead appear anywhere in the stream.
     pub fn check(
         &mut self,
         text_pattern: &TextPattern,
         config: &Config) -> TestResultKind {
-        self.check_extended(text_pattern, false, config)
+        if self.unordered_checks {
+            self.check_unordered(text_pattern, config)
+        } else {
+            self.check_extended(text_pattern, false, config)
+        }
     }
 
     /// Verifies that the very-next non-whitespace line matches a text pattern.
@@ -74,15 +130,79 @@ impl TestRunState {
         self.check_extended(text_pattern, true, config)
     }
 
+    /// Skips exactly a number of lines of the stream, as done by the `CHECK-SKIP` directive.
+    ///
+    /// Fails without consuming anything if the stream has fewer lines left.
+    pub fn skip_lines(&mut self, line_count: usize) -> TestResultKind {
+        let remaining_line_count = self.unprocessed_output_stream().lines().count();
+        if remaining_line_count < line_count {
+            return TestResultKind::Fail {
+                reason: TestFailReason::SkippedPastEndOfOutput { line_count, remaining_line_count },
+                hint: None,
+            };
+        }
+
+        self.last_match = None;
+        for _ in 0..line_count {
+            self.eat_until_end_of_line();
+        }
+
+        TestResultKind::Pass
+    }
+
+    /// Verifies that a text pattern appears subsequently in the stream, and that none
+    /// of the forbidden patterns appear before it.
+    ///
+    /// Also returns the line of the forbidding directive, if one was violated.
+    pub fn check_end(
+        &mut self,
+        end_pattern: &TextPattern,
+        forbidden_patterns: &[(u32, &TextPattern)],
+        config: &Config) -> (TestResultKind, Option<u32>) {
+        let window_start = self.current_stream_byte_position.0;
+
+        let end_result = self.check_extended(end_pattern, false, config);
+        let window_end = match self.last_match {
+            Some(span) if !end_result.is_erroneous() => span.start,
+            _ => return (end_result, None),
+        };
+        let output = self.complete_output_stream.clone();
+        let window_text = &output[window_start..window_end];
+        let window_starts_line = is_line_start(&output, window_start);
+
+        for &(line_number, forbidden_pattern) in forbidden_patterns {
+            let forbidden_match = match find_at(forbidden_pattern, window_text, window_starts_line, 0, &mut self.variables, &mut self.regex_cache, config) {
+                Ok(forbidden_match) => forbidden_match,
+                Err(message) => return (TestResultKind::Error { message }, Some(line_number)),
+            };
+
+            if forbidden_match.is_some() {
+                let reason = TestFailReason::ForbiddenTextFound {
+                    forbidden_pattern: forbidden_pattern.clone(),
+                    window_end_pattern: end_pattern.clone(),
+                    window_text: window_text.to_owned(),
+                    window_start_line_number: self.complete_output_stream[..window_start].matches('\n').count() + 1,
+                };
+                return (TestResultKind::Fail { reason, hint: None }, Some(line_number));
+            }
+        }
+
+        (TestResultKind::Pass, None)
+    }
+
     fn check_extended(
         &mut self,
         text_pattern: &TextPattern,
         require_on_next_line: bool,
         config: &Config) -> TestResultKind {
 
+        self.last_match = None;
         self.eat_whitespace();
 
-        let next_relative_matched_range = self.next_unprocessed_byte_index_of(text_pattern, config);
+        let next_relative_matched_range = match self.next_unprocessed_byte_index_of(text_pattern, config) {
+            Ok(range) => range,
+            Err(message) => return TestResultKind::Error { message },
+        };
 
         match next_relative_matched_range {
             Some(matched_range) => {
@@ -91,13 +211,17 @@ impl TestRunState {
                     match self.unprocessed_output_stream().find("\n") {
                         Some(index_of_first_new_line_byte) => {
                             if matched_range.start.0 >= index_of_first_new_line_byte {
+                                let expected = vars::resolve::substitute_variables(text_pattern, config, &mut self.variables).to_string();
+                                let next_line = &self.unprocessed_output_stream()[..index_of_first_new_line_byte];
+
                                 return TestResultKind::Fail {
                                     reason: TestFailReason::CheckFailed(model::CheckFailureInfo {
                                         complete_output_text: self.complete_output_stream.clone(),
                                         successfully_checked_until_byte_index: self.current_stream_byte_position.0,
                                         expected_pattern: text_pattern.clone(),
                                     }),
-                                    hint: Some(format!("found a match for '{}', but it does not appear on the next line, as required by the CHECK-NEXT directive", text_pattern)),
+                                    hint: Some(format!("found a match for '{}', but it does not appear on the next line, as required by the CHECK-NEXT directive\n\
+                                                        (- expected, + next line)\n{}", text_pattern, crate::diff::render(&expected, next_line))),
                                 };
                             }
                         },
@@ -105,7 +229,14 @@ impl TestRunState {
                     }
                 }
 
+                let match_start = self.current_stream_byte_position.0 + matched_range.start.0;
                 self.current_stream_byte_position += matched_range.end;
+                self.last_match = Some(model::Span { start: match_start, end: self.current_stream_byte_position.0 });
+
+                if self.unordered_checks {
+                    let line_span = line_span_of(&self.complete_output_stream, match_start, self.current_stream_byte_position.0);
+                    self.claimed_lines.push(line_span);
+                }
 
                 // No other checks should run against the partial line.
                 self.eat_until_end_of_line();
@@ -125,6 +256,97 @@ impl TestRunState {
         }
     }
 
+    /// Verifies that a text pattern appears anywhere in the stream, on lines not
+    /// already matched by another check.
+    fn check_unordered(
+        &mut self,
+        text_pattern: &TextPattern,
+        config: &Config) -> TestResultKind {
+        self.last_match = None;
+
+        let output = self.complete_output_stream.clone();
+
+        let mut search_start = 0;
+        let mut found_claimed_match = false;
+        loop {
+            let FoundMatch { range: matched_range, captures: new_variables } = match find_at(text_pattern, &output, true, search_start, &mut self.variables, &mut self.regex_cache, config) {
+                Ok(Some(found)) => found,
+                Ok(None) => break,
+                Err(message) => return TestResultKind::Error { message },
+            };
+            let line_span = line_span_of(&output, matched_range.start, matched_range.end);
+
+            if self.claimed_lines.iter().any(|claimed| claimed.start < line_span.end && line_span.start < claimed.end) {
+                found_claimed_match = true;
+                search_start = line_span.end;
+
+                if search_start >= output.len() { break; }
+                continue;
+            }
+
+            if let Err(message) = self.bind_captures(new_variables, text_pattern) {
+                return TestResultKind::Error { message };
+            }
+            self.claimed_lines.push(line_span);
+            self.last_match = Some(model::Span { start: matched_range.start, end: matched_range.end });
+            // Any following CHECK-NEXT is relative to the matched line.
+            self.current_stream_byte_position = AbsoluteByteIndex(line_span.end);
+
+            return TestResultKind::Pass;
+        }
+
+        TestResultKind::Fail {
+            reason: TestFailReason::CheckFailed(model::CheckFailureInfo {
+                complete_output_text: self.complete_output_stream.clone(),
+                successfully_checked_until_byte_index: 0,
+                expected_pattern: text_pattern.clone(),
+            }),
+            hint: if found_claimed_match {
+                Some(format!("found a match for '{}', but only on lines already matched by other checks", text_pattern))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Clears a captured variable, as done by the `UNDEF` directive.
+    pub fn undefine(&mut self, name: &str) {
+        vars::resolve::undefine(name, &mut self.variables, &self.initial_variables);
+    }
+
+    /// Gets the warnings raised whilst evaluating the checks.
+    pub fn warnings(&self) -> &[String] { &self.warnings }
+
+    /// Raises a warning about a check that could not be evaluated as intended.
+    pub fn warn(&mut self, warning: String) {
+        if !self.warnings.contains(&warning) { self.warnings.push(warning); }
+    }
+
+    fn bind_captures(&mut self, captures: HashMap<String, String>, text_pattern: &TextPattern) -> Result<(), String> {
+        for name in vars::resolve::bind_captures(captures, &mut self.variables, &self.initial_variables)? {
+            if self.shadowed_variable_names.insert(name.clone()) {
+                self.warnings.push(format!("named capture '{}' in pattern '{}' shadows a variable of the same name", name, text_pattern));
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the output matched by the most recent check, which passed, against its directive.
+    ///
+    /// Checks that matched nothing but an empty string, such as `.*` on its own, verify nothing
+    /// and raise a warning.
+    pub fn record_match(&mut self, directive_line_number: u32) {
+        if let Some(span) = self.last_match.take() {
+            if span.start == span.end {
+                self.warn(format!("the check on line {} matched an empty string, so it verifies nothing", directive_line_number));
+            }
+            self.check_matches.push(model::CheckMatch::new(directive_line_number, &self.complete_output_stream, span));
+        }
+    }
+
+    /// Gets the output regions matched by the recorded checks.
+    pub fn check_matches(&self) -> &[model::CheckMatch] { &self.check_matches }
+
     pub fn unprocessed_output_bytes(&self) -> &[u8] {
         &self.complete_output_stream.as_bytes()[self.current_stream_byte_position.0..]
     }
@@ -134,6 +356,17 @@ impl TestRunState {
         convert_bytes_to_str(self.unprocessed_output_bytes())
     }
 
+    /// Gets the complete output of the program.
+    pub fn program_output(&self) -> model::ProgramOutput {
+        model::ProgramOutput { stdout: self.complete_output_stream.clone(), stderr: self.complete_stderr.clone() }
+    }
+
+    /// Gets the complete output stream, including already checked text.
+    pub fn complete_output_stream(&self) -> &str { &self.complete_output_stream }
+
+    /// Gets the test-local regex cache.
+    pub fn regex_cache(&self) -> &vars::resolve::RegexCache { &self.regex_cache }
+
     /// Gets all variables in scope.
     pub fn variables(&self) -> &Variables { &self.variables }
 
@@ -167,25 +400,23 @@ impl TestRunState {
     /// N.B. Does not advance the unprocessed stream pointer. This only takes a mutable
     /// reference because of the need to resolve the internal test variable list.
     fn next_unprocessed_byte_index_of(&mut self, text_pattern: &TextPattern, config: &Config)
-        -> Option<MatchedRange> {
-        let regex = vars::resolve::text_pattern(text_pattern, config, &mut self.variables);
-        let output_str = self.unprocessed_output_stream();
-
-        debug!("converting expected text pattern to regex: {:?}", regex);
+        -> Result<Option<MatchedRange>, String> {
+        let output = self.complete_output_stream.clone();
+        let output_str = &output[self.current_stream_byte_position.0..];
+        let starts_line = is_line_start(&output, self.current_stream_byte_position.0);
 
-        match regex.find(output_str) {
-            Some(regex_match) => {
+        match find_at(text_pattern, output_str, starts_line, 0, &mut self.variables, &mut self.regex_cache, config)? {
+            Some(found_match) => {
                 let matched_range = MatchedRange {
-                    start: RelativeByteIndex(regex_match.start()),
-                    end: RelativeByteIndex(regex_match.end()),
+                    start: RelativeByteIndex(found_match.range.start),
+                    end: RelativeByteIndex(found_match.range.end),
                 };
 
-                let new_variables = process_captures(&regex, regex_match.as_str());
-                self.variables.extend(new_variables);
+                self.bind_captures(found_match.captures, text_pattern)?;
 
-                Some(matched_range)
+                Ok(Some(matched_range))
             },
-            None => None,
+            None => Ok(None),
         }
     }
 
@@ -201,6 +432,91 @@ impl std::ops::AddAssign<RelativeByteIndex> for AbsoluteByteIndex {
     }
 }
 
+/// Finds the first match of a pattern in a piece of text, starting at a byte index, with the
+/// engine that the pattern selects. Also returns the named captures of regexes.
+///
+/// Anchored patterns only match at the start of a line, where `starts_line` tells whether the
+/// text itself starts a line of the output.
+fn find_at(
+    text_pattern: &TextPattern,
+    text: &str,
+    starts_line: bool,
+    start: usize,
+    variables: &mut Variables,
+    regex_cache: &mut vars::resolve::RegexCache,
+    config: &Config) -> Result<Option<FoundMatch>, String> {
+    let anchored = text_pattern.anchored || config.anchor_checks_to_line_start;
+
+    let mut search_start = start;
+    loop {
+        let found = match find_anywhere_at(text_pattern, text, search_start, variables, regex_cache, config)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let at_line_start = match found.range.start {
+            0 => starts_line,
+            match_start => is_line_start(text, match_start),
+        };
+        if !anchored || at_line_start {
+            return Ok(Some(found));
+        }
+
+        // The next match at the start of a line is on a later line.
+        search_start = match text[found.range.start..].find('\n') {
+            Some(idx) => found.range.start + idx + 1,
+            None => return Ok(None),
+        };
+    }
+}
+
+fn find_anywhere_at(
+    text_pattern: &TextPattern,
+    text: &str,
+    start: usize,
+    variables: &mut Variables,
+    regex_cache: &mut vars::resolve::RegexCache,
+    config: &Config) -> Result<Option<FoundMatch>, String> {
+    let matcher = match text_pattern.matcher.as_ref().or(config.default_matcher.as_ref()) {
+        Some(matcher_name) => matcher::find(matcher_name, config)?,
+        None => None,
+    };
+
+    match matcher {
+        Some(matcher) => {
+            let needle = vars::resolve::literal_text(text_pattern, matcher.name(), config, variables)?;
+            match matcher.find_at(&needle, text, start) {
+                Some(range) => Ok(Some(FoundMatch { range: matcher::validate_range(&*matcher, range, text, start)?, captures: HashMap::new() })),
+                None => Ok(None),
+            }
+        },
+        None => {
+            let regex = vars::resolve::text_pattern(text_pattern, config, variables, regex_cache)?;
+            debug!("converting expected text pattern to regex: {:?}", regex);
+
+            Ok(vars::resolve::find_at(&regex, text_pattern, text, start).map(|regex_match| FoundMatch {
+                range: regex_match.range(),
+                captures: process_captures(&regex, regex_match.as_str()),
+            }))
+        },
+    }
+}
+
+/// Whether a byte index is at the start of a line of some text.
+fn is_line_start(text: &str, idx: usize) -> bool {
+    idx == 0 || text[..idx].ends_with('\n')
+}
+
+/// Gets the span of the whole lines that a byte range touches, including the final new line.
+fn line_span_of(text: &str, start: usize, end: usize) -> model::Span {
+    let line_start = text[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    // A range ending in a new line does not touch the line after it.
+    let search_from = if end > start && text[..end].ends_with('\n') { end - 1 } else { end };
+    let line_end = text[search_from..].find('\n').map(|i| search_from + i + 1).unwrap_or(text.len());
+
+    model::Span { start: line_start, end: line_end }
+}
+
 fn convert_bytes_to_str(bytes: &[u8]) -> &str {
     std::str::from_utf8(bytes).expect("invalid UTF-8 in output stream")
 }
@@ -223,7 +539,7 @@ fn process_captures(
 
     for capture_name in regex.capture_names() {
         // we only care about named captures.
-        if let Some(name) = capture_name {
+        if let Some(name) = capture_name.filter(|name| !vars::resolve::is_numeric_capture(name)) {
             let captured_value = captures.name(name).unwrap();
 
             variables.insert(name.to_owned(), captured_value.as_str().to_owned());
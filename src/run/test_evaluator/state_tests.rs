@@ -2,7 +2,8 @@
 
 use crate::{
     Config,
-    model::{self, TestFailReason},
+    model::{self, CommandKind, TestFailReason, TextPattern},
+    parse, vars,
 };
 use super::*;
 
@@ -51,7 +52,8 @@ fn check_next_works_standalone_in_very_basic_scenario() {
                 TestFailReason::CheckFailed(..) => {
                     assert_eq!(test_state.unprocessed_output_stream(), "hello \nworld",
                                "errors should not consume any of the underlying stream");
-                    assert_eq!(hint, Some("found a match for \'world\', but it does not appear on the next line, as required by the CHECK-NEXT directive".to_owned()));
+                    assert_eq!(hint, Some("found a match for \'world\', but it does not appear on the next line, as required by the CHECK-NEXT directive\n\
+                                           (- expected, + next line)\n- world\n+ hello ".to_owned()));
                 },
                 r => panic!("unexpected test failure reason: {:?}", r),
             }
@@ -105,7 +107,8 @@ fn check_next_rejects_matches_not_on_next_line() {
                 TestFailReason::CheckFailed(..) => {
                     assert!(test_state.unprocessed_output_stream().starts_with("Helium"),
                             "errors should not consume any of the underlying stream");
-                    assert_eq!(hint, Some("found a match for \'Lithium\', but it does not appear on the next line, as required by the CHECK-NEXT directive".to_owned()));
+                    assert_eq!(hint, Some("found a match for \'Lithium\', but it does not appear on the next line, as required by the CHECK-NEXT directive\n\
+                                           (- expected, + next line)\n- Lithium\n+ Helium, is an element.".to_owned()));
                 },
                 r => panic!("unexpected test failure reason: {:?}", r),
             }
@@ -114,6 +117,24 @@ fn check_next_rejects_matches_not_on_next_line() {
     }
 }
 
+#[test]
+fn check_skip_advances_an_exact_number_of_lines() {
+    let mut test_state = fixture_program_prints_periodic_table_in_order();
+    let config = Config::default();
+
+    test_state.check(&model::PatternComponent::Text("Helium".to_owned()).into(), &config).unwrap();
+    assert_eq!(test_state.skip_lines(2), TestResultKind::Pass);
+    test_state.check_next(&model::PatternComponent::Text("Boron".to_owned()).into(), &config).unwrap();
+
+    match test_state.skip_lines(20) {
+        TestResultKind::Fail { reason: TestFailReason::SkippedPastEndOfOutput { line_count: 20, remaining_line_count: 7 }, .. } => (),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!(test_state.unprocessed_output_stream().starts_with("Carbon"), "failed skips should not consume any of the stream");
+    assert_eq!(test_state.skip_lines(7), TestResultKind::Pass);
+    assert_eq!(test_state.unprocessed_output_stream(), "");
+}
+
 #[test]
 fn check_with_nonexistent_regex_produces_failure() {
     let mut test_state = fixture_program_prints_periodic_table_in_order();
@@ -139,3 +160,212 @@ fn check_with_nonexistent_regex_produces_failure() {
         panic!("expected the pattern to fail: {:?}", res);
     }
 }
+
+#[test]
+fn passing_checks_record_matched_output_regions() {
+    let mut test_state = fixture_program_prints_periodic_table_in_order();
+    let config = Config::default();
+
+    test_state.check(&model::PatternComponent::Text("Helium".to_owned()).into(), &config).unwrap();
+    test_state.record_match(3);
+    test_state.check(&model::PatternComponent::Regex("Lithium.*\n.*Beryllium".to_owned()).into(), &config).unwrap();
+    test_state.record_match(4);
+
+    let matches = test_state.check_matches();
+    assert_eq!(matches.len(), 2);
+    assert_eq!((matches[0].directive_line_number, matches[0].span), (3, model::Span { start: 25, end: 31 }));
+    assert_eq!((matches[0].start_line, matches[0].end_line), (2, 2));
+    assert_eq!(&test_state.complete_output_stream()[matches[1].span.start..matches[1].span.end], "Lithium, is an element.\nBeryllium");
+    assert_eq!((matches[1].start_line, matches[1].end_line), (3, 4));
+}
+
+#[test]
+fn checks_matching_empty_strings_are_warned_about() {
+    let mut test_state = fixture_program_prints_periodic_table_in_order();
+    let config = Config::default();
+
+    test_state.check(&TextPattern::literal("Helium"), &config).unwrap();
+    test_state.record_match(3);
+    assert!(test_state.warnings().is_empty());
+
+    test_state.check(&TextPattern::regex("[0-9]*"), &config).unwrap();
+    test_state.record_match(4);
+    assert_eq!(test_state.warnings(), ["the check on line 4 matched an empty string, so it verifies nothing".to_owned()]);
+}
+
+#[test]
+fn unordered_checks_match_anywhere_but_only_once_per_line() {
+    let mut test_state = fixture_program_prints_periodic_table_in_order();
+    test_state.set_unordered_checks(true);
+    let config = Config::default();
+
+    test_state.check(&model::PatternComponent::Text("Lithium".to_owned()).into(), &config).unwrap();
+    test_state.check(&model::PatternComponent::Text("Helium".to_owned()).into(), &config).unwrap();
+    // CHECK-NEXT is still relative to the line of the previous match.
+    test_state.check_next(&model::PatternComponent::Text("Lithium".to_owned()).into(), &config).unwrap();
+
+    match test_state.check(&model::PatternComponent::Text("Helium".to_owned()).into(), &config) {
+        TestResultKind::Fail { hint, .. } => {
+            assert_eq!(hint, Some("found a match for 'Helium', but only on lines already matched by other checks".to_owned()));
+        },
+        res => panic!("expected the pattern to fail: {:?}", res),
+    }
+}
+
+#[test]
+fn check_end_rejects_forbidden_text_within_the_window() {
+    let config = Config::default();
+    let forbidden_pattern: model::TextPattern = model::PatternComponent::Text("Hydrogen".to_owned()).into();
+
+    let mut test_state = fixture_program_prints_periodic_table_in_order();
+    test_state.check(&model::PatternComponent::Text("Helium".to_owned()).into(), &config).unwrap();
+    let (result, _) = test_state.check_end(&model::PatternComponent::Text("Carbon".to_owned()).into(), &[(7, &forbidden_pattern)], &config);
+    result.unwrap();
+
+    let mut test_state = fixture_program_prints_periodic_table_in_order();
+    let (result, forbidding_line_number) = test_state.check_end(&model::PatternComponent::Text("Carbon".to_owned()).into(), &[(7, &forbidden_pattern)], &config);
+    assert_eq!(forbidding_line_number, Some(7));
+    match result {
+        TestResultKind::Fail { reason: TestFailReason::ForbiddenTextFound { window_start_line_number, .. }, .. } => assert_eq!(window_start_line_number, 1),
+        res => panic!("expected the forbidden text to be found: {:?}", res),
+    }
+}
+
+#[test]
+fn captures_shadow_constants_until_undefined() {
+    let config = Config::default();
+    let mut test_state = TestRunState::new(vec![("element".to_owned(), "Carbon".to_owned())].into_iter().collect());
+    test_state.append_program_output("Hydrogen\nHelium\nCarbon\n");
+
+    test_state.check(&parse::text_pattern("[[element:H\\w+]]"), &config).unwrap();
+    test_state.check(&parse::text_pattern("[[element:H\\w+]]"), &config).unwrap();
+    assert_eq!(test_state.variables()["element"], "Helium");
+    assert_eq!(test_state.warnings(), &["named capture 'element' in pattern '[[element:H\\w+]]' shadows a variable of the same name".to_owned()]);
+
+    test_state.undefine("element");
+    assert_eq!(test_state.variables()["element"], "Carbon");
+    test_state.check(&parse::text_pattern("$$element"), &config).unwrap();
+
+    test_state.check(&parse::text_pattern("[[captured:\\w*]]"), &config).unwrap();
+    test_state.undefine("captured");
+    match test_state.check(&parse::text_pattern("$$captured"), &config) {
+        TestResultKind::Error { message } => assert_eq!(message, "variable 'captured' used in pattern '$$captured' is not defined"),
+        res => panic!("expected an undefined variable error: {:?}", res),
+    }
+}
+
+#[test]
+fn captures_cannot_shadow_constants() {
+    let config = Config::default();
+    let initial_variables = vec![
+        ("cc".to_owned(), "clang".to_owned()),
+        (vars::qualified_name(vars::CONFIG_NAMESPACE, "cc"), "clang".to_owned()),
+    ].into_iter().collect();
+    let mut test_state = TestRunState::new(initial_variables);
+    test_state.append_program_output("compiler gcc\ncompiler clang\ntcc\n");
+
+    test_state.check(&parse::text_pattern("compiler [[compiler:\\w+]]"), &config).unwrap();
+    test_state.check(&parse::text_pattern("compiler $$cfg:cc"), &config).unwrap();
+    assert_eq!(test_state.variables()["cap:compiler"], "gcc");
+
+    match test_state.check(&parse::text_pattern("[[cc:\\w+]]"), &config) {
+        TestResultKind::Error { message } => assert_eq!(message, "named capture 'cc' would shadow the constant of the same name, consider renaming the capture"),
+        res => panic!("expected a shadowing error: {:?}", res),
+    }
+}
+
+#[test]
+fn checks_match_with_the_engine_they_select() {
+    let mut config = Config::default();
+    let mut test_state = TestRunState::new(vec![("n".to_owned(), "2".to_owned())].into_iter().collect());
+    test_state.append_program_output("sum: 1 + 1 = [2]\nSUM:   1 +  1\nsum: 3\n");
+
+    let exact = parse::command_kind("CHECK[exact]: 1 + 1 = [$$n]", &[]).unwrap();
+    assert_eq!(exact.to_string(), "CHECK[exact]: 1 + 1 = [$$n]");
+    match exact {
+        CommandKind::Check(ref pattern) => test_state.check(pattern, &config).unwrap(),
+        kind => panic!("expected a CHECK: {:?}", kind),
+    }
+    test_state.check_next(&TextPattern::literal("SUM: 1 + 1").with_matcher("whitespace"), &config).unwrap();
+
+    config.default_matcher = Some("case-insensitive".to_owned());
+    test_state.check(&parse::text_pattern("SUM: 3"), &config).unwrap();
+
+    match test_state.check(&parse::text_pattern("[[\\d]]"), &config) {
+        TestResultKind::Error { message } => assert_eq!(message, "pattern '[[\\d]]' contains regexes, which the 'case-insensitive' matcher does not support"),
+        res => panic!("expected an unsupported pattern error: {:?}", res),
+    }
+    assert!(parse::command_kind("RUN[exact]: true", &[]).is_err());
+}
+
+#[test]
+fn anchored_checks_only_match_at_the_start_of_a_line() {
+    let mut config = Config::default();
+    let mut test_state = TestRunState::new(HashMap::new());
+    test_state.append_program_output("error: not found\nnot found: x\n  indented\n");
+
+    let anchored = parse::command_kind("CHECK{^}: not found", &[]).unwrap();
+    assert_eq!(anchored.to_string(), "CHECK{^}: not found");
+    match anchored {
+        CommandKind::Check(ref pattern) => test_state.check(pattern, &config).unwrap(),
+        kind => panic!("expected a CHECK: {:?}", kind),
+    }
+    // The check matched the second line, rather than the middle of the first.
+    test_state.check_next(&TextPattern::literal("indented"), &config).unwrap();
+
+    let mut test_state = TestRunState::new(HashMap::new());
+    test_state.append_program_output("  indented\n");
+    config.anchor_checks_to_line_start = true;
+    assert!(test_state.check(&TextPattern::literal("indented").with_matcher("exact"), &config).is_erroneous());
+
+    assert_eq!(parse::command_kind("CHECK[exact]{^}: 1 + 1", &[]).unwrap().to_string(), "CHECK[exact]{^}: 1 + 1");
+    assert!(parse::command_kind("RUN{^}: true", &[]).is_err());
+}
+
+#[test]
+fn json_output_is_checked_semantically() {
+    let config = Config::default();
+    let test_file = model::TestFile::new(model::TestFilePath::new("/nonexistent/json.txt", "json.txt"));
+    let mut test_state = TestRunState::new(HashMap::new());
+    test_state.append_program_output("{\"b\": [1, 2], \"a\": {\"x\": \"y\", \"z\": null}}\n");
+    let check = |directive: &str| match parse::command_kind(&format!("CHECK-JSON: {}", directive), &[]).unwrap() {
+        CommandKind::CheckJson(ref json_check) => check_json(&test_state, &test_file, json_check, &config),
+        kind => panic!("expected a CHECK-JSON: {:?}", kind),
+    };
+
+    assert_eq!(check("$.b[1] == 2.0"), TestResultKind::Pass);
+    assert_eq!(check(r#"$.a == {"z": null, "x": "y"}"#), TestResultKind::Pass);
+    match check(r#"$.a.x == "z""#) {
+        TestResultKind::Fail { reason: TestFailReason::JsonMismatch { actual, .. }, .. } => assert_eq!(actual.as_deref(), Some("\"y\"")),
+        other => panic!("expected a mismatch, got {:?}", other),
+    }
+    match check("$.b[2] == 3") {
+        TestResultKind::Fail { reason, .. } => assert_eq!(reason.short_message(), "expected '$.b[2]' to be 3, but the output has no such value"),
+        other => panic!("expected a missing value, got {:?}", other),
+    }
+}
+
+#[test]
+fn diagnostics_are_checked_by_line() {
+    let config = Config::default();
+    let check = |directives: &str| {
+        let text = format!("RUN: cc @file\n{}\n", directives);
+        let test_file = parse::test_file(model::TestFilePath::new("/nonexistent/diag.c", "diag.c"), &text).unwrap();
+        let mut test_state = TestRunState::new(HashMap::new());
+        test_state.append_program_output("C:\\src\\main.c:1:3: warning: unused variable y\nmain.c:3:12: error: expected ;\n");
+
+        for command in test_file.commands.iter() {
+            if let CommandKind::Check(ref pattern) = command.kind {
+                let result = test_state.check(pattern, &config);
+                if result.is_erroneous() { return result; }
+            }
+        }
+        TestResultKind::Pass
+    };
+
+    assert_eq!(check("CHECK-DIAG: warning@-1: unused variable [[\\w+]]\nCHECK-DIAG: error@+0: expected ;"), TestResultKind::Pass);
+    assert_eq!(check("CHECK-DIAG: warning@1: unused\nCHECK-DIAG: error: expected"), TestResultKind::Pass);
+    assert!(check("CHECK-DIAG: error@4: expected ;").is_erroneous());
+    // The column of a diagnostic is not mistaken for its line.
+    assert!(check("CHECK-DIAG: warning@3: unused").is_erroneous());
+}
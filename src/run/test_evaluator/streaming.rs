@@ -0,0 +1,266 @@
+//! Evaluation of checks concurrently with the execution of the program under test.
+
+use super::{state::TestRunState, CommandLine};
+use crate::{
+    model::{Command, CommandKind, ProgramOutput, ResourceUsage, TestFailReason, TestFile, TestResultKind},
+    Config,
+};
+use std::{io::Read, process, sync::Arc, thread};
+
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Checks if the directives in a test file can be evaluated while streaming.
+pub fn supports(test_file: &TestFile) -> bool {
+    let mut has_checks = false;
+
+    for command in test_file.commands.iter() {
+        match command.kind {
+            CommandKind::Check(..) | CommandKind::CheckNext(..) | CommandKind::CheckBlock(..) => has_checks = true,
+            // The exit status of negated commands always matters, so they cannot be killed early.
+            CommandKind::Run(ref invocation) if invocation.negated => return false,
+            // So does that of expected failures, which may only fail by exiting unsuccessfully.
+            CommandKind::XFail => return false,
+            CommandKind::Run(..) | CommandKind::RunBackground(..) | CommandKind::WaitFor(..) | CommandKind::Resource(..) | CommandKind::Depends(..) | CommandKind::Owner(..) | CommandKind::Include(..) | CommandKind::NormalizePreset(..) | CommandKind::CheckUnordered | CommandKind::AllowStderr => (),
+            _ => return false,
+        }
+    }
+
+    has_checks
+}
+
+/// The state of the checks after some output has been evaluated.
+enum Progress<'a> {
+    /// More output is needed to decide the outcome.
+    Pending,
+    /// All checks have passed.
+    Passed,
+    /// A check has failed, and more output cannot change that.
+    Failed {
+        result: TestResultKind,
+        /// The failing directive.
+        check: &'a Command,
+    },
+}
+
+/// Runs a program, evaluating its output against the checks as the output arrives.
+///
+/// Also returns the line of the failing directive, if a directive failed, and the resources used by the program.
+pub fn run(
+    mut command: process::Command,
+    command_line: CommandLine,
+    test_run_state: &mut TestRunState,
+    test_file: &TestFile,
+    config: &Config,
+) -> (ProgramOutput, TestResultKind, Option<u32>, Option<ResourceUsage>) {
+    super::isolate_streams(&mut command, config);
+    super::use_own_process_group(&mut command);
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return (ProgramOutput::empty(), super::spawn_error(e, config), None, None),
+    };
+
+    let mut stderr = super::forwarded(child.stderr.take().unwrap(), super::Stream::Stderr, config);
+    let stderr_reader = thread::spawn(move || {
+        let mut bytes = Vec::new();
+        stderr.read_to_end(&mut bytes).ok();
+        bytes
+    });
+
+    let mut stdout = super::forwarded(child.stdout.take().unwrap(), super::Stream::Stdout, config);
+    let mut stdout_bytes = Vec::new();
+    // The number of bytes of `stdout_bytes` that have been given to the test state.
+    let mut evaluated_byte_count = 0;
+    let mut next_check_index = 0;
+    let mut buffer = [0u8; READ_BUFFER_SIZE];
+
+    let mut progress = loop {
+        let read_byte_count = match stdout.read(&mut buffer) {
+            Ok(0) | Err(..) => break Progress::Pending,
+            Ok(count) => count,
+        };
+        stdout_bytes.extend_from_slice(&buffer[..read_byte_count]);
+
+        // Only evaluate complete lines, a partial line could still grow into a match.
+        let complete_line_byte_count = match stdout_bytes.iter().rposition(|&b| b == b'\n') {
+            Some(newline_index) => newline_index + 1,
+            None => continue,
+        };
+        let unevaluated_byte_count = complete_line_byte_count - evaluated_byte_count;
+
+        // Appending re-allocates the stream, so defer it while the pipe is full to
+        // avoid quadratic copying for verbose programs.
+        let pipe_drained = read_byte_count < READ_BUFFER_SIZE;
+        if unevaluated_byte_count == 0 || !(pipe_drained || unevaluated_byte_count >= evaluated_byte_count) {
+            continue;
+        }
+
+        test_run_state.append_program_output(&String::from_utf8_lossy(&stdout_bytes[evaluated_byte_count..complete_line_byte_count]));
+        evaluated_byte_count = complete_line_byte_count;
+
+        match evaluate_checks(test_run_state, test_file, &mut next_check_index, false, config) {
+            Progress::Pending => continue,
+            finished => break finished,
+        }
+    };
+
+    let killed_early = match progress {
+        Progress::Pending => false,
+        _ => {
+            super::kill_process_group(&mut child);
+            true
+        },
+    };
+
+    let (status, resource_usage) = match super::wait(&mut child) {
+        Ok((status, resource_usage)) => (Some(status), resource_usage),
+        Err(..) => (None, None),
+    };
+    let stderr_bytes = stderr_reader.join().unwrap_or_default();
+
+    if !killed_early {
+        test_run_state.append_program_output(&String::from_utf8_lossy(&stdout_bytes[evaluated_byte_count..]));
+    }
+
+    let program_output = ProgramOutput {
+        stdout: Arc::from(String::from_utf8_lossy(&stdout_bytes)),
+        stderr: Arc::from(String::from_utf8_lossy(&stderr_bytes)),
+    };
+
+    if !killed_early {
+        match status {
+            Some(status) if !status.success() => {
+                return (program_output, super::unsuccessful_execution(status, command_line), None, resource_usage);
+            },
+            _ => (),
+        }
+
+        progress = evaluate_checks(test_run_state, test_file, &mut next_check_index, true, config);
+    }
+
+    super::cleanup_temporary_files(test_run_state, config);
+
+    let (result, failing_line_number) = match progress {
+        Progress::Failed { result, check } => {
+            let result = super::explain_self_match(test_run_state, result, test_file, config);
+            (super::explain_origin(result, check), Some(check.line_number))
+        },
+        _ => (TestResultKind::Pass, None),
+    };
+    (program_output, super::apply_expected_failure(result, test_file), failing_line_number, resource_usage)
+}
+
+/// Evaluates as many of the remaining checks as possible against the output so far.
+fn evaluate_checks<'a>(
+    test_run_state: &mut TestRunState,
+    test_file: &'a TestFile,
+    next_check_index: &mut usize,
+    is_complete_output: bool,
+    config: &Config,
+) -> Progress<'a> {
+    let checks: Vec<_> = test_file.commands.iter()
+        .filter(|c| matches!(c.kind, CommandKind::Check(..) | CommandKind::CheckNext(..) | CommandKind::CheckBlock(..)))
+        .collect();
+
+    while let Some(check) = checks.get(*next_check_index) {
+        let result = match check.kind {
+            CommandKind::Check(ref text_pattern) | CommandKind::CheckBlock(ref text_pattern) => test_run_state.check(text_pattern, config),
+            CommandKind::CheckNext(ref text_pattern) => test_run_state.check_next(text_pattern, config),
+            _ => unreachable!(),
+        };
+
+        if !result.is_erroneous() {
+            test_run_state.record_match(check.line_number);
+            *next_check_index += 1;
+            continue;
+        }
+
+        // A `CHECK-NEXT` can never be fixed by more output once the next line is complete.
+        let is_certain_failure = match (&check.kind, &result) {
+            (CommandKind::CheckNext(..), _) => test_run_state.unprocessed_output_stream().trim_start().contains('\n'),
+            (_, TestResultKind::Fail { reason: TestFailReason::CheckFailed(..), .. }) => false,
+            _ => true,
+        };
+
+        return if is_complete_output || is_certain_failure {
+            Progress::Failed { result, check }
+        } else {
+            Progress::Pending
+        };
+    }
+
+    Progress::Passed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::TestFilePath;
+    use std::{collections::HashMap, time::{Duration, Instant}};
+
+    fn test_file(text: &str) -> TestFile {
+        crate::parse::test_file(TestFilePath::new("/nonexistent/streaming.txt", "streaming.txt"), text).unwrap()
+    }
+
+    #[test]
+    fn tests_whose_exit_status_matters_are_not_streamed() {
+        assert!(supports(&test_file("RUN: true\nCHECK: a\n")));
+        assert!(!supports(&test_file("RUN: true\n")));
+        assert!(!supports(&test_file("RUN: !false\nCHECK: a\n")));
+        assert!(!supports(&test_file("RUN: true\nXFAIL:\nCHECK: a\n")));
+        assert!(!supports(&test_file("RUN: true\nCHECK-SAME-AS: true\n")));
+    }
+
+    #[test]
+    fn checks_only_fail_early_once_more_output_cannot_fix_them() {
+        let config = Config::default();
+        let test_file = test_file("CHECK: a\nCHECK-NEXT: b\n");
+        let mut test_run_state = TestRunState::new(HashMap::new());
+        let mut next_check_index = 0;
+
+        // The line after the match may still be on its way.
+        test_run_state.append_program_output("x\na\n");
+        assert!(matches!(evaluate_checks(&mut test_run_state, &test_file, &mut next_check_index, false, &config), Progress::Pending));
+        assert_eq!(next_check_index, 1);
+
+        test_run_state.append_program_output("c\n");
+        assert!(matches!(evaluate_checks(&mut test_run_state, &test_file, &mut next_check_index, false, &config), Progress::Failed { check, .. } if check.line_number == 2));
+
+        // A missing `CHECK` may appear later, until the output is complete.
+        let test_file = self::test_file("CHECK: z\n");
+        let mut test_run_state = TestRunState::new(HashMap::new());
+        let mut next_check_index = 0;
+        test_run_state.append_program_output("a\n");
+        assert!(matches!(evaluate_checks(&mut test_run_state, &test_file, &mut next_check_index, false, &config), Progress::Pending));
+        assert!(matches!(evaluate_checks(&mut test_run_state, &test_file, &mut next_check_index, true, &config), Progress::Failed { check, .. } if check.line_number == 1));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn partial_lines_are_not_checked_until_they_are_complete() {
+        let config = Config::default();
+        // Checked early, the number would only match `1`, leaving `23` as the next line.
+        let test_file = test_file("CHECK: count [[\\d+]]\nCHECK-NEXT: done\n");
+        let mut command = process::Command::new("sh");
+        command.arg("-c").arg("printf 'count 1'; sleep 0.2; printf '23\\ndone\\n'");
+
+        let (_, result, failing_line_number, _) = run(command, CommandLine("printf".to_owned()), &mut TestRunState::new(HashMap::new()), &test_file, &config);
+        assert_eq!((result, failing_line_number), (TestResultKind::Pass, None));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn programs_are_killed_along_with_their_children_once_checks_pass() {
+        let config = Config::default();
+        let test_file = test_file("CHECK: ready\n");
+        let mut command = process::Command::new("sh");
+        // The background child holds on to the output streams, which are only closed if it is killed too.
+        command.arg("-c").arg("echo ready; sleep 30 & sleep 30");
+
+        let start_time = Instant::now();
+        let (output, result, _, _) = run(command, CommandLine("sh".to_owned()), &mut TestRunState::new(HashMap::new()), &test_file, &config);
+        assert_eq!(result, TestResultKind::Pass);
+        assert_eq!(&output.stdout[..], "ready\n");
+        assert!(start_time.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,144 @@
+//! Hooks run once the artifacts of a suite have been saved, such as to upload them to
+//! object storage from CI.
+//!
+//! Hooks are only run if artifacts were written. Before they run, a manifest listing every
+//! artifact saved by the suite is written to `manifest.txt` in the artifacts directory. Files
+//! left in the directory by earlier runs are not listed.
+
+use crate::Config;
+use std::{fs, path::{Path, PathBuf}, process};
+
+/// The file in the artifacts directory listing every artifact, one relative path per line.
+pub const MANIFEST_FILE_NAME: &str = "manifest.txt";
+/// The absolute path of the artifacts directory, as given to `Config::after_artifacts_command`.
+pub const DIRECTORY_VARIABLE: &str = "LIT_ARTIFACTS_DIRECTORY";
+/// The absolute path of the manifest, as given to `Config::after_artifacts_command`.
+pub const MANIFEST_VARIABLE: &str = "LIT_ARTIFACTS_MANIFEST";
+/// Either `successful` or `failed`, as given to `Config::after_artifacts_command`.
+pub const SUITE_STATUS_VARIABLE: &str = "LIT_SUITE_STATUS";
+
+/// The artifacts saved by a run of a suite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    /// The paths of all artifacts, relative to the artifacts directory, in sorted order.
+    pub files: Vec<PathBuf>,
+    /// Whether the suite passed.
+    pub is_successful: bool,
+}
+
+impl Manifest {
+    /// Lists the saved artifacts that are underneath a directory, given their absolute paths.
+    pub fn new(artifacts_directory: &Path, saved_files: &[PathBuf], is_successful: bool) -> Self {
+        let manifest_path = artifacts_directory.join(MANIFEST_FILE_NAME);
+        let mut files: Vec<_> = saved_files.iter()
+            .filter(|path| **path != manifest_path)
+            .filter_map(|path| path.strip_prefix(artifacts_directory).ok())
+            .map(Path::to_owned)
+            .collect();
+
+        // Artifacts such as the rerun manifest are saved again by every shell of a matrix.
+        files.sort();
+        files.dedup();
+        Manifest { files, is_successful }
+    }
+
+    /// Renders the manifest as one relative path per line.
+    pub fn render(&self) -> String {
+        self.files.iter().map(|path| format!("{}\n", path.display())).collect()
+    }
+}
+
+/// Runs the hooks of a config on the artifacts saved by a suite, given their absolute paths,
+/// if it has any hooks and artifacts were written.
+pub fn run(artifacts_directory: &Path, saved_files: &[PathBuf], is_successful: bool, config: &Config) -> Result<(), String> {
+    if config.after_artifacts.is_none() && config.after_artifacts_command.is_none() {
+        return Ok(());
+    }
+
+    let manifest = Manifest::new(artifacts_directory, saved_files, is_successful);
+    if manifest.files.is_empty() {
+        return Ok(());
+    }
+
+    let manifest_path = artifacts_directory.join(MANIFEST_FILE_NAME);
+    fs::write(&manifest_path, manifest.render()).map_err(|e| format!("could not save '{}': {}", manifest_path.display(), e))?;
+
+    if let Some(ref after_artifacts) = config.after_artifacts {
+        (after_artifacts.0)(artifacts_directory, &manifest);
+    }
+
+    if let Some(ref command_line) = config.after_artifacts_command {
+        let status = process::Command::new(&config.shell)
+            .arg("-c").arg(command_line)
+            .env(DIRECTORY_VARIABLE, artifacts_directory)
+            .env(MANIFEST_VARIABLE, &manifest_path)
+            .env(SUITE_STATUS_VARIABLE, if is_successful { "successful" } else { "failed" })
+            .stdin(process::Stdio::null())
+            .status()
+            .map_err(|e| format!("could not run after-artifacts command '{}': {}", command_line, e))?;
+
+        if !status.success() {
+            return Err(format!("after-artifacts command '{}' exited unsuccessfully ({})", command_line, status));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn hooks_are_given_the_artifacts_of_the_run_but_only_if_there_are_any() {
+        let root = tempfile::tempdir().unwrap();
+        let artifacts_directory = root.path().join("artifacts");
+        let manifests = Arc::new(Mutex::new(Vec::new()));
+
+        let mut config = Config { shell: "sh".to_owned(), ..Config::default() };
+        let seen_manifests = manifests.clone();
+        config.set_after_artifacts(move |_, manifest| {
+            seen_manifests.lock().unwrap().push(manifest.clone());
+        });
+        config.after_artifacts_command = Some("cp \"$LIT_ARTIFACTS_MANIFEST\" \"$LIT_ARTIFACTS_DIRECTORY/../uploaded-$LIT_SUITE_STATUS.txt\"".to_owned());
+
+        // Files left behind by earlier runs are not artifacts of this one.
+        fs::create_dir_all(artifacts_directory.join("a.txt")).unwrap();
+        fs::write(artifacts_directory.join("stale.txt"), "").unwrap();
+        run(&artifacts_directory, &[], true, &config).unwrap();
+        assert!(manifests.lock().unwrap().is_empty());
+
+        let saved_files = vec![artifacts_directory.join("seed.txt"), artifacts_directory.join("a.txt/stdout.txt"), artifacts_directory.join("seed.txt")];
+        run(&artifacts_directory, &saved_files, false, &config).unwrap();
+        run(&artifacts_directory, &saved_files, false, &config).unwrap();
+
+        let expected_files = vec![PathBuf::from("a.txt/stdout.txt"), PathBuf::from("seed.txt")];
+        assert_eq!(*manifests.lock().unwrap(), vec![Manifest { files: expected_files.clone(), is_successful: false }; 2]);
+        assert_eq!(fs::read_to_string(root.path().join("uploaded-failed.txt")).unwrap(), "a.txt/stdout.txt\nseed.txt\n");
+
+        config.after_artifacts_command = Some("exit 3".to_owned());
+        assert!(run(&artifacts_directory, &saved_files, true, &config).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "artifacts")]
+    fn failing_hooks_fail_strict_suites() {
+        let artifacts_directory = tempfile::tempdir().unwrap();
+        let files = Arc::new(Mutex::new(Vec::new()));
+        let seen_files = files.clone();
+
+        let outcome = crate::run::tests(crate::event_handler::Default::default(), |config| {
+            config.shell = "sh".to_owned();
+            config.strict = true;
+            config.save_artifacts_to_directory = Some(artifacts_directory.path().to_owned());
+            config.after_artifacts_command = Some("exit 3".to_owned());
+            let seen_files = seen_files.clone();
+            config.set_after_artifacts(move |_, manifest| *seen_files.lock().unwrap() = manifest.files.clone());
+            config.add_test_source(crate::test_source::InMemory::new().with_file("passes.txt", "RUN: echo hello\nCHECK: hello\n"));
+        });
+
+        assert_eq!(outcome, Err(crate::run::SuiteError::Failed { failed_count: 0 }));
+        assert!(files.lock().unwrap().contains(&PathBuf::from("passes.txt/stdout.txt")));
+    }
+}
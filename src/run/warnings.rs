@@ -0,0 +1,111 @@
+//! Aggregation of identical warnings, such as one raised by hundreds of test files,
+//! so that they do not flood the output.
+
+use crate::{event_handler::EventHandler, messages::{Catalog, MessageId}};
+use std::collections::HashMap;
+
+/// Passes warnings on to an event handler, counting them so that `Config::strict` can
+/// fail the suite.
+#[derive(Default)]
+pub struct Counter {
+    count: usize,
+}
+
+impl Counter {
+    /// Notes a warning.
+    pub fn note(&mut self, event_handler: &mut dyn EventHandler, message: &str) {
+        self.count += 1;
+        event_handler.note_warning(message);
+    }
+
+    /// Notes a warning raised at a location, such as `path:line`, unless it has been shown
+    /// too often already. It is counted either way.
+    pub fn note_repeated(&mut self, event_handler: &mut dyn EventHandler, repeated: &mut Repeated, warning: &str, location: &str, message: &str) {
+        self.count += 1;
+        if repeated.record(warning, location) {
+            event_handler.note_warning(message);
+        }
+    }
+
+    /// Gets the number of warnings raised so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+/// Counts how often each warning is raised, and decides which ones are shown.
+pub struct Repeated {
+    /// The number of times each warning is shown before it is only counted.
+    limit: Option<usize>,
+    /// Each distinct warning, along with its number of occurrences and where it was first raised.
+    warnings: Vec<(String, usize, String)>,
+    indices: HashMap<String, usize>,
+}
+
+impl Repeated {
+    /// Creates an aggregator, showing each warning up to `limit` times, or always if there is no limit.
+    pub fn new(limit: Option<usize>) -> Self {
+        Repeated { limit, warnings: Vec::new(), indices: HashMap::new() }
+    }
+
+    /// Records a warning raised at a location, such as `path:line`, returning whether it should be shown.
+    pub fn record(&mut self, warning: &str, location: &str) -> bool {
+        let index = match self.indices.get(warning) {
+            Some(&index) => index,
+            None => {
+                self.warnings.push((warning.to_owned(), 0, location.to_owned()));
+                self.indices.insert(warning.to_owned(), self.warnings.len() - 1);
+                self.warnings.len() - 1
+            },
+        };
+
+        let occurrences = &mut self.warnings[index].1;
+        *occurrences += 1;
+        self.limit.map(|limit| *occurrences <= limit).unwrap_or(true)
+    }
+
+    /// Summarizes the warnings that were raised more often than they were shown,
+    /// in the order they were first raised.
+    pub fn summaries(&self, messages: &Catalog) -> Vec<String> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Vec::new(),
+        };
+
+        self.warnings.iter()
+            .filter(|(_, occurrences, _)| *occurrences > limit)
+            .map(|(warning, occurrences, first_location)| messages.format(MessageId::RepeatedWarning, &[warning, occurrences, first_location]))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summarizes_warnings_raised_more_often_than_the_limit() {
+        let mut repeated = Repeated::new(Some(2));
+        let shown: Vec<_> = (1..=4).map(|i| repeated.record("unknown directive", &format!("test{}.txt:1", i))).collect();
+        assert_eq!(shown, vec![true, true, false, false]);
+        assert!(repeated.record("test is empty", "empty.txt"));
+
+        assert_eq!(repeated.summaries(&Catalog::default()), vec!["warning 'unknown directive' occurred 4 times (first at test1.txt:1)"]);
+        assert!(Repeated::new(None).summaries(&Catalog::default()).is_empty());
+    }
+    #[test]
+    fn counts_warnings_whether_or_not_they_are_shown() {
+        struct Shown(Vec<String>);
+        impl EventHandler for Shown {
+            fn note_warning(&mut self, message: &str) { self.0.push(message.to_owned()); }
+        }
+
+        let (mut counter, mut repeated, mut shown) = (Counter::default(), Repeated::new(Some(1)), Shown(Vec::new()));
+        counter.note(&mut shown, "no timing history");
+        counter.note_repeated(&mut shown, &mut repeated, "test is empty", "a.txt", "a.txt: test is empty");
+        counter.note_repeated(&mut shown, &mut repeated, "test is empty", "b.txt", "b.txt: test is empty");
+
+        assert_eq!(counter.count(), 3);
+        assert_eq!(shown.0, vec!["no timing history", "a.txt: test is empty"]);
+    }
+}
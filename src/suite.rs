@@ -0,0 +1,229 @@
+//! A builder for test suites defined in code, rather than on disk.
+//!
+//! ```
+//! use lit::Suite;
+//!
+//! let result = Suite::new()
+//!     .configure(|config| config.shell = "sh".to_owned())
+//!     .test("math/add.sh", "# RUN: sh @file\n# CHECK: 3\necho $((1 + 2))\n")
+//!     .run(lit::event_handler::Default::default());
+//!
+//! assert!(result.is_ok());
+//! ```
+//!
+//! The tests are parsed and evaluated exactly like tests on disk. They are written
+//! to a temporary directory before running, so `@file` refers to a real file.
+
+use crate::{event_handler::EventHandler, run::{self, SuiteError}, test_source, Config};
+use std::path::PathBuf;
+
+/// A function that customizes the config of a suite.
+type ConfigureFn = Box<dyn Fn(&mut Config)>;
+
+/// A test suite built up from inline tests.
+#[derive(Default)]
+pub struct Suite {
+    /// The relative path and text of each test.
+    tests: Vec<(PathBuf, String)>,
+    configure_fns: Vec<ConfigureFn>,
+}
+
+impl Suite {
+    /// Creates a suite without any tests.
+    pub fn new() -> Self { Suite::default() }
+
+    /// Adds a test, given its path relative to the suite, and its text.
+    pub fn test(mut self, relative_path: impl Into<PathBuf>, text: impl Into<String>) -> Self {
+        self.tests.push((relative_path.into(), text.into()));
+        self
+    }
+
+    /// Adds a function that customizes the config the suite runs with.
+    ///
+    /// Functions are applied in the order they were added.
+    pub fn configure(mut self, configure_fn: impl Fn(&mut Config) + 'static) -> Self {
+        self.configure_fns.push(Box::new(configure_fn));
+        self
+    }
+
+    /// Runs every test in the suite, returning `Ok` if all of them pass, and why the suite failed otherwise.
+    pub fn run(self, event_handler: impl EventHandler) -> Result<(), SuiteError> {
+        let Suite { tests, configure_fns } = self;
+
+        run::tests(event_handler, |config| {
+            for configure_fn in configure_fns.iter() {
+                configure_fn(config);
+            }
+
+            let source = tests.iter().fold(test_source::InMemory::new(), |source, (relative_path, text)| {
+                source.with_file(relative_path.clone(), text.clone())
+            });
+            config.add_test_source(source);
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{event_handler::SuiteProgress, model::*};
+
+    /// Marks failures of tests under `known/` as expected.
+    struct KnownIssues;
+
+    impl EventHandler for KnownIssues {
+        fn on_test_result_pending(&mut self, result: &mut TestResult, _: &Config) {
+            if let (true, TestResultKind::Fail { reason, .. }) = (result.path.relative.starts_with("known"), &result.overall_result) {
+                result.overall_result = TestResultKind::ExpectedFailure { actual_reason: reason.clone() };
+            }
+        }
+    }
+
+    /// Records the display name and label of every result.
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl EventHandler for Recorder {
+        fn on_test_finished(&mut self, result: TestResult, _: &SuiteProgress, config: &Config) {
+            self.0.lock().unwrap().push(format!("{}: {}", result.display_name(config), result.overall_result.machine_label()));
+        }
+    }
+
+    /// Records the progress of the suite as of every finished test.
+    struct ProgressRecorder(std::sync::Arc<std::sync::Mutex<Vec<SuiteProgress>>>);
+
+    impl EventHandler for ProgressRecorder {
+        fn on_test_finished(&mut self, _: TestResult, progress: &SuiteProgress, _: &Config) {
+            self.0.lock().unwrap().push(progress.clone());
+        }
+    }
+
+    #[test]
+    fn failing_tests_fail_the_suite() {
+        let suite = Suite::new()
+            .configure(|config| config.shell = "sh".to_owned())
+            .test("passes.txt", "RUN: echo hello\nCHECK: hello\n")
+            .test("nested/fails.txt", "RUN: echo hello\nCHECK: goodbye\n");
+
+        assert_eq!(suite.run(crate::event_handler::Default::default()), Err(SuiteError::Failed { failed_count: 1 }));
+    }
+
+    #[test]
+    fn cancelled_suites_report_their_tests_as_not_run() {
+        let results = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cancellation_token = crate::run::CancellationToken::new();
+        cancellation_token.cancel();
+
+        let suite = Suite::new()
+            .configure(move |config| { config.shell = "sh".to_owned(); config.cancellation_token = cancellation_token.clone(); })
+            .test("passes.txt", "RUN: echo hello\nCHECK: hello\n");
+
+        assert_eq!(suite.run(Recorder(results.clone())), Err(SuiteError::Cancelled { failed_count: 0, not_run_count: 1 }));
+        assert_eq!(*results.lock().unwrap(), vec!["passes.txt: not-run".to_owned()]);
+    }
+
+    #[test]
+    fn timed_out_suites_return_rather_than_exiting() {
+        let results = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let outcome = crate::run::tests(Recorder(results.clone()), |config| {
+            config.shell = "sh".to_owned();
+            config.suite_timeout = Some(std::time::Duration::from_secs(0));
+            config.add_test_source(test_source::InMemory::new().with_file("passes.txt", "RUN: echo hello\nCHECK: hello\n"));
+        });
+
+        assert_eq!(outcome, Err(SuiteError::TimedOut { failed_count: 0, not_run_count: 1 }));
+        assert_eq!(*results.lock().unwrap(), vec!["passes.txt: not-run".to_owned()]);
+    }
+
+    #[test]
+    fn event_handlers_are_told_the_progress_of_the_suite() {
+        let progress = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let suite = Suite::new()
+            .configure(|config| { config.shell = "sh".to_owned(); config.shell_matrix = vec!["sh".to_owned(), "bash".to_owned()]; })
+            .test("passes.txt", "RUN: echo hello\nCHECK: hello\n")
+            .test("fails.txt", "RUN: echo hello\nCHECK: goodbye\n");
+
+        assert_eq!(suite.run(ProgressRecorder(progress.clone())), Err(SuiteError::Failed { failed_count: 2 }));
+
+        let progress = progress.lock().unwrap();
+        let counts: Vec<_> = progress.iter().map(|p| (p.completed, p.remaining)).collect();
+        assert_eq!(counts, vec![(1, 3), (2, 2), (3, 1), (4, 0)]);
+        assert_eq!(progress[3].failures, 2);
+        assert_eq!(progress[3].percentage(), 100.0);
+        assert_eq!(progress[3].estimated_time_remaining(), Some(std::time::Duration::from_secs(0)));
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn event_handlers_keep_receiving_events_in_other_message_formats() {
+        let results = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let suite = Suite::new()
+            .configure(|config| { config.shell = "sh".to_owned(); config.message_format = crate::config::MessageFormat::JsonLines; })
+            .test("passes.txt", "RUN: echo hello\nCHECK: hello\n");
+
+        assert_eq!(suite.run(Recorder(results.clone())), Ok(()));
+        assert_eq!(*results.lock().unwrap(), vec!["passes.txt: pass".to_owned()]);
+    }
+
+    #[test]
+    fn event_handlers_can_reclassify_results() {
+        let suite = Suite::new()
+            .configure(|config| config.shell = "sh".to_owned())
+            .test("known/fails.txt", "RUN: echo hello\nCHECK: goodbye\n");
+
+        assert_eq!(suite.run(KnownIssues), Ok(()));
+    }
+
+    #[test]
+    fn strict_mode_fails_suites_with_empty_tests_or_warnings() {
+        let run = |strict, text: &str| Suite::new()
+            .configure(move |config| { config.shell = "sh".to_owned(); config.strict = strict; })
+            .test("test.txt", text)
+            .run(Recorder(Default::default()));
+        let shadows_a_variable = "RUN: echo hello\nCHECK: [[file:hello]]\n";
+
+        assert_eq!(run(false, "nothing to see here\n"), Ok(()));
+        assert_eq!(run(true, "nothing to see here\n"), Err(SuiteError::Failed { failed_count: 0 }));
+        assert_eq!(run(false, shadows_a_variable), Ok(()));
+        assert_eq!(run(true, shadows_a_variable), Err(SuiteError::Failed { failed_count: 0 }));
+    }
+
+    #[test]
+    fn directive_separators_can_be_configured_per_extension() {
+        let suite = Suite::new()
+            .configure(|config| { config.shell = "sh".to_owned(); config.set_directive_separator("legacy", '>').unwrap(); })
+            .test("colon.txt", "RUN: echo hello\nCHECK: hello\n")
+            .test("arrow.legacy", "RUN> echo hello\nCHECK> hello\nNOTE: colons are plain text here\n");
+
+        assert_eq!(suite.run(Recorder(Default::default())), Ok(()));
+    }
+
+    #[test]
+    fn failing_build_commands_abort_the_suite() {
+        let run = |build_command: &'static str| {
+            let results = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+            let outcome = Suite::new()
+                .configure(move |config| { config.shell = "sh".to_owned(); config.build_command = Some(build_command.to_owned()); })
+                .test("passes.txt", "RUN: echo hello\nCHECK: hello\n")
+                .run(Recorder(results.clone()));
+            let results = results.lock().unwrap().len();
+            (outcome, results)
+        };
+
+        assert_eq!(run("echo building"), (Ok(()), 1));
+        assert_eq!(run("exit 1"), (Err(SuiteError::NotStarted), 0));
+    }
+
+    #[test]
+    fn shell_matrices_run_every_test_with_every_shell() {
+        let results = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let suite = Suite::new()
+            .configure(|config| config.shell_matrix = vec!["sh".to_owned(), "bash".to_owned()])
+            .test("shell-name.txt", "RUN: basename $0\nCHECK: bash\n");
+
+        assert_eq!(suite.run(Recorder(results.clone())), Err(SuiteError::Failed { failed_count: 1 }));
+
+        let mut results = results.lock().unwrap().clone();
+        results.sort();
+        assert_eq!(results, vec!["shell-name.txt [bash]: pass", "shell-name.txt [sh]: fail"]);
+    }
+}
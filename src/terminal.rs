@@ -0,0 +1,164 @@
+//! Presentation of output containing terminal control sequences, such as progress bars
+//! redrawn with carriage returns, in failure context.
+
+use crate::config::ControlSequences;
+use std::borrow::Cow;
+
+const ESCAPE: char = '\u{1b}';
+const BELL: char = '\u{7}';
+
+/// Prepares program output for showing in failure context.
+pub fn present(text: &str, control_sequences: ControlSequences) -> Cow<'_, str> {
+    let has_control_characters = text.chars().any(|c| c.is_control() && c != '\n' && c != '\t');
+
+    match control_sequences {
+        ControlSequences::Raw => Cow::Borrowed(text),
+        _ if !has_control_characters => Cow::Borrowed(text),
+        ControlSequences::Escape => Cow::Owned(self::escape(text)),
+        ControlSequences::Render => Cow::Owned(self::render(text)),
+    }
+}
+
+/// Replaces control characters, other than newlines and tabs, with visible escapes such as `\r` and `\x1b`.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\n' | '\t' => escaped.push(c),
+            '\r' => escaped.push_str("\\r"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Simulates how a terminal would display the text once it has all been written.
+///
+/// Carriage returns, backspaces, cursor movement and erasure are applied, and all other
+/// control sequences, such as colors, are dropped.
+pub fn render(text: &str) -> String {
+    let mut screen = Screen::default();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => { screen.row += 1; screen.column = 0; },
+            '\r' => screen.column = 0,
+            '\u{8}' => screen.column = screen.column.saturating_sub(1),
+            ESCAPE => match chars.next() {
+                // Control sequences, such as `ESC[2K`, end with a character in the range '@' to '~'.
+                Some('[') => {
+                    let mut parameters = String::new();
+                    let command = loop {
+                        match chars.next() {
+                            Some(c) if ('@'..='~').contains(&c) => break Some(c),
+                            Some(c) => parameters.push(c),
+                            None => break None,
+                        }
+                    };
+                    if let Some(command) = command {
+                        screen.apply(command, &parameters);
+                    }
+                },
+                // Operating system commands, such as hyperlinks, end with a bell or `ESC\`.
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == BELL || (c == ESCAPE && chars.next_if_eq(&'\\').is_some()) { break; }
+                    }
+                },
+                _ => (),
+            },
+            c if c.is_control() && c != '\t' => (),
+            c => screen.write(c),
+        }
+    }
+
+    screen.into_string(text.ends_with('\n'))
+}
+
+/// The characters on a terminal, along with the position of the cursor.
+#[derive(Default)]
+struct Screen {
+    lines: Vec<Vec<char>>,
+    row: usize,
+    column: usize,
+}
+
+impl Screen {
+    fn write(&mut self, c: char) {
+        let column = self.column;
+        let line = self.line();
+
+        if line.len() <= column { line.resize(column + 1, ' '); }
+        line[column] = c;
+        self.column += 1;
+    }
+
+    /// Applies a control sequence, such as `K` for erasing a line.
+    fn apply(&mut self, command: char, parameters: &str) {
+        let count = parameters.parse::<usize>().unwrap_or(1).max(1);
+        let mode = parameters.parse::<usize>().unwrap_or(0);
+
+        match command {
+            'A' => self.row = self.row.saturating_sub(count),
+            'B' => self.row += count,
+            'C' => self.column += count,
+            'D' => self.column = self.column.saturating_sub(count),
+            'G' => self.column = count - 1,
+            'K' => {
+                let column = self.column;
+                let line = self.line();
+                match mode {
+                    0 => line.truncate(column),
+                    1 => line.iter_mut().take(column + 1).for_each(|c| *c = ' '),
+                    _ => line.clear(),
+                }
+            },
+            'J' if mode == 2 || mode == 3 => {
+                self.lines.clear();
+                self.row = 0;
+                self.column = 0;
+            },
+            // Colors and other styling do not affect the text.
+            _ => (),
+        }
+    }
+
+    fn line(&mut self) -> &mut Vec<char> {
+        if self.lines.len() <= self.row { self.lines.resize(self.row + 1, Vec::new()); }
+        &mut self.lines[self.row]
+    }
+
+    fn into_string(mut self, ends_with_newline: bool) -> String {
+        // The cursor is moved onto a new, empty line by a trailing newline.
+        if ends_with_newline { self.line(); }
+
+        let mut lines: Vec<String> = self.lines.iter().map(|line| line.iter().collect::<String>().trim_end().to_owned()).collect();
+        if ends_with_newline && lines.last().map(|l| l.is_empty()).unwrap_or(false) {
+            lines.pop();
+            return lines.iter().map(|line| format!("{}\n", line)).collect();
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_progress_bars_as_their_final_state() {
+        assert_eq!(render("downloading  10%\rdownloading 100%\ndone\n"), "downloading 100%\ndone\n");
+        assert_eq!(render("\u{1b}[32mok\u{1b}[0m\n\u{1b}]8;;https://example.com\u{7}link\u{1b}]8;;\u{7}"), "ok\nlink");
+        assert_eq!(render("step 1\nstep 2\n\u{1b}[1A\u{1b}[2Kfinished\n"), "step 1\nfinished\n");
+        assert_eq!(render("abc\u{8}\u{8}X\u{1b}[K"), "aX");
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        assert_eq!(escape("\u{1b}[31merror\u{1b}[0m\r\n\ttab"), "\\x1b[31merror\\x1b[0m\\r\n\ttab");
+        assert!(matches!(present("plain\n", ControlSequences::Render), Cow::Borrowed(..)));
+    }
+}
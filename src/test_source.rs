@@ -0,0 +1,233 @@
+//! Sources that test files can be loaded from.
+//!
+//! By default, tests are discovered on disk via `Config::test_paths`. Extra
+//! sources can be registered with `Config::add_test_source`, for example to
+//! compile a suite into the test binary via `include_str!`.
+
+use crate::{Config, json, model::TestFilePath};
+use std::{fmt, fs, path::{Path, PathBuf}, process, sync::Mutex};
+
+/// The directory of each crate that `CargoWorkspace` finds tests in, by default.
+pub const DEFAULT_CARGO_TEST_DIRECTORY: &str = "tests/lit";
+
+/// A source of test files.
+pub trait TestSource: fmt::Debug + Send + Sync {
+    /// Lists all tests provided by the source.
+    fn test_paths(&self, config: &Config) -> Result<Vec<TestFilePath>, String>;
+
+    /// Reads the text of a test.
+    ///
+    /// Returns `None` if the test is not provided by this source.
+    fn read(&self, path: &TestFilePath) -> Option<Result<String, String>>;
+}
+
+/// Tests discovered on disk, underneath `Config::test_paths`.
+#[derive(Clone, Debug, Default)]
+pub struct Filesystem;
+
+/// Tests held in memory, such as those embedded into a binary via `include_str!`.
+///
+/// So that commands can still refer to the test via `@file`, the tests are written
+/// to a private temporary directory the first time they are listed.
+#[derive(Debug, Default)]
+pub struct InMemory {
+    /// The relative path and text of each test.
+    files: Vec<(PathBuf, String)>,
+    materialized_directory: Mutex<Option<tempfile::TempDir>>,
+}
+
+/// Tests in the `tests/lit` directory of every member of a cargo workspace, found via
+/// `cargo metadata`.
+///
+/// The relative path of each test starts with the name of its crate, such as
+/// `mytool/basic.txt`, so that reports and artifacts are grouped by crate.
+#[derive(Debug)]
+pub struct CargoWorkspace {
+    manifest_path: Option<PathBuf>,
+    test_directory: PathBuf,
+    /// The test directory of each member, once the tests have been listed.
+    member_test_directories: Mutex<Vec<PathBuf>>,
+}
+
+impl TestSource for Filesystem {
+    fn test_paths(&self, config: &Config) -> Result<Vec<TestFilePath>, String> {
+        crate::run::find_files::on_disk(config)
+    }
+
+    fn read(&self, path: &TestFilePath) -> Option<Result<String, String>> {
+        Some(fs::read_to_string(&path.absolute).map_err(|e| format!("could not open {}: {}", path.absolute.display(), e)))
+    }
+}
+
+impl InMemory {
+    /// Creates a source without any tests.
+    pub fn new() -> Self { InMemory::default() }
+
+    /// Adds a test, given its path relative to the suite, and its text.
+    pub fn with_file(mut self, relative_path: impl Into<PathBuf>, text: impl Into<String>) -> Self {
+        self.files.push((relative_path.into(), text.into()));
+        self
+    }
+}
+
+impl TestSource for InMemory {
+    fn test_paths(&self, config: &Config) -> Result<Vec<TestFilePath>, String> {
+        let mut materialized_directory = self.materialized_directory.lock().unwrap();
+
+        if materialized_directory.is_none() {
+            let directory = match config.temp_root {
+                Some(ref temp_root) => tempfile::tempdir_in(temp_root),
+                None => tempfile::tempdir(),
+            };
+            let directory = directory.map_err(|e| format!("could not create directory for in-memory tests: {}", e))?;
+
+            for (relative_path, text) in self.files.iter() {
+                let absolute_path = directory.path().join(relative_path);
+                let write_result = fs::create_dir_all(absolute_path.parent().unwrap()).and_then(|_| fs::write(&absolute_path, text));
+                write_result.map_err(|e| format!("could not write in-memory test '{}': {}", relative_path.display(), e))?;
+            }
+
+            *materialized_directory = Some(directory);
+        }
+
+        let root = materialized_directory.as_ref().unwrap().path();
+        Ok(self.files.iter().map(|(relative_path, _)| TestFilePath::new(root.join(relative_path), relative_path)).collect())
+    }
+
+    fn read(&self, path: &TestFilePath) -> Option<Result<String, String>> {
+        let materialized_directory = self.materialized_directory.lock().unwrap();
+        let root = materialized_directory.as_ref()?.path();
+
+        self.files.iter()
+            .find(|(relative_path, _)| path.absolute == root.join(relative_path))
+            .map(|(_, text)| Ok(text.clone()))
+    }
+}
+
+impl CargoWorkspace {
+    /// Creates a source for the workspace containing the current directory.
+    pub fn new() -> Self {
+        CargoWorkspace {
+            manifest_path: None,
+            test_directory: DEFAULT_CARGO_TEST_DIRECTORY.into(),
+            member_test_directories: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Uses the workspace of a `Cargo.toml`, rather than that of the current directory.
+    pub fn with_manifest_path(mut self, manifest_path: impl Into<PathBuf>) -> Self {
+        self.manifest_path = Some(manifest_path.into());
+        self
+    }
+
+    /// Sets the directory, relative to each crate, that tests are found in.
+    pub fn with_test_directory(mut self, test_directory: impl Into<PathBuf>) -> Self {
+        self.test_directory = test_directory.into();
+        self
+    }
+
+    /// Gets the name and test directory of every workspace member, whether the test directory exists or not.
+    pub fn members(&self) -> Result<Vec<(String, PathBuf)>, String> {
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let mut command = process::Command::new(cargo);
+        command.args(["metadata", "--format-version", "1", "--no-deps"]);
+        if let Some(ref manifest_path) = self.manifest_path {
+            command.arg("--manifest-path").arg(manifest_path);
+        }
+
+        let output = command.output().map_err(|e| format!("could not run 'cargo metadata': {}", e))?;
+        if !output.status.success() {
+            return Err(format!("'cargo metadata' failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+
+        self::members_from_metadata(&String::from_utf8_lossy(&output.stdout), &self.test_directory)
+    }
+}
+
+impl Default for CargoWorkspace {
+    fn default() -> Self { CargoWorkspace::new() }
+}
+
+impl TestSource for CargoWorkspace {
+    fn test_paths(&self, config: &Config) -> Result<Vec<TestFilePath>, String> {
+        let mut test_paths = Vec::new();
+        let mut member_test_directories = self.member_test_directories.lock().unwrap();
+
+        for (name, test_directory) in self.members()? {
+            if !test_directory.is_dir() { continue; }
+
+            for absolute_path in crate::run::find_files::in_path(&test_directory.display().to_string(), config)? {
+                let absolute_path = PathBuf::from(absolute_path);
+                let relative_path = Path::new(&name).join(absolute_path.strip_prefix(&test_directory).unwrap());
+                test_paths.push(TestFilePath::new(absolute_path, relative_path));
+            }
+            member_test_directories.push(test_directory);
+        }
+
+        Ok(test_paths)
+    }
+
+    fn read(&self, path: &TestFilePath) -> Option<Result<String, String>> {
+        let member_test_directories = self.member_test_directories.lock().unwrap();
+
+        if member_test_directories.iter().any(|directory| path.absolute.starts_with(directory)) {
+            Filesystem.read(path)
+        } else {
+            None
+        }
+    }
+}
+
+/// Finds the name and test directory of every package in the output of `cargo metadata`.
+fn members_from_metadata(metadata: &str, test_directory: &Path) -> Result<Vec<(String, PathBuf)>, String> {
+    let metadata = json::Value::parse(metadata).map_err(|e| format!("could not parse the output of 'cargo metadata': {}", e))?;
+    let packages = metadata.get("packages").and_then(json::Value::as_array).ok_or("'cargo metadata' did not list any packages")?;
+
+    packages.iter().map(|package| {
+        let name = package.get("name").and_then(json::Value::as_str);
+        let manifest_path = package.get("manifest_path").and_then(json::Value::as_str);
+
+        match (name, manifest_path.map(Path::new).and_then(Path::parent)) {
+            (Some(name), Some(crate_directory)) => Ok((name.to_owned(), crate_directory.join(test_directory))),
+            _ => Err("'cargo metadata' listed a package without a name or manifest path".to_owned()),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn in_memory_tests_are_listed_and_read() {
+        let source = InMemory::new().with_file("nested/a.txt", "; RUN: true\n");
+        let test_paths = source.test_paths(&Config::default()).unwrap();
+
+        assert_eq!(test_paths.len(), 1);
+        assert_eq!(test_paths[0].relative, PathBuf::from("nested/a.txt"));
+        assert!(test_paths[0].absolute.is_file());
+        assert_eq!(source.read(&test_paths[0]), Some(Ok("; RUN: true\n".to_owned())));
+        assert_eq!(source.read(&TestFilePath::new("/elsewhere/a.txt", "a.txt")), None);
+    }
+
+    #[test]
+    fn finds_the_test_directories_of_workspace_members() {
+        let metadata = r#"{"packages":[{"name":"parser","manifest_path":"/ws/parser/Cargo.toml"},{"name":"cli","manifest_path":"/ws/cli/Cargo.toml"}]}"#;
+        let members = members_from_metadata(metadata, Path::new(DEFAULT_CARGO_TEST_DIRECTORY)).unwrap();
+
+        assert_eq!(members, vec![("parser".to_owned(), PathBuf::from("/ws/parser/tests/lit")), ("cli".to_owned(), PathBuf::from("/ws/cli/tests/lit"))]);
+        assert!(members_from_metadata("{}", Path::new("tests")).is_err());
+    }
+
+    #[test]
+    fn lists_the_tests_of_this_crate() {
+        let manifest_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let source = CargoWorkspace::new().with_manifest_path(manifest_path).with_test_directory("integration-tests");
+        let mut config = Config::default();
+        config.add_extension("txt");
+
+        let test_paths = source.test_paths(&config).unwrap();
+        assert!(test_paths.iter().any(|p| p.relative == Path::new("lit/check-next-simple.txt")));
+        assert!(source.read(&test_paths[0]).unwrap().is_ok());
+    }
+}
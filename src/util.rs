@@ -1,54 +1,124 @@
 //! Utility functions for internal use.
 
-const DEFAULT_INDENT_ATOM: &'static str = "  ";
-const TRUNCATED_TEXT_MARKER: &'static str = "... (truncated)";
+/// The units of durations, in milliseconds, largest first.
+const DURATION_UNITS: &[(&str, u64)] = &[("h", 3_600_000), ("m", 60_000), ("s", 1000), ("ms", 1)];
+/// The units of sizes, in bytes, largest first.
+const BYTE_SIZE_UNITS: &[(&str, u64)] = &[
+    ("GiB", 1 << 30), ("GB", 1_000_000_000), ("MiB", 1 << 20), ("MB", 1_000_000), ("KiB", 1 << 10), ("KB", 1000), ("B", 1),
+];
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum TruncateDirection { Top, Bottom }
+/// Counts the single character insertions, deletions and substitutions needed to turn one string into another.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
 
-/// Indents a piece of text.
-pub fn indent(text: &str, level: usize) -> String {
-    indent_ext(text, level, DEFAULT_INDENT_ATOM)
+    for (i, a_char) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution_cost = if a_char == b_char { 0 } else { 1 };
+            row.push((previous_row[j] + substitution_cost).min(previous_row[j + 1] + 1).min(row[j] + 1));
+        }
+        previous_row = row;
+    }
+
+    previous_row[b.len()]
 }
 
-pub fn indent_ext(text: &str, level: usize, indentation_atom: &str) -> String {
-    let indent = (0..level).into_iter().map(|_| indentation_atom).collect::<Vec<_>>().join("");
-    text.lines().map(|l| format!("{}{}", indent, l.trim())).collect::<Vec<_>>().join("\n") + "\n"
+/// Finds the candidate most likely to have been meant by a misspelled name, if any are close enough.
+pub fn closest_match<'a>(name: &str, candidates: impl IntoIterator<Item=&'a str>) -> Option<&'a str> {
+    // Allow roughly one typo for every three characters, so that short names are not matched to anything.
+    let max_distance = (name.chars().count() / 3).max(1);
+
+    candidates.into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
 }
 
-pub fn decorate_with_line_numbers(text: &str, starts_from_line_number: usize) -> String {
-    let max_line_num_digits = (starts_from_line_number + text.lines().count()).to_string().len();
+/// Checks if text matches a pattern in which `*` matches any sequence of characters.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == text,
+        Some(star_idx) => {
+            let (prefix, rest) = (&pattern[..star_idx], &pattern[star_idx + 1..]);
 
-    text.lines().enumerate().map(|(relative_lineno, line)| {
-        let line_number_str = (starts_from_line_number + relative_lineno).to_string();
-        let number_of_pad_chars = max_line_num_digits - line_number_str.len();
-        let horizontal_padding_str = (0..number_of_pad_chars).into_iter().map(|_| " ").collect::<String>();
+            text.starts_with(prefix) && (prefix.len()..=text.len())
+                .filter(|&idx| text.is_char_boundary(idx))
+                .any(|idx| glob_matches(rest, &text[idx..]))
+        },
+    }
+}
 
-        format!("{}{}|      {}", line_number_str, horizontal_padding_str, line)
-    }).collect::<Vec<_>>().join("\n")
+/// Parses a duration with a unit, such as `2s`, `1.5s` or `500ms`.
+pub fn parse_duration(text: &str) -> Result<std::time::Duration, String> {
+    self::parse_quantity(text, DURATION_UNITS).map(std::time::Duration::from_millis)
+        .map_err(|_| format!("invalid duration '{}', expected a number followed by 'ms', 's', 'm' or 'h'", text))
 }
 
-pub fn truncate_to_max_lines(
-    text: &str,
-    max_line_count: usize,
-    truncate_direction: TruncateDirection) -> String {
-    let lines = text.lines().collect::<Vec<_>>();
+/// Formats a duration in the largest unit it is a whole number of, so that it parses back identically.
+pub fn format_duration(duration: std::time::Duration) -> String {
+    self::format_quantity(duration.as_millis() as u64, DURATION_UNITS)
+}
 
-    let is_truncated = lines.len() > max_line_count;
+/// Parses a size with a unit, such as `100MB`, `1.5GiB` or `512B`.
+pub fn parse_byte_size(text: &str) -> Result<u64, String> {
+    self::parse_quantity(text, BYTE_SIZE_UNITS)
+        .map_err(|_| format!("invalid size '{}', expected a number followed by 'B', 'KB', 'MB', 'GB', 'KiB', 'MiB' or 'GiB'", text))
+}
 
-    let truncated_lines: Vec<_> = match truncate_direction {
-        TruncateDirection::Bottom => lines.into_iter().take(max_line_count).collect(),
-        TruncateDirection::Top => lines.into_iter().rev().take(max_line_count).rev().collect(),
-    };
+/// Formats a size in the largest unit it is a whole number of, so that it parses back identically.
+pub fn format_byte_size(bytes: u64) -> String {
+    self::format_quantity(bytes, BYTE_SIZE_UNITS)
+}
 
-    let truncated_text = truncated_lines.join("\n");
+/// Parses a possibly fractional number followed by one of the units, giving a whole number of the smallest unit.
+fn parse_quantity(text: &str, units: &[(&str, u64)]) -> Result<u64, ()> {
+    let text = text.trim();
+    let number_length = text.find(|c: char| !c.is_ascii_digit() && c != '.').ok_or(())?;
+    let (number, unit) = (&text[..number_length], text[number_length..].trim());
 
-    if is_truncated {
-        match truncate_direction {
-            TruncateDirection::Bottom => truncated_text.to_owned() + "\n\n" + TRUNCATED_TEXT_MARKER,
-            TruncateDirection::Top => TRUNCATED_TEXT_MARKER.to_string() + "\n\n" + &truncated_text[..],
-        }
-    } else {
-        truncated_text // the text was not actually truncated
+    let multiplier = units.iter().find(|&&(name, _)| name == unit).map(|&(_, multiplier)| multiplier).ok_or(())?;
+    let number: f64 = number.parse().map_err(|_| ())?;
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+fn format_quantity(amount: u64, units: &[(&str, u64)]) -> String {
+    let &(unit, multiplier) = units.iter().find(|&&(_, multiplier)| amount.is_multiple_of(multiplier) && amount >= multiplier)
+        .unwrap_or(&units[units.len() - 1]);
+
+    format!("{}{}", amount / multiplier, unit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_quantities_with_units() {
+        assert_eq!(parse_duration("1.5s"), Ok(std::time::Duration::from_millis(1500)));
+        assert_eq!(parse_duration(" 250 ms"), Ok(std::time::Duration::from_millis(250)));
+        assert!(parse_duration("2").is_err());
+        assert_eq!(format_duration(std::time::Duration::from_millis(120_000)), "2m");
+        assert_eq!(format_duration(std::time::Duration::from_millis(1500)), "1500ms");
+
+        assert_eq!(parse_byte_size("100MB"), Ok(100_000_000));
+        assert_eq!(parse_byte_size("1.5KiB"), Ok(1536));
+        assert!(parse_byte_size("100 apples").is_err());
+        assert_eq!(format_byte_size(100_000_000), "100MB");
+        assert_eq!(format_byte_size(3 << 20), "3MiB");
+        assert_eq!(format_byte_size(0), "0B");
+    }
+
+    #[test]
+    fn suggests_the_closest_candidate() {
+        let candidates = || vec!["CHECK", "CHECK-NEXT", "RUN"];
+
+        assert_eq!(edit_distance("CHEK", "CHECK"), 1);
+        assert_eq!(edit_distance("", "RUN"), 3);
+        assert_eq!(closest_match("CHEK", candidates()), Some("CHECK"));
+        assert_eq!(closest_match("CHECK-NXET", candidates()), Some("CHECK-NEXT"));
+        assert_eq!(closest_match("NOTE", candidates()), None);
     }
 }
@@ -1,10 +1,80 @@
 use std::collections::HashMap;
+use std::net::{Ipv4Addr, TcpListener};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub mod resolve;
 
 pub type Variables = HashMap<String, String>;
 
+/// The namespace of constants from the configuration, as in `@cfg:name`.
+pub const CONFIG_NAMESPACE: &str = "cfg";
+/// The namespace of environment variables of lit itself, as in `@env:PATH`.
+pub const ENVIRONMENT_NAMESPACE: &str = "env";
+/// The namespace of named captures from passing checks, as in `@cap:name`.
+pub const CAPTURE_NAMESPACE: &str = "cap";
+
+/// All namespaces that variable names can be qualified with.
+pub const NAMESPACES: &[&str] = &[CONFIG_NAMESPACE, ENVIRONMENT_NAMESPACE, CAPTURE_NAMESPACE];
+
+/// The number of unique IDs handed out so far by this process.
+static UNIQUE_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Generates the variables that are resolved freshly for every test file.
+///
+/// * `@free_port` - a TCP port that was unused on the loopback interface
+/// * `@unique_id` - an identifier that is unique to this test file execution
+pub fn runtime_variables() -> Variables {
+    let mut variables = Variables::new();
+
+    if let Some(port) = free_port() {
+        variables.insert("free_port".to_owned(), port.to_string());
+    }
+    variables.insert("unique_id".to_owned(), unique_id());
+
+    variables
+}
+
+/// Chooses a seed for a run that did not ask for a specific one.
+pub fn random_seed() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0));
+    hasher.finish()
+}
+
+/// Qualifies a variable name with a namespace.
+pub fn qualified_name(namespace: &str, name: &str) -> String {
+    format!("{}:{}", namespace, name)
+}
+
+/// Verifies that a constant name is not qualified, as namespaces are reserved.
+pub fn validate_constant_name(name: &str) -> Result<(), String> {
+    match name.split_once(':') {
+        Some((namespace, _)) if NAMESPACES.contains(&namespace) => {
+            Err(format!("constant '{}' uses the reserved namespace prefix '{}:'", name, namespace))
+        },
+        Some(..) => Err(format!("constant names cannot contain ':' but got '{}'", name)),
+        None => Ok(()),
+    }
+}
+
+/// Asks the operating system for a currently unused TCP port.
+fn free_port() -> Option<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).ok()?;
+    listener.local_addr().ok().map(|addr| addr.port())
+}
+
+fn unique_id() -> String {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let counter = UNIQUE_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+
+    format!("{}-{:x}-{}", std::process::id(), timestamp, counter)
+}
+
 pub trait VariablesExt {
     fn as_map(&self) -> &HashMap<String, String>;
 
@@ -15,9 +85,52 @@ pub trait VariablesExt {
             .map(|(_,v)| Path::new(v).to_owned())
             .collect()
     }
+
+    /// Gets every variable, sorted by name, so that the order is the same across runs.
+    fn sorted_pairs(&self) -> Vec<(&str, &str)> {
+        let mut pairs: Vec<_> = self.as_map().iter().map(|(name, value)| (&name[..], &value[..])).collect();
+        pairs.sort();
+        pairs
+    }
+
+    /// Renders every variable as a `name=value` line, sorted by name, so that logs and
+    /// artifacts of different runs can be diffed.
+    fn dump(&self) -> String {
+        self.sorted_pairs().into_iter().map(|(name, value)| format!("{}={}\n", name, value)).collect()
+    }
 }
 
 impl VariablesExt for Variables {
     fn as_map(&self) -> &Self { self }
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runtime_variables_are_unique() {
+        let (first, second) = (runtime_variables(), runtime_variables());
+
+        assert_ne!(first["unique_id"], second["unique_id"]);
+        assert!(first["free_port"].parse::<u16>().is_ok());
+    }
+
+    #[test]
+    fn variables_are_dumped_in_order_of_their_names() {
+        let variables: Variables = vec![("tempfile", "/tmp/a"), ("cc", "clang"), ("cfg:cc", "gcc")].into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+
+        assert_eq!(variables.sorted_pairs(), vec![("cc", "clang"), ("cfg:cc", "gcc"), ("tempfile", "/tmp/a")]);
+        assert_eq!(variables.dump(), "cc=clang\ncfg:cc=gcc\ntempfile=/tmp/a\n");
+    }
+
+    #[test]
+    fn constant_names_cannot_use_namespaces() {
+        assert!(validate_constant_name("cc").is_ok());
+        assert_eq!(validate_constant_name("cfg:cc").unwrap_err(), "constant 'cfg:cc' uses the reserved namespace prefix 'cfg:'");
+        assert!(validate_constant_name("a:b").is_err());
+    }
+}
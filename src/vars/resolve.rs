@@ -1,13 +1,45 @@
 //! Utilities for resolving/substituting variables within different types.
+//!
+//! When several sources define a bare variable name, earlier sources here shadow
+//! later ones:
+//!
+//! 1. Named captures from passing checks, until cleared by an `UNDEF` directive.
+//! 2. Values decided at run time, such as `@free_port`.
+//! 3. Variables from the executor and the test file, such as `@file`.
+//! 4. Constants from the configuration, which captures are never allowed to shadow.
+//! 5. Lazily created variables, such as `@tempfile`.
+//!
+//! Names qualified with a namespace only ever refer to one source - `@cfg:name` to a
+//! constant, `@env:NAME` to an environment variable, and `@cap:name` to a capture.
 
 use crate::model::*;
-use crate::vars::Variables;
-use crate::Config;
+use crate::vars::{self, Variables};
+use crate::{Config, config::RegexOptions};
 
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// The most regexes a cache holds before it evicts the least recently compiled one.
+const MAX_CACHED_REGEXES: usize = 1024;
 
 lazy_static! {
-    static ref CONSTANT_REGEX: Regex = Regex::new("@([_a-zA-Z]+)").unwrap();
+    static ref CONSTANT_REGEX: Regex = Regex::new("@((?:cfg|env|cap):[_a-zA-Z][_a-zA-Z0-9]*|[_a-zA-Z]+)").unwrap();
+
+    /// Regexes for patterns that do not interpolate any variables, shared by all tests.
+    static ref GLOBAL_REGEX_CACHE: Mutex<RegexCache> = Mutex::new(RegexCache::new());
+}
+
+/// A cache of compiled regexes, keyed on the generated regex source.
+#[derive(Debug, Default)]
+pub struct RegexCache {
+    regexes: HashMap<(RegexOptions, String), Regex>,
+    /// The keys of the cached regexes, from the least to the most recently compiled.
+    insertion_order: VecDeque<(RegexOptions, String)>,
+    /// The number of lookups that reused an already compiled regex.
+    pub hits: usize,
+    /// The number of lookups that required compiling a new regex.
+    pub misses: usize,
 }
 
 /// A span representing where a constant name resides in a string.
@@ -17,17 +49,72 @@ struct ConstantSpan {
     name: String,
     /// The index of the first character.
     start: usize,
-    /// The index of the last character.
-    end: usize,
 }
 
+/// Builds the regex that a text pattern corresponds to, reusing previously compiled regexes.
+///
+/// Patterns that interpolate variables are cached in the given test-local cache, all
+/// other patterns are cached globally.
+///
+/// Returns an error if the generated regex is invalid, or too large.
 pub fn text_pattern(pattern: &TextPattern, config: &Config,
-                    variables: &mut Variables) -> Regex {
-    let regex_parts: Vec<_> = pattern.components.iter().map(|comp| match *comp {
+                    variables: &mut Variables,
+                    test_cache: &mut RegexCache) -> Result<Regex, String> {
+    let regex_source = self::regex_source(pattern, config, variables)?;
+    let interpolates_variables = pattern.components.iter().any(|c| matches!(*c, PatternComponent::Variable(..)));
+
+    if interpolates_variables {
+        test_cache.get_or_build(regex_source, pattern, &config.regex_options)
+    } else {
+        GLOBAL_REGEX_CACHE.lock().unwrap().get_or_build(regex_source, pattern, &config.regex_options)
+    }
+}
+
+/// Gets the text that a pattern matches literally, as given to matchers other than regexes,
+/// with its variables substituted.
+///
+/// Returns an error if the pattern contains regexes.
+pub fn literal_text(pattern: &TextPattern, matcher_name: &str, config: &Config, variables: &mut Variables) -> Result<String, String> {
+    pattern.components.iter().map(|component| match *component {
+        PatternComponent::Text(ref text) => Ok(text.clone()),
+        PatternComponent::Variable(ref name) => match config.try_lookup_variable(name, variables) {
+            Some(value) => Ok(value.to_owned()),
+            None => Err(format!("variable '{}' used in pattern '{}' is not defined", name, pattern)),
+        },
+        _ => Err(format!("pattern '{}' contains regexes, which the '{}' matcher does not support", pattern, matcher_name)),
+    }).collect()
+}
+
+/// Substitutes the variables of a pattern that are defined, such as to show what it expected.
+///
+/// Regexes are kept as they were written.
+pub fn substitute_variables(pattern: &TextPattern, config: &Config, variables: &mut Variables) -> TextPattern {
+    let components = pattern.components.iter().map(|component| match *component {
+        PatternComponent::Variable(ref name) => match config.try_lookup_variable(name, variables) {
+            Some(value) => PatternComponent::Text(value.to_owned()),
+            None => component.clone(),
+        },
+        _ => component.clone(),
+    }).collect();
+
+    TextPattern { components, ..pattern.clone() }
+}
+
+/// Gets the hit/miss statistics of the global regex cache.
+pub fn global_regex_cache_statistics() -> (usize, usize) {
+    let cache = GLOBAL_REGEX_CACHE.lock().unwrap();
+    (cache.hits, cache.misses)
+}
+
+fn regex_source(pattern: &TextPattern, config: &Config,
+                variables: &mut Variables) -> Result<String, String> {
+    let regex_parts = pattern.components.iter().enumerate().map(|(component_idx, comp)| Ok(match *comp {
         PatternComponent::Text(ref text) => regex::escape(text),
         PatternComponent::Variable(ref name) => {
-            // FIXME: proper error handling.
-            let value = config.lookup_variable(name, variables);
+            let value = match config.try_lookup_variable(name, variables) {
+                Some(value) => value,
+                None => return Err(format!("variable '{}' used in pattern '{}' is not defined", name, pattern)),
+            };
 
             let var_resolution_log = format!("resolving '@{}' to '{}' in {:?}", name, value, pattern);
             debug!("{}", var_resolution_log);
@@ -38,10 +125,194 @@ pub fn text_pattern(pattern: &TextPattern, config: &Config,
 
             value.to_owned()
         },
-        PatternComponent::Regex(ref regex) => regex.clone(),
-        PatternComponent::NamedRegex { ref name, ref regex } => format!("(?P<{}>{})", name, regex),
-    }).collect();
-    Regex::new(&regex_parts.join("")).expect("generated invalid line match regex")
+        PatternComponent::Regex(ref regex) => config.expand_pattern_macro(regex).into_owned(),
+        PatternComponent::NamedRegex { ref name, ref regex } => format!("(?P<{}>{})", name, config.expand_pattern_macro(regex)),
+        PatternComponent::Numeric { ref format, .. } => {
+            let number_regex = numeric_bounds(comp).and_then(|_| number_regex(format))
+                .map_err(|e| format!("invalid numeric pattern in '{}': {}", pattern, e))?;
+            format!("(?P<{}>{})", numeric_capture_name(component_idx), number_regex)
+        },
+    })).collect::<Result<Vec<_>, String>>()?;
+
+    Ok(regex_parts.join(""))
+}
+
+/// Finds the first match of the regex built for a pattern, starting at a byte index.
+///
+/// Matches with numbers outside the tolerance of their numeric components are skipped.
+pub fn find_at<'t>(regex: &Regex, pattern: &TextPattern, text: &'t str, start: usize) -> Option<regex::Match<'t>> {
+    let numeric_bounds: Vec<_> = pattern.components.iter().enumerate()
+        .filter_map(|(idx, component)| Some((numeric_capture_name(idx), numeric_bounds(component).ok()??)))
+        .collect();
+
+    if numeric_bounds.is_empty() {
+        return regex.find_at(text, start);
+    }
+
+    let mut search_start = start;
+    while search_start <= text.len() {
+        let captures = regex.captures_at(text, search_start)?;
+        let whole_match = captures.get(0).unwrap();
+
+        let within_tolerance = numeric_bounds.iter().all(|(name, (value, tolerance))| {
+            captures.name(name).and_then(|n| parse_number(n.as_str()).ok())
+                // Allow for the rounding error of the subtraction, so that the bounds themselves match.
+                .map(|actual| (actual - value).abs() <= tolerance + f64::EPSILON * actual.abs().max(value.abs()))
+                .unwrap_or(false)
+        });
+        if within_tolerance {
+            return Some(whole_match);
+        }
+
+        search_start = match whole_match.end() {
+            end if end > whole_match.start() => end,
+            end => end + text[end..].chars().next().map(char::len_utf8).unwrap_or(1),
+        };
+    }
+
+    None
+}
+
+/// Checks whether a capture group holds a number matched by a numeric component.
+///
+/// These are not bound as variables.
+pub fn is_numeric_capture(name: &str) -> bool { name.starts_with(NUMERIC_CAPTURE_PREFIX) }
+
+const NUMERIC_CAPTURE_PREFIX: &str = "__lit_numeric_";
+
+fn numeric_capture_name(component_idx: usize) -> String {
+    format!("{}{}", NUMERIC_CAPTURE_PREFIX, component_idx)
+}
+
+/// Gets the expected value and tolerance of a numeric component, or `None` for other components.
+fn numeric_bounds(component: &PatternComponent) -> Result<Option<(f64, f64)>, String> {
+    let (value, tolerance) = match *component {
+        PatternComponent::Numeric { ref value, ref tolerance, .. } => (value, tolerance),
+        _ => return Ok(None),
+    };
+
+    if value.is_empty() {
+        return Err("expected a value after the format, such as '[[#%.3f, 1.234 +- 0.001]]'".to_owned());
+    }
+    let value = parse_number(value)?;
+    let tolerance = match *tolerance {
+        Some(ref tolerance) => parse_number(tolerance).ok().filter(|t| *t >= 0.0)
+            .ok_or_else(|| format!("tolerance '{}' is not a non-negative number", tolerance))?,
+        None => 0.0,
+    };
+
+    Ok(Some((value, tolerance)))
+}
+
+fn parse_number(text: &str) -> Result<f64, String> {
+    text.parse().map_err(|_| format!("'{}' is not a number", text))
+}
+
+/// Builds the regex matching numbers printed in a `printf`-style format.
+///
+/// `%d`, `%f` and `%e` match integers, decimals and scientific notation, with a precision
+/// such as `%.3f` requiring exactly that many digits after the point. `%g` matches any of them.
+fn number_regex(format: &str) -> Result<String, String> {
+    let invalid = || format!("unsupported number format '{}', expected one of '%d', '%f', '%e' or '%g', optionally with a precision such as '%.3f'", format);
+    let spec = format.strip_prefix('%').ok_or_else(invalid)?;
+    let (precision, conversion) = match spec.strip_prefix('.') {
+        Some(rest) => {
+            let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+            (Some(digits.parse::<usize>().map_err(|_| invalid())?), &rest[digits.len()..])
+        },
+        None => (None, spec),
+    };
+
+    let fraction = match precision {
+        Some(0) => String::new(),
+        Some(precision) => format!("\\.[0-9]{{{}}}", precision),
+        None => "(?:\\.[0-9]*)?".to_owned(),
+    };
+    let body = match conversion {
+        "d" | "i" if precision.is_none() => "[0-9]+".to_owned(),
+        "f" => format!("[0-9]+{}", fraction),
+        "e" => format!("[0-9]+{}[eE][-+]?[0-9]+", fraction),
+        "g" if precision.is_none() => "(?:[0-9]+(?:\\.[0-9]*)?|\\.[0-9]+)(?:[eE][-+]?[0-9]+)?".to_owned(),
+        _ => return Err(invalid()),
+    };
+
+    Ok(format!("[-+]?{}", body))
+}
+
+fn build_regex(regex_source: &str, pattern: &TextPattern, options: &RegexOptions) -> Result<Regex, String> {
+    let regex = RegexBuilder::new(regex_source)
+        .size_limit(options.size_limit)
+        .unicode(options.unicode)
+        .dot_matches_new_line(options.dot_matches_new_line)
+        .case_insensitive(options.case_insensitive)
+        .build();
+
+    match regex {
+        Ok(regex) => Ok(regex),
+        Err(regex::Error::CompiledTooBig(limit)) => Err(format!(
+            "the regex generated for pattern '{}' exceeds the size limit of {} bytes, consider raising 'Config::regex_options.size_limit'",
+            pattern, limit)),
+        Err(e) => Err(format!("the regex generated for pattern '{}' is invalid: {}", pattern, e)),
+    }
+}
+
+impl RegexCache {
+    /// Creates a new, empty cache.
+    pub fn new() -> Self { RegexCache::default() }
+
+    fn get_or_build(&mut self, regex_source: String, pattern: &TextPattern, options: &RegexOptions) -> Result<Regex, String> {
+        let key = (options.clone(), regex_source);
+
+        if let Some(regex) = self.regexes.get(&key) {
+            self.hits += 1;
+            return Ok(regex.clone());
+        }
+
+        self.misses += 1;
+        let regex = build_regex(&key.1, pattern, options)?;
+        if self.regexes.len() >= MAX_CACHED_REGEXES {
+            if let Some(oldest_key) = self.insertion_order.pop_front() {
+                self.regexes.remove(&oldest_key);
+            }
+        }
+        self.insertion_order.push_back(key.clone());
+        self.regexes.insert(key, regex.clone());
+        Ok(regex)
+    }
+}
+
+/// Binds the named captures of a passing check, shadowing any existing variables.
+///
+/// Returns the names of the captures that shadow a variable which did not come
+/// from a capture. Returns an error if a capture would shadow a constant.
+pub fn bind_captures(captures: HashMap<String, String>,
+                     variables: &mut Variables,
+                     initial_variables: &Variables) -> Result<Vec<String>, String> {
+    let mut names: Vec<_> = captures.keys().cloned().collect();
+    names.sort();
+
+    if let Some(name) = names.iter().find(|name| initial_variables.contains_key(&vars::qualified_name(vars::CONFIG_NAMESPACE, name))) {
+        return Err(format!("named capture '{}' would shadow the constant of the same name, consider renaming the capture", name));
+    }
+    let shadowing_names = names.into_iter().filter(|name| initial_variables.contains_key(name)).collect();
+
+    for (name, value) in captures {
+        variables.insert(vars::qualified_name(vars::CAPTURE_NAMESPACE, &name), value.clone());
+        variables.insert(name, value);
+    }
+    Ok(shadowing_names)
+}
+
+/// Clears a captured variable, revealing the variable it shadowed, if any.
+pub fn undefine(name: &str,
+                variables: &mut Variables,
+                initial_variables: &Variables) {
+    variables.remove(&vars::qualified_name(vars::CAPTURE_NAMESPACE, name));
+
+    match initial_variables.get(name) {
+        Some(initial_value) => { variables.insert(name.to_owned(), initial_value.clone()); },
+        None => { variables.remove(name); },
+    }
 }
 
 pub fn invocation(invocation: &Invocation,
@@ -56,7 +327,6 @@ pub fn invocation(invocation: &Invocation,
         ConstantSpan {
             name: name,
             start: mat.start(),
-            end: mat.end(),
         }
     });
 
@@ -115,7 +385,7 @@ mod test {
 
         fn resolve(s: &str) -> String {
             let text_pattern = parse::text_pattern(s);
-            vars::resolve::text_pattern(&text_pattern, &Config::default(), &mut VARIABLES.clone()).as_str().to_owned()
+            vars::resolve::text_pattern(&text_pattern, &Config::default(), &mut VARIABLES.clone(), &mut vars::resolve::RegexCache::new()).unwrap().as_str().to_owned()
         }
 
         #[test]
@@ -135,6 +405,95 @@ mod test {
             assert_eq!(resolve("goodbye $$name").as_str(),
                        "goodbye bob");
         }
+
+        #[test]
+        fn stops_variable_names_before_trailing_underscores() {
+            assert_eq!(resolve("$$name_[[\\d]]").as_str(),
+                       "bob_\\d");
+            assert_eq!(resolve("cost: $$ $$po").as_str(),
+                       "cost: \\$\\$ polonium");
+        }
+
+        #[test]
+        fn expands_named_patterns_from_config() {
+            let mut config = Config::default();
+            config.patterns.insert("ident".to_owned(), "[a-z]+".to_owned());
+
+            let text_pattern = parse::text_pattern("fn [[:ident:]]() [[name::ident:]] [[:unknown:]]");
+            let regex = vars::resolve::text_pattern(&text_pattern, &config, &mut VARIABLES.clone(), &mut vars::resolve::RegexCache::new()).unwrap();
+            assert_eq!(regex.as_str(), "fn (?:[a-z]+)\\(\\) (?P<name>(?:[a-z]+)) :unknown:");
+        }
+
+        #[test]
+        fn applies_regex_options() {
+            let mut config = Config::default();
+            config.regex_options.case_insensitive = true;
+
+            let regex = vars::resolve::text_pattern(&parse::text_pattern("hello"), &config, &mut VARIABLES.clone(), &mut vars::resolve::RegexCache::new()).unwrap();
+            assert!(regex.is_match("HeLLo"));
+        }
+
+        #[test]
+        fn caches_regexes_with_variables_per_test() {
+            let config = Config::default();
+            let mut cache = vars::resolve::RegexCache::new();
+            let text_pattern = parse::text_pattern("hello $$name");
+
+            for _ in 0..3 {
+                let regex = vars::resolve::text_pattern(&text_pattern, &config, &mut VARIABLES.clone(), &mut cache).unwrap();
+                assert_eq!(regex.as_str(), "hello bob");
+            }
+
+            assert_eq!((cache.hits, cache.misses), (2, 1));
+        }
+
+        #[test]
+        fn evicts_the_oldest_regexes_once_full() {
+            let config = Config::default();
+            let mut cache = vars::resolve::RegexCache::new();
+            let mut variables = VARIABLES.clone();
+
+            for i in 0..(vars::resolve::MAX_CACHED_REGEXES + 1) {
+                variables.insert("name".to_owned(), i.to_string());
+                vars::resolve::text_pattern(&parse::text_pattern("hello $$name"), &config, &mut variables, &mut cache).unwrap();
+            }
+            assert_eq!(cache.regexes.len(), vars::resolve::MAX_CACHED_REGEXES);
+
+            variables.insert("name".to_owned(), "0".to_owned());
+            vars::resolve::text_pattern(&parse::text_pattern("hello $$name"), &config, &mut variables, &mut cache).unwrap();
+            assert_eq!(cache.misses, vars::resolve::MAX_CACHED_REGEXES + 2);
+        }
+
+        #[test]
+        fn matches_numbers_within_a_tolerance() {
+            let text_pattern = parse::text_pattern("took [[#%.3f, 1.234 +- 0.001]]s");
+            let regex = vars::resolve::text_pattern(&text_pattern, &Config::default(), &mut VARIABLES.clone(), &mut vars::resolve::RegexCache::new()).unwrap();
+            let find = |text| vars::resolve::find_at(&regex, &text_pattern, text, 0).map(|m| m.as_str());
+
+            assert_eq!(text_pattern.to_string(), "took [[#%.3f, 1.234 +- 0.001]]s");
+            assert_eq!(find("took 1.235s"), Some("took 1.235s"));
+            assert_eq!(find("took 1.236s, then took 1.233s"), Some("took 1.233s"));
+            assert_eq!(find("took 1.23s"), None);
+            assert_eq!(find("took 2.234s"), None);
+        }
+
+        #[test]
+        fn reports_invalid_numeric_patterns() {
+            let resolve = |s| vars::resolve::text_pattern(&parse::text_pattern(s), &Config::default(), &mut VARIABLES.clone(), &mut vars::resolve::RegexCache::new()).unwrap_err();
+
+            assert!(resolve("[[#%x, 1]]").contains("unsupported number format '%x'"));
+            assert!(resolve("[[#%f, one]]").contains("'one' is not a number"));
+            assert!(resolve("[[#%f, 1 +- -0.5]]").contains("tolerance '-0.5' is not a non-negative number"));
+        }
+
+        #[test]
+        fn reports_regexes_exceeding_size_limit() {
+            let mut config = Config::default();
+            config.regex_options.size_limit = 16;
+
+            let error = vars::resolve::text_pattern(&parse::text_pattern("[[\\w{100}]]"), &config, &mut VARIABLES.clone(), &mut vars::resolve::RegexCache::new()).unwrap_err();
+            assert!(error.contains("exceeds the size limit"), "unexpected error: {}", error);
+        }
     }
 
     mod invocation {
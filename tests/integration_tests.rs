@@ -13,6 +13,7 @@ fn integration_tests() {
         for ext in lit::INTEGRATION_TEST_FILE_EXTENSIONS {
             config.add_extension(ext);
         }
+        config.helper_executable = Some(env!("CARGO_BIN_EXE_lit").into());
     }).expect("unit test(s) failed");
 
     // Now run the tests again but use a custom shell instead.
@@ -21,7 +22,26 @@ fn integration_tests() {
         for ext in lit::INTEGRATION_TEST_FILE_EXTENSIONS {
             config.add_extension(ext);
         }
+        config.helper_executable = Some(env!("CARGO_BIN_EXE_lit").into());
 
         config.shell = "sh".to_string();
     }).expect("unit test(s) failed");
+
+    // And once more, evaluating checks while the programs are still running.
+    run::tests(lit::event_handler::Default::default(), |config| {
+        config.add_search_path(format!("{}/integration-tests", CRATE_PATH));
+        for ext in lit::INTEGRATION_TEST_FILE_EXTENSIONS {
+            config.add_extension(ext);
+        }
+        config.helper_executable = Some(env!("CARGO_BIN_EXE_lit").into());
+
+        config.early_exit_on_check_completion = true;
+    }).expect("unit test(s) failed");
+
+    // Finally, run a suite that is embedded into the test binary instead of read from disk.
+    run::tests(lit::event_handler::Default::default(), |config| {
+        config.add_test_source(lit::test_source::InMemory::new()
+            .with_file("embedded/single-check.sh", include_str!("../integration-tests/single-check.sh"))
+            .with_file("embedded/check-same-as.sh", include_str!("../integration-tests/check-same-as.sh")));
+    }).expect("unit test(s) failed");
 }